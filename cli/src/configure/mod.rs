@@ -39,6 +39,10 @@ pub(crate) fn configure_disks(matches: &ArgMatches) -> Result<Disks, DistinstErr
                 )
             })?;
 
+        if let Some(size) = matches.value_of("swap-file") {
+            disks.swap_file = Some(SwapFile::new(parse_size(size)?));
+        }
+
         eprintln!("distinst: disks configured");
     }
 
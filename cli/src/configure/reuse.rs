@@ -6,7 +6,7 @@ pub(crate) fn reused(disks: &mut Disks, parts: Option<Values>) -> Result<(), Dis
     if let Some(parts) = parts {
         for part in parts {
             let values: Vec<&str> = part.split(':').collect();
-            if values.len() < 3 || values.len() > 5 {
+            if values.len() < 3 || values.len() > 6 {
                 return Err(DistinstError::ReusedArgs);
             }
 
@@ -22,7 +22,7 @@ pub(crate) fn reused(disks: &mut Disks, parts: Option<Values>) -> Result<(), Dis
                 },
             );
 
-            let (mut key, mut mount, mut flags) = (None, None, None);
+            let (mut key, mut mount, mut flags, mut subvolumes) = (None, None, None, None);
 
             for value in values.iter().skip(3) {
                 if value.starts_with("mount=") {
@@ -31,6 +31,8 @@ pub(crate) fn reused(disks: &mut Disks, parts: Option<Values>) -> Result<(), Dis
                     flags = Some(parse_flags(&value[6..]));
                 } else if value.starts_with("keyid=") {
                     key = Some(String::from(&value[6..]));
+                } else if value.starts_with("subvolumes=") {
+                    subvolumes = Some(parse_subvolumes(&value[11..]));
                 } else {
                     return Err(DistinstError::InvalidField { field: (*value).into() });
                 }
@@ -70,6 +72,10 @@ pub(crate) fn reused(disks: &mut Disks, parts: Option<Values>) -> Result<(), Dis
             if let Some(flags) = flags {
                 partition.flags = flags;
             }
+
+            if let Some(subvolumes) = subvolumes {
+                partition.set_subvolumes(subvolumes);
+            }
         }
     }
 
@@ -0,0 +1,98 @@
+//! Newline-delimited JSON records of the progress of an install or upgrade, which are written
+//! to stdout by `--json-events`.
+//!
+//! Each record is a JSON object on its own line, whose `type` is `status`, `error`, `log`,
+//! `upgrade`, or `finished`. Steps, error codes, and upgrade events are named as they are in
+//! the README, and fields are only ever added to records, never renamed or removed.
+
+use distinst::{Error, ErrorCode, Status, UpgradeEvent};
+use serde_json::{json, Value};
+use std::fmt::Display;
+
+fn emit(record: Value) { println!("{}", record); }
+
+/// The step of the install and its progress, from 0 to 100.
+pub fn status(status: &Status) {
+    emit(json!({
+        "type": "status",
+        "step": status.step.as_str(),
+        "percent": status.percent,
+    }));
+}
+
+/// An error which failed a step of the install.
+pub fn error(error: &Error) {
+    let code = error.code();
+    emit(json!({
+        "type": "error",
+        "step": error.step.as_str(),
+        "code": code.as_str(),
+        "exit_status": code.exit_status(),
+        "errno": error.err.raw_os_error(),
+        "message": error.err.to_string(),
+    }));
+}
+
+/// A line which was logged by distinst.
+pub fn log<L: Display>(level: L, message: &str) {
+    emit(json!({
+        "type": "log",
+        "level": level.to_string().to_lowercase(),
+        "message": message,
+    }));
+}
+
+/// An event from the package manager during an upgrade.
+pub fn upgrade(event: &UpgradeEvent) {
+    let mut record = match *event {
+        UpgradeEvent::AttemptingRepair => json!({ "event": "attempting-repair" }),
+        UpgradeEvent::AttemptingUpgrade => json!({ "event": "attempting-upgrade" }),
+        UpgradeEvent::ResumingUpgrade => json!({ "event": "resuming-upgrade" }),
+        UpgradeEvent::DpkgInfo(message) => json!({ "event": "dpkg-info", "message": message }),
+        UpgradeEvent::DpkgErr(message) => json!({ "event": "dpkg-error", "message": message }),
+        UpgradeEvent::UpgradeInfo(message) => {
+            json!({ "event": "upgrade-info", "message": message })
+        }
+        UpgradeEvent::UpgradeErr(message) => {
+            json!({ "event": "upgrade-error", "message": message })
+        }
+        UpgradeEvent::PackageProcessing(package) => {
+            json!({ "event": "package-processing", "package": package })
+        }
+        UpgradeEvent::PackageProgress(percent) => {
+            json!({ "event": "package-progress", "percent": percent })
+        }
+        UpgradeEvent::PackageSettingUp(package) => {
+            json!({ "event": "package-setting-up", "package": package })
+        }
+        UpgradeEvent::PackageUnpacking { package, version, over } => json!({
+            "event": "package-unpacking",
+            "package": package,
+            "version": version,
+            "over": over,
+        }),
+    };
+
+    record["type"] = "upgrade".into();
+    emit(record);
+}
+
+/// The outcome of the install or upgrade, which is always the last record.
+pub fn finished(failure: Option<(ErrorCode, String)>) {
+    let record = match failure {
+        None => json!({
+            "type": "finished",
+            "success": true,
+            "exit_status": 0,
+        }),
+        Some((code, message)) => json!({
+            "type": "finished",
+            "success": false,
+            "code": code.as_str(),
+            "exit_status": code.exit_status(),
+            "message": message,
+        }),
+    };
+
+    emit(record);
+}
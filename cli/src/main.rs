@@ -5,13 +5,15 @@ extern crate failure;
 extern crate failure_derive;
 extern crate libc;
 extern crate pbr;
+extern crate serde_json;
 
 mod configure;
 mod errors;
+mod events;
 
 use clap::{App, Arg, ArgMatches, Values};
 use configure::*;
-use distinst::{timezones::Timezones, *};
+use distinst::{auto::InstallOptions, plan::InstallPlan, timezones::Timezones, *};
 use errors::DistinstError;
 
 use pbr::ProgressBar;
@@ -22,7 +24,9 @@ use std::{
     path::{Path, PathBuf},
     process::exit,
     rc::Rc,
-    sync::atomic::Ordering,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::Duration,
 };
 
 fn main() {
@@ -68,7 +72,13 @@ fn main() {
                 .long("squashfs")
                 .help("define the squashfs image which will be installed")
                 .takes_value(true)
-                .required(true),
+                .required_unless_one(&[
+                    "plan",
+                    "resume",
+                    "upgrade",
+                    "restore-tables",
+                    "resume-move",
+                ]),
         )
         .arg(
             Arg::with_name("hostname")
@@ -76,7 +86,7 @@ fn main() {
                 .long("hostname")
                 .help("define the hostname that the new system will have")
                 .takes_value(true)
-                .required(true),
+                .required_unless_one(&["plan", "upgrade", "restore-tables", "resume-move"]),
         )
         .arg(
             Arg::with_name("keyboard")
@@ -102,7 +112,13 @@ fn main() {
                 .long("remove")
                 .help("defines the manifest file that contains the packages to remove post-install")
                 .takes_value(true)
-                .required(true),
+                .required_unless_one(&[
+                    "plan",
+                    "resume",
+                    "upgrade",
+                    "restore-tables",
+                    "resume-move",
+                ]),
         )
         .arg(
             Arg::with_name("disk")
@@ -111,7 +127,63 @@ fn main() {
                 .help("defines a disk that will be manipulated in the installation process")
                 .takes_value(true)
                 .multiple(true)
-                .required(true),
+                .required_unless_one(&[
+                    "plan",
+                    "resume",
+                    "upgrade",
+                    "restore-tables",
+                    "resume-move",
+                ]),
+        )
+        .arg(
+            Arg::with_name("plan")
+                .long("plan")
+                .help(
+                    "reads the disk layout and install configuration from a TOML or JSON plan \
+                     file",
+                )
+                .takes_value(true)
+                .conflicts_with_all(&[
+                    "squashfs",
+                    "hostname",
+                    "remove",
+                    "disk",
+                    "table",
+                    "new",
+                    "use",
+                    "delete",
+                    "move",
+                    "logical",
+                    "logical-modify",
+                    "logical-remove",
+                    "logical-remove-all",
+                    "decrypt",
+                    "swap-file",
+                ]),
+        )
+        .arg(
+            Arg::with_name("resume")
+                .long("resume")
+                .help(
+                    "resumes an install which failed after its image was extracted, by \
+                     configuring the target and installing its bootloader again",
+                )
+                .conflicts_with_all(&[
+                    "disk",
+                    "table",
+                    "new",
+                    "use",
+                    "delete",
+                    "move",
+                    "logical",
+                    "logical-modify",
+                    "logical-remove",
+                    "logical-remove-all",
+                    "decrypt",
+                    "swap-file",
+                    "test",
+                    "dry-run",
+                ]),
         )
         .arg(
             Arg::with_name("table")
@@ -145,6 +217,11 @@ fn main() {
                 .long("test")
                 .help("simply test whether the provided arguments pass the partitioning stage"),
         )
+        .arg(
+            Arg::with_name("dry-run")
+                .long("dry-run")
+                .help("prints the disk operations that would be performed as JSON, and exits"),
+        )
         .arg(
             Arg::with_name("hardware-support")
                 .long("hardware-support")
@@ -220,22 +297,120 @@ fn main() {
                 .takes_value(true)
                 .multiple(true),
         )
+        .arg(
+            Arg::with_name("swap-file")
+                .long("swap-file")
+                .help("creates a swap file of the given size, such as 4096M, within the root")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("run-ubuntu-drivers")
                 .long("run-ubuntu-drivers")
                 .help("use ubuntu-drivers to find drivers then install in the chroot, some may have proprietary licenses")
         )
+        .arg(
+            Arg::with_name("verify-image")
+                .long("verify-image")
+                .help("verify the squashfs image against /cdrom/md5sum.txt before partitioning")
+                .conflicts_with("resume"),
+        )
+        .arg(
+            Arg::with_name("image-sha256")
+                .long("image-sha256")
+                .help("verify the squashfs image against a SHA-256 digest before partitioning")
+                .takes_value(true)
+                .conflicts_with("resume"),
+        )
+        .arg(
+            Arg::with_name("image-format")
+                .long("image-format")
+                .help("select the format of the squashfs image, rather than detecting it")
+                .takes_value(true)
+                .possible_values(&["auto", "squashfs", "tar", "directory", "oci", "raw"])
+                .conflicts_with("resume"),
+        )
+        .arg(
+            Arg::with_name("move-journal")
+                .long("move-journal")
+                .help(
+                    "journals partition moves at this path, which must be on persistent storage \
+                     that is not being partitioned, so that an interrupted move may be resumed",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("hook")
+                .long("hook")
+                .help(
+                    "runs a command before or after a step, given as \
+                     <before|after>-<step>=<command>",
+                )
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("upgrade")
+                .long("upgrade")
+                .help(
+                    "upgrades the install which the recovery partition was booted for to the \
+                     next release, as configured by /cdrom/recovery.conf",
+                )
+                .conflicts_with_all(&["plan", "resume", "dry-run"]),
+        )
+        .arg(
+            Arg::with_name("restore-tables")
+                .long("restore-tables")
+                .help(
+                    "restores the partition tables which were backed up before a failed install \
+                     partitioned the disks",
+                )
+                .conflicts_with_all(&["plan", "resume", "upgrade", "dry-run"]),
+        )
+        .arg(
+            Arg::with_name("resume-move")
+                .long("resume-move")
+                .help(
+                    "finishes moving the partition whose move was interrupted, as recorded by the \
+                     journal given to --move-journal, and recreates its partition table entry",
+                )
+                .requires("move-journal")
+                .conflicts_with_all(&["plan", "resume", "upgrade", "restore-tables", "dry-run"]),
+        )
+        .arg(
+            Arg::with_name("json-events")
+                .long("json-events")
+                .help(
+                    "writes the progress, errors, and logs of the install to stdout as \
+                     newline-delimited JSON, instead of a progress bar",
+                ),
+        )
         .get_matches();
 
-    if let Err(err) = distinst::log(|_level, _message| {}) {
+    let json_events = matches.is_present("json-events");
+
+    let logging = if json_events {
+        distinst::log(|level, message| events::log(level, message))
+    } else {
+        distinst::log(|_level, _message| {})
+    };
+
+    if let Err(err) = logging {
         eprintln!("Failed to initialize logging: {}", err);
     }
 
-    let squashfs = matches.value_of("squashfs").unwrap();
-    let hostname = matches.value_of("hostname").unwrap();
-    let mut keyboard = matches.values_of("keyboard").unwrap();
-    let lang = matches.value_of("lang").unwrap();
-    let remove = matches.value_of("remove").unwrap();
+    if matches.is_present("upgrade") {
+        exit(upgrade(json_events));
+    }
+
+    if matches.is_present("restore-tables") {
+        exit(restore_tables(matches.value_of("move-journal").map(Path::new), json_events));
+    }
+
+    if matches.is_present("resume-move") {
+        let journal = matches.value_of("move-journal").expect("required by clap");
+        exit(resume_move(Path::new(journal), json_events));
+    }
 
     let tzs_;
     let timezone = match matches.values_of("timezone") {
@@ -244,14 +419,14 @@ fn main() {
             tzs_ = Timezones::new().expect("failed to get timzones");
             let zone = tzs_
                 .zones()
-                .into_iter()
+                .iter()
                 .find(|z| z.name() == zone)
-                .expect(&format!("failed to find zone: {}", zone));
+                .unwrap_or_else(|| panic!("failed to find zone: {}", zone));
             let region = zone
                 .regions()
-                .into_iter()
+                .iter()
                 .find(|r| r.name() == region)
-                .expect(&format!("failed to find region: {}", region));
+                .unwrap_or_else(|| panic!("failed to find region: {}", region));
             Some(region.clone())
         }
         None => None,
@@ -281,18 +456,21 @@ fn main() {
     let res = {
         let mut installer = Installer::default();
 
-        {
-            let pb_opt = pb_opt.clone();
-            installer.on_error(move |error| {
-                if let Some(mut pb) = pb_opt.borrow_mut().take() {
-                    pb.finish_println("");
-                }
+        if json_events {
+            installer.on_error(events::error);
+            installer.on_status(events::status);
+        } else {
+            {
+                let pb_opt = pb_opt.clone();
+                installer.on_error(move |error| {
+                    if let Some(mut pb) = pb_opt.borrow_mut().take() {
+                        pb.finish_println("");
+                    }
 
-                eprintln!("Error: {:?}", error);
-            });
-        }
+                    eprintln!("Error ({}): {:?}", error.code().as_str(), error);
+                });
+            }
 
-        {
             let pb_opt = pb_opt.clone();
             let mut step_opt = None;
             installer.on_status(move |status| {
@@ -313,6 +491,7 @@ fn main() {
                         Step::Extract => "Extracting filesystem ",
                         Step::Configure => "Configuring installation",
                         Step::Bootloader => "Installing bootloader ",
+                        Step::Verify => "Verifying image ",
                     });
                     *pb_opt.borrow_mut() = Some(pb);
                 }
@@ -331,15 +510,59 @@ fn main() {
             installer.set_user_callback(move || user_account.clone());
         }
 
-        let disks = match configure_disks(&matches) {
-            Ok(disks) => disks,
-            Err(why) => {
-                eprintln!("distinst: {}", why);
-                exit(1);
+        for hook in matches.values_of("hook").into_iter().flatten() {
+            match hook.parse::<HookCommand>() {
+                Ok(hook) => installer.add_hook_command(hook.step, hook.time, hook.command),
+                Err(why) => {
+                    eprintln!("distinst: {}", why);
+                    exit(ErrorCode::InvalidConfig.exit_status());
+                }
             }
+        }
+
+        let resume = matches.is_present("resume");
+
+        // Resumed installs read the configuration of their disks from the install journal.
+        let (disks, mut config) = match matches.value_of("plan") {
+            Some(plan) if resume => match InstallPlan::from_path(plan) {
+                Ok(plan) => (Disks::default(), plan.config.into()),
+                Err(why) => {
+                    eprintln!("distinst: {}", why);
+                    exit(ErrorCode::InvalidConfig.exit_status());
+                }
+            },
+            Some(plan) => match InstallPlan::from_path(plan).and_then(InstallPlan::apply) {
+                Ok(plan) => plan,
+                Err(why) => {
+                    eprintln!("distinst: {}", why);
+                    exit(ErrorCode::InvalidConfig.exit_status());
+                }
+            },
+            None if resume => (Disks::default(), configure_config(&matches)),
+            None => match configure_disks(&matches) {
+                Ok(disks) => (disks, configure_config(&matches)),
+                Err(why) => {
+                    eprintln!("distinst: {}", why);
+                    exit(ErrorCode::InvalidConfig.exit_status());
+                }
+            },
         };
 
-        configure_signal_handling();
+        config.flags |= install_flags(&matches);
+
+        if let Some(sha256) = matches.value_of("image-sha256") {
+            config.squashfs_sha256 = Some(sha256.into());
+        }
+
+        if let Some(format) = matches.value_of("image-format") {
+            config.image_format = format.parse().expect("image format was validated by clap");
+        }
+
+        if let Some(journal) = matches.value_of("move-journal") {
+            config.move_journal = Some(journal.into());
+        }
+
+        configure_signal_handling(installer.cancellation());
 
         if matches.is_present("test") {
             PARTITIONING_TEST.store(true, Ordering::Relaxed);
@@ -355,8 +578,22 @@ fn main() {
             NO_EFI_VARIABLES.store(true, Ordering::Relaxed);
         }
 
-        fn take_optional_string(argument: Option<&str>) -> Option<String> {
-            argument.map(String::from).and_then(|x| if x.is_empty() { None } else { Some(x) })
+        if matches.is_present("dry-run") {
+            let report = installer.dry_run(&disks, &config).and_then(|report| {
+                serde_json::to_string_pretty(&report)
+                    .map_err(|why| io::Error::new(io::ErrorKind::Other, why))
+            });
+
+            match report {
+                Ok(report) => {
+                    println!("{}", report);
+                    exit(0);
+                }
+                Err(why) => {
+                    eprintln!("distinst: dry run failed: {}", why);
+                    exit(ErrorCode::of(&why).exit_status());
+                }
+            }
         }
 
         // The lock is an `OwnedFd`, which on drop will close / unlock the inhibitor.
@@ -377,20 +614,11 @@ fn main() {
             }
         };
 
-        installer.install(
-            disks,
-            &Config {
-                flags:            install_flags(&matches),
-                hostname:         hostname.into(),
-                keyboard_layout:  keyboard.next().map(String::from).unwrap(),
-                keyboard_model:   take_optional_string(keyboard.next()),
-                keyboard_variant: take_optional_string(keyboard.next()),
-                old_root:         None,
-                lang:             lang.into(),
-                remove:           remove.into(),
-                squashfs:         squashfs.into(),
-            },
-        )
+        if resume {
+            installer.resume(&config)
+        } else {
+            installer.install(disks, &config)
+        }
     };
 
     if let Some(mut pb) = pb_opt.borrow_mut().take() {
@@ -398,19 +626,178 @@ fn main() {
     }
 
     let status = match res {
+        Ok(()) if json_events => {
+            events::finished(None);
+            0
+        }
         Ok(()) => {
             println!("install was successful");
             0
         }
         Err(err) => {
-            println!("install failed: {}", err);
-            1
+            let code = ErrorCode::of(&err);
+            if json_events {
+                events::finished(Some((code, err.to_string())));
+            } else {
+                println!("install failed ({}): {}", code.as_str(), err);
+            }
+            code.exit_status()
         }
     };
 
     exit(status);
 }
 
+/// Upgrades the install which the recovery partition was booted for, returning the exit status.
+fn upgrade(json_events: bool) -> i32 {
+    let result = Disks::probe_devices()
+        .map_err(|why| (ErrorCode::from(&why), why.to_string()))
+        .and_then(|mut disks| {
+            let option = InstallOptions::new(&disks, 0, 0).recovery_option.ok_or_else(|| {
+                (ErrorCode::RecoveryOption, "/cdrom/recovery.conf was not found".to_owned())
+            })?;
+
+            let mut env =
+                RecoveryEnv::new().map_err(|why| (ErrorCode::of(&why), why.to_string()))?;
+
+            distinst::upgrade(
+                &mut env,
+                &mut disks,
+                &option,
+                |event| {
+                    if json_events {
+                        events::upgrade(&event);
+                    } else {
+                        println!("{:?}", event);
+                    }
+                },
+                || true,
+            )
+            .map_err(|why| (ErrorCode::from(&why), why.to_string()))
+        });
+
+    match result {
+        Ok(()) => {
+            if json_events {
+                events::finished(None);
+            } else {
+                println!("upgrade was successful");
+            }
+            0
+        }
+        Err((code, why)) => {
+            let status = code.exit_status();
+            if json_events {
+                events::finished(Some((code, why)));
+            } else {
+                println!("upgrade failed ({}): {}", code.as_str(), why);
+            }
+            status
+        }
+    }
+}
+
+/// Restores the partition tables which were backed up by a failed install, returning the exit
+/// status. Every disk is attempted, and the status is that of the first disk which failed.
+fn restore_tables(move_journal: Option<&Path>, json_events: bool) -> i32 {
+    let results = match Disks::restore_tables(TABLES_PATH, move_journal) {
+        Ok(results) => results,
+        Err(why) => vec![Err(why)],
+    };
+
+    let mut failure = None;
+    for result in results {
+        match result {
+            Ok(device) => {
+                if !json_events {
+                    println!("restored the partition table of {}", device.display());
+                }
+            }
+            Err(why) => {
+                if !json_events {
+                    eprintln!("distinst: failed to restore partition tables: {}", why);
+                }
+                failure.get_or_insert_with(|| (ErrorCode::from(&why), why.to_string()));
+            }
+        }
+    }
+
+    let status = failure.as_ref().map_or(0, |&(code, _)| code.exit_status());
+    if json_events {
+        events::finished(failure);
+    }
+    status
+}
+
+/// Finishes the partition move which was interrupted, returning the exit status. With JSON
+/// events, the progress of the move is reported as the status of the partition step.
+fn resume_move(journal: &Path, json_events: bool) -> i32 {
+    use distinst::operations::MoveOptions;
+
+    let mut last = None;
+    let progress = |copied: u64, total: u64| {
+        let percent = (copied * 100 / total.max(1)) as i32;
+        if json_events && last != Some(percent) {
+            last = Some(percent);
+            events::status(&Status { step: Step::Partition, percent });
+        }
+    };
+
+    match operations::resume_move(journal, &MoveOptions::default(), progress) {
+        Ok(journal) => {
+            if json_events {
+                events::finished(None);
+            } else {
+                let (start, end) = journal.target_sectors();
+                println!(
+                    "moved the partition on {} to sectors {} through {}, and recreated its \
+                     partition table entry",
+                    journal.device.display(),
+                    start,
+                    end
+                );
+            }
+            0
+        }
+        Err(why) => {
+            let code = match why.kind() {
+                io::ErrorKind::NotFound => ErrorCode::NotFound,
+                _ => ErrorCode::PartitionTable,
+            };
+            let status = code.exit_status();
+            if json_events {
+                events::finished(Some((code, why.to_string())));
+            } else {
+                eprintln!("distinst: failed to resume the partition move: {}", why);
+            }
+            status
+        }
+    }
+}
+
+fn configure_config(matches: &ArgMatches) -> Config {
+    fn take_optional_string(argument: Option<&str>) -> Option<String> {
+        argument.map(String::from).and_then(|x| if x.is_empty() { None } else { Some(x) })
+    }
+
+    let mut keyboard = matches.values_of("keyboard").unwrap();
+
+    Config {
+        flags:            0,
+        hostname:         matches.value_of("hostname").unwrap().into(),
+        keyboard_layout:  keyboard.next().map(String::from).unwrap(),
+        keyboard_model:   take_optional_string(keyboard.next()),
+        keyboard_variant: take_optional_string(keyboard.next()),
+        old_root:         None,
+        lang:             matches.value_of("lang").unwrap().into(),
+        remove:           matches.value_of("remove").unwrap_or("").into(),
+        squashfs:         matches.value_of("squashfs").unwrap_or("").into(),
+        squashfs_sha256:  None,
+        image_format:     ImageFormat::Auto,
+        move_journal:     None,
+    }
+}
+
 fn install_flags(matches: &ArgMatches) -> u8 {
     let mut flags = 0;
 
@@ -429,13 +816,19 @@ fn install_flags(matches: &ArgMatches) -> u8 {
         0
     };
 
+    flags += if matches.occurrences_of("verify-image") != 0 { distinst::VERIFY_IMAGE } else { 0 };
+
     flags
 }
 
-fn configure_signal_handling() {
+/// Cancels the install on SIGINT. As the signal handler may only set a flag, a thread waits
+/// for the flag to be set, and then cancels the install.
+fn configure_signal_handling(cancellation: Cancellation) {
+    static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
     extern "C" fn handler(signal: i32) {
         match signal {
-            libc::SIGINT => KILL_SWITCH.store(true, Ordering::SeqCst),
+            libc::SIGINT => INTERRUPTED.store(true, Ordering::SeqCst),
             _ => unreachable!(),
         }
     }
@@ -444,8 +837,18 @@ fn configure_signal_handling() {
         eprintln!("distinst: signal handling error: {}", io::Error::last_os_error());
         exit(1);
     }
+
+    thread::spawn(move || {
+        while !INTERRUPTED.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_millis(100));
+        }
+
+        eprintln!("distinst: cancelling the install");
+        cancellation.cancel();
+    });
 }
 
+#[allow(clippy::large_enum_variant)]
 enum PartType {
     /// A normal partition with a standard file system
     Fs(Option<FileSystem>),
@@ -479,9 +882,81 @@ fn parse_key(
     Ok(())
 }
 
+/// Parses an option for formatting a LUKS volume, returning `false` if the field is not one.
+fn parse_luks_option(field: &str, luks: &mut LuksOptions) -> Result<bool, DistinstError> {
+    let mut pair = field.splitn(2, '=');
+    let (key, value) = match (pair.next(), pair.next()) {
+        (Some(key), Some(value)) => (key, value),
+        _ => return Ok(false),
+    };
+
+    let number = |value: &str| {
+        value.parse::<u32>().map_err(|_| DistinstError::ArgNaN { arg: field.into() })
+    };
+
+    let small_number = |value: &str| {
+        value.parse::<u16>().map_err(|_| DistinstError::ArgNaN { arg: field.into() })
+    };
+
+    match key {
+        "cipher" => luks.cipher = Some(value.into()),
+        "key-size" => luks.key_size = small_number(value)?,
+        "pbkdf" => {
+            let pbkdf = value.parse::<Pbkdf>();
+            let pbkdf = pbkdf.map_err(|_| DistinstError::InvalidField { field: field.into() })?;
+            luks.pbkdf = Some(pbkdf);
+        }
+        "pbkdf-memory" => luks.pbkdf_memory = Some(number(value)?),
+        "pbkdf-iterations" => luks.pbkdf_iterations = Some(number(value)?),
+        "sector-size" => luks.sector_size = Some(small_number(value)?),
+        "label" => luks.label = Some(value.into()),
+        _ => return Ok(false),
+    }
+
+    Ok(true)
+}
+
+/// Parses an unlock method to enroll, returning `false` if the field is not one.
+///
+/// `tpm2` and `fido2` accept a device, or `auto`, and `tpm2-pcrs` accepts PCRs such as `7+11`.
+fn parse_enrollment(field: &str, enrollments: &mut Vec<Enrollment>) -> Result<bool, DistinstError> {
+    let mut pair = field.splitn(2, '=');
+    let (key, value) = match (pair.next(), pair.next()) {
+        (Some(key), Some(value)) => (key, value),
+        _ => return Ok(false),
+    };
+
+    let device = if value == "auto" { None } else { Some(value.to_owned()) };
+
+    match key {
+        "tpm2" | "tpm2-pcrs" => {
+            let position = enrollments.iter().position(|e| e.kind() == "tpm2");
+            let index = position.unwrap_or_else(|| {
+                enrollments.push(Enrollment::tpm2());
+                enrollments.len() - 1
+            });
+
+            if let Enrollment::Tpm2 { device: ref mut tpm, ref mut pcrs } = enrollments[index] {
+                if key == "tpm2" {
+                    *tpm = device;
+                } else {
+                    *pcrs = pcrs_from_str(value)
+                        .map_err(|_| DistinstError::InvalidField { field: field.into() })?;
+                }
+            }
+        }
+        "fido2" => enrollments.push(Enrollment::Fido2 { device }),
+        _ => return Ok(false),
+    }
+
+    Ok(true)
+}
+
 fn parse_fs(fs: &str) -> Result<PartType, DistinstError> {
     if fs.starts_with("enc=") {
         let (mut pass, mut keydata) = (None, None);
+        let mut luks = LuksOptions::default();
+        let mut enrollments = Vec::new();
 
         let mut fields = fs[4..].split(',');
         let physical_volume =
@@ -490,17 +965,24 @@ fn parse_fs(fs: &str) -> Result<PartType, DistinstError> {
         let volume_group = fields.next().map(|vg| vg.into()).ok_or(DistinstError::NoVolumeGroup)?;
 
         for field in fields {
-            parse_key(field, &mut pass, &mut keydata)?;
+            let parsed =
+                parse_luks_option(field, &mut luks)? || parse_enrollment(field, &mut enrollments)?;
+            if !parsed {
+                parse_key(field, &mut pass, &mut keydata)?;
+            }
         }
 
-        Ok(PartType::Lvm(
-            volume_group,
-            if pass.is_none() && keydata.is_none() {
-                None
-            } else {
-                Some(LvmEncryption::new(physical_volume, pass, keydata))
-            },
-        ))
+        let encryption = if pass.is_none() && keydata.is_none() {
+            None
+        } else {
+            let encryption = LvmEncryption::new(physical_volume, pass, keydata)
+                .with_luks(luks)
+                .with_enrollments(enrollments);
+            encryption.validate()?;
+            Some(encryption)
+        };
+
+        Ok(PartType::Lvm(volume_group, encryption))
     } else if fs.starts_with("lvm=") {
         let mut fields = fs[4..].split(',');
         Ok(PartType::Lvm(
@@ -513,42 +995,29 @@ fn parse_fs(fs: &str) -> Result<PartType, DistinstError> {
 }
 
 fn parse_sector(sector: &str) -> Result<Sector, DistinstError> {
-    let result = if sector.ends_with("MiB") {
-        sector[..sector.len() - 3].parse::<i64>().ok().and_then(|mebibytes| {
-            format!("{}M", (mebibytes * 1_048_576) / 1_000_000).parse::<Sector>().ok()
-        })
-    } else {
-        sector.parse::<Sector>().ok()
-    };
-
-    result.ok_or_else(|| DistinstError::InvalidSectorValue { value: sector.into() })
+    distinst::parse_sector(sector)
+        .map_err(|_| DistinstError::InvalidSectorValue { value: sector.into() })
 }
 
 fn parse_flags(flags: &str) -> Vec<PartitionFlag> {
-    // TODO: implement FromStr for PartitionFlag
-    flags
+    flags.split(',').filter_map(partition_flag_from_str).collect::<Vec<_>>()
+}
+
+/// Parses a list of btrfs subvolumes, written as `default` for the default layout, or as
+/// comma-separated `name=target` pairs, where the target may be omitted.
+fn parse_subvolumes(subvolumes: &str) -> Vec<Subvolume> {
+    if subvolumes == "default" {
+        return Subvolume::default_layout();
+    }
+
+    subvolumes
         .split(',')
-        .filter_map(|flag| match flag {
-            "esp" => Some(PartitionFlag::PED_PARTITION_ESP),
-            "boot" => Some(PartitionFlag::PED_PARTITION_BOOT),
-            "root" => Some(PartitionFlag::PED_PARTITION_ROOT),
-            "swap" => Some(PartitionFlag::PED_PARTITION_SWAP),
-            "hidden" => Some(PartitionFlag::PED_PARTITION_HIDDEN),
-            "raid" => Some(PartitionFlag::PED_PARTITION_RAID),
-            "lvm" => Some(PartitionFlag::PED_PARTITION_LVM),
-            "lba" => Some(PartitionFlag::PED_PARTITION_LBA),
-            "hpservice" => Some(PartitionFlag::PED_PARTITION_HPSERVICE),
-            "palo" => Some(PartitionFlag::PED_PARTITION_PALO),
-            "prep" => Some(PartitionFlag::PED_PARTITION_PREP),
-            "msft_reserved" => Some(PartitionFlag::PED_PARTITION_MSFT_RESERVED),
-            "apple_tv_recovery" => Some(PartitionFlag::PED_PARTITION_APPLE_TV_RECOVERY),
-            "diag" => Some(PartitionFlag::PED_PARTITION_DIAG),
-            "legacy_boot" => Some(PartitionFlag::PED_PARTITION_LEGACY_BOOT),
-            "msft_data" => Some(PartitionFlag::PED_PARTITION_MSFT_DATA),
-            "irst" => Some(PartitionFlag::PED_PARTITION_IRST),
-            _ => None,
+        .map(|subvolume| {
+            let mut fields = subvolume.splitn(2, '=');
+            let name = fields.next().unwrap_or("");
+            Subvolume::new(name, fields.next().map(PathBuf::from))
         })
-        .collect::<Vec<_>>()
+        .collect()
 }
 
 fn find_disk_mut<'a>(disks: &'a mut Disks, block: &str) -> Result<&'a mut Disk, DistinstError> {
@@ -2,10 +2,10 @@ use std::{
     ffi::OsStr,
     io::Result,
     path::{Path, PathBuf},
-    process::Stdio,
 };
 use sys_mount::*;
 use crate::command::Command;
+use distinst_executor::Redirect;
 
 /// Defines the location where a `chroot` will be performed, as well as storing
 /// handles to all of the binding mounts that the chroot requires.
@@ -60,8 +60,8 @@ impl<'a> Chroot<'a> {
             ..arg(&self.path);
             ..arg(cmd.as_ref());
             ..args(args);
-            ..stderr(Stdio::piped());
-            ..stdout(Stdio::piped());
+            ..stderr(Redirect::Piped);
+            ..stdout(Redirect::Piped);
         };
 
         if self.clear_envs {
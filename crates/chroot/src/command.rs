@@ -1,27 +1,47 @@
-use libc;
+use distinst_executor::{self as executor, Handles, Invocation, Redirect, Stream};
 use std::{
     ffi::OsStr,
-    fs::File,
-    io::{self, BufRead, BufReader, Error, ErrorKind, Write},
-    os::unix::io::{FromRawFd, IntoRawFd},
-    process::{self, Child, ExitStatus, Stdio},
-    thread,
-    time::Duration,
+    io::{self, Error, ErrorKind},
+    mem,
+    process::Stdio,
 };
 
-/// Convenient wrapper around `process::Command` to make it easier to work with.
+/// Where the stdout or stderr of a `Command` is directed.
+#[derive(Debug)]
+pub enum Destination {
+    /// A redirect that every executor understands.
+    Redirect(Redirect),
+    /// A handle that is given to the process when it is spawned. Executors which do not
+    /// spawn processes treat the stream as inherited.
+    Stdio(Stdio),
+}
+
+impl From<Redirect> for Destination {
+    fn from(redirect: Redirect) -> Self { Destination::Redirect(redirect) }
+}
+
+impl From<Stdio> for Destination {
+    fn from(stdio: Stdio) -> Self { Destination::Stdio(stdio) }
+}
+
+/// Convenient wrapper around an `Invocation` to make it easier to work with.
+///
+/// Commands are executed by the current `distinst_executor::Executor`.
 pub struct Command<'a> {
-    cmd:   process::Command,
-    stdin: Option<&'a str>,
+    invocation: Invocation,
+    handles:    Handles,
+    stdin:      Option<&'a str>,
 }
 
 impl<'a> Command<'a> {
     pub fn new<S: AsRef<OsStr>>(program: S) -> Self {
-        Command { cmd: process::Command::new(program), stdin: None }
+        let invocation =
+            Invocation::new(program).stdout(Redirect::Inherit).stderr(Redirect::Inherit);
+        Command { invocation, handles: Handles::default(), stdin: None }
     }
 
     pub fn arg<S: AsRef<OsStr>>(&mut self, arg: S) -> &mut Command<'a> {
-        self.cmd.arg(arg);
+        self.invocation.args.push(arg.as_ref().to_owned());
         self
     }
 
@@ -29,68 +49,62 @@ impl<'a> Command<'a> {
         &mut self,
         args: I,
     ) -> &mut Command<'a> {
-        self.cmd.args(args);
+        self.invocation.args.extend(args.into_iter().map(|arg| arg.as_ref().to_owned()));
         self
     }
 
-    pub fn env(&mut self, key: &str, value: &str) { self.cmd.env(key, value); }
+    pub fn env(&mut self, key: &str, value: &str) {
+        self.invocation.envs.push((key.into(), value.into()));
+    }
 
-    pub fn env_clear(&mut self) { self.cmd.env_clear(); }
+    pub fn env_clear(&mut self) { self.invocation.env_clear = true; }
 
+    /// Stdin is null by default, and is ignored if `stdin_input` is given.
     pub fn stdin(&mut self, stdio: Stdio) -> &mut Self {
-        self.cmd.stdin(stdio);
+        self.handles.stdin = Some(stdio);
         self
     }
 
-    pub fn stderr(&mut self, stdio: Stdio) -> &mut Self {
-        self.cmd.stderr(stdio);
+    pub fn stderr<D: Into<Destination>>(&mut self, destination: D) -> &mut Self {
+        let (redirect, handle) = split(destination.into());
+        self.invocation.stderr = redirect;
+        self.handles.stderr = handle;
         self
     }
 
-    pub fn stdout(&mut self, stdio: Stdio) -> &mut Self {
-        self.cmd.stdout(stdio);
+    pub fn stdout<D: Into<Destination>>(&mut self, destination: D) -> &mut Self {
+        let (redirect, handle) = split(destination.into());
+        self.invocation.stdout = redirect;
+        self.handles.stdout = handle;
         self
     }
 
-    /// Run the program, check the status, and get the output of `stdout`
+    /// Writes the `input` to the stdin of the program when it is run.
     pub fn stdin_input(mut self, input: &'a str) -> Self {
         self.stdin = Some(input);
-        self.stdin(Stdio::piped());
         self
     }
 
-    pub fn stdin_redirect(&mut self, child: &mut Child) -> io::Result<()> {
-        match self.stdin {
-            Some(input) => child.stdin.as_mut().unwrap().write_all(input.as_bytes()),
-            None => Ok(()),
-        }
+    /// The invocation that will be handed to the executor.
+    pub fn invocation(&self) -> Invocation {
+        let mut invocation = self.invocation.clone();
+        invocation.stdin = self.stdin.map(|input| input.as_bytes().to_vec());
+        invocation
     }
 
+    /// Run the program, check the status, and get the output of `stdout`
     pub fn run_with_stdout(&mut self) -> io::Result<String> {
-        let cmd = format!("{:?}", self.cmd);
-        info!("running {}", cmd);
-
-        self.cmd.stdout(Stdio::piped());
+        self.stdout(Redirect::Piped);
+        let invocation = self.invocation();
+        info!("running {}", invocation);
 
-        let mut child = self.cmd.spawn().map_err(|why| {
-            Error::new(why.kind(), format!("failed to spawn process {}: {}", cmd, why))
-        })?;
+        let handles = mem::take(&mut self.handles);
+        let output = executor::execute_with_handles(&invocation, handles, &mut |_, _| ())
+            .map_err(|why| spawn_error(&invocation, why))?;
 
-        self.stdin_redirect(&mut child)?;
-
-        child
-            .wait_with_output()
-            .map_err(|why| {
-                Error::new(why.kind(), format!("failed to get output of {}: {}", cmd, why))
-            })
-            .and_then(|output| {
-                String::from_utf8(output.stdout).map_err(|why| {
-                    Error::new(
-                        ErrorKind::Other,
-                        format!("command output has invalid UTF-8: {}", why),
-                    )
-                })
-            })
+        String::from_utf8(output.stdout).map_err(|why| {
+            Error::new(ErrorKind::Other, format!("command output has invalid UTF-8: {}", why))
+        })
     }
 
     /// Run the program and check the status.
@@ -104,87 +118,57 @@ impl<'a> Command<'a> {
         I: Fn(&str),
         E: Fn(&str),
     {
-        let cmd = format!("{:?}", self.cmd);
-        info!("running {}", cmd);
-
-        let mut child = self.cmd.spawn().map_err(|why| {
-            Error::new(why.kind(), format!("failed to spawn process {}: {}", cmd, why))
-        })?;
-
-        self.stdin_redirect(&mut child)?;
+        let invocation = self.invocation();
+        info!("running {}", invocation);
 
-        let mut stdout_buffer = String::new();
-        let mut stdout = child.stdout.take().map(non_blocking).map(BufReader::new);
+        let mut lines = |stream, line: &str| match stream {
+            Stream::Stdout => info(line),
+            Stream::Stderr => error(line),
+        };
 
-        let mut stderr_buffer = String::new();
-        let mut stderr = child.stderr.take().map(non_blocking).map(BufReader::new);
+        let handles = mem::take(&mut self.handles);
+        let output = executor::execute_with_handles(&invocation, handles, &mut lines)
+            .map_err(|why| spawn_error(&invocation, why))?;
 
-        loop {
-            thread::sleep(Duration::from_millis(16));
-            match child.try_wait()? {
-                Some(status) => return status_as_result(status, &cmd),
-                None => {
-                    if let Some(ref mut stdout) = stdout {
-                        non_blocking_line_reading(stdout, &mut stdout_buffer, &info)?;
-                    }
-
-                    if let Some(ref mut stderr) = stderr {
-                        non_blocking_line_reading(stderr, &mut stderr_buffer, &error)?;
-                    }
-                }
-            }
-        }
+        status_as_result(output.code, &invocation)
     }
 }
 
-fn status_as_result(status: ExitStatus, cmd: &str) -> io::Result<()> {
-    if status.success() {
-        Ok(())
-    } else if let Some(127) = status.code() {
-        Err(io::Error::new(io::ErrorKind::NotFound, format!("command {} was not found", cmd)))
-    } else {
-        Err(io::Error::new(
-            io::ErrorKind::Other,
-            format!("command failed with exit status: {}", status),
-        ))
-    }
+fn spawn_error(invocation: &Invocation, why: Error) -> Error {
+    Error::new(why.kind(), format!("failed to spawn process {}: {}", invocation, why))
 }
 
-fn non_blocking<F: IntoRawFd>(fd: F) -> File {
-    let fd = fd.into_raw_fd();
-    unsafe {
-        let flags = libc::fcntl(fd, libc::F_GETFL, 0);
-        libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK);
-        File::from_raw_fd(fd)
+/// Handles are given to the process in place of the redirect, which is recorded as inherited.
+fn split(destination: Destination) -> (Redirect, Option<Stdio>) {
+    match destination {
+        Destination::Redirect(redirect) => (redirect, None),
+        Destination::Stdio(stdio) => (Redirect::Inherit, Some(stdio)),
     }
 }
 
-fn non_blocking_line_reading<B: BufRead, F: Fn(&str)>(
-    reader: &mut B,
-    buffer: &mut String,
-    callback: F,
-) -> io::Result<()> {
-    loop {
-        match reader.read_line(buffer) {
-            Ok(0) => break,
-            Ok(read) => {
-                callback(&buffer[..read - 1]);
-                buffer.clear();
-            }
-            Err(ref why) if why.kind() == io::ErrorKind::WouldBlock => break,
-            Err(why) => {
-                buffer.clear();
-                return Err(why);
-            }
-        }
-    }
-
-    Ok(())
+fn status_as_result(code: Option<i32>, invocation: &Invocation) -> io::Result<()> {
+    match code {
+        Some(0) => Ok(()),
+        Some(127) => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("command {} was not found", invocation),
+        )),
+        Some(code) => Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("command failed with exit status: {}", code),
+        )),
+        None => Err(io::Error::new(
+            io::ErrorKind::Other,
+            "command failed with exit status: terminated by signal",
+        )),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use distinst_executor::{Output, RecordingExecutor};
+    use std::{cell::RefCell, sync::Arc};
 
     #[test]
     fn command_not_found() {
@@ -198,4 +182,44 @@ mod tests {
             "Hello, Command!\n".to_owned()
         );
     }
+
+    #[test]
+    fn command_with_executor() {
+        let recorder = Arc::new(RecordingExecutor::new());
+        recorder.reply("passwd", Output::status(0).with_stdout("password updated\n"));
+
+        let lines = RefCell::new(Vec::new());
+        executor::with_executor(recorder.clone(), || {
+            Command::new("passwd")
+                .stdin_input("pass\npass\n")
+                .arg("user")
+                .stdout(Redirect::Piped)
+                .run_with_callbacks(|info| lines.borrow_mut().push(info.to_owned()), |_| ())
+                .unwrap();
+        });
+
+        let invocations = recorder.invocations();
+        assert_eq!(recorder.command_lines(), vec!["passwd user"]);
+        assert_eq!(invocations[0].stdin, Some(b"pass\npass\n".to_vec()));
+        assert_eq!(lines.into_inner(), vec!["password updated".to_owned()]);
+    }
+
+    #[test]
+    fn command_with_stdio() {
+        let lines = RefCell::new(Vec::new());
+        Command::new("echo")
+            .arg("Hello, Stdio!")
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .run_with_callbacks(|info| lines.borrow_mut().push(info.to_owned()), |_| ())
+            .unwrap();
+        assert_eq!(lines.into_inner(), vec!["Hello, Stdio!".to_owned()]);
+
+        let recorder = Arc::new(RecordingExecutor::new());
+        executor::with_executor(recorder.clone(), || {
+            Command::new("true").stdin(Stdio::null()).stdout(Stdio::null()).run().unwrap();
+        });
+
+        assert_eq!(recorder.invocations()[0].stdout, Redirect::Inherit);
+    }
 }
@@ -5,11 +5,12 @@ extern crate cascade;
 #[macro_use]
 extern crate log;
 
-extern crate libc;
+extern crate distinst_executor;
 extern crate sys_mount;
 
 mod chroot;
 mod command;
 mod sd_nspawn;
 
-pub use self::{chroot::Chroot, command::Command, sd_nspawn::SystemdNspawn};
+pub use self::{chroot::Chroot, command::{Command, Destination}, sd_nspawn::SystemdNspawn};
+pub use distinst_executor::Redirect;
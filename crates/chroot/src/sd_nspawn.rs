@@ -2,21 +2,20 @@ use std::{
     ffi::OsStr,
     io::Result,
     path::{Path, PathBuf},
-    process::Stdio,
 };
 use crate::command::Command;
+use distinst_executor::Redirect;
 
 /// Defines the location where a `chroot` will be performed, with `systemd-nspawn`.
 pub struct SystemdNspawn<'a> {
-    pub path:   PathBuf,
-    clear_envs: bool,
-    envs:       Vec<(&'a str, &'a str)>,
+    pub path: PathBuf,
+    envs:     Vec<(&'a str, &'a str)>,
 }
 
 impl<'a> SystemdNspawn<'a> {
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
         let path = path.as_ref().canonicalize()?;
-        Ok(SystemdNspawn { path, clear_envs: false, envs: Vec::new() })
+        Ok(SystemdNspawn { path, envs: Vec::new() })
     }
 
     /// Set an environment variable to define for this chroot.
@@ -42,8 +41,8 @@ impl<'a> SystemdNspawn<'a> {
             ..arg(&self.path);
             ..arg(cmd.as_ref());
             ..args(args);
-            ..stderr(Stdio::piped());
-            ..stdout(Stdio::piped());
+            ..stderr(Redirect::Piped);
+            ..stdout(Redirect::Piped);
         };
 
         for &(key, value) in &self.envs {
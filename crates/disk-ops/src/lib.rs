@@ -4,7 +4,11 @@
 extern crate derive_new;
 extern crate disk_types;
 extern crate distinst_bootloader as bootloader;
+extern crate distinst_executor as executor;
 extern crate distinst_external_commands as external;
+#[macro_use]
+extern crate lazy_static;
+extern crate libc;
 extern crate libparted;
 #[macro_use]
 extern crate log;
@@ -17,6 +21,7 @@ extern crate tempdir;
 mod mklabel;
 mod mkpart;
 mod mvpart;
+pub mod native;
 mod ops;
 pub mod parted;
 mod resize;
@@ -49,7 +54,7 @@ impl BlockCoordinates {
 }
 
 /// Defines how many sectors to skip, and how the partition is.
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct OffsetCoordinates {
     pub skip:   u64,
     pub offset: i64,
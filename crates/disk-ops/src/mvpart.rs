@@ -1,10 +1,221 @@
-use super::OffsetCoordinates;
+//! Moves partitions by copying their sectors to their new location on the same disk.
+//!
+//! Sectors are copied in windows which are never larger than the distance that the partition
+//! is moving, starting from the edge which is moving into free space. The destination of a
+//! window therefore never overlaps a sector which has yet to be copied, so a window which was
+//! interrupted can simply be copied again. Each window is synced to the disk before it is
+//! recorded in the journal of the move, which `resume_move` uses to finish an interrupted move.
+//!
+//! As the table entry of a partition is removed while it is moved, the journal also records
+//! the entry that the partition is given at its new location, which `resume_move` creates once
+//! the move has been finished.
+
+use super::{native, OffsetCoordinates, MEBIBYTE};
+use disk_types::{
+    partition_flag_from_str, partition_flag_to_str,
+    partitioner::{NewPartition, Partitioner},
+    PartitionType,
+};
 use std::{
-    fs::OpenOptions,
+    fmt,
+    fs::{self, File, OpenOptions},
     io::{self, Read, Seek, SeekFrom, Write},
-    path::Path,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
 };
 
+/// The first line of every journal, which identifies its format.
+const JOURNAL_HEADER: &str = "distinst-move 1";
+
+/// Memory passed to `O_DIRECT` reads and writes must be aligned to the logical block size of
+/// the disk, which this is a multiple of.
+const ALIGNMENT: usize = 4096;
+
+/// Options which tune how a partition is moved.
+#[derive(Debug, Clone, SmartDefault, PartialEq)]
+pub struct MoveOptions {
+    /// The size in bytes of the buffer that sectors are copied through, which is rounded
+    /// down to a multiple of the sector size.
+    #[default(16 * MEBIBYTE as usize)]
+    pub buffer_size: usize,
+    /// Opens the disk with `O_DIRECT`, so that copied sectors bypass the page cache.
+    pub direct:      bool,
+    /// Where the progress of the move is journaled. Moves without a journal cannot be resumed,
+    /// and a journal only survives a power loss if it is written to persistent storage.
+    pub journal:     Option<PathBuf>,
+    /// The table entry that the partition is given once it has been moved, whose sectors are
+    /// those that it is moved to. When it is set, the journal is kept after the sectors have
+    /// been copied, so that `resume_move` may still create the entry, and is to be removed
+    /// with `MoveJournal::remove` once the entry has been created.
+    pub entry:       Option<NewPartition>,
+}
+
+/// The progress of a partition move, which is written after each window has been copied.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MoveJournal {
+    /// The disk that the partition is being moved on.
+    pub device:      PathBuf,
+    pub sector_size: u64,
+    pub coords:      OffsetCoordinates,
+    /// Sectors which have been copied and synced, counted from the edge of the partition that
+    /// is copied first.
+    pub copied:      u64,
+    /// The table entry of the partition at its new location.
+    pub entry:       Option<NewPartition>,
+}
+
+impl MoveJournal {
+    /// Reads the journal of an interrupted move.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let data = fs::read_to_string(path)?;
+        Self::parse(&data).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("move journal at {:?} is invalid", path),
+            )
+        })
+    }
+
+    /// Writes the journal to a temporary file which is synced and then renamed, so that a
+    /// partially written journal is never read.
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let temporary = path.with_extension("partial");
+        let mut file = File::create(&temporary)?;
+        file.write_all(self.to_string().as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temporary, path)?;
+
+        match path.parent() {
+            Some(parent) if parent != Path::new("") => File::open(parent)?.sync_all(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Removes a journal, if it exists.
+    pub fn remove<P: AsRef<Path>>(path: P) -> io::Result<()> {
+        match fs::remove_file(path.as_ref()) {
+            Err(ref why) if why.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    /// The first and last sectors that the partition occupies once the move has completed.
+    pub fn target_sectors(&self) -> (u64, u64) {
+        let start = (self.coords.skip as i64 + self.coords.offset) as u64;
+        (start, start + self.coords.length)
+    }
+
+    /// True if the journal records the same move of the same partition.
+    fn is_same_move(&self, other: &MoveJournal) -> bool {
+        self.device == other.device
+            && self.sector_size == other.sector_size
+            && self.coords == other.coords
+    }
+
+    fn parse(data: &str) -> Option<Self> {
+        let mut lines = data.lines().peekable();
+        if lines.next()? != JOURNAL_HEADER {
+            return None;
+        }
+
+        let device = PathBuf::from(field(&mut lines, "device")?);
+        let sector_size = field(&mut lines, "sector_size")?.parse().ok()?;
+        let skip = field(&mut lines, "skip")?.parse().ok()?;
+        let offset = field(&mut lines, "offset")?.parse().ok()?;
+        let length = field(&mut lines, "length")?.parse().ok()?;
+        let copied = field(&mut lines, "copied")?.parse().ok()?;
+
+        if sector_size == 0 || copied > length {
+            return None;
+        }
+
+        let coords = OffsetCoordinates { skip, offset, length };
+        let mut journal = MoveJournal { device, sector_size, coords, copied, entry: None };
+
+        if lines.peek().is_some() {
+            let kind = match field(&mut lines, "kind")? {
+                "primary" => PartitionType::Primary,
+                "logical" => PartitionType::Logical,
+                _ => return None,
+            };
+
+            let fs = match field(&mut lines, "fs")? {
+                "none" => None,
+                fs => Some(fs.parse().ok()?),
+            };
+
+            let flags = field(&mut lines, "flags")?
+                .split(',')
+                .filter(|flag| !flag.is_empty())
+                .map(partition_flag_from_str)
+                .collect::<Option<Vec<_>>>()?;
+
+            let label = Some(field(&mut lines, "label")?)
+                .filter(|label| !label.is_empty())
+                .map(String::from);
+
+            let (start, end) = journal.target_sectors();
+            journal.entry = Some(NewPartition { start, end, fs, label, flags, kind });
+        }
+
+        Some(journal)
+    }
+}
+
+/// Reads the value of the next line of a journal, whose name must match.
+fn field<'a, I: Iterator<Item = &'a str>>(lines: &mut I, name: &str) -> Option<&'a str> {
+    let mut fields = lines.next()?.splitn(2, ' ');
+    if fields.next()? == name {
+        Some(fields.next().unwrap_or(""))
+    } else {
+        None
+    }
+}
+
+impl fmt::Display for MoveJournal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}\ndevice {}\nsector_size {}\nskip {}\noffset {}\nlength {}\ncopied {}\n",
+            JOURNAL_HEADER,
+            self.device.display(),
+            self.sector_size,
+            self.coords.skip,
+            self.coords.offset,
+            self.coords.length,
+            self.copied
+        )?;
+
+        if let Some(ref entry) = self.entry {
+            let kind = match entry.kind {
+                PartitionType::Logical => "logical",
+                _ => "primary",
+            };
+
+            let fs: &str = entry.fs.map_or("none", Into::into);
+            let flags = entry
+                .flags
+                .iter()
+                .filter_map(|&flag| partition_flag_to_str(flag))
+                .collect::<Vec<_>>()
+                .join(",");
+
+            write!(
+                f,
+                "kind {}\nfs {}\nflags {}\nlabel {}\n",
+                kind,
+                fs,
+                flags,
+                entry.label.as_ref().map_or("", String::as_str)
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
 /// Performs direct reads & writes on the disk to shift a partition either to the left or right,
 /// using the supplied offset coordinates to determine where the partition is, and where it
 /// should be.
@@ -12,6 +223,22 @@ pub fn move_partition<P: AsRef<Path>>(
     path: P,
     coords: OffsetCoordinates,
     bs: u64,
+) -> io::Result<()> {
+    move_partition_with(path, coords, bs, &MoveOptions::default(), |_, _| ())
+}
+
+/// Moves a partition as `move_partition` does, with the given options, reporting the sectors
+/// which have been copied, and the sectors that will be copied in total, as it progresses.
+///
+/// If the journal of the options records an interrupted move of the same partition, the move
+/// resumes from where it was interrupted. A journal of any other move is an error, as that
+/// move must be finished with `resume_move` before the disk can be modified further.
+pub fn move_partition_with<P: AsRef<Path>, F: FnMut(u64, u64)>(
+    path: P,
+    coords: OffsetCoordinates,
+    bs: u64,
+    options: &MoveOptions,
+    progress: F,
 ) -> io::Result<()> {
     info!(
         "moving partition on {} with {} sector size: {{ skip: {}; offset: {}; length: {} }}",
@@ -22,33 +249,369 @@ pub fn move_partition<P: AsRef<Path>>(
         coords.length
     );
 
-    let mut disk = OpenOptions::new().read(true).write(true).open(&path)?;
+    let mut journal = MoveJournal {
+        device: path.as_ref().to_path_buf(),
+        sector_size: bs,
+        coords,
+        copied: 0,
+        entry: None,
+    };
 
-    let source_skip = coords.skip;
-    let offset_skip = (source_skip as i64 + coords.offset) as u64;
+    let (start, end) = journal.target_sectors();
+    journal.entry = options.entry.clone().map(|entry| NewPartition { start, end, ..entry });
 
-    // TODO: Rather than writing one sector at a time, use a significantly larger
-    // buffer size to improve the performance of partition moves.
-    let mut buffer = vec![0; bs as usize];
+    if let Some(ref journal_path) = options.journal {
+        match MoveJournal::load(journal_path) {
+            Ok(ref existing) if existing.is_same_move(&journal) => {
+                info!("resuming the move after {} copied sectors", existing.copied);
+                journal.copied = existing.copied;
+            }
+            Ok(existing) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!(
+                        "{:?} records an interrupted move of a partition on {}, which must be \
+                         resumed first",
+                        journal_path,
+                        existing.device.display()
+                    ),
+                ));
+            }
+            Err(ref why) if why.kind() == io::ErrorKind::NotFound => (),
+            Err(why) => return Err(why),
+        }
+    }
 
-    // Some dynamic dispatching, based on whether we need to move forward or
-    // backwards.
-    let range: Box<dyn Iterator<Item = u64>> = if coords.offset > 0 {
-        Box::new((0..coords.length).rev())
-    } else {
-        Box::new(0..coords.length)
-    };
+    copy_sectors(&mut journal, options, progress)?;
 
-    // Write one sector at a time, until all sectors have been moved.
-    for sector in range {
-        let input = source_skip + sector;
-        disk.seek(SeekFrom::Start(input * bs))?;
-        disk.read_exact(&mut buffer[..bs as usize])?;
+    match options.journal {
+        Some(ref path) if options.entry.is_none() => MoveJournal::remove(path),
+        _ => Ok(()),
+    }
+}
 
-        let offset = offset_skip + sector;
-        disk.seek(SeekFrom::Start(offset * bs))?;
-        disk.write_all(&buffer[..bs as usize])?;
+/// Finishes the move which was interrupted after writing the journal at the given path,
+/// creates the table entry of the partition at its new location, and then removes the journal.
+/// The returned journal describes the completed move.
+///
+/// The entry is created by the native partition table backend, so a move is refused before any
+/// sector is copied if its journal does not record an entry, or if the entry cannot be created
+/// natively, such as the logical partitions of an MBR table.
+pub fn resume_move<P: AsRef<Path>, F: FnMut(u64, u64)>(
+    path: P,
+    options: &MoveOptions,
+    progress: F,
+) -> io::Result<MoveJournal> {
+    let path = path.as_ref();
+    let mut journal = MoveJournal::load(path)?;
+    let entry = journal.entry.clone().ok_or_else(|| {
+        let why = format!("{:?} does not record the table entry of the moved partition", path);
+        io::Error::new(io::ErrorKind::InvalidData, why)
+    })?;
+
+    let creatable = entry.kind == PartitionType::Primary
+        && native::probe(&journal.device)?.map_or(false, |table| table.is_modifiable());
+    if !creatable {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!(
+                "the table entry of the partition moved on {} cannot be recreated natively",
+                journal.device.display()
+            ),
+        ));
     }
 
-    disk.sync_all()
+    info!(
+        "resuming the move of a partition on {} after {} of {} sectors",
+        journal.device.display(),
+        journal.copied,
+        journal.coords.length
+    );
+
+    let options = MoveOptions { journal: Some(path.to_path_buf()), ..options.clone() };
+    copy_sectors(&mut journal, &options, progress)?;
+    create_entry(&journal.device, entry)?;
+    MoveJournal::remove(path)?;
+    Ok(journal)
+}
+
+/// Creates the table entry of a moved partition, unless it was created before the move was
+/// interrupted.
+fn create_entry(device: &Path, entry: NewPartition) -> io::Result<()> {
+    let existing = native::probe(device)?
+        .into_iter()
+        .flat_map(|table| table.partitions())
+        .find(|partition| partition.start <= entry.end && partition.end >= entry.start);
+
+    match existing {
+        Some(ref partition) if partition.start == entry.start && partition.end == entry.end => {
+            info!("partition {} already has its table entry", partition.number);
+            Ok(())
+        }
+        Some(partition) => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!(
+                "partition {} on {} overlaps the sectors of the moved partition",
+                partition.number,
+                device.display()
+            ),
+        )),
+        None => {
+            info!("creating the table entry of the moved partition on {}", device.display());
+            native::NativePartitioner
+                .create(device, entry)
+                .map(|_| ())
+                .map_err(|why| io::Error::new(io::ErrorKind::Other, why.to_string()))
+        }
+    }
+}
+
+fn copy_sectors<F: FnMut(u64, u64)>(
+    journal: &mut MoveJournal,
+    options: &MoveOptions,
+    mut progress: F,
+) -> io::Result<()> {
+    let coords = journal.coords;
+    let bs = journal.sector_size;
+    let distance = coords.offset.abs() as u64;
+
+    if distance != 0 {
+        let mut open = OpenOptions::new();
+        open.read(true).write(true);
+        if options.direct {
+            open.custom_flags(libc::O_DIRECT);
+        }
+
+        let mut disk = open.open(&journal.device)?;
+
+        // The journal exists before the first sector is written, so that a move which was
+        // interrupted during its first window can be detected.
+        if let Some(ref path) = options.journal {
+            journal.write(path)?;
+        }
+
+        let window = distance.min(coords.length);
+        let chunk = (options.buffer_size as u64 / bs).max(1);
+        let mut buffer = AlignedBuffer::new((chunk * bs) as usize);
+        let destination = (coords.skip as i64 + coords.offset) as u64;
+
+        progress(journal.copied, coords.length);
+        while journal.copied < coords.length {
+            let window_end = coords.length.min(journal.copied + window);
+            let mut copied = journal.copied;
+            while copied < window_end {
+                let count = chunk.min(window_end - copied);
+
+                // Partitions moving to the right are copied from their end.
+                let sector =
+                    if coords.offset > 0 { coords.length - copied - count } else { copied };
+                let data = &mut buffer.as_mut_slice()[..(count * bs) as usize];
+
+                disk.seek(SeekFrom::Start((coords.skip + sector) * bs))?;
+                disk.read_exact(data)?;
+                disk.seek(SeekFrom::Start((destination + sector) * bs))?;
+                disk.write_all(data)?;
+
+                copied += count;
+                progress(copied, coords.length);
+            }
+
+            disk.sync_data()?;
+            journal.copied = window_end;
+            if let Some(ref path) = options.journal {
+                journal.write(path)?;
+            }
+        }
+
+        disk.sync_all()?;
+    }
+
+    Ok(())
+}
+
+/// A buffer whose memory is aligned for `O_DIRECT`.
+struct AlignedBuffer {
+    data:  Vec<u8>,
+    start: usize,
+    len:   usize,
+}
+
+impl AlignedBuffer {
+    fn new(len: usize) -> Self {
+        let data = vec![0; len + ALIGNMENT];
+        let start = data.as_ptr().align_offset(ALIGNMENT);
+        assert!(start < ALIGNMENT, "unable to align the buffer of a partition move");
+        AlignedBuffer { data, start, len }
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] { &mut self.data[self.start..self.start + self.len] }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use disk_types::{FileSystem, PartitionTable};
+    use libparted::PartitionFlag;
+    use std::io::Cursor;
+    use tempdir::TempDir;
+
+    const BS: u64 = 512;
+
+    /// A disk of 64 sectors with an empty MBR table, each of whose other sectors is filled with
+    /// its own index.
+    fn disk(dir: &TempDir) -> (PathBuf, Vec<u8>) {
+        let mut data = (0..64u8).flat_map(|sector| vec![sector; BS as usize]).collect::<Vec<_>>();
+        let table = native::NativeTable::new(PartitionTable::Msdos, 64, BS).unwrap();
+        table.write(&mut Cursor::new(&mut data)).unwrap();
+
+        let path = dir.path().join("disk");
+        fs::write(&path, &data).unwrap();
+        (path, data)
+    }
+
+    fn entry(start: u64, end: u64) -> NewPartition {
+        NewPartition {
+            start,
+            end,
+            fs: None,
+            label: None,
+            flags: Vec::new(),
+            kind: PartitionType::Primary,
+        }
+    }
+
+    fn moved(mut data: Vec<u8>, coords: OffsetCoordinates) -> Vec<u8> {
+        let start = (coords.skip * BS) as usize;
+        let end = start + (coords.length * BS) as usize;
+        let destination = ((coords.skip as i64 + coords.offset) as u64 * BS) as usize;
+        data.copy_within(start..end, destination);
+        data
+    }
+
+    fn options(dir: &TempDir) -> MoveOptions {
+        MoveOptions {
+            buffer_size: 2 * BS as usize,
+            direct:      false,
+            journal:     Some(dir.path().join("journal")),
+            entry:       None,
+        }
+    }
+
+    #[test]
+    fn move_both_directions() {
+        let dir = TempDir::new("distinst-move").unwrap();
+        for &offset in &[3, 20, -3, -20] {
+            let (path, data) = disk(&dir);
+            let coords = OffsetCoordinates { skip: 24, offset, length: 16 };
+            let mut reports = Vec::new();
+            move_partition_with(&path, coords, BS, &options(&dir), |copied, total| {
+                reports.push((copied, total))
+            })
+            .unwrap();
+
+            assert_eq!(fs::read(&path).unwrap(), moved(data, coords), "offset {}", offset);
+            assert_eq!(reports.first(), Some(&(0, 16)));
+            assert_eq!(reports.last(), Some(&(16, 16)));
+            assert!(!dir.path().join("journal").exists());
+        }
+    }
+
+    #[test]
+    fn resume_interrupted_window() {
+        let dir = TempDir::new("distinst-move").unwrap();
+        let (path, data) = disk(&dir);
+        let coords = OffsetCoordinates { skip: 24, offset: 3, length: 16 };
+
+        // The first window of three sectors was copied and journaled, and then the move was
+        // interrupted while the second window was partially written.
+        let mut interrupted = data.clone();
+        let end = ((24 + 16) * BS) as usize;
+        let window = (3 * BS) as usize;
+        interrupted.copy_within(end - window..end, end);
+        for byte in &mut interrupted[end - window..end - window + BS as usize] {
+            *byte = 0xFF;
+        }
+
+        fs::write(&path, &interrupted).unwrap();
+        let journal_path = dir.path().join("journal");
+        let journal = MoveJournal {
+            device: path.clone(),
+            sector_size: BS,
+            coords,
+            copied: 3,
+            entry: Some(entry(27, 43)),
+        };
+        journal.write(&journal_path).unwrap();
+        assert_eq!(MoveJournal::load(&journal_path).unwrap(), journal);
+
+        let completed = resume_move(&journal_path, &options(&dir), |_, _| ()).unwrap();
+        assert_eq!(completed.copied, 16);
+        assert_eq!(completed.target_sectors(), (27, 43));
+        assert_eq!(&fs::read(&path).unwrap()[BS as usize..], &moved(data, coords)[BS as usize..]);
+        assert!(!journal_path.exists());
+
+        // The table entry which was removed for the move has been created at its new location.
+        let table = native::probe(&path).unwrap().unwrap();
+        let layout =
+            table.partitions().iter().map(|part| (part.start, part.end)).collect::<Vec<_>>();
+        assert_eq!(layout, vec![(27, 43)]);
+    }
+
+    #[test]
+    fn resume_requires_an_entry() {
+        let dir = TempDir::new("distinst-move").unwrap();
+        let (path, data) = disk(&dir);
+        let coords = OffsetCoordinates { skip: 24, offset: 3, length: 16 };
+        let journal_path = dir.path().join("journal");
+        let journal =
+            MoveJournal { device: path.clone(), sector_size: BS, coords, copied: 3, entry: None };
+        journal.write(&journal_path).unwrap();
+
+        // Nothing is copied, as the partition could not be given an entry afterwards.
+        assert!(resume_move(&journal_path, &options(&dir), |_, _| ()).is_err());
+        assert_eq!(fs::read(&path).unwrap(), data);
+        assert!(journal_path.exists());
+    }
+
+    #[test]
+    fn journaled_entry() {
+        let dir = TempDir::new("distinst-move").unwrap();
+        let (path, data) = disk(&dir);
+        let coords = OffsetCoordinates { skip: 24, offset: -20, length: 16 };
+        let options = MoveOptions {
+            entry: Some(NewPartition {
+                fs: Some(FileSystem::Ext4),
+                label: Some("Pop OS".into()),
+                flags: vec![PartitionFlag::PED_PARTITION_BOOT, PartitionFlag::PED_PARTITION_LVM],
+                ..entry(0, 0)
+            }),
+            ..options(&dir)
+        };
+
+        // The journal is kept until the entry has been created by the caller.
+        move_partition_with(&path, coords, BS, &options, |_, _| ()).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), moved(data, coords));
+
+        let journal = MoveJournal::load(options.journal.as_ref().unwrap()).unwrap();
+        assert_eq!(journal.copied, 16);
+        assert_eq!(
+            journal.entry,
+            Some(NewPartition { start: 4, end: 20, ..options.entry.unwrap() })
+        );
+    }
+
+    #[test]
+    fn other_move_must_resume_first() {
+        let dir = TempDir::new("distinst-move").unwrap();
+        let (path, _) = disk(&dir);
+        let coords = OffsetCoordinates { skip: 24, offset: 3, length: 16 };
+        let options = options(&dir);
+        let journal =
+            MoveJournal { device: path.clone(), sector_size: BS, coords, copied: 3, entry: None };
+        journal.write(options.journal.as_ref().unwrap()).unwrap();
+
+        let other = OffsetCoordinates { skip: 0, offset: 3, length: 16 };
+        let error = move_partition_with(&path, other, BS, &options, |_, _| ()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+    }
 }
@@ -0,0 +1,462 @@
+//! GUID partition tables, which are made of a protective MBR, a primary header and array of
+//! partition entries at the start of the disk, and a backup of both at the end of the disk.
+
+use super::{
+    check_bounds, le_u16, le_u32, le_u64, mbr, not_found, random_bytes, read_sectors,
+    write_sectors, NativePartition,
+};
+use disk_types::{partitioner::NewPartition, FileSystem, PartitionType};
+use libparted::PartitionFlag;
+use std::io::{self, Read, Seek, Write};
+
+const SIGNATURE: &[u8] = b"EFI PART";
+const REVISION: u32 = 0x0001_0000;
+const HEADER_SIZE: usize = 92;
+
+/// The number of entries in the arrays of new tables.
+const ENTRIES: usize = 128;
+const ENTRY_SIZE: usize = 128;
+
+/// The length of a partition name, in UTF-16 code units.
+const NAME_LENGTH: usize = 36;
+
+/// Larger partition entry arrays are assumed to be corrupt.
+const MAX_ENTRIES_SIZE: usize = 1 << 20;
+
+/// Attribute bits of partition entries.
+const LEGACY_BIOS_BOOTABLE: u64 = 1 << 2;
+const HIDDEN: u64 = 1 << 62;
+
+const BASIC_DATA: &str = "EBD0A0A2-B9E5-4433-87C0-68B6B72699C7";
+const BIOS_BOOT: &str = "21686148-6449-6E6F-744E-656564454649";
+const EFI_SYSTEM: &str = "C12A7328-F81F-11D2-BA4B-00A0C93EC93B";
+const LINUX_DATA: &str = "0FC63DAF-8483-4772-8E79-3D69D8477DE4";
+const LINUX_LVM: &str = "E6D6D379-F507-44C2-A23C-238F2A3DF928";
+const LINUX_RAID: &str = "A19D880F-05FC-4D3B-A006-743F0F84911E";
+const LINUX_SWAP: &str = "0657FD6D-A4AB-4C6F-8488-D2F1E8D2D3A2";
+const MICROSOFT_RESERVED: &str = "E3C9E316-0B5C-4DB8-817D-F92DF00215AE";
+const WINDOWS_RECOVERY: &str = "DE94BBA4-06D1-4D40-A16A-BFD50179D6AC";
+
+/// A GUID partition table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gpt {
+    sector_size:  u64,
+    sectors:      u64,
+    disk_guid:    [u8; 16],
+    /// The boot code of the protective MBR, which is preserved.
+    boot_code:    Vec<u8>,
+    first_usable: u64,
+    entry_size:   usize,
+    entries:      Vec<Entry>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Entry {
+    type_guid:   [u8; 16],
+    unique_guid: [u8; 16],
+    first:       u64,
+    last:        u64,
+    attributes:  u64,
+    name:        Vec<u16>,
+    /// The bytes of entries which are larger than 128 bytes, which are preserved.
+    rest:        Vec<u8>,
+}
+
+impl Entry {
+    fn parse(data: &[u8]) -> Self {
+        let mut type_guid = [0; 16];
+        let mut unique_guid = [0; 16];
+        type_guid.copy_from_slice(&data[0..16]);
+        unique_guid.copy_from_slice(&data[16..32]);
+
+        Entry {
+            type_guid,
+            unique_guid,
+            first: le_u64(&data[32..40]),
+            last: le_u64(&data[40..48]),
+            attributes: le_u64(&data[48..56]),
+            name: data[56..128].chunks(2).map(le_u16).collect(),
+            rest: data[128..].to_vec(),
+        }
+    }
+
+    fn serialize(&self, data: &mut [u8]) {
+        data[0..16].copy_from_slice(&self.type_guid);
+        data[16..32].copy_from_slice(&self.unique_guid);
+        data[32..40].copy_from_slice(&self.first.to_le_bytes());
+        data[40..48].copy_from_slice(&self.last.to_le_bytes());
+        data[48..56].copy_from_slice(&self.attributes.to_le_bytes());
+        for (unit, bytes) in self.name.iter().zip(data[56..128].chunks_mut(2)) {
+            bytes.copy_from_slice(&unit.to_le_bytes());
+        }
+        data[128..].copy_from_slice(&self.rest);
+    }
+
+    fn empty(size: usize) -> Self {
+        Entry {
+            type_guid:   [0; 16],
+            unique_guid: [0; 16],
+            first:       0,
+            last:        0,
+            attributes:  0,
+            name:        vec![0; NAME_LENGTH],
+            rest:        vec![0; size - ENTRY_SIZE],
+        }
+    }
+
+    fn is_used(&self) -> bool { self.type_guid != [0; 16] }
+
+    fn label(&self) -> Option<String> {
+        let length = self.name.iter().position(|&unit| unit == 0).unwrap_or(NAME_LENGTH);
+        if length == 0 {
+            None
+        } else {
+            Some(String::from_utf16_lossy(&self.name[..length]))
+        }
+    }
+}
+
+impl Gpt {
+    pub fn new(sectors: u64, sector_size: u64) -> io::Result<Self> {
+        let mut disk_guid = [0; 16];
+        random_guid(&mut disk_guid)?;
+        let entry_sectors = sectors_for(ENTRIES * ENTRY_SIZE, sector_size);
+
+        Ok(Gpt {
+            sector_size,
+            sectors,
+            disk_guid,
+            boot_code: vec![0; 440],
+            first_usable: 2 + entry_sectors,
+            entry_size: ENTRY_SIZE,
+            entries: (0..ENTRIES).map(|_| Entry::empty(ENTRY_SIZE)).collect(),
+        })
+    }
+
+    /// Reads the table from its primary header, or from its backup header if the primary
+    /// header is corrupt.
+    pub fn read<D: Read + Seek>(
+        disk: &mut D,
+        sector_size: u64,
+        sectors: u64,
+        mbr: &[u8],
+    ) -> io::Result<Self> {
+        let primary = read_header(disk, sector_size, 1);
+        let (header, entries) = match primary {
+            Ok(primary) => primary,
+            Err(why) => {
+                warn!("primary GPT header is invalid, so the backup will be used: {}", why);
+                read_header(disk, sector_size, sectors - 1)?
+            }
+        };
+
+        let entry_size = le_u32(&header[84..88]) as usize;
+        let mut disk_guid = [0; 16];
+        disk_guid.copy_from_slice(&header[56..72]);
+
+        Ok(Gpt {
+            sector_size,
+            sectors,
+            disk_guid,
+            boot_code: mbr[..440].to_vec(),
+            first_usable: le_u64(&header[40..48]),
+            entry_size,
+            entries: entries.chunks(entry_size).map(Entry::parse).collect(),
+        })
+    }
+
+    pub fn partitions(&self) -> Vec<NativePartition> {
+        self.entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| entry.is_used())
+            .map(|(index, entry)| NativePartition {
+                number: index as u32 + 1,
+                start:  entry.first,
+                end:    entry.last,
+                kind:   PartitionType::Primary,
+                label:  entry.label(),
+            })
+            .collect()
+    }
+
+    pub fn add(&mut self, partition: &NewPartition) -> io::Result<u32> {
+        if partition.kind != PartitionType::Primary {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "GPT tables do not have extended or logical partitions",
+            ));
+        }
+
+        check_bounds(partition.start, partition.end, self.usable(), self.partitions())?;
+
+        let index = self.entries.iter().position(|entry| !entry.is_used()).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::Other, "GPT table has no free partition entries")
+        })?;
+
+        let mut entry = Entry::empty(self.entry_size);
+        entry.type_guid = guid(type_guid(partition));
+        random_guid(&mut entry.unique_guid)?;
+        entry.first = partition.start;
+        entry.last = partition.end;
+
+        if partition.flags.contains(&PartitionFlag::PED_PARTITION_LEGACY_BOOT) {
+            entry.attributes |= LEGACY_BIOS_BOOTABLE;
+        }
+
+        if partition.flags.contains(&PartitionFlag::PED_PARTITION_HIDDEN) {
+            entry.attributes |= HIDDEN;
+        }
+
+        if let Some(ref label) = partition.label {
+            let units = label.encode_utf16().collect::<Vec<_>>();
+            if units.len() > NAME_LENGTH {
+                warn!("partition name {:?} is too long, and will be truncated", label);
+            }
+
+            for (unit, name) in units.into_iter().zip(entry.name.iter_mut()) {
+                *name = unit;
+            }
+        }
+
+        self.entries[index] = entry;
+        Ok(index as u32 + 1)
+    }
+
+    pub fn remove(&mut self, number: u32) -> io::Result<()> {
+        let index = self.index(number)?;
+        self.entries[index] = Entry::empty(self.entry_size);
+        Ok(())
+    }
+
+    pub fn resize(&mut self, number: u32, start: u64, end: u64) -> io::Result<()> {
+        let index = self.index(number)?;
+        let others = self.partitions().into_iter().filter(|part| part.number != number);
+        check_bounds(start, end, self.usable(), others)?;
+
+        let entry = &mut self.entries[index];
+        entry.first = start;
+        entry.last = end;
+        Ok(())
+    }
+
+    /// Writes the backup entries and header first, so that the primary header remains valid
+    /// until the backup has been written.
+    pub fn write<D: Write + Seek>(&self, disk: &mut D) -> io::Result<()> {
+        let entry_sectors = self.entry_sectors();
+        let mut entries = vec![0; (entry_sectors * self.sector_size) as usize];
+        for (entry, data) in self.entries.iter().zip(entries.chunks_mut(self.entry_size)) {
+            entry.serialize(data);
+        }
+
+        let entries_crc = crc32(&entries[..self.entries.len() * self.entry_size]);
+        let backup_lba = self.sectors - 1;
+        let backup_entries_lba = backup_lba - entry_sectors;
+
+        let backup = self.header(backup_lba, 1, backup_entries_lba, entries_crc);
+        write_sectors(disk, self.sector_size, backup_entries_lba, &entries)?;
+        write_sectors(disk, self.sector_size, backup_lba, &backup)?;
+
+        let primary = self.header(1, backup_lba, 2, entries_crc);
+        write_sectors(disk, self.sector_size, 2, &entries)?;
+        write_sectors(disk, self.sector_size, 1, &primary)?;
+
+        let mut protective = mbr::protective(self.sectors);
+        protective[..440].copy_from_slice(&self.boot_code);
+        write_sectors(disk, self.sector_size, 0, &protective)
+    }
+
+    fn header(&self, current: u64, backup: u64, entries: u64, entries_crc: u32) -> Vec<u8> {
+        let mut header = vec![0; self.sector_size as usize];
+        header[0..8].copy_from_slice(SIGNATURE);
+        header[8..12].copy_from_slice(&REVISION.to_le_bytes());
+        header[12..16].copy_from_slice(&(HEADER_SIZE as u32).to_le_bytes());
+        header[24..32].copy_from_slice(&current.to_le_bytes());
+        header[32..40].copy_from_slice(&backup.to_le_bytes());
+        header[40..48].copy_from_slice(&self.first_usable.to_le_bytes());
+        header[48..56].copy_from_slice(&self.usable().1.to_le_bytes());
+        header[56..72].copy_from_slice(&self.disk_guid);
+        header[72..80].copy_from_slice(&entries.to_le_bytes());
+        header[80..84].copy_from_slice(&(self.entries.len() as u32).to_le_bytes());
+        header[84..88].copy_from_slice(&(self.entry_size as u32).to_le_bytes());
+        header[88..92].copy_from_slice(&entries_crc.to_le_bytes());
+
+        let crc = crc32(&header[..HEADER_SIZE]);
+        header[16..20].copy_from_slice(&crc.to_le_bytes());
+        header
+    }
+
+    fn entry_sectors(&self) -> u64 {
+        sectors_for(self.entries.len() * self.entry_size, self.sector_size)
+    }
+
+    /// The last usable sector is derived from the size of the disk, so that a table which is
+    /// written to a disk that has grown spans all of it.
+    fn usable(&self) -> (u64, u64) { (self.first_usable, self.sectors - 2 - self.entry_sectors()) }
+
+    fn index(&self, number: u32) -> io::Result<usize> {
+        let index = (number as usize).wrapping_sub(1);
+        match self.entries.get(index) {
+            Some(entry) if entry.is_used() => Ok(index),
+            _ => Err(not_found(number)),
+        }
+    }
+}
+
+/// Reads and validates the header at `lba`, and the partition entries that it points to.
+fn read_header<D: Read + Seek>(
+    disk: &mut D,
+    sector_size: u64,
+    lba: u64,
+) -> io::Result<(Vec<u8>, Vec<u8>)> {
+    let invalid = |why: &str| io::Error::new(io::ErrorKind::InvalidData, why.to_owned());
+
+    let mut header = read_sectors(disk, sector_size, lba, 1)?;
+    if !header.starts_with(SIGNATURE) {
+        return Err(invalid("GPT header signature not found"));
+    }
+
+    let size = le_u32(&header[12..16]) as usize;
+    if size < HEADER_SIZE || size > header.len() {
+        return Err(invalid("GPT header has an invalid size"));
+    }
+
+    let crc = le_u32(&header[16..20]);
+    header[16..20].copy_from_slice(&[0; 4]);
+    if crc32(&header[..size]) != crc {
+        return Err(invalid("GPT header checksum does not match"));
+    }
+    header[16..20].copy_from_slice(&crc.to_le_bytes());
+
+    let count = le_u32(&header[80..84]) as usize;
+    let entry_size = le_u32(&header[84..88]) as usize;
+    let length = count * entry_size;
+    if entry_size < ENTRY_SIZE || length == 0 || length > MAX_ENTRIES_SIZE {
+        return Err(invalid("GPT header has an invalid partition entry array"));
+    }
+
+    let lba = le_u64(&header[72..80]);
+    let mut entries = read_sectors(disk, sector_size, lba, sectors_for(length, sector_size))?;
+    entries.truncate(length);
+    if crc32(&entries) != le_u32(&header[88..92]) {
+        return Err(invalid("GPT partition entry checksum does not match"));
+    }
+
+    Ok((header, entries))
+}
+
+/// Selects the partition type of a new partition from its flags and file system.
+fn type_guid(partition: &NewPartition) -> &'static str {
+    use self::PartitionFlag::*;
+    let has = |flag| partition.flags.contains(&flag);
+
+    if has(PED_PARTITION_ESP) || has(PED_PARTITION_BOOT) {
+        EFI_SYSTEM
+    } else if has(PED_PARTITION_BIOS_GRUB) {
+        BIOS_BOOT
+    } else if has(PED_PARTITION_RAID) {
+        LINUX_RAID
+    } else if has(PED_PARTITION_LVM) {
+        LINUX_LVM
+    } else if has(PED_PARTITION_SWAP) {
+        LINUX_SWAP
+    } else if has(PED_PARTITION_MSFT_RESERVED) {
+        MICROSOFT_RESERVED
+    } else if has(PED_PARTITION_DIAG) {
+        WINDOWS_RECOVERY
+    } else if has(PED_PARTITION_MSFT_DATA) {
+        BASIC_DATA
+    } else {
+        match partition.fs {
+            Some(FileSystem::Swap) => LINUX_SWAP,
+            Some(FileSystem::Lvm) => LINUX_LVM,
+            Some(FileSystem::Fat16)
+            | Some(FileSystem::Fat32)
+            | Some(FileSystem::Ntfs)
+            | Some(FileSystem::Exfat) => BASIC_DATA,
+            _ => LINUX_DATA,
+        }
+    }
+}
+
+/// Converts a GUID from its textual form to its mixed-endian form on the disk.
+fn guid(text: &str) -> [u8; 16] {
+    let digits = text.chars().filter(|&c| c != '-').collect::<String>();
+    let mut bytes = [0; 16];
+    for (index, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&digits[index * 2..index * 2 + 2], 16)
+            .expect("invalid GUID constant");
+    }
+
+    bytes[0..4].reverse();
+    bytes[4..6].reverse();
+    bytes[6..8].reverse();
+    bytes
+}
+
+/// Generates a random version 4 GUID.
+fn random_guid(guid: &mut [u8; 16]) -> io::Result<()> {
+    random_bytes(guid)?;
+    guid[7] = (guid[7] & 0x0F) | 0x40;
+    guid[8] = (guid[8] & 0x3F) | 0x80;
+    Ok(())
+}
+
+fn sectors_for(bytes: usize, sector_size: u64) -> u64 {
+    (bytes as u64 + sector_size - 1) / sector_size
+}
+
+/// The CRC-32 checksum which is used by GPT headers.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn guids() {
+        let esp = guid(EFI_SYSTEM);
+        assert_eq!(&esp[..8], &[0x28, 0x73, 0x2A, 0xC1, 0x1F, 0xF8, 0xD2, 0x11]);
+        assert_eq!(&esp[8..], &[0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9, 0x3B]);
+    }
+
+    #[test]
+    fn backup_header_recovers_table() {
+        let sectors = 65_536;
+        let mut disk = Cursor::new(vec![0; sectors as usize * 512]);
+        let mut gpt = Gpt::new(sectors, 512).unwrap();
+        let partition = NewPartition {
+            start: 2048,
+            end:   4095,
+            fs:    None,
+            label: Some("root".into()),
+            flags: Vec::new(),
+            kind:  PartitionType::Primary,
+        };
+        gpt.add(&partition).unwrap();
+        gpt.write(&mut disk).unwrap();
+
+        // Corrupt the primary header, which the backup header stands in for.
+        disk.get_mut()[512 + 24] ^= 0xFF;
+        let mbr = disk.get_ref()[..512].to_vec();
+        let read = Gpt::read(&mut disk, 512, sectors, &mbr).unwrap();
+        assert_eq!(read, gpt);
+        assert_eq!(read.partitions()[0].label.as_deref(), Some("root"));
+    }
+}
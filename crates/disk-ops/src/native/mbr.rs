@@ -0,0 +1,350 @@
+//! MBR partition tables, which hold four primary partitions in the first sector of the disk.
+//! One of these may be an extended partition, whose logical partitions are chained together by
+//! the extended boot records that precede each of them.
+
+use super::{
+    check_bounds, le_u32, not_found, random_bytes, read_sectors, unsupported, write_sectors,
+    NativePartition, BOOT_SIGNATURE,
+};
+use disk_types::{partitioner::NewPartition, FileSystem, PartitionType};
+use libparted::PartitionFlag;
+use std::io::{self, Read, Seek, Write};
+
+const ENTRIES_OFFSET: usize = 446;
+const ENTRY_SIZE: usize = 16;
+
+const ACTIVE: u8 = 0x80;
+
+/// The CHS address of sectors which can only be addressed by their LBA.
+const LBA_ONLY: [u8; 3] = [0xFE, 0xFF, 0xFF];
+
+const EXTENDED: u8 = 0x05;
+const EXTENDED_LBA: u8 = 0x0F;
+const EXTENDED_LINUX: u8 = 0x85;
+const EFI_SYSTEM: u8 = 0xEF;
+const FAT16: u8 = 0x0E;
+const FAT32: u8 = 0x0C;
+const GPT_PROTECTIVE: u8 = 0xEE;
+const LINUX_DATA: u8 = 0x83;
+const LINUX_LVM: u8 = 0x8E;
+const LINUX_RAID: u8 = 0xFD;
+const LINUX_SWAP: u8 = 0x82;
+const NTFS: u8 = 0x07;
+
+/// Limits the chain of extended boot records that is followed, in case it loops.
+const MAX_LOGICAL_PARTITIONS: usize = 128;
+
+/// An MBR partition table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mbr {
+    sector_size: u64,
+    sectors:     u64,
+    /// The first sector of the disk, whose boot code and disk signature are preserved.
+    boot:        Vec<u8>,
+    primary:     [Option<Entry>; 4],
+    /// Logical partitions, which are only read.
+    logical:     Vec<NativePartition>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Entry {
+    active: bool,
+    kind:   u8,
+    start:  u64,
+    length: u64,
+}
+
+impl Entry {
+    fn parse(data: &[u8]) -> Option<Self> {
+        let (kind, start, length) = (data[4], le_u32(&data[8..12]), le_u32(&data[12..16]));
+        if kind == 0 || length == 0 {
+            return None;
+        }
+
+        Some(Entry { active: data[0] == ACTIVE, kind, start: start.into(), length: length.into() })
+    }
+
+    fn serialize(&self, data: &mut [u8]) {
+        data[0] = if self.active { ACTIVE } else { 0 };
+        data[1..4].copy_from_slice(&LBA_ONLY);
+        data[4] = self.kind;
+        data[5..8].copy_from_slice(&LBA_ONLY);
+        data[8..12].copy_from_slice(&(self.start as u32).to_le_bytes());
+        data[12..16].copy_from_slice(&(self.length as u32).to_le_bytes());
+    }
+
+    fn is_extended(&self) -> bool { [EXTENDED, EXTENDED_LBA, EXTENDED_LINUX].contains(&self.kind) }
+
+    fn end(&self) -> u64 { self.start + self.length - 1 }
+}
+
+impl Mbr {
+    pub fn new(sectors: u64, sector_size: u64) -> io::Result<Self> {
+        let mut boot = vec![0; sector_size as usize];
+        random_bytes(&mut boot[440..444])?;
+        boot[510..512].copy_from_slice(&BOOT_SIGNATURE);
+
+        Ok(Mbr { sector_size, sectors, boot, primary: [None; 4], logical: Vec::new() })
+    }
+
+    pub fn read<D: Read + Seek>(
+        disk: &mut D,
+        sector_size: u64,
+        sectors: u64,
+        mut boot: Vec<u8>,
+    ) -> io::Result<Self> {
+        let mut primary = [None; 4];
+        for (index, entry) in primary.iter_mut().enumerate() {
+            *entry = Entry::parse(&boot[entry_range(index)]);
+            boot[entry_range(index)].copy_from_slice(&[0; ENTRY_SIZE]);
+        }
+
+        let mut mbr = Mbr { sector_size, sectors, boot, primary, logical: Vec::new() };
+        if let Some(extended) = primary.iter().flatten().find(|entry| entry.is_extended()) {
+            mbr.logical = read_logical(disk, sector_size, extended.start)?;
+        }
+
+        Ok(mbr)
+    }
+
+    pub fn has_extended(&self) -> bool {
+        self.primary.iter().flatten().any(|entry| entry.is_extended())
+    }
+
+    pub fn partitions(&self) -> Vec<NativePartition> {
+        let primary = self.primary.iter().enumerate().filter_map(|(index, entry)| {
+            entry.map(|entry| NativePartition {
+                number: index as u32 + 1,
+                start:  entry.start,
+                end:    entry.end(),
+                kind:   if entry.is_extended() {
+                    PartitionType::Extended
+                } else {
+                    PartitionType::Primary
+                },
+                label:  None,
+            })
+        });
+
+        primary.chain(self.logical.iter().cloned()).collect()
+    }
+
+    pub fn add(&mut self, partition: &NewPartition) -> io::Result<u32> {
+        match partition.kind {
+            PartitionType::Primary => (),
+            PartitionType::Extended => return Err(unsupported("creating extended partitions")),
+            PartitionType::Logical => return Err(unsupported("creating logical partitions")),
+        }
+
+        check_bounds(partition.start, partition.end, self.usable(), self.partitions())?;
+        if partition.end >= 1 << 32 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "MBR partitions must be within the first 2^32 sectors of the disk",
+            ));
+        }
+
+        let index = self.primary.iter().position(Option::is_none).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::Other, "MBR table has no free primary partitions")
+        })?;
+
+        let flags = &partition.flags;
+        self.primary[index] = Some(Entry {
+            active: flags.contains(&PartitionFlag::PED_PARTITION_BOOT),
+            kind:   partition_type(partition),
+            start:  partition.start,
+            length: partition.end - partition.start + 1,
+        });
+
+        Ok(index as u32 + 1)
+    }
+
+    pub fn remove(&mut self, number: u32) -> io::Result<()> {
+        let index = self.index(number)?;
+        self.primary[index] = None;
+        Ok(())
+    }
+
+    pub fn resize(&mut self, number: u32, start: u64, end: u64) -> io::Result<()> {
+        let index = self.index(number)?;
+        let others = self.partitions().into_iter().filter(|part| part.number != number);
+        check_bounds(start, end, self.usable(), others)?;
+
+        if let Some(ref mut entry) = self.primary[index] {
+            entry.start = start;
+            entry.length = end - start + 1;
+        }
+
+        Ok(())
+    }
+
+    pub fn write<D: Write + Seek>(&self, disk: &mut D) -> io::Result<()> {
+        let mut boot = self.boot.clone();
+        for (index, entry) in self.primary.iter().enumerate() {
+            match entry {
+                Some(entry) => entry.serialize(&mut boot[entry_range(index)]),
+                None => boot[entry_range(index)].copy_from_slice(&[0; ENTRY_SIZE]),
+            }
+        }
+
+        write_sectors(disk, self.sector_size, 0, &boot)
+    }
+
+    fn usable(&self) -> (u64, u64) { (1, self.sectors - 1) }
+
+    /// The index of a primary partition, which may be removed or resized as long as it is
+    /// not an extended partition.
+    fn index(&self, number: u32) -> io::Result<usize> {
+        let index = (number as usize).wrapping_sub(1);
+        match self.primary.get(index) {
+            Some(Some(entry)) if entry.is_extended() => {
+                Err(unsupported("modifying extended partitions"))
+            }
+            Some(Some(_)) => Ok(index),
+            _ if number > 4 => Err(unsupported("modifying logical partitions")),
+            _ => Err(not_found(number)),
+        }
+    }
+}
+
+/// True if the MBR only protects a GPT.
+pub fn is_protective(mbr: &[u8]) -> bool {
+    (0..4).any(|index| mbr[entry_range(index)][4] == GPT_PROTECTIVE)
+}
+
+/// A protective MBR for a GPT, whose one partition spans the whole disk.
+pub fn protective(sectors: u64) -> Vec<u8> {
+    let mut mbr = vec![0; 512];
+    let entry = Entry {
+        active: false,
+        kind:   GPT_PROTECTIVE,
+        start:  1,
+        length: (sectors - 1).min(u64::from(u32::MAX)),
+    };
+
+    entry.serialize(&mut mbr[entry_range(0)]);
+    mbr[entry_range(0)][1..4].copy_from_slice(&[0x00, 0x02, 0x00]);
+    mbr[510..512].copy_from_slice(&BOOT_SIGNATURE);
+    mbr
+}
+
+/// Follows the chain of extended boot records, each of which describes one logical
+/// partition relative to itself, and links to the next relative to the extended partition.
+fn read_logical<D: Read + Seek>(
+    disk: &mut D,
+    sector_size: u64,
+    extended: u64,
+) -> io::Result<Vec<NativePartition>> {
+    let mut logical = Vec::new();
+    let mut next = 0;
+
+    for number in 5..5 + MAX_LOGICAL_PARTITIONS as u32 {
+        let ebr_lba = extended + next;
+        let ebr = read_sectors(disk, sector_size, ebr_lba, 1)?;
+        if ebr[510..512] != BOOT_SIGNATURE {
+            break;
+        }
+
+        if let Some(entry) = Entry::parse(&ebr[entry_range(0)]) {
+            logical.push(NativePartition {
+                number,
+                start: ebr_lba + entry.start,
+                end: ebr_lba + entry.end(),
+                kind: PartitionType::Logical,
+                label: None,
+            });
+        }
+
+        match Entry::parse(&ebr[entry_range(1)]) {
+            Some(link) if link.is_extended() => next = link.start,
+            _ => break,
+        }
+    }
+
+    Ok(logical)
+}
+
+/// Selects the partition type of a new partition from its flags and file system.
+fn partition_type(partition: &NewPartition) -> u8 {
+    let has = |flag| partition.flags.contains(&flag);
+
+    if has(PartitionFlag::PED_PARTITION_ESP) {
+        EFI_SYSTEM
+    } else if has(PartitionFlag::PED_PARTITION_RAID) {
+        LINUX_RAID
+    } else if has(PartitionFlag::PED_PARTITION_LVM) {
+        LINUX_LVM
+    } else if has(PartitionFlag::PED_PARTITION_SWAP) {
+        LINUX_SWAP
+    } else {
+        match partition.fs {
+            Some(FileSystem::Fat16) => FAT16,
+            Some(FileSystem::Fat32) => FAT32,
+            Some(FileSystem::Ntfs) | Some(FileSystem::Exfat) => NTFS,
+            Some(FileSystem::Swap) => LINUX_SWAP,
+            Some(FileSystem::Lvm) => LINUX_LVM,
+            _ => LINUX_DATA,
+        }
+    }
+}
+
+fn entry_range(index: usize) -> std::ops::Range<usize> {
+    ENTRIES_OFFSET + index * ENTRY_SIZE..ENTRIES_OFFSET + (index + 1) * ENTRY_SIZE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn write_entry(sector: &mut [u8], index: usize, kind: u8, start: u32, length: u32) {
+        Entry { active: false, kind, start: start.into(), length: length.into() }
+            .serialize(&mut sector[entry_range(index)]);
+    }
+
+    #[test]
+    fn logical_partitions_are_listed() {
+        let mut image = vec![0; 4096 * 512];
+        let mut mbr = Mbr::new(4096, 512).unwrap();
+        mbr.primary[0] =
+            Some(Entry { active: true, kind: LINUX_DATA, start: 2048, length: 1024 });
+        mbr.primary[1] =
+            Some(Entry { active: false, kind: EXTENDED_LBA, start: 3072, length: 1024 });
+        mbr.write(&mut Cursor::new(&mut image[..])).unwrap();
+
+        let mut first = vec![0; 512];
+        write_entry(&mut first, 0, LINUX_DATA, 1, 255);
+        write_entry(&mut first, 1, EXTENDED, 256, 512);
+        first[510..512].copy_from_slice(&BOOT_SIGNATURE);
+        image[3072 * 512..3073 * 512].copy_from_slice(&first);
+
+        let mut second = vec![0; 512];
+        write_entry(&mut second, 0, LINUX_SWAP, 1, 511);
+        second[510..512].copy_from_slice(&BOOT_SIGNATURE);
+        image[(3072 + 256) * 512..(3073 + 256) * 512].copy_from_slice(&second);
+
+        let mut disk = Cursor::new(image);
+        let boot = disk.get_ref()[..512].to_vec();
+        let mut read = Mbr::read(&mut disk, 512, 4096, boot).unwrap();
+        let layout = read
+            .partitions()
+            .into_iter()
+            .map(|part| (part.number, part.start, part.end, part.kind))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            layout,
+            vec![
+                (1, 2048, 3071, PartitionType::Primary),
+                (2, 3072, 4095, PartitionType::Extended),
+                (5, 3073, 3327, PartitionType::Logical),
+                (6, 3329, 3839, PartitionType::Logical),
+            ]
+        );
+
+        assert!(read.has_extended());
+        assert!(read.remove(5).is_err());
+        assert!(read.remove(2).is_err());
+        read.remove(1).unwrap();
+    }
+}
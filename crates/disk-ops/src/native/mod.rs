@@ -0,0 +1,438 @@
+//! A partition table backend which reads and writes GPT and MBR tables itself, rather than
+//! through libparted.
+//!
+//! Tables are read from and written to anything that can be read, written, and seeked, so
+//! they may be manipulated on block devices, disk images, and in-memory buffers alike. As no
+//! state is shared between tables, the tables of different disks may be modified in parallel.
+//!
+//! The logical partitions of MBR tables are listed, but cannot be created or removed, so
+//! changes to those must still be made with libparted.
+
+mod gpt;
+mod mbr;
+
+pub use self::{gpt::Gpt, mbr::Mbr};
+
+use crate::mkpart::PartitionCreate;
+use disk_types::{
+    partitioner::{NewPartition, PartitionError, Partitioner},
+    PartitionTable, PartitionType,
+};
+use external::blockdev;
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    os::unix::{fs::FileTypeExt, io::AsRawFd},
+    path::{Path, PathBuf},
+};
+
+/// `BLKSSZGET`, which gets the logical sector size of a block device.
+const BLKSSZGET: libc::c_ulong = 0x1268;
+
+const BOOT_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+
+/// A partition in a table which was read by the native backend.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NativePartition {
+    pub number: u32,
+    pub start:  u64,
+    /// The last sector of the partition, which is inclusive.
+    pub end:    u64,
+    pub kind:   PartitionType,
+    pub label:  Option<String>,
+}
+
+/// A GPT or MBR partition table.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NativeTable {
+    Gpt(Gpt),
+    Msdos(Mbr),
+}
+
+impl NativeTable {
+    /// Creates an empty table for a disk with the given number of sectors.
+    pub fn new(kind: PartitionTable, sectors: u64, sector_size: u64) -> io::Result<Self> {
+        match kind {
+            PartitionTable::Gpt => Gpt::new(sectors, sector_size).map(NativeTable::Gpt),
+            PartitionTable::Msdos => Mbr::new(sectors, sector_size).map(NativeTable::Msdos),
+        }
+    }
+
+    /// Reads the table of a disk, if it has one.
+    pub fn read<D: Read + Seek>(disk: &mut D, sector_size: u64) -> io::Result<Option<Self>> {
+        let sectors = disk.seek(SeekFrom::End(0))? / sector_size;
+        let mbr = read_sectors(disk, sector_size, 0, 1)?;
+        if mbr[510..512] != BOOT_SIGNATURE {
+            return Ok(None);
+        }
+
+        let table = if mbr::is_protective(&mbr) {
+            NativeTable::Gpt(Gpt::read(disk, sector_size, sectors, &mbr)?)
+        } else {
+            NativeTable::Msdos(Mbr::read(disk, sector_size, sectors, mbr)?)
+        };
+
+        Ok(Some(table))
+    }
+
+    pub fn kind(&self) -> PartitionTable {
+        match self {
+            NativeTable::Gpt(_) => PartitionTable::Gpt,
+            NativeTable::Msdos(_) => PartitionTable::Msdos,
+        }
+    }
+
+    /// True if partitions may be added to and removed from this table, which is not the case
+    /// for MBR tables with an extended partition.
+    pub fn is_modifiable(&self) -> bool {
+        match self {
+            NativeTable::Gpt(_) => true,
+            NativeTable::Msdos(mbr) => !mbr.has_extended(),
+        }
+    }
+
+    /// The partitions of the table, ordered by their number.
+    pub fn partitions(&self) -> Vec<NativePartition> {
+        match self {
+            NativeTable::Gpt(gpt) => gpt.partitions(),
+            NativeTable::Msdos(mbr) => mbr.partitions(),
+        }
+    }
+
+    /// Adds a partition to the table, and returns its number.
+    pub fn add(&mut self, partition: &NewPartition) -> io::Result<u32> {
+        match self {
+            NativeTable::Gpt(gpt) => gpt.add(partition),
+            NativeTable::Msdos(mbr) => mbr.add(partition),
+        }
+    }
+
+    pub fn remove(&mut self, number: u32) -> io::Result<()> {
+        match self {
+            NativeTable::Gpt(gpt) => gpt.remove(number),
+            NativeTable::Msdos(mbr) => mbr.remove(number),
+        }
+    }
+
+    /// Removes the partition which contains the given sector.
+    pub fn remove_at_sector(&mut self, sector: u64) -> io::Result<()> {
+        let number = self
+            .partitions()
+            .into_iter()
+            .find(|partition| partition.start <= sector && partition.end >= sector)
+            .map(|partition| partition.number)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no partition was found at sector {}", sector),
+                )
+            })?;
+
+        self.remove(number)
+    }
+
+    /// Changes the first and last sectors of a partition.
+    pub fn resize(&mut self, number: u32, start: u64, end: u64) -> io::Result<()> {
+        match self {
+            NativeTable::Gpt(gpt) => gpt.resize(number, start, end),
+            NativeTable::Msdos(mbr) => mbr.resize(number, start, end),
+        }
+    }
+
+    /// Writes the table to a disk. The writes are not synced.
+    pub fn write<D: Write + Seek>(&self, disk: &mut D) -> io::Result<()> {
+        match self {
+            NativeTable::Gpt(gpt) => gpt.write(disk),
+            NativeTable::Msdos(mbr) => mbr.write(disk),
+        }
+    }
+}
+
+impl<'a> From<&'a PartitionCreate> for NewPartition {
+    fn from(partition: &'a PartitionCreate) -> Self {
+        NewPartition {
+            start: partition.start_sector,
+            end:   partition.end_sector,
+            fs:    partition.file_system,
+            label: partition.label.clone(),
+            flags: partition.flags.clone(),
+            kind:  partition.kind,
+        }
+    }
+}
+
+/// Implements `Partitioner` with the native backend, for block devices and disk images.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NativePartitioner;
+
+impl Partitioner for NativePartitioner {
+    fn create(
+        &mut self,
+        device: &Path,
+        data: NewPartition,
+    ) -> Result<(u32, PathBuf), PartitionError> {
+        let number = modify(device, PartitionError::CreatePartition, |table| table.add(&data))?;
+        Ok((number, partition_path(device, number)))
+    }
+
+    fn delete(&mut self, device: &Path, number: u32) -> Result<(), PartitionError> {
+        modify(device, PartitionError::RemovePartition, |table| table.remove(number))
+    }
+
+    fn resize(
+        &mut self,
+        device: &Path,
+        number: u32,
+        start: u64,
+        end: u64,
+    ) -> Result<(), PartitionError> {
+        modify(device, PartitionError::ResizePartition, |table| table.resize(number, start, end))
+    }
+}
+
+/// Reads the table of a device, if it has one.
+pub fn probe(device: &Path) -> io::Result<Option<NativeTable>> {
+    let mut file = File::open(device)?;
+    let sector_size = sector_size(&file)?;
+    NativeTable::read(&mut file, sector_size)
+}
+
+/// Writes a table to a device, and then has the kernel reread it if the device is a block
+/// device.
+pub fn commit_table(device: &Path, file: &mut File, table: &NativeTable) -> io::Result<()> {
+    info!("writing {:?} table to {}", table.kind(), device.display());
+    table.write(file)?;
+    file.sync_all()?;
+
+    if file.metadata()?.file_type().is_block_device() {
+        reread_table(device)?;
+    }
+
+    Ok(())
+}
+
+/// Has the kernel reread the partition table of a block device, trying three times before
+/// returning an error.
+pub fn reread_table(device: &Path) -> io::Result<()> {
+    for attempt in 0..3 {
+        ::std::thread::sleep(::std::time::Duration::from_secs(1));
+        match blockdev(device, &["--flushbufs", "--rereadpt"]) {
+            Ok(()) => break,
+            Err(why) if attempt == 2 => {
+                return Err(io::Error::new(
+                    why.kind(),
+                    format!("failed to synchronize disk: {}", why),
+                ));
+            }
+            Err(_) => (),
+        }
+    }
+
+    Ok(())
+}
+
+/// The logical sector size of a block device, or 512 for a disk image.
+pub fn sector_size(file: &File) -> io::Result<u64> {
+    if !file.metadata()?.file_type().is_block_device() {
+        return Ok(512);
+    }
+
+    let mut size: libc::c_int = 0;
+    if unsafe { libc::ioctl(file.as_raw_fd(), BLKSSZGET, &mut size) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(size as u64)
+}
+
+/// The path of the block device of a partition, such as `/dev/sda1` or `/dev/nvme0n1p1`.
+pub fn partition_path(device: &Path, number: u32) -> PathBuf {
+    let mut path = device.as_os_str().to_owned();
+    if device.to_string_lossy().ends_with(|c: char| c.is_ascii_digit()) {
+        path.push("p");
+    }
+
+    path.push(number.to_string());
+    PathBuf::from(path)
+}
+
+fn modify<T, F>(
+    device: &Path,
+    error: fn(io::Error) -> PartitionError,
+    func: F,
+) -> Result<T, PartitionError>
+where
+    F: FnOnce(&mut NativeTable) -> io::Result<T>,
+{
+    let mut file =
+        OpenOptions::new().read(true).write(true).open(device).map_err(PartitionError::OpenDisk)?;
+
+    let sector_size = sector_size(&file).map_err(PartitionError::OpenDisk)?;
+    let mut table = NativeTable::read(&mut file, sector_size)
+        .and_then(|table| {
+            table
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "partition table not found"))
+        })
+        .map_err(PartitionError::OpenDisk)?;
+
+    let value = func(&mut table).map_err(error)?;
+    commit_table(device, &mut file, &table).map_err(PartitionError::CommitToDisk)?;
+    Ok(value)
+}
+
+fn read_sectors<D: Read + Seek>(
+    disk: &mut D,
+    sector_size: u64,
+    lba: u64,
+    count: u64,
+) -> io::Result<Vec<u8>> {
+    let mut data = vec![0; (count * sector_size) as usize];
+    disk.seek(SeekFrom::Start(lba * sector_size))?;
+    disk.read_exact(&mut data)?;
+    Ok(data)
+}
+
+fn write_sectors<D: Write + Seek>(
+    disk: &mut D,
+    sector_size: u64,
+    lba: u64,
+    data: &[u8],
+) -> io::Result<()> {
+    disk.seek(SeekFrom::Start(lba * sector_size))?;
+    disk.write_all(data)
+}
+
+/// Checks that a partition lies within the usable sectors of a table, and does not overlap
+/// any of its other partitions.
+fn check_bounds<I: IntoIterator<Item = NativePartition>>(
+    start: u64,
+    end: u64,
+    usable: (u64, u64),
+    others: I,
+) -> io::Result<()> {
+    if start > end || start < usable.0 || end > usable.1 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "partition at {} - {} is outside of the usable sectors {} - {}",
+                start, end, usable.0, usable.1
+            ),
+        ));
+    }
+
+    for other in others {
+        if other.kind != PartitionType::Logical && start <= other.end && end >= other.start {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "partition at {} - {} overlaps partition {} at {} - {}",
+                    start, end, other.number, other.start, other.end
+                ),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn unsupported(what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Other,
+        format!("{} is not supported by the native partition table backend", what),
+    )
+}
+
+fn not_found(number: u32) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("partition {} was not found", number))
+}
+
+/// Fills a buffer with random bytes, for the identifiers of new tables and partitions.
+fn random_bytes(buffer: &mut [u8]) -> io::Result<()> {
+    File::open("/dev/urandom")?.read_exact(buffer)
+}
+
+fn le_u16(data: &[u8]) -> u16 { u16::from_le_bytes([data[0], data[1]]) }
+
+fn le_u32(data: &[u8]) -> u32 { u32::from_le_bytes([data[0], data[1], data[2], data[3]]) }
+
+fn le_u64(data: &[u8]) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&data[..8]);
+    u64::from_le_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libparted::PartitionFlag;
+    use std::io::Cursor;
+
+    /// An empty disk of 64 MiB, held in memory.
+    fn disk() -> Cursor<Vec<u8>> { Cursor::new(vec![0; 64 * 1024 * 1024]) }
+
+    fn partition(start: u64, end: u64) -> NewPartition {
+        NewPartition {
+            start,
+            end,
+            fs: None,
+            label: None,
+            flags: Vec::new(),
+            kind: PartitionType::Primary,
+        }
+    }
+
+    #[test]
+    fn empty_disk() {
+        assert_eq!(NativeTable::read(&mut disk(), 512).unwrap(), None);
+    }
+
+    #[test]
+    fn tables_round_trip() {
+        for &kind in &[PartitionTable::Gpt, PartitionTable::Msdos] {
+            let mut disk = disk();
+            let sectors = disk.get_ref().len() as u64 / 512;
+            let mut table = NativeTable::new(kind, sectors, 512).unwrap();
+
+            let esp = NewPartition {
+                fs: Some(disk_types::FileSystem::Fat32),
+                label: Some("EFI".into()),
+                flags: vec![PartitionFlag::PED_PARTITION_ESP],
+                ..partition(2048, 4095)
+            };
+            assert_eq!(table.add(&esp).unwrap(), 1);
+            assert_eq!(table.add(&partition(4096, 8191)).unwrap(), 2);
+            assert_eq!(table.add(&partition(8192, 16383)).unwrap(), 3);
+            table.remove_at_sector(5000).unwrap();
+            table.resize(3, 4096, 16383).unwrap();
+
+            table.write(&mut disk).unwrap();
+            let read = NativeTable::read(&mut disk, 512).unwrap().unwrap();
+            assert_eq!(read, table, "{:?}", kind);
+
+            let layout = read
+                .partitions()
+                .into_iter()
+                .map(|part| (part.number, part.start, part.end))
+                .collect::<Vec<_>>();
+            assert_eq!(layout, vec![(1, 2048, 4095), (3, 4096, 16383)], "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn overlaps_rejected() {
+        for &kind in &[PartitionTable::Gpt, PartitionTable::Msdos] {
+            let mut table = NativeTable::new(kind, 131_072, 512).unwrap();
+            table.add(&partition(2048, 4095)).unwrap();
+            assert!(table.add(&partition(4095, 8191)).is_err());
+            assert!(table.add(&partition(131_072, 131_073)).is_err());
+            assert!(table.resize(1, 2048, 200_000).is_err());
+            assert!(table.remove(2).is_err());
+        }
+    }
+
+    #[test]
+    fn partition_paths() {
+        assert_eq!(partition_path(Path::new("/dev/sda"), 1), Path::new("/dev/sda1"));
+        assert_eq!(partition_path(Path::new("/dev/nvme0n1"), 2), Path::new("/dev/nvme0n1p2"));
+    }
+}
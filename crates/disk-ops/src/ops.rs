@@ -1,15 +1,17 @@
 //! Contains source code for applying physical disk operations to disks.
 
 use super::*;
-use disk_types::{FileSystem, PartitionTable, PartitionType};
-use external::{blockdev, mkfs};
+use disk_types::{partitioner::NewPartition, FileSystem, PartitionTable, PartitionType};
+use external::{mkfs, wipefs};
 use libparted::{Device, Disk as PedDisk, Partition as PedPartition};
 use mkpart::PartitionCreate;
+use native::NativeTable;
 use parted::*;
 use rayon::prelude::*;
 use resize::PartitionChange;
 use std::{
-    io,
+    fs::OpenOptions,
+    io::{self, Seek, SeekFrom},
     path::{Path, PathBuf},
 };
 
@@ -34,6 +36,9 @@ pub struct DiskOps<'a> {
     pub remove_partitions: Vec<u64>,
     pub change_partitions: Vec<PartitionChange>,
     pub create_partitions: Vec<PartitionCreate>,
+    /// Where the journals of partition moves are kept, so that an interrupted move may be
+    /// resumed. This should be on persistent storage that is not being partitioned.
+    pub move_journal:      Option<PathBuf>,
 }
 
 impl<'a> DiskOps<'a> {
@@ -44,6 +49,74 @@ impl<'a> DiskOps<'a> {
             && self.create_partitions.is_empty()
     }
 
+    /// True if these operations can be performed by the native partition table backend,
+    /// rather than by libparted. Existing partitions must not be changed, only primary
+    /// partitions may be created, and MBR tables must not have an extended partition.
+    pub fn is_native(&self) -> bool {
+        if !self.change_partitions.is_empty()
+            || self.create_partitions.iter().any(|part| part.kind != PartitionType::Primary)
+        {
+            return false;
+        }
+
+        self.mklabel.is_some()
+            || match native::probe(self.device_path) {
+                Ok(Some(table)) => table.is_modifiable(),
+                _ => false,
+            }
+    }
+
+    /// Performs every operation with the native partition table backend, writing the table
+    /// to the disk once, and returns the partitions which are to be formatted.
+    pub fn commit_native(self) -> io::Result<FormatPartitions> {
+        info!("{}: executing operations natively", self.device_path.display());
+
+        if self.mklabel.is_some() {
+            let _ = wipefs(self.device_path);
+        }
+
+        let mut file = OpenOptions::new().read(true).write(true).open(self.device_path)?;
+        let sector_size = native::sector_size(&file)?;
+        let mut table = match self.mklabel {
+            Some(kind) => {
+                info!("writing {:?} table on {}", kind, self.device_path.display());
+                let sectors = file.seek(SeekFrom::End(0))? / sector_size;
+                NativeTable::new(kind, sectors, sector_size)?
+            }
+            None => NativeTable::read(&mut file, sector_size)?.ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("partition table not found on {}", self.device_path.display()),
+                )
+            })?,
+        };
+
+        for &sector in &self.remove_partitions {
+            info!("removing partition at sector {} on {}", sector, self.device_path.display());
+            table.remove_at_sector(sector)?;
+        }
+
+        let mut created = Vec::new();
+        for partition in &self.create_partitions {
+            info!("creating partition ({:?}) on {}", partition, self.device_path.display());
+            let number = table.add(&NewPartition::from(partition))?;
+            created.push((number, partition.file_system));
+        }
+
+        native::commit_table(self.device_path, &mut file, &table)?;
+
+        // Partitions without a file system, such as members of a RAID array, are left
+        // unformatted.
+        let format = created
+            .into_iter()
+            .filter_map(|(number, fs)| {
+                fs.map(|fs| (native::partition_path(self.device_path, number), fs))
+            })
+            .collect();
+
+        Ok(FormatPartitions(format))
+    }
+
     /// The first stage of disk operations, where a new partition table may be
     /// generated
     pub fn remove(self) -> io::Result<ChangePartitions<'a>> {
@@ -75,6 +148,7 @@ impl<'a> DiskOps<'a> {
             device_path:       self.device_path,
             change_partitions: self.change_partitions,
             create_partitions: self.create_partitions,
+            move_journal:      self.move_journal,
         })
     }
 }
@@ -85,12 +159,20 @@ pub struct ChangePartitions<'a> {
     device_path:       &'a Path,
     change_partitions: Vec<PartitionChange>,
     create_partitions: Vec<PartitionCreate>,
+    move_journal:      Option<PathBuf>,
 }
 
 impl<'a> ChangePartitions<'a> {
     /// The second stage of disk operations, where existing partitions will be
     /// modified.
-    pub fn change(self) -> io::Result<CreatePartitions<'a>> {
+    pub fn change(self) -> io::Result<CreatePartitions<'a>> { self.change_with(|_, _| ()) }
+
+    /// Modifies existing partitions, giving the progress of each partition move to
+    /// `move_progress`, as the number of sectors copied so far, and the number to copy.
+    pub fn change_with<F: FnMut(u64, u64)>(
+        self,
+        mut move_progress: F,
+    ) -> io::Result<CreatePartitions<'a>> {
         info!("{}: executing change operations", self.device_path.display(),);
 
         let mut device = open_device(self.device_path)?;
@@ -165,6 +247,8 @@ impl<'a> ChangePartitions<'a> {
             transform(
                 change,
                 resize_op,
+                self.move_journal.as_deref(),
+                &mut move_progress,
                 // This is the delete function.
                 |partition| {
                     let mut disk = open_disk(unsafe { &mut (*device) })?;
@@ -227,30 +311,17 @@ impl<'a> CreatePartitions<'a> {
             }
 
             if partition.kind != PartitionType::Extended {
-                // Open a second instance of the disk which we need to get the new partition ID.
-                let path = get_partition_id(self.device_path, partition.start_sector as i64)?;
-                self.format_partitions.push((
-                    path,
-                    partition
-                        .file_system
-                        .expect("file system does not exist when creating partition"),
-                ));
-            }
-        }
-
-        // Attempt to sync three times before returning an error.
-        for attempt in 0..3 {
-            ::std::thread::sleep(::std::time::Duration::from_secs(1));
-            let result = blockdev(self.device_path, &["--flushbufs", "--rereadpt"]);
-            if result.is_err() && attempt == 2 {
-                result.map_err(|why| {
-                    io::Error::new(why.kind(), format!("failed to synchronize disk: {}", why))
-                })?
-            } else {
-                break;
+                // Partitions without a file system, such as members of a RAID array, are left
+                // unformatted.
+                if let Some(fs) = partition.file_system {
+                    // Open a second instance of the disk which we need to get the new partition ID.
+                    let path = get_partition_id(self.device_path, partition.start_sector as i64)?;
+                    self.format_partitions.push((path, fs));
+                }
             }
         }
 
+        native::reread_table(self.device_path)?;
         Ok(FormatPartitions(self.format_partitions))
     }
 }
@@ -290,11 +361,12 @@ impl FormatPartitions {
     /// Finally, format all of the modified and created partitions.
     pub fn format(self) -> io::Result<()> {
         info!("executing format operations");
+        let context = executor::Context::current();
         self.0
             .par_iter()
             .map(|&(ref part, fs)| {
                 info!("formatting {} with {:?}", part.display(), fs);
-                mkfs(part, fs).map_err(|why| {
+                context.enter(|| mkfs(part, fs)).map_err(|why| {
                     io::Error::new(
                         why.kind(),
                         format!("failed to format {} with {}: {}", part.display(), fs, why),
@@ -1,6 +1,20 @@
 use bootloader::Bootloader;
 use libparted::{Device, Disk as PedDisk, DiskType as PedDiskType};
-use std::{io, path::Path};
+use std::{
+    io,
+    path::Path,
+    sync::{Mutex, MutexGuard},
+};
+
+lazy_static! {
+    static ref LIBPARTED: Mutex<()> = Mutex::new(());
+}
+
+/// Serializes the use of libparted, which is not thread safe, so that disks which are
+/// modified by the native partition table backend may be committed in parallel with others.
+pub fn lock() -> MutexGuard<'static, ()> {
+    LIBPARTED.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
 
 /// Gets a `libparted::Device` from the given name.
 pub fn get_device<'a, P: AsRef<Path>>(name: P) -> io::Result<Device<'a>> {
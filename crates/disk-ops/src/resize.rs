@@ -1,12 +1,15 @@
 use self::FileSystem::*;
-use super::{move_partition, BlockCoordinates, OffsetCoordinates, MEBIBYTE, MEGABYTE};
-use disk_types::{FileSystem, PartitionType};
-use external::{blockdev, fsck};
+use super::{
+    move_partition_with, BlockCoordinates, MoveJournal, MoveOptions, OffsetCoordinates, MEBIBYTE,
+    MEGABYTE,
+};
+use disk_types::{partitioner::NewPartition, FileSystem, PartitionType};
+use external::{blockdev, fsck, fsck_before_resize};
+use executor::{self, Invocation, Redirect};
 use libparted::PartitionFlag;
 use std::{
-    io::{self, Write},
+    io,
     path::{Path, PathBuf},
-    process::{Command, Stdio},
 };
 use sys_mount::*;
 use tempdir::TempDir;
@@ -21,6 +24,8 @@ pub const BTRFS: u8 = 0b100;
 pub const XFS: u8 = 0b1000;
 /// This is a NTFS partition.
 pub const NTFS: u8 = 0b10000;
+/// This is an ext2, ext3, or ext4 partition.
+pub const EXT: u8 = 0b100000;
 
 /// Defines the unit of measurement to pass on to resizing tools.
 ///
@@ -108,10 +113,7 @@ pub fn resize_partition<P: AsRef<Path>>(
 ) -> io::Result<()> {
     info!("resizing {} to {}", path.as_ref().display(), size);
 
-    let mut resize_cmd = Command::new(cmd);
-    if !args.is_empty() {
-        resize_cmd.args(args);
-    }
+    let resize_cmd = Invocation::new(cmd).args(args);
 
     // Attempt to sync three times before returning an error.
     for attempt in 0..3 {
@@ -124,15 +126,13 @@ pub fn resize_partition<P: AsRef<Path>>(
         }
     }
 
-    let fsck_options = if options & BTRFS != 0 {
-        Some(("btrfsck", "--repair"))
-    } else if options & XFS != 0 {
-        Some(("xfs_repair", "-v"))
-    } else {
-        None
-    };
+    // resize2fs refuses to resize a file system that has not been checked since it was last
+    // mounted. No other file system is checked or repaired here, as `transform` has already
+    // rejected any file system that `fsck_before_resize` found errors on.
+    let checked =
+        if options & EXT != 0 { fsck(path.as_ref(), Some(("e2fsck", "-f"))) } else { Ok(()) };
 
-    fsck(path.as_ref(), fsck_options).and_then(|_| {
+    checked.and_then(|_| {
         // Btrfs is a strange case that needs resize operations to be performed while
         // it is mounted.
         let (npath, _mount) = if options & (BTRFS | XFS) != 0 {
@@ -145,32 +145,34 @@ pub fn resize_partition<P: AsRef<Path>>(
             (path.as_ref().to_path_buf(), None)
         };
 
-        if options & NO_SIZE != 0 {
-            resize_cmd.arg(&npath);
+        let resize_cmd = if options & NO_SIZE != 0 {
+            resize_cmd.arg(&npath)
         } else if options & SIZE_BEFORE_PATH != 0 {
-            resize_cmd.arg(size).arg(&npath);
+            resize_cmd.arg(size).arg(&npath)
         } else {
-            resize_cmd.arg(&npath).arg(size);
+            resize_cmd.arg(&npath).arg(size)
         };
 
-        let status = if options & NTFS != 0 {
+        let resize_cmd = if options & NTFS != 0 {
             ntfs_dry_run(&npath, size)?;
-
-            info!("executing {:?}", resize_cmd);
-            let mut child = resize_cmd.stdin(Stdio::piped()).spawn()?;
-            child.stdin.as_mut().expect("failed to get stdin").write_all(b"y\n")?;
-            child.wait()?
+            resize_cmd.stdin(&b"y\n"[..])
         } else {
-            info!("executing {:?}", resize_cmd);
-            resize_cmd.status()?
+            resize_cmd
         };
 
-        if status.success() {
+        info!("executing {}", resize_cmd);
+        let status = run(&resize_cmd)?;
+
+        if status == Some(0) {
             Ok(())
         } else {
             Err(io::Error::new(
                 io::ErrorKind::Other,
-                format!("resize for {:?} failed with status: {}", path.as_ref().display(), status),
+                format!(
+                    "resize for {:?} failed with status: {}",
+                    path.as_ref().display(),
+                    status_string(status)
+                ),
             ))
         }
     })
@@ -212,13 +214,20 @@ pub struct PartitionChange {
 }
 
 /// Performs all move & resize operations for a given partition.
-pub fn transform<DELETE, CREATE>(
+///
+/// Moves are journaled to `move_journal`, if it is given, so that they may be finished with
+/// `resume_move` should they be interrupted. Their progress is given to `move_progress`, as
+/// the number of sectors copied so far, and the number of sectors to copy.
+pub fn transform<PROGRESS, DELETE, CREATE>(
     mut change: PartitionChange,
     mut resize: ResizeOperation,
+    move_journal: Option<&Path>,
+    mut move_progress: PROGRESS,
     mut delete: DELETE,
     mut create: CREATE,
 ) -> io::Result<()>
 where
+    PROGRESS: FnMut(u64, u64),
     DELETE: FnMut(u32) -> io::Result<()>,
     CREATE: FnMut(
         u64,
@@ -248,7 +257,7 @@ where
             BTRFS | SIZE_BEFORE_PATH,
         ),
         Some(Ext2) | Some(Ext3) | Some(Ext4) => {
-            ("resize2fs", &[], ResizeUnit::AbsoluteSectorsWithUnit, 0)
+            ("resize2fs", &[], ResizeUnit::AbsoluteSectorsWithUnit, EXT)
         }
         // Some(Exfat) => (),
         // Some(F2fs) => ("resize.f2fs"),
@@ -275,6 +284,13 @@ where
         fs => unimplemented!("{:?} handling", fs),
     };
 
+    // A file system with errors may lose data when it is resized or moved, so any errors
+    // must be repaired before it is touched.
+    if let Some(fs) = change.filesystem {
+        let report = fsck_before_resize(&change.path, fs)?;
+        info!("{}: file system is {:?}", change.path.display(), report.status);
+    }
+
     let fs = match change.filesystem {
         Some(Fat16) | Some(Fat32) => "vfat",
         Some(fs) => fs.into(),
@@ -326,12 +342,14 @@ where
             let abs_sectors = resize.absolute_sectors();
             resize.old.resize_to(abs_sectors); // TODO: NLL
 
-            move_partition(&change.device_path, resize.offset(), 512).map_err(|why| {
-                io::Error::new(
-                    why.kind(),
-                    format!("failed to move partition at {}: {}", change.path.display(), why),
-                )
-            })?;
+            journaled_move(&change, resize.offset(), move_journal, &mut move_progress).map_err(
+                |why| {
+                    io::Error::new(
+                        why.kind(),
+                        format!("failed to move partition at {}: {}", change.path.display(), why),
+                    )
+                },
+            )?;
 
             moving = false;
         }
@@ -345,6 +363,10 @@ where
             change.kind,
         )?;
 
+        if let Some(journal) = move_journal {
+            MoveJournal::remove(journal)?;
+        }
+
         change.num = num;
         change.path = path;
 
@@ -366,12 +388,14 @@ where
         let abs_sectors = resize.absolute_sectors();
         resize.old.resize_to(abs_sectors); // TODO: NLL
 
-        move_partition(&change.device_path, resize.offset(), 512).map_err(|why| {
-            io::Error::new(
-                why.kind(),
-                format!("failed to move partition at {}: {}", change.path.display(), why),
-            )
-        })?;
+        journaled_move(&change, resize.offset(), move_journal, &mut move_progress).map_err(
+            |why| {
+                io::Error::new(
+                    why.kind(),
+                    format!("failed to move partition at {}: {}", change.path.display(), why),
+                )
+            },
+        )?;
 
         create(
             resize.new.start,
@@ -381,39 +405,83 @@ where
             change.label,
             change.kind,
         )?;
+
+        if let Some(journal) = move_journal {
+            MoveJournal::remove(journal)?;
+        }
     }
 
     Ok(())
 }
 
-fn ntfs_dry_run(path: &Path, size: &str) -> io::Result<()> {
-    let mut consistency_check = Command::new("ntfsresize");
-    consistency_check.args(&["-f", "-f", "--no-action", "-s"]).arg(size).arg(path);
+/// Moves a partition with a journal of its progress and of the table entry that it is given
+/// afterwards, so that the move may be resumed if the installer is interrupted. The progress
+/// of the move is logged and given to `progress` at each percent. The journal is removed once
+/// the entry is created.
+fn journaled_move<F: FnMut(u64, u64)>(
+    change: &PartitionChange,
+    coords: OffsetCoordinates,
+    journal: Option<&Path>,
+    mut progress: F,
+) -> io::Result<()> {
+    if journal.is_none() {
+        warn!("moving {} without a journal, so the move cannot be resumed", change.path.display());
+    }
 
-    info!("executing {:?}", consistency_check);
-    let mut child = consistency_check.stdin(Stdio::piped()).spawn()?;
-    child.stdin.as_mut().expect("failed to get stdin").write_all(b"y\n")?;
+    // The sectors of the entry are those that the partition is moved to.
+    let entry = NewPartition {
+        start: 0,
+        end:   0,
+        fs:    change.filesystem,
+        label: change.label.clone(),
+        flags: change.new_flags.clone(),
+        kind:  change.kind,
+    };
 
-    let status = child.wait()?;
-    if status.success() {
-        Ok(())
-    } else {
-        Err(io::Error::new(io::ErrorKind::Other, format!("ntfsresize exited with {:?}", status)))
-    }
+    let options = MoveOptions {
+        journal: journal.map(Path::to_path_buf),
+        entry: Some(entry),
+        ..MoveOptions::default()
+    };
+
+    let mut last = None;
+    move_partition_with(&change.device_path, coords, 512, &options, |copied, total| {
+        let percent = copied * 100 / total.max(1);
+        if last != Some(percent) {
+            last = Some(percent);
+            info!("moved {}% of the partition ({} of {} sectors)", percent, copied, total);
+            progress(copied, total);
+        }
+    })
 }
 
-fn ntfs_consistency_check(path: &Path) -> io::Result<()> {
-    let mut consistency_check = Command::new("ntfsresize");
-    consistency_check.args(&["-i", "-f"]).arg(path);
+fn ntfs_dry_run(path: &Path, size: &str) -> io::Result<()> {
+    ntfsresize(
+        Invocation::new("ntfsresize").args(&["-f", "-f", "--no-action", "-s"]).arg(size).arg(path),
+    )
+}
 
-    info!("executing {:?}", consistency_check);
-    let mut child = consistency_check.stdin(Stdio::piped()).spawn()?;
-    child.stdin.as_mut().expect("failed to get stdin").write_all(b"y\n")?;
+fn ntfsresize(consistency_check: Invocation) -> io::Result<()> {
+    let consistency_check = consistency_check.stdin(&b"y\n"[..]);
+    info!("executing {}", consistency_check);
 
-    let status = child.wait()?;
-    if status.success() {
+    let status = run(&consistency_check)?;
+    if status == Some(0) {
         Ok(())
     } else {
-        Err(io::Error::new(io::ErrorKind::Other, format!("ntfsresize exited with {:?}", status)))
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("ntfsresize exited with {}", status_string(status)),
+        ))
     }
 }
+
+/// Executes the command with its output inherited, and returns the exit code.
+fn run(invocation: &Invocation) -> io::Result<Option<i32>> {
+    let invocation = invocation.clone().stdout(Redirect::Inherit).stderr(Redirect::Inherit);
+    executor::output(&invocation).map(|output| output.code)
+}
+
+fn status_string(status: Option<i32>) -> String {
+    status.map_or_else(|| "signal".into(), |code| code.to_string())
+}
@@ -1,16 +1,81 @@
 use crate::fs::FileSystem;
-use std::io;
-use std::path::Path;
-use std::process::{Command, ExitStatus, Stdio};
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
 
+/// The outcome of checking a file system.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FsckStatus {
+    /// No errors were found.
+    Clean,
+    /// Errors were found and have since been corrected.
+    Repaired,
+    /// Errors were found which have not been corrected.
+    NeedsRepair,
+    /// The file system cannot be checked.
+    Unsupported,
+}
+
+/// The parsed result of a file system check.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct FsckReport {
+    pub status: FsckStatus,
+    /// The exit code of the last command that was executed, if any.
+    pub code:   Option<i32>,
+    /// The combined stdout and stderr of the last command that was executed.
+    pub output: String,
+}
+
+impl FsckReport {
+    /// A report for a file system which has no check command.
+    pub fn unsupported() -> Self {
+        FsckReport { status: FsckStatus::Unsupported, code: None, output: String::new() }
+    }
+
+    /// Whether the file system may be safely modified.
+    pub fn is_consistent(&self) -> bool {
+        match self.status {
+            FsckStatus::Clean | FsckStatus::Repaired | FsckStatus::Unsupported => true,
+            FsckStatus::NeedsRepair => false,
+        }
+    }
+}
+
+/// Checks, and where possible repairs, the file system on a block device.
 pub trait Fscker {
-    fn fsck(path: &Path, fs: FileSystem) -> Result<(), FsckError>;
+    /// Checks the file system without modifying it.
+    fn check(path: &Path, fs: FileSystem) -> Result<FsckReport, FsckError>;
+
+    /// Repairs the file system, reporting on its state after the repair.
+    fn repair(path: &Path, fs: FileSystem) -> Result<FsckReport, FsckError>;
 }
 
 #[derive(Debug, Error)]
 pub enum FsckError {
-    #[error(display = "fsck I/O error: {:?}", _0)]
+    #[error(display = "fsck I/O error: {}", _0)]
     Io(io::Error),
-    #[error(display = "command failed with exit status: {}", _0)]
-    BadStatus(ExitStatus),
+    #[error(display = "{} failed with status {:?}: {}", command, status, output)]
+    BadStatus { command: &'static str, status: Option<i32>, output: String },
+    #[error(
+        display = "{:?} file system at {:?} has errors which must be repaired manually: {}",
+        fs,
+        path,
+        output
+    )]
+    NeedsRepair { path: PathBuf, fs: FileSystem, output: String },
+}
+
+impl From<io::Error> for FsckError {
+    fn from(why: io::Error) -> Self { FsckError::Io(why) }
+}
+
+impl From<FsckError> for io::Error {
+    fn from(why: FsckError) -> Self {
+        match why {
+            FsckError::Io(why) => why,
+            why => io::Error::new(io::ErrorKind::Other, why),
+        }
+    }
 }
@@ -2,7 +2,8 @@ use std::{fmt, str::FromStr};
 use sys_mount::FilesystemType as MountFS;
 
 /// Describes a file system format, such as ext4 or fat32.
-#[derive(Debug, PartialEq, Copy, Clone, Hash)]
+#[derive(Debug, PartialEq, Copy, Clone, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum FileSystem {
     Btrfs,
     Exfat,
@@ -1,11 +1,17 @@
+extern crate distinst_executor;
 #[macro_use]
 extern crate err_derive;
 #[macro_use]
 extern crate log;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
 
+pub mod checker;
 mod device;
 mod fs;
 mod partition;
+pub mod partitioner;
 mod sector;
 mod table;
 mod usage;
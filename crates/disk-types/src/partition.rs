@@ -5,7 +5,7 @@ use crate::{
     usage::sectors_used,
 };
 use libparted::PartitionFlag;
-use os_detect::{detect_os_from_device, OS};
+use os_detect::{detect_os_from_device, mount_for_probe, OS};
 use std::{io, path::Path};
 use sys_mount::*;
 use tempdir::TempDir;
@@ -54,7 +54,8 @@ pub trait PartitionExt: BlockDeviceExt + SectorExt {
     /// True if the partition is a swap partition.
     fn is_swap(&self) -> bool { self.get_file_system().map_or(false, |fs| fs == FileSystem::Swap) }
 
-    /// Mount the file system at a temporary directory, and allow the caller to scan it.
+    /// Mount the file system read-only at a temporary directory, and allow the caller to
+    /// scan it.
     fn probe<T, F>(&self, mut func: F) -> T
     where
         F: FnMut(Option<(&Path, UnmountDrop<Mount>)>) -> T,
@@ -63,15 +64,10 @@ pub trait PartitionExt: BlockDeviceExt + SectorExt {
             self.get_file_system().and_then(|fs| TempDir::new("distinst").ok().map(|t| (fs, t)));
 
         if let Some((fs, tempdir)) = mount {
-            let fs = match fs {
-                FileSystem::Fat16 | FileSystem::Fat32 => "vfat",
-                fs => fs.into(),
-            };
-
             // Mount the FS to the temporary directory
             let base = tempdir.path();
-            if let Ok(m) = Mount::new(self.get_device_path(), base, fs, MountFlags::empty(), None) {
-                return func(Some((base, m.into_unmount_drop(UnmountFlags::DETACH))));
+            if let Ok(m) = mount_for_probe(self.get_device_path(), base, mount_type(fs), None) {
+                return func(Some((base, m)));
             }
         }
 
@@ -81,7 +77,8 @@ pub trait PartitionExt: BlockDeviceExt + SectorExt {
     /// Detects if an OS is installed to this partition, and if so, what the OS
     /// is named.
     fn probe_os(&self) -> Option<OS> {
-        self.get_file_system().and_then(|fs| detect_os_from_device(self.get_device_path(), fs))
+        self.get_file_system()
+            .and_then(|fs| detect_os_from_device(self.get_device_path(), mount_type(fs)))
     }
 
     /// True if the sectors in the compared partition differs from the source.
@@ -119,18 +116,60 @@ pub trait PartitionExt: BlockDeviceExt + SectorExt {
     }
 }
 
+/// The file system type that the kernel is given when mounting the file system.
+fn mount_type(fs: FileSystem) -> &'static str {
+    match fs {
+        Fat16 | Fat32 => "vfat",
+        fs => fs.into(),
+    }
+}
+
 /// Defines whether the partition is a primary, logical, or extended partition.
 ///
 /// # Note
 ///
 /// This only applies for MBR partition tables.
-#[derive(Debug, PartialEq, Clone, Copy, Hash)]
+#[derive(Debug, PartialEq, Clone, Copy, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum PartitionType {
     Primary,
     Logical,
     Extended,
 }
 
+/// The names of partition flags, as they are written in plans, on the command line, and in the
+/// journals of partition moves.
+const PARTITION_FLAGS: &[(&str, PartitionFlag)] = &[
+    ("esp", PartitionFlag::PED_PARTITION_ESP),
+    ("boot", PartitionFlag::PED_PARTITION_BOOT),
+    ("root", PartitionFlag::PED_PARTITION_ROOT),
+    ("swap", PartitionFlag::PED_PARTITION_SWAP),
+    ("hidden", PartitionFlag::PED_PARTITION_HIDDEN),
+    ("raid", PartitionFlag::PED_PARTITION_RAID),
+    ("lvm", PartitionFlag::PED_PARTITION_LVM),
+    ("bios_grub", PartitionFlag::PED_PARTITION_BIOS_GRUB),
+    ("lba", PartitionFlag::PED_PARTITION_LBA),
+    ("hpservice", PartitionFlag::PED_PARTITION_HPSERVICE),
+    ("palo", PartitionFlag::PED_PARTITION_PALO),
+    ("prep", PartitionFlag::PED_PARTITION_PREP),
+    ("msft_reserved", PartitionFlag::PED_PARTITION_MSFT_RESERVED),
+    ("apple_tv_recovery", PartitionFlag::PED_PARTITION_APPLE_TV_RECOVERY),
+    ("diag", PartitionFlag::PED_PARTITION_DIAG),
+    ("legacy_boot", PartitionFlag::PED_PARTITION_LEGACY_BOOT),
+    ("msft_data", PartitionFlag::PED_PARTITION_MSFT_DATA),
+    ("irst", PartitionFlag::PED_PARTITION_IRST),
+];
+
+/// Converts the name of a partition flag into its libparted equivalent.
+pub fn partition_flag_from_str(flag: &str) -> Option<PartitionFlag> {
+    PARTITION_FLAGS.iter().find(|&&(name, _)| name == flag).map(|&(_, flag)| flag)
+}
+
+/// Obtains the name of a partition flag, which is the inverse of `partition_flag_from_str`.
+pub fn partition_flag_to_str(flag: PartitionFlag) -> Option<&'static str> {
+    PARTITION_FLAGS.iter().find(|&&(_, value)| value == flag).map(|&(name, _)| name)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -158,17 +197,21 @@ mod tests {
     }
 
     impl BlockDeviceExt for Fake {
-        fn get_device_name(&self) -> &str { "fictional" }
+        fn get_device_name(&self) -> String { "fictional".into() }
 
         fn get_device_path(&self) -> &Path { Path::new("/dev/fictional")  }
     }
 
+    impl SectorExt for Fake {
+        fn get_sectors(&self) -> u64 { self.end_sector - self.start_sector + 1 }
+    }
+
     impl PartitionExt for Fake {
         fn get_file_system(&self) -> Option<FileSystem> { self.filesystem }
 
         fn get_partition_flags(&self) -> &[PartitionFlag] { &self.flags }
 
-        fn get_partition_label(&self) -> Option<&str> { self.name.as_ref().map(|s| s.as_str()) }
+        fn get_partition_label(&self) -> Option<&str> { self.name.as_deref() }
 
         fn get_partition_type(&self) -> PartitionType { self.part_type }
 
@@ -1,15 +1,20 @@
 use crate::{FileSystem, PartitionType};
 use libparted::PartitionFlag;
-use std::io;
-use std::path::{Path, PathBuf};
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
 
+/// A partition to be added to the partition table of a device.
+#[derive(Debug, Clone, PartialEq)]
 pub struct NewPartition {
     pub start: u64,
-    pub end: u64,
-    pub fs: Option<FileSystem>,
+    /// The last sector of the partition, which is inclusive.
+    pub end:   u64,
+    pub fs:    Option<FileSystem>,
     pub label: Option<String>,
     pub flags: Vec<PartitionFlag>,
-    pub kind: PartitionType,
+    pub kind:  PartitionType,
 }
 
 #[derive(Debug, Error)]
@@ -22,13 +27,31 @@ pub enum PartitionError {
     CommitToDisk(io::Error),
     #[error(display = "failed to create partition: {}", _0)]
     CreatePartition(io::Error),
+    #[error(display = "failed to resize partition: {}", _0)]
+    ResizePartition(io::Error),
     #[error(display = "failed to retrieve new partition info: {}", _0)]
-    GetNewData(io::Error)
-
+    GetNewData(io::Error),
 }
 
+/// Modifies the partition table of a device, committing each change as it is made.
 pub trait Partitioner {
-    fn create(&mut self, device: &Path, data: NewPartition) -> Result<(), PartitionError>;
+    /// Adds a partition to the table, returning its number and the path of its block device.
+    fn create(
+        &mut self,
+        device: &Path,
+        data: NewPartition,
+    ) -> Result<(u32, PathBuf), PartitionError>;
+
+    /// Removes the partition with the given number from the table.
+    fn delete(&mut self, device: &Path, number: u32) -> Result<(), PartitionError>;
 
-    fn delete(&mut self, device: &Path, number: u32) -> Result<Result<(Option<u32>, PathBuf), PartitionError>, PartitionError>;
+    /// Changes the first and last sectors of a partition in the table, without touching the
+    /// sectors that it occupies.
+    fn resize(
+        &mut self,
+        device: &Path,
+        number: u32,
+        start: u64,
+        end: u64,
+    ) -> Result<(), PartitionError>;
 }
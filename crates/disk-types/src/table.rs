@@ -1,7 +1,8 @@
 use crate::{device::BlockDeviceExt, partition::PartitionType};
 
 /// Specifies whether the partition table on the disk is **MSDOS** or **GPT**.
-#[derive(Debug, PartialEq, Clone, Copy, Hash)]
+#[derive(Debug, PartialEq, Clone, Copy, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum PartitionTable {
     Msdos,
     Gpt,
@@ -58,7 +59,7 @@ mod tests {
     }
 
     impl BlockDeviceExt for FictionalBlock {
-        fn get_device_name(&self) -> &str { "fictional" }
+        fn get_device_name(&self) -> String { "fictional".into() }
 
         fn get_device_path(&self) -> &Path { Path::new("/dev/fictional")  }
 
@@ -1,82 +1,40 @@
 use crate::fs::FileSystem;
+use distinst_executor::{self as executor, Invocation, Output, Redirect};
 use std::{
     io::{self, BufRead, Cursor},
     path::Path,
-    process::{Command, Stdio},
 };
 
 /// Executes a given file system's dump command to obtain the minimum shrink
 /// size
 pub fn sectors_used<P: AsRef<Path>>(part: P, fs: FileSystem) -> io::Result<u64> {
     use self::FileSystem::*;
+    let part = part.as_ref();
     match fs {
         Ext2 | Ext3 | Ext4 => {
-            let reader = Cursor::new(
-                Command::new("dumpe2fs")
-                    .arg("-h")
-                    .arg(part.as_ref())
-                    .stdout(Stdio::piped())
-                    .stderr(Stdio::null())
-                    .output()?
-                    .stdout,
-            );
-
+            let reader = Cursor::new(dump("dumpe2fs", &["-h"], part)?.stdout);
             get_ext4_usage(reader.lines().skip(1))
         }
         Fat16 | Fat32 => {
-            let mut cmd = Command::new("fsck.fat")
-                .arg("-nv")
-                .arg(part.as_ref())
-                .stdout(Stdio::piped())
-                .stderr(Stdio::null())
-                .output()?;
-
-            if !cmd.status.success() {
-                // If a failure occurred, try to correct any fixable errors.
-                Command::new("fsck.fat")
-                    .arg("-fy")
-                    .arg(part.as_ref())
-                    .stdout(Stdio::piped())
-                    .stderr(Stdio::null())
-                    .output()?;
-
-                // Then re-run the fsck command to get the status again.
-                cmd = Command::new("fsck.fat")
-                    .arg("-nv")
-                    .arg(part.as_ref())
-                    .stdout(Stdio::piped())
-                    .stderr(Stdio::null())
-                    .output()?;
-            }
-
+            // The summary is reported even when errors are found, which are never corrected
+            // here, as probing must not write to the device.
+            let cmd = dump("fsck.fat", &["-nv"], part)?;
             let reader = Cursor::new(cmd.stdout);
             get_fat_usage(reader.lines().skip(1))
         }
         Ntfs => {
-            let cmd = Command::new("ntfsresize")
-                .arg("--info")
-                .arg("--force")
-                .arg("--no-progress-bar")
-                .arg(part.as_ref())
-                .stdout(Stdio::piped())
-                .stderr(Stdio::null())
-                .output()?;
+            let cmd = dump("ntfsresize", &["--info", "--force", "--no-progress-bar"], part)?;
 
+            let success = cmd.success();
             let reader = Cursor::new(cmd.stdout).lines().skip(1);
-            if cmd.status.success() {
+            if success {
                 get_ntfs_usage(reader)
             } else {
                 get_ntfs_size(reader)
             }
         }
         Btrfs => {
-            let cmd = Command::new("btrfs")
-                .arg("filesystem")
-                .arg("show")
-                .arg(part.as_ref())
-                .stdout(Stdio::piped())
-                .stderr(Stdio::null())
-                .output()?;
+            let cmd = dump("btrfs", &["filesystem", "show"], part)?;
 
             let reader = Cursor::new(cmd.stdout).lines().skip(1);
             get_btrfs_usage(reader)
@@ -85,6 +43,11 @@ pub fn sectors_used<P: AsRef<Path>>(part: P, fs: FileSystem) -> io::Result<u64>
     }
 }
 
+/// Executes a dump command on the partition, and captures its stdout.
+fn dump(cmd: &str, args: &[&str], part: &Path) -> io::Result<Output> {
+    executor::output(&Invocation::new(cmd).args(args).arg(part).stderr(Redirect::Null))
+}
+
 fn get_btrfs_usage<R: Iterator<Item = io::Result<String>>>(mut reader: R) -> io::Result<u64> {
     parse_field_as_unit(&mut reader, "Total devices", 6).map(|used| used / 512)
 }
@@ -231,6 +194,8 @@ fn parse_field_as_unit<R: Iterator<Item = io::Result<String>>>(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use distinst_executor::{with_executor, RecordingExecutor};
+    use std::sync::Arc;
 
     const FAT_INPUT: &str = r#"fsck.fat 4.1 (2017-01-24)
 Checking we can access the last sector of the filesystem
@@ -429,4 +394,33 @@ Please make a test run using both the -n and -s options before real resizing!"#;
         let reader = BTRFS_INPUT.lines().map(|x| Ok(x.into()));
         assert_eq!(get_btrfs_usage(reader).unwrap(), 224);
     }
+
+    #[test]
+    fn fat_usage_with_errors() {
+        let recorder = Arc::new(RecordingExecutor::new());
+        recorder.reply("fsck.fat", Output::status(1).with_stdout(FAT_INPUT));
+
+        let used = with_executor(recorder.clone(), || sectors_used("/dev/sdb1", FileSystem::Fat32));
+        assert_eq!(used.unwrap(), 8);
+        assert_eq!(recorder.command_lines(), vec!["fsck.fat -nv /dev/sdb1"]);
+    }
+
+    #[test]
+    fn usage_probes_are_read_only() {
+        let matrix = [
+            (FileSystem::Ext4, "dumpe2fs -h /dev/sdb1"),
+            (FileSystem::Fat16, "fsck.fat -nv /dev/sdb1"),
+            (FileSystem::Fat32, "fsck.fat -nv /dev/sdb1"),
+            (FileSystem::Ntfs, "ntfsresize --info --force --no-progress-bar /dev/sdb1"),
+            (FileSystem::Btrfs, "btrfs filesystem show /dev/sdb1"),
+        ];
+
+        for &(fs, command) in &matrix {
+            // A check which finds errors must not be followed by a repair.
+            let recorder = Arc::new(RecordingExecutor::new());
+            recorder.reply("fsck.fat", Output::status(1));
+            with_executor(recorder.clone(), || sectors_used("/dev/sdb1", fs)).unwrap_err();
+            assert_eq!(recorder.command_lines(), vec![command], "{:?}", fs);
+        }
+    }
 }
@@ -88,7 +88,7 @@ pub fn detect_fs_on_device(path: &Path) -> Option<PartitionInfo> {
 /// # Note
 ///
 /// The `device_path` field may be used for identification of the device in the system.
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct Disk {
     /// The model name of the device, assigned by the manufacturer.
     pub model_name:  String,
@@ -270,6 +270,8 @@ impl Disk {
                 || x.bitflags & FORMAT != 0
                 || x.target.is_some()
                 || x.volume_group.is_some()
+                || x.raid.is_some()
+                || x.esp_mirror
         })
     }
 
@@ -778,26 +780,48 @@ impl Disk {
             remove_partitions,
             change_partitions,
             create_partitions,
+            move_journal: None,
         })
     }
 
     /// Attempts to commit all changes that have been made to the disk.
+    ///
+    /// Changes which the native partition table backend supports are written without holding
+    /// the libparted lock, so that such disks may be committed in parallel.
     pub fn commit(&mut self) -> Result<Option<FormatPartitions>, DiskError> {
+        self.commit_with_journal(None, |_, _| ())
+    }
+
+    /// Commits all changes that have been made to the disk, journaling any partition moves
+    /// at `move_journal` so that an interrupted move may be resumed. The progress of each move
+    /// is given to `move_progress`, as the number of sectors copied, and the number to copy.
+    pub fn commit_with_journal<F: FnMut(u64, u64)>(
+        &mut self,
+        move_journal: Option<&Path>,
+        move_progress: F,
+    ) -> Result<Option<FormatPartitions>, DiskError> {
         info!("committing changes to {}: {:#?}", self.path().display(), self);
-        Disk::from_name_with_serial(&self.device_path, &self.serial).and_then(|source| {
-            source.diff(self).and_then(|ops| {
-                if ops.is_empty() {
-                    Ok(None)
-                } else {
-                    let partitions_to_format = ops
-                        .remove()
-                        .and_then(|ops| ops.change())
-                        .and_then(|ops| ops.create())
-                        .map(Some)?;
+        let source = {
+            let _parted = parted::lock();
+            Disk::from_name_with_serial(&self.device_path, &self.serial)?
+        };
 
-                    Ok(partitions_to_format)
-                }
-            })
+        source.diff(self).and_then(|mut ops| {
+            ops.move_journal = move_journal.map(Path::to_path_buf);
+            if ops.is_empty() {
+                Ok(None)
+            } else if ops.is_native() {
+                Ok(Some(ops.commit_native()?))
+            } else {
+                let _parted = parted::lock();
+                let partitions_to_format = ops
+                    .remove()
+                    .and_then(|ops| ops.change_with(move_progress))
+                    .and_then(|ops| ops.create())
+                    .map(Some)?;
+
+                Ok(partitions_to_format)
+            }
         })
     }
 
@@ -815,8 +839,17 @@ impl Disk {
                 let mount = partition.target.as_ref().map(|ref path| path.to_path_buf());
                 let vg = partition.volume_group.as_ref().cloned();
                 let keyid = partition.key_id.as_ref().cloned();
-                if mount.is_some() || vg.is_some() || keyid.is_some() {
-                    Some((start, mount, vg, keyid))
+                let raid = partition.raid.as_ref().cloned();
+                let esp_mirror = partition.esp_mirror;
+                let subvolumes = partition.subvolumes.clone();
+                if mount.is_some()
+                    || vg.is_some()
+                    || keyid.is_some()
+                    || raid.is_some()
+                    || esp_mirror
+                    || !subvolumes.is_empty()
+                {
+                    Some((start, mount, vg, keyid, raid, esp_mirror, subvolumes))
                 } else {
                     None
                 }
@@ -824,10 +857,13 @@ impl Disk {
             .collect::<Vec<_>>();
 
         // Reload the disk data by re-probing and replacing `self` with the new data.
-        *self = Disk::from_name_with_serial(&self.device_path, &self.serial)?;
+        *self = {
+            let _parted = parted::lock();
+            Disk::from_name_with_serial(&self.device_path, &self.serial)?
+        };
 
         // Then re-add the critical information which was lost.
-        for (sector, mount, vg, keyid) in collected {
+        for (sector, mount, vg, keyid, raid, esp_mirror, subvolumes) in collected {
             info!("checking for mount target at {}", sector);
             let part = self
                 .get_partition_at(sector)
@@ -837,6 +873,9 @@ impl Disk {
             part.target = mount;
             part.volume_group = vg;
             part.key_id = keyid;
+            part.raid = raid;
+            part.esp_mirror = esp_mirror;
+            part.subvolumes = subvolumes;
         }
 
         Ok(())
@@ -5,17 +5,16 @@ use super::{
     },
     detect_fs_on_device, find_partition, find_partition_mut,
     partitions::{FORMAT, REMOVE, SOURCE},
-    Disk, LvmEncryption, PartitionTable, PVS,
+    Disk, LvmEncryption, PartitionTable, RaidDevice, RaidLevel, SwapFile, PVS,
 };
 use disk_types::{BlockDeviceExt, PartitionExt, PartitionTableExt, SectorExt};
 use crate::external::{
-    cryptsetup_close, cryptsetup_open, lvs, physical_volumes_to_deactivate, pvs, vgdeactivate,
-    CloseBy,
+    btrfs_subvolume_create, btrfs_subvolume_set_default, cryptsetup_close, cryptsetup_open, lvs,
+    mdadm_stop, mdstat, mkswapfile, physical_volumes_to_deactivate, pvs, vgdeactivate, CloseBy,
 };
 use itertools::Itertools;
 use libparted::{Device, DeviceType};
-use misc;
-use partition_identity::PartitionID;
+use partition_identity::{PartitionID, PartitionIdentifiers};
 use proc_mounts::{MountIter, MOUNTS, SWAPS};
 use rayon::{iter::IntoParallelRefIterator, prelude::*};
 use std::{
@@ -29,12 +28,59 @@ use std::{
     time::Duration,
 };
 use sys_mount::{swapoff, unmount, Mount, MountFlags, Mounts, Unmount, UnmountFlags};
+use tempdir::TempDir;
+
+/// Describes how a target will be mounted by `Disks::mount_all_targets`.
+#[derive(Debug, PartialEq)]
+pub(crate) enum MountKind {
+    Direct { device: PathBuf, fs: &'static str, options: Option<String> },
+    Bind { source: PathBuf },
+}
+
+/// Joins an absolute mount target onto the base directory that targets are mounted within.
+fn target_mount(base_dir: &Path, target: &Path) -> PathBuf {
+    // Path mangling commences here, since we need to concatenate an absolute
+    // path onto another absolute path, and the standard library opts for
+    // overwriting the original path when doing that.
+
+    // Ensure that the base_dir path has the ending '/'.
+    let base_dir = base_dir.as_os_str().as_bytes();
+    let mut target_mount: Vec<u8> = if base_dir[base_dir.len() - 1] == b'/' {
+        base_dir.to_owned()
+    } else {
+        let mut temp = base_dir.to_owned();
+        temp.push(b'/');
+        temp
+    };
+
+    // Cut the starting '/' from the target path if it exists.
+    let target_path = target.as_os_str().as_bytes();
+    let target_path = if !target_path.is_empty() && target_path[0] == b'/' {
+        if target_path.len() > 1 {
+            &target_path[1..]
+        } else {
+            b""
+        }
+    } else {
+        target_path
+    };
+
+    // Append the target path to the base_dir, and return it as a path type.
+    target_mount.extend_from_slice(target_path);
+    PathBuf::from(OsString::from_vec(target_mount))
+}
 
 /// A configuration of disks, both physical and logical.
-#[derive(Debug, Default, PartialEq)]
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
 pub struct Disks {
-    pub physical: Vec<Disk>,
-    pub logical:  Vec<LogicalDevice>,
+    pub physical:  Vec<Disk>,
+    pub logical:   Vec<LogicalDevice>,
+    /// Software RAID arrays, which are assembled from partitions of the physical disks.
+    #[serde(default)]
+    pub raid:      Vec<RaidDevice>,
+    /// A swap file to create within the root file system.
+    #[serde(default)]
+    pub swap_file: Option<SwapFile>,
 }
 
 impl Disks {
@@ -165,83 +211,101 @@ impl Disks {
         Box::new(iterator)
     }
 
-    /// Mounts all targets in this disks object.
-    pub fn mount_all_targets<P: AsRef<Path>>(&self, base_dir: P) -> io::Result<Mounts> {
-        let base_dir = base_dir.as_ref();
-        let targets =
-            self.get_partitions().filter(|part| part.target.is_some() && part.filesystem.is_some());
+    /// Searches for a RAID array by its name.
+    pub fn get_raid_device(&self, name: &str) -> Option<&RaidDevice> {
+        self.raid.iter().find(|d| d.name == name)
+    }
 
-        enum MountKind {
-            Direct { device: PathBuf, fs: &'static str },
-            Bind { source: PathBuf },
-        }
+    /// Searches for a RAID array by its name.
+    pub fn get_raid_device_mut(&mut self, name: &str) -> Option<&mut RaidDevice> {
+        self.raid.iter_mut().find(|d| d.name == name)
+    }
 
-        // The mount path will actually consist of the target concatenated with the
-        // root. NOTE: It is assumed that the target is an absolute path.
-        let paths: BTreeMap<PathBuf, MountKind> = targets
-            .map(|target| {
-                // Path mangling commences here, since we need to concatenate an absolute
-                // path onto another absolute path, and the standard library opts for
-                // overwriting the original path when doing that.
-                let target_mount: PathBuf = {
-                    // Ensure that the base_dir path has the ending '/'.
-                    let base_dir = base_dir.as_os_str().as_bytes();
-                    let mut target_mount: Vec<u8> = if base_dir[base_dir.len() - 1] == b'/' {
-                        base_dir.to_owned()
-                    } else {
-                        let mut temp = base_dir.to_owned();
-                        temp.push(b'/');
-                        temp
-                    };
+    /// Returns a slice of RAID arrays stored within the configuration.
+    pub fn get_raid_devices(&self) -> &[RaidDevice] { &self.raid }
 
-                    // Cut the starting '/' from the target path if it exists.
-                    let target_path = target.target.as_ref().unwrap().as_os_str().as_bytes();
-                    let target_path = if !target_path.is_empty() && target_path[0] == b'/' {
-                        if target_path.len() > 1 {
-                            &target_path[1..]
-                        } else {
-                            b""
-                        }
-                    } else {
-                        target_path
-                    };
+    /// Returns a mutable slice of RAID arrays stored within the configuration.
+    pub fn get_raid_devices_mut(&mut self) -> &mut [RaidDevice] { &mut self.raid }
 
-                    // Append the target path to the base_dir, and return it as a path type.
-                    target_mount.extend_from_slice(target_path);
-                    PathBuf::from(OsString::from_vec(target_mount))
-                };
+    /// Uses a boxed iterator to get an iterator over all partitions of RAID arrays.
+    pub fn get_raid_partitions<'a>(&'a self) -> Box<dyn Iterator<Item = &'a PartitionInfo> + 'a> {
+        Box::new(self.raid.iter().flat_map(|array| {
+            array.get_file_system().into_iter().chain(array.get_partitions().iter())
+        }))
+    }
 
-                // If a partition is already mounted, we should perform a bind mount.
-                // If it is not mounted, we can mount it directly.
-                let kind = if let Some(source) = target.mount_point.clone() {
-                    MountKind::Bind { source }
-                } else {
-                    let fs = match target.filesystem.unwrap() {
-                        FileSystem::Fat16 | FileSystem::Fat32 => "vfat",
-                        fs => fs.into(),
-                    };
+    /// The physical partitions which are, or will be, members of the named array.
+    pub fn get_raid_members<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> impl Iterator<Item = &'a PartitionInfo> + 'a {
+        self.get_physical_partitions().filter(move |p| p.raid.as_deref() == Some(name))
+    }
 
-                    MountKind::Direct { device: target.device_path.clone(), fs }
-                };
-                (target_mount, kind)
-            })
-            .collect();
+    /// Defines a new array from the partitions which have been marked as its members with
+    /// `PartitionBuilder::raid` or `PartitionInfo::set_raid`. An array of the same name
+    /// is replaced.
+    ///
+    /// The capacity of the array is derived from its level and its smallest member.
+    pub fn add_raid_device(
+        &mut self,
+        name: &str,
+        level: RaidLevel,
+    ) -> Result<&mut RaidDevice, DiskError> {
+        let (members, smallest) =
+            self.get_raid_members(name).fold((0, u64::max_value()), |(count, smallest), p| {
+                (count + 1, smallest.min(p.get_sectors()))
+            });
+
+        if members < level.minimum_members() {
+            return Err(DiskError::RaidMembers {
+                name: name.to_owned(),
+                level: level.to_string(),
+                members,
+                required: level.minimum_members(),
+            });
+        }
+
+        self.raid.retain(|array| array.name != name);
+        let array = RaidDevice::new(name.to_owned(), level, level.capacity(members, smallest));
+        self.raid.push(array);
+        Ok(self.raid.last_mut().expect("array was just pushed"))
+    }
+
+    /// Creates the arrays which do not exist yet, and then applies the partitions of every
+    /// array. This must be done after the physical disks have been committed, and before the
+    /// logical devices are.
+    pub fn commit_raid_devices(&mut self) -> Result<(), DiskError> {
+        for id in 0..self.raid.len() {
+            let members = self
+                .get_raid_members(&self.raid[id].name)
+                .map(|p| p.get_device_path().to_path_buf())
+                .collect::<Vec<_>>();
+
+            self.raid[id].commit(&members)?;
+        }
+
+        Ok(())
+    }
 
+    /// Mounts all targets in this disks object.
+    pub fn mount_all_targets<P: AsRef<Path>>(&self, base_dir: P) -> io::Result<Mounts> {
         // Each mount directory will be created and then mounted before progressing to
         // the next mount in the map. The BTreeMap that the mount targets were
         // collected into will ensure that mounts are created and mounted in
         // the correct order.
         let mut mounts = Vec::new();
 
-        for (target_mount, kind) in paths {
+        for (target_mount, kind) in self.mount_targets(base_dir.as_ref()) {
             if let Err(why) = fs::create_dir_all(&target_mount) {
                 error!("unable to create '{}': {}", why, target_mount.display());
             }
 
             let mount = match kind {
-                MountKind::Direct { device, fs } => {
+                MountKind::Direct { device, fs, options } => {
                     info!("mounting {:?} ({}) to {:?}", device, fs, target_mount);
-                    Mount::new(device, &target_mount, fs, MountFlags::empty(), None)?
+                    let options = options.as_deref();
+                    Mount::new(device, &target_mount, fs, MountFlags::empty(), options)?
                 }
                 MountKind::Bind { source } => {
                     info!("bind mounting {:?} to {:?}", source, target_mount);
@@ -255,9 +319,115 @@ impl Disks {
         Ok(Mounts(mounts))
     }
 
+    /// Collects the mounts that `mount_all_targets` will perform, in the order that they will
+    /// be mounted.
+    pub(crate) fn mount_targets(&self, base_dir: &Path) -> BTreeMap<PathBuf, MountKind> {
+        let mut mounts = BTreeMap::new();
+        let targets =
+            self.get_partitions().filter(|part| part.target.is_some() && part.filesystem.is_some());
+
+        for target in targets {
+            if !target.subvolumes.is_empty() {
+                // Subvolumes are always mounted directly, as btrfs permits a file system to be
+                // mounted more than once.
+                for subvolume in &target.subvolumes {
+                    if let Some(ref path) = subvolume.target {
+                        let kind = MountKind::Direct {
+                            device:  target.device_path.clone(),
+                            fs:      FileSystem::Btrfs.into(),
+                            options: Some(subvolume.mount_option()),
+                        };
+
+                        mounts.insert(target_mount(base_dir, path), kind);
+                    }
+                }
+
+                continue;
+            }
+
+            // If a partition is already mounted, we should perform a bind mount.
+            // If it is not mounted, we can mount it directly.
+            let kind = if let Some(source) = target.mount_point.clone() {
+                MountKind::Bind { source }
+            } else {
+                let fs = match target.filesystem.unwrap() {
+                    FileSystem::Fat16 | FileSystem::Fat32 => "vfat",
+                    fs => fs.into(),
+                };
+
+                MountKind::Direct { device: target.device_path.clone(), fs, options: None }
+            };
+
+            mounts.insert(target_mount(base_dir, target.target.as_ref().unwrap()), kind);
+        }
+
+        mounts
+    }
+
+    /// Creates the btrfs subvolumes of every partition that defines them, which must be done
+    /// after the partitions have been formatted.
+    ///
+    /// Subvolumes which already exist are kept as they are. The subvolume that is mounted at `/`
+    /// becomes the default subvolume of its file system, so that the root file system may be
+    /// mounted without specifying a subvolume.
+    pub fn create_subvolumes(&self) -> Result<(), DiskError> {
+        for partition in self.get_partitions().filter(|part| !part.subvolumes.is_empty()) {
+            let device = partition.get_device_path();
+            info!("creating btrfs subvolumes on {}", device.display());
+
+            let tempdir = TempDir::new("distinst")?;
+            let _mount =
+                Mount::new(device, tempdir.path(), "btrfs", MountFlags::empty(), Some("subvolid=5"))
+                    .map_err(|why| DiskError::SubvolumeMount { device: device.to_path_buf(), why })?
+                    .into_unmount_drop(UnmountFlags::DETACH);
+
+            for subvolume in &partition.subvolumes {
+                let path = tempdir.path().join(&subvolume.name);
+                let error = |why| DiskError::SubvolumeCreate {
+                    device: device.to_path_buf(),
+                    subvolume: subvolume.name.clone(),
+                    why,
+                };
+
+                if !path.exists() {
+                    btrfs_subvolume_create(&path).map_err(error)?;
+                }
+
+                if subvolume.is_mounted_at(Path::new("/")) {
+                    btrfs_subvolume_set_default(&path).map_err(error)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Creates the swap file, if one was requested, within the install that is mounted at
+    /// `root`. The swap file will be created on the root file system.
+    pub fn create_swap_file(&self, root: &Path) -> Result<(), DiskError> {
+        let swap = match self.swap_file {
+            Some(ref swap) => swap,
+            None => return Ok(()),
+        };
+
+        let error = |why| DiskError::SwapFile { path: swap.path.clone(), why };
+        let fs = self.find_partition(Path::new("/")).and_then(|(_, root)| root.filesystem);
+        let fs = fs.ok_or_else(|| {
+            error(io::Error::new(io::ErrorKind::NotFound, "root does not have a file system"))
+        })?;
+
+        let path = swap.path_within(root);
+        info!("creating {} MiB swap file at {}", swap.bytes() / 1_048_576, path.display());
+        mkswapfile(&path, swap.bytes(), fs).map_err(error)
+    }
+
     /// Get all partitions across all physical and logical devices.
     pub fn get_partitions<'a>(&'a self) -> Box<dyn Iterator<Item = &'a PartitionInfo> + 'a> {
-        Box::new(self.get_physical_partitions().chain(self.get_logical_partitions()))
+        Box::new(
+            self.get_physical_partitions()
+                .chain(self.get_raid_partitions())
+                .chain(self.get_logical_partitions()),
+        )
     }
 
     pub fn get_partitions_mut<'a>(
@@ -267,10 +437,32 @@ impl Disks {
             self.physical
                 .iter_mut()
                 .flat_map(|dev| dev.get_partitions_mut())
+                .chain(self.raid.iter_mut().flat_map(|dev| dev.get_partitions_mut()))
                 .chain(self.logical.iter_mut().flat_map(|dev| dev.get_partitions_mut())),
         )
     }
 
+    /// Reads the identifiers of every partition from the system, which is required after a
+    /// configuration has been deserialized, as identifiers are not serialized.
+    pub fn reload_identifiers(&mut self) {
+        let devices = self
+            .physical
+            .iter_mut()
+            .map(|disk| (&mut disk.file_system, &mut disk.partitions))
+            .chain(
+                self.raid.iter_mut().map(|md| (&mut md.disk.file_system, &mut md.disk.partitions)),
+            )
+            .chain(self.logical.iter_mut().map(|lvm| (&mut lvm.file_system, &mut lvm.partitions)));
+
+        for (file_system, partitions) in devices {
+            for partition in file_system.iter_mut().chain(partitions.iter_mut()) {
+                if !partition.device_path.as_os_str().is_empty() {
+                    partition.identifiers = PartitionIdentifiers::from_path(&partition.device_path);
+                }
+            }
+        }
+    }
+
     /// Returns a list of device paths which will be modified by this
     /// configuration.
     pub fn get_device_paths_to_modify(&self) -> Vec<PathBuf> {
@@ -393,7 +585,21 @@ impl Disks {
             Ok(())
         };
 
-        let devices_to_modify = self.get_device_paths_to_modify();
+        let mut devices_to_modify = self.get_device_paths_to_modify();
+
+        // Arrays are stopped when one of their members will be modified, so the volumes that
+        // they hold must be deactivated first.
+        let arrays_to_stop = mdstat()
+            .map_err(|why| DiskError::ExternalCommand { why })?
+            .into_iter()
+            .filter(|array| array.members.iter().any(|member| devices_to_modify.contains(member)))
+            .collect::<Vec<_>>();
+
+        for array in &arrays_to_stop {
+            devices_to_modify.push(array.device.clone());
+            devices_to_modify.extend(array_partitions(&array.device));
+        }
+
         info!("devices to modify: {:?}", devices_to_modify);
         let volume_map = pvs().map_err(|why| DiskError::ExternalCommand { why })?;
         info!("volume map: {:?}", volume_map);
@@ -401,20 +607,22 @@ impl Disks {
         info!("pvs: {:?}", pvs);
 
         // Handle LVM on LUKS
+        let context = executor::Context::current();
         pvs.par_iter()
             .map(|pv| {
-                let dev = CloseBy::Path(&pv);
-                match volume_map.get(pv) {
-                    Some(&Some(ref vg)) => umount(vg).and_then(|_| {
-                        vgdeactivate(vg)
-                            .and_then(|_| cryptsetup_close(dev))
-                            .map_err(|why| DiskError::ExternalCommand { why })
-                    }),
-                    Some(&None) => {
-                        cryptsetup_close(dev).map_err(|why| DiskError::ExternalCommand { why })
+                context.enter(|| {
+                    let dev = CloseBy::Path(&pv);
+                    match volume_map.get(pv) {
+                        Some(&Some(ref vg)) => umount(vg).and_then(|_| {
+                            vgdeactivate(vg)
+                                .and_then(|_| cryptsetup_close(dev))
+                                .map_err(|why| DiskError::ExternalCommand { why })
+                        }),
+                        Some(&None) => cryptsetup_close(dev)
+                            .map_err(|why| DiskError::ExternalCommand { why }),
+                        None => Ok(()),
                     }
-                    None => Ok(()),
-                }
+                })
             })
             .collect::<Result<(), DiskError>>()?;
 
@@ -432,6 +640,13 @@ impl Disks {
                     Ok(())
                 }
             })
+            .collect::<Result<(), DiskError>>()?;
+
+        arrays_to_stop
+            .into_iter()
+            .map(|array| {
+                mdadm_stop(&array.device).map_err(|why| DiskError::ExternalCommand { why })
+            })
             .collect::<Result<(), DiskError>>()
     }
 
@@ -571,6 +786,11 @@ impl Disks {
     /// Probes for and returns disk information for every disk in the system.
     pub fn probe_devices() -> Result<Disks, DiskError> {
         let mut disks = Disks::default();
+        let arrays = mdstat().unwrap_or_else(|why| {
+            warn!("unable to read the assembled RAID arrays: {}", why);
+            Vec::new()
+        });
+
         for mut device in Device::devices(true) {
             if let Some(name) = device.path().file_name().and_then(|x| x.to_str()) {
                 // Ignore CDROM devices
@@ -583,11 +803,40 @@ impl Disks {
                     | DeviceType::PED_DEVICE_LOOP
                     | DeviceType::PED_DEVICE_FILE
                     | DeviceType::PED_DEVICE_DM => continue,
+                    DeviceType::PED_DEVICE_MD => {
+                        let disk = Disk::new(&mut device, false)?;
+                        let path = misc::canonicalize(disk.get_device_path());
+                        let array =
+                            arrays.iter().find(|array| array.device == path).and_then(|array| {
+                                Some((array.name.clone(), array.level.parse().ok()?))
+                            });
+
+                        match array {
+                            Some((name, level)) => {
+                                disks.raid.push(RaidDevice::from_disk(name, level, disk))
+                            }
+                            // Arrays of other levels are treated as any other disk.
+                            None => disks.add(disk),
+                        }
+                    }
                     _ => disks.add(Disk::new(&mut device, false)?),
                 }
             }
         }
 
+        for array in &disks.raid {
+            let members = arrays
+                .iter()
+                .find(|md| md.name == array.name)
+                .map_or(&[][..], |md| md.members.as_slice());
+
+            for partition in disks.physical.iter_mut().flat_map(|d| d.partitions.iter_mut()) {
+                if members.iter().any(|member| *member == partition.device_path) {
+                    partition.raid = Some(array.name.clone());
+                }
+            }
+        }
+
         // Collect all of the extended partition information for each contained
         // partition in parallel.
         let mounts = MOUNTS.read().expect("failed to get mounts in Disk::new");
@@ -605,28 +854,30 @@ impl Disks {
             },
         );
 
+        disks.raid.par_iter_mut().flat_map(|device| device.get_partitions_mut()).for_each(|part| {
+            part.collect_extended_information(&mounts, &swaps);
+        });
+
         Ok(disks)
     }
 
     /// Locate a partition which contains the given file.
     ///
-    /// ```rust
-    /// extern crate disk_types;
-    /// use disk_types::FileSystem;
+    /// ```rust,no_run
+    /// extern crate distinst_disks;
+    /// use distinst_disks::{disk_types::FileSystem, Disks};
+    /// use std::fs;
     ///
-    /// Disks::probe_for(
-    ///     "recovery.conf",
+    /// let recovery_conf = Disks::probe_for(
     ///     "/recovery",
-    ///     |fs| fs == Fat16 || fs == Fat32,
-    ///     |recovery_path| {
-    ///         let mut file = File::open(recovery_path)?;
-    ///         let mut output = String::new();
-    ///         file.read_to_end(&mut output)?;
+    ///     |fs| fs == FileSystem::Fat16 || fs == FileSystem::Fat32,
+    ///     |_partition, path| path.join("recovery.conf").exists(),
+    ///     |_partition, path| fs::read_to_string(path.join("recovery.conf")),
+    /// );
     ///
-    ///         println!("Found recovery.conf at {:?}:\n\n{}", recovery_path, output);
-    ///         Ok(())
-    ///     }
-    /// )
+    /// if let Ok(Ok(output)) = recovery_conf {
+    ///     println!("Found recovery.conf:\n\n{}", output);
+    /// }
     /// ```
     pub fn probe_for<T, E, F, C, S>(
         expected_at: E,
@@ -696,18 +947,24 @@ impl Disks {
     }
 
     /// Finds the partition block path and associated partition information that is associated with
-    /// the given target mount point. Scans both physical and logical partitions.
+    /// the given target mount point. Scans physical, RAID, and logical partitions.
     pub fn find_partition<'a>(&'a self, target: &Path) -> Option<(&'a Path, &'a PartitionInfo)> {
-        find_partition(&self.physical, target).or_else(|| find_partition(&self.logical, target))
+        find_partition(&self.physical, target)
+            .or_else(|| find_partition(&self.raid, target))
+            .or_else(|| find_partition(&self.logical, target))
     }
 
     /// Finds the partition block path and associated partition information that is associated with
-    /// the given target mount point. Scans both physical and logical partitions. Mutable variant.
+    /// the given target mount point. Scans physical, RAID, and logical partitions. Mutable variant.
     pub fn find_partition_mut<'a>(
         &'a mut self,
         target: &Path,
     ) -> Option<(PathBuf, &'a mut PartitionInfo)> {
-        match find_partition_mut(&mut self.physical, target) {
+        if let partition @ Some(_) = find_partition_mut(&mut self.physical, target) {
+            return partition;
+        }
+
+        match find_partition_mut(&mut self.raid, target) {
             partition @ Some(_) => partition,
             None => find_partition_mut(&mut self.logical, target),
         }
@@ -717,8 +974,16 @@ impl Disks {
     pub fn find_volume_paths<'a>(&'a self, volume_group: &str) -> Vec<(&'a Path, &'a Path)> {
         let mut volumes = Vec::new();
 
-        for disk in &self.physical {
-            for partition in disk.get_partitions() {
+        let devices = self
+            .physical
+            .iter()
+            .map(|disk| (disk.get_device_path(), None, disk.get_partitions()))
+            .chain(self.raid.iter().map(|array| {
+                (array.get_device_path(), array.get_file_system(), array.get_partitions())
+            }));
+
+        for (device, file_system, partitions) in devices {
+            for partition in file_system.into_iter().chain(partitions.iter()) {
                 // The volume group may be stored in either the `original_vg`
                 // or `volume_group` fields. This combines the optionals.
                 let vg: Option<&String> = partition
@@ -729,7 +994,7 @@ impl Disks {
 
                 if let Some(ref pvg) = vg {
                     if pvg.as_str() == volume_group {
-                        volumes.push((disk.get_device_path(), partition.get_device_path()));
+                        volumes.push((device, partition.get_device_path()));
                     }
                 }
             }
@@ -803,6 +1068,16 @@ impl Disks {
         }
     }
 
+    /// Obtains the paths to the devices and partitions of the EFI partitions which mirror the
+    /// EFI partition that `get_base_partitions` locates.
+    pub fn get_esp_mirrors(&self) -> Vec<(&Path, &PartitionInfo)> {
+        self.physical
+            .iter()
+            .flat_map(|disk| disk.partitions.iter().map(move |p| (disk.get_device_path(), p)))
+            .filter(|(_, partition)| partition.esp_mirror)
+            .collect()
+    }
+
     /// Ensure that keyfiles have key paths.
     pub fn verify_keyfile_paths(&self) -> Result<(), DiskError> {
         info!("verifying if keyfiles have paths");
@@ -841,7 +1116,7 @@ impl Disks {
     }
 
     /// Maps key paths to their keyfile IDs TODO
-    fn resolve_keyfile_paths(&mut self) -> Result<(), DiskError> {
+    pub(crate) fn resolve_keyfile_paths(&mut self) -> Result<(), DiskError> {
         let mut temp: Vec<(String, Option<(PathBuf, PathBuf)>)> = Vec::new();
 
         'outer: for logical_device in &mut self.logical {
@@ -898,12 +1173,23 @@ impl Disks {
         self.get_logical_devices().iter().any(|d| d.get_device_path() == device)
     }
 
+    fn device_is_raid(&self, device: &Path) -> bool {
+        self.get_raid_devices().iter().any(|d| d.get_device_path() == device)
+    }
+
     /// Validates that partitions are configured correctly.
     ///
     /// - EFI installs must contain a `/boot/efi` partition as Fat16 / Fat32
-    /// - MBR installs on logical devices must have a `/boot` partition
+    /// - MBR installs on logical devices or RAID arrays must have a `/boot` partition
     /// - Boot partitions must not be on a logical volume
     /// - EFI boot partitions must have the ESP flag set
+    /// - Partitions with subvolumes must use btrfs, and mount a subvolume at their own target
+    /// - Swap files may only be created on root file systems which support them
+    /// - LUKS options of volumes which will be encrypted must be accepted by cryptsetup
+    /// - RAID members must belong to a defined array, and must not be mounted or used otherwise
+    /// - New RAID arrays must have enough members for their level
+    /// - Mirrored EFI partitions are only valid on EFI installs, must meet the requirements of
+    ///   the EFI partition, and must each be on a separate disk from the EFI partition
     pub fn verify_partitions(&self, bootloader: Bootloader) -> io::Result<()> {
         let (root_device, root) = self.find_partition(Path::new("/")).ok_or_else(|| {
             io::Error::new(io::ErrorKind::InvalidInput, "root partition was not defined")
@@ -926,14 +1212,122 @@ impl Disks {
             }
         }
 
+        for partition in self.get_partitions().filter(|part| !part.subvolumes.is_empty()) {
+            let target = partition.target.as_deref().unwrap_or_else(|| Path::new("none"));
+
+            if partition.filesystem != Some(Btrfs) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("partition at {} has subvolumes, but is not btrfs", target.display()),
+                ));
+            }
+
+            if partition.target.is_some() && partition.get_subvolume(target).is_none() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("partition at {} lacks a subvolume mounted there", target.display()),
+                ));
+            }
+        }
+
+        let encryptions = self
+            .get_physical_partitions()
+            .filter_map(|partition| partition.volume_group.as_ref())
+            .filter_map(|(_, encryption)| encryption.as_ref());
+
+        for encryption in encryptions {
+            encryption
+                .validate()
+                .map_err(|why| io::Error::new(io::ErrorKind::InvalidInput, why.to_string()))?;
+        }
+
+        for partition in self.get_physical_partitions() {
+            let name = match partition.raid {
+                Some(ref name) => name,
+                None => continue,
+            };
+
+            let error = |why: &str| {
+                let device = partition.get_device_path().display();
+                let why = format!("{} is a member of RAID array '{}', {}", device, name, why);
+                io::Error::new(io::ErrorKind::InvalidInput, why)
+            };
+
+            if self.get_raid_device(name).is_none() {
+                return Err(error("which was not defined"));
+            }
+
+            if partition.target.is_some()
+                || partition.volume_group.is_some()
+                || partition.key_id.is_some()
+            {
+                return Err(error("and cannot be used otherwise"));
+            }
+        }
+
+        for array in self.get_raid_devices().iter().filter(|array| !array.is_source) {
+            let members = self.get_raid_members(&array.name).count();
+            if members < array.level.minimum_members() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "{} array '{}' requires {} members, not {}",
+                        array.level,
+                        array.name,
+                        array.level.minimum_members(),
+                        members
+                    ),
+                ));
+            }
+        }
+
+        if self.swap_file.is_some() {
+            match root.filesystem {
+                Some(Btrfs) | Some(Ext2) | Some(Ext3) | Some(Ext4) | Some(Xfs) => (),
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "root file system does not support swap files",
+                    ));
+                }
+            }
+        }
+
         let boot_partition = if bootloader == Bootloader::Efi {
             Some(("/boot/efi", "EFI", true))
-        } else if self.device_is_logical(root_device) {
+        } else if self.device_is_logical(root_device) || self.device_is_raid(root_device) {
             Some(("/boot", "boot", false))
         } else {
             None
         };
 
+        let mirrors = self.get_esp_mirrors();
+        if !mirrors.is_empty() && bootloader != Bootloader::Efi {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "EFI system partitions can only be mirrored on EFI installs",
+            ));
+        }
+
+        for (device, mirror) in &mirrors {
+            if mirror.target.is_some()
+                || mirror.volume_group.is_some()
+                || mirror.key_id.is_some()
+                || mirror.raid.is_some()
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "{} mirrors the EFI partition, and cannot be used otherwise",
+                        mirror.get_device_path().display()
+                    ),
+                ));
+            }
+
+            let disk = self.find_disk(device).expect("mirror was found on this disk");
+            verify_esp(disk, mirror, "mirrored EFI")?;
+        }
+
         if let Some((partition, kind, is_efi)) = boot_partition {
             let device = {
                 let (device, boot) =
@@ -955,46 +1349,7 @@ impl Disks {
                 };
 
                 if is_efi {
-                    // Check if the EFI partition is on a GPT disk.
-                    if device.get_partition_table() != Some(PartitionTable::Gpt) {
-                        return Err(io::Error::new(
-                            io::ErrorKind::InvalidInput,
-                            "EFI installs cannot be done on disks without a GPT partition layout.",
-                        ));
-                    }
-
-                    if !boot.flags.contains(&PartitionFlag::PED_PARTITION_ESP) {
-                        return Err(io::Error::new(
-                            io::ErrorKind::InvalidInput,
-                            format!("{} partition did not have ESP flag set", kind),
-                        ));
-                    }
-
-                    match boot.filesystem {
-                        Some(Fat16) | Some(Fat32) => (),
-                        Some(_) => {
-                            return Err(io::Error::new(
-                                io::ErrorKind::InvalidInput,
-                                format!("{} partition has invalid file system", kind),
-                            ));
-                        }
-                        None => {
-                            return Err(io::Error::new(
-                                io::ErrorKind::InvalidInput,
-                                format!("{} partition does not have a file system", kind),
-                            ));
-                        }
-                    }
-
-                    // 256 MiB should be the minimal size of the ESP partition.
-                    const REQUIRED_SECTORS: u64 = 524_288;
-
-                    if boot.get_sectors() < REQUIRED_SECTORS {
-                        return Err(io::Error::new(
-                            io::ErrorKind::InvalidInput,
-                            "the ESP partition must be at least 256 MiB in size",
-                        ));
-                    }
+                    verify_esp(device, boot, kind)?;
                 }
 
                 device
@@ -1006,6 +1361,23 @@ impl Disks {
                     format!("{} partition cannot be on logical device", kind),
                 ));
             }
+
+            if is_efi {
+                let mut disks = vec![device.get_device_path()];
+                for &(mirror_device, _) in &mirrors {
+                    if disks.contains(&mirror_device) {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!(
+                                "{} already has an EFI partition, and cannot hold a mirror",
+                                mirror_device.display()
+                            ),
+                        ));
+                    }
+
+                    disks.push(mirror_device);
+                }
+            }
         }
 
         Ok(())
@@ -1015,9 +1387,17 @@ impl Disks {
     pub fn initialize_volume_groups(&mut self) -> Result<(), DiskError> {
         let mut existing_devices: Vec<LogicalDevice> = Vec::new();
 
-        for disk in &self.physical {
-            let sector_size = disk.get_logical_block_size();
-            for partition in disk.get_partitions().iter() {
+        let devices = self
+            .physical
+            .iter()
+            .map(|disk| (disk.get_logical_block_size(), None, disk.get_partitions()))
+            .chain(self.raid.iter().map(|array| {
+                let sector_size = array.disk.get_logical_block_size();
+                (sector_size, array.get_file_system(), array.get_partitions())
+            }));
+
+        for (sector_size, file_system, partitions) in devices {
+            for partition in file_system.into_iter().chain(partitions.iter()) {
                 if let Some(ref lvm) = partition.volume_group {
                     // TODO: NLL
                     let push = match existing_devices.iter_mut().find(|d| d.volume_group == lvm.0) {
@@ -1093,6 +1473,24 @@ impl Disks {
         }
     }
 
+    /// By default, the `device_path` field of new logical volumes is not populated, so this
+    /// derives it from the volume group and volume name.
+    pub(crate) fn assign_logical_device_paths(&mut self) {
+        for device in &mut self.logical {
+            for partition in
+                device.file_system.as_mut().into_iter().chain(device.partitions.iter_mut())
+            {
+                // ... unless it is populated, due to existing beforehand.
+                if partition.flag_is_enabled(SOURCE) {
+                    continue;
+                }
+                let label = partition.name.as_ref().expect("logical partition should have name");
+                partition.device_path =
+                    PathBuf::from(format!("/dev/mapper/{}-{}", device.volume_group, label));
+            }
+        }
+    }
+
     /// Applies all logical device operations, which are to be performed after all physical disk
     /// operations have completed.
     ///
@@ -1108,20 +1506,7 @@ impl Disks {
             device.validate()?;
         }
 
-        // By default, the `device_path` field is not populated, so let's fix that.
-        for device in &mut self.logical {
-            for partition in
-                device.file_system.as_mut().into_iter().chain(device.partitions.iter_mut())
-            {
-                // ... unless it is populated, due to existing beforehand.
-                if partition.flag_is_enabled(SOURCE) {
-                    continue;
-                }
-                let label = partition.name.as_ref().expect("logical partition should have name");
-                partition.device_path =
-                    PathBuf::from(format!("/dev/mapper/{}-{}", device.volume_group, label));
-            }
-        }
+        self.assign_logical_device_paths();
 
         // Ensure that the keyfile paths are mapped to their mount targets.
         self.resolve_keyfile_paths()?;
@@ -1180,8 +1565,79 @@ impl IntoIterator for Disks {
 impl FromIterator<Disk> for Disks {
     fn from_iter<I: IntoIterator<Item = Disk>>(iter: I) -> Self {
         // TODO: Also collect LVM Devices
-        Disks { physical: iter.into_iter().collect(), logical: Vec::new() }
+        Disks {
+            physical:  iter.into_iter().collect(),
+            logical:   Vec::new(),
+            raid:      Vec::new(),
+            swap_file: None,
+        }
+    }
+}
+
+/// Checks that an EFI partition is on a GPT disk, has the ESP flag, is formatted with FAT,
+/// and is large enough to hold the boot loader and kernels.
+fn verify_esp(disk: &Disk, partition: &PartitionInfo, kind: &str) -> io::Result<()> {
+    // Check if the EFI partition is on a GPT disk.
+    if disk.get_partition_table() != Some(PartitionTable::Gpt) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "EFI installs cannot be done on disks without a GPT partition layout.",
+        ));
+    }
+
+    if !partition.flags.contains(&PartitionFlag::PED_PARTITION_ESP) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} partition did not have ESP flag set", kind),
+        ));
+    }
+
+    match partition.filesystem {
+        Some(FileSystem::Fat16) | Some(FileSystem::Fat32) => (),
+        Some(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} partition has invalid file system", kind),
+            ));
+        }
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} partition does not have a file system", kind),
+            ));
+        }
     }
+
+    // 256 MiB should be the minimal size of the ESP partition.
+    const REQUIRED_SECTORS: u64 = 524_288;
+
+    if partition.get_sectors() < REQUIRED_SECTORS {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the ESP partition must be at least 256 MiB in size",
+        ));
+    }
+
+    Ok(())
+}
+
+/// The partitions of an assembled array, such as `/dev/md127p1`.
+fn array_partitions(device: &Path) -> Vec<PathBuf> {
+    let name = match device.file_name().and_then(|name| name.to_str()) {
+        Some(name) => name,
+        None => return Vec::new(),
+    };
+
+    let prefix = [name, "p"].concat();
+    fs::read_dir(Path::new("/sys/block").join(name))
+        .map(|entries| {
+            entries
+                .filter_map(Result::ok)
+                .filter(|entry| entry.file_name().to_string_lossy().starts_with(&prefix))
+                .map(|entry| Path::new("/dev").join(entry.file_name()))
+                .collect()
+        })
+        .unwrap_or_default()
 }
 
 fn find_device_path_of_mount<P: AsRef<Path>>(path: P) -> io::Result<PathBuf> {
@@ -1,4 +1,6 @@
+use super::{Enrollment, LuksOptions};
 use crate::external::{cryptsetup_encrypt, cryptsetup_open, pvcreate};
+use serde::{Serialize, Serializer};
 use std::{
     fmt,
     path::{Path, PathBuf},
@@ -6,19 +8,37 @@ use std::{
 use crate::DiskError;
 
 /// A structure which contains the encryption settings for a physical volume.
-#[derive(Clone, PartialEq)]
+///
+/// When serialized, the password is replaced with an empty string.
+#[derive(Clone, PartialEq, Deserialize, Serialize)]
 pub struct LvmEncryption {
     pub physical_volume: String,
+    #[serde(serialize_with = "redact_password")]
     pub password:        Option<String>,
     pub keydata:         Option<(String, Option<(PathBuf, PathBuf)>)>,
+    /// Options for formatting the volume, which are unused when it is opened.
+    #[serde(default)]
+    pub luks:            LuksOptions,
+    /// Additional methods of unlocking the volume, which are enrolled once it is installed.
+    #[serde(default)]
+    pub enrollments:     Vec<Enrollment>,
+}
+
+/// Whether a password was set is kept, as that determines how the volume is unlocked at boot.
+fn redact_password<S: Serializer>(
+    password: &Option<String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    password.as_ref().map(|_| "").serialize(serializer)
 }
 
 impl fmt::Debug for LvmEncryption {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "LvmEncryption {{ physical_volume: {}, password: hidden, keydata: {:?} }}",
-            self.physical_volume, self.keydata
+            "LvmEncryption {{ physical_volume: {}, password: hidden, keydata: {:?}, luks: {:?}, \
+             enrollments: {:?} }}",
+            self.physical_volume, self.keydata, self.luks, self.enrollments
         )
     }
 }
@@ -33,9 +53,35 @@ impl LvmEncryption {
             physical_volume,
             password: password.into(),
             keydata: keydata.into().map(|key| (key, None)),
+            luks: LuksOptions::default(),
+            enrollments: Vec::new(),
         }
     }
 
+    /// Sets the options that the volume will be formatted with.
+    pub fn with_luks(mut self, options: LuksOptions) -> LvmEncryption {
+        self.luks = options;
+        self
+    }
+
+    /// Sets the methods of unlocking the volume that will be enrolled after it is installed.
+    pub fn with_enrollments(mut self, enrollments: Vec<Enrollment>) -> LvmEncryption {
+        self.enrollments = enrollments;
+        self
+    }
+
+    /// Ensures that the LUKS options and enrollments are valid, before any disk is modified.
+    pub fn validate(&self) -> Result<(), DiskError> {
+        self.enrollments
+            .iter()
+            .try_for_each(Enrollment::validate)
+            .and_then(|_| self.luks.validate())
+            .map_err(|why| DiskError::LuksOptions {
+                pv:  self.physical_volume.clone(),
+                why: why.into(),
+            })
+    }
+
     /// Encrypts a new partition with the settings stored in the structure.
     pub fn encrypt(&self, device: &Path) -> Result<(), DiskError> {
         cryptsetup_encrypt(device, self)
@@ -0,0 +1,136 @@
+use std::{ffi::OsString, path::Path};
+
+/// The PCR that TPM2 enrollments are bound to by default, which measures the Secure Boot state.
+const DEFAULT_PCRS: &[u8] = &[7];
+
+fn default_pcrs() -> Vec<u8> { DEFAULT_PCRS.to_vec() }
+
+/// An additional method of unlocking a LUKS2 volume, which is enrolled with
+/// `systemd-cryptenroll` after the install has been configured.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Enrollment {
+    /// Seals the volume key to a TPM2 chip, with a policy bound to the given PCRs.
+    Tpm2 {
+        /// The TPM2 device to enroll with, which is found automatically if it is not set.
+        #[serde(default)]
+        device: Option<String>,
+        #[serde(default = "default_pcrs")]
+        pcrs:   Vec<u8>,
+    },
+    /// Binds the volume to a FIDO2 security token.
+    Fido2 {
+        /// The hidraw device of the token, which is found automatically if it is not set.
+        #[serde(default)]
+        device: Option<String>,
+    },
+}
+
+impl Enrollment {
+    pub fn tpm2() -> Enrollment { Enrollment::Tpm2 { device: None, pcrs: default_pcrs() } }
+
+    pub fn fido2() -> Enrollment { Enrollment::Fido2 { device: None } }
+
+    /// The name of this method, as `systemd-cryptenroll` and `crypttab` refer to it.
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            Enrollment::Tpm2 { .. } => "tpm2",
+            Enrollment::Fido2 { .. } => "fido2",
+        }
+    }
+
+    /// Ensures that `systemd-cryptenroll` will accept this enrollment.
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if let Enrollment::Tpm2 { ref pcrs, .. } = *self {
+            if pcrs.iter().any(|&pcr| pcr > 23) {
+                return Err("TPM2 PCRs must be between 0 and 23");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The arguments of a `systemd-cryptenroll` command which enrolls this method on `device`,
+    /// after unlocking it with the key in `unlock_key_file`.
+    ///
+    /// Slots which were previously enrolled with the same method are wiped, so that repeating
+    /// an enrollment does not accumulate slots.
+    pub fn cryptenroll_args(&self, device: &Path, unlock_key_file: &Path) -> Vec<OsString> {
+        let mut unlock = OsString::from("--unlock-key-file=");
+        unlock.push(unlock_key_file);
+
+        let mut args = vec![unlock, ["--wipe-slot=", self.kind()].concat().into()];
+
+        match *self {
+            Enrollment::Tpm2 { ref device, ref pcrs } => {
+                let device = device.as_ref().map_or("auto", String::as_str);
+                args.push(["--tpm2-device=", device].concat().into());
+                args.push(["--tpm2-pcrs=", &pcrs_to_string(pcrs)].concat().into());
+            }
+            Enrollment::Fido2 { ref device } => {
+                let device = device.as_ref().map_or("auto", String::as_str);
+                args.push(["--fido2-device=", device].concat().into());
+            }
+        }
+
+        args.push(device.into());
+        args
+    }
+
+    /// The option which unlocks the volume with this method at boot, for `/etc/crypttab`.
+    pub fn crypttab_option(&self) -> &'static str {
+        match *self {
+            Enrollment::Tpm2 { .. } => "tpm2-device=auto",
+            Enrollment::Fido2 { .. } => "fido2-device=auto",
+        }
+    }
+}
+
+/// Formats PCRs as `systemd-cryptenroll` expects them, such as `7+11`.
+pub fn pcrs_to_string(pcrs: &[u8]) -> String {
+    pcrs.iter().map(ToString::to_string).collect::<Vec<_>>().join("+")
+}
+
+/// Parses PCRs which are separated with `+` or `,`, such as `7+11`.
+pub fn pcrs_from_str(pcrs: &str) -> Result<Vec<u8>, &'static str> {
+    pcrs.split(|c| c == '+' || c == ',')
+        .map(|pcr| pcr.parse::<u8>().map_err(|_| "TPM2 PCRs must be numbers, such as 7+11"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enrollment_args() {
+        let tpm2 = Enrollment::Tpm2 { device: None, pcrs: pcrs_from_str("7+11").unwrap() };
+        let args = tpm2.cryptenroll_args(Path::new("/dev/sda3"), Path::new("/dev/stdin"));
+        assert_eq!(
+            args,
+            vec![
+                "--unlock-key-file=/dev/stdin",
+                "--wipe-slot=tpm2",
+                "--tpm2-device=auto",
+                "--tpm2-pcrs=7+11",
+                "/dev/sda3",
+            ]
+        );
+
+        let fido2 = Enrollment::Fido2 { device: Some("/dev/hidraw0".into()) };
+        let args = fido2.cryptenroll_args(Path::new("/dev/sda3"), Path::new("/boot/key/data"));
+        assert_eq!(
+            args,
+            vec![
+                "--unlock-key-file=/boot/key/data",
+                "--wipe-slot=fido2",
+                "--fido2-device=/dev/hidraw0",
+                "/dev/sda3",
+            ]
+        );
+
+        assert_eq!(Enrollment::tpm2().crypttab_option(), "tpm2-device=auto");
+        assert!(Enrollment::Tpm2 { device: None, pcrs: vec![24] }.validate().is_err());
+        assert!(pcrs_from_str("7+x").is_err());
+    }
+}
@@ -0,0 +1,200 @@
+use std::{ffi::OsString, fmt, str::FromStr};
+
+/// The key derivation function that LUKS uses to derive a key from a passphrase.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Pbkdf {
+    Argon2i,
+    Argon2id,
+    Pbkdf2,
+}
+
+impl Pbkdf {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Pbkdf::Argon2i => "argon2i",
+            Pbkdf::Argon2id => "argon2id",
+            Pbkdf::Pbkdf2 => "pbkdf2",
+        }
+    }
+
+    /// True if the cost of this function is measured in memory, in addition to iterations.
+    pub fn is_memory_hard(self) -> bool { self != Pbkdf::Pbkdf2 }
+}
+
+impl fmt::Display for Pbkdf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { f.write_str(self.as_str()) }
+}
+
+impl FromStr for Pbkdf {
+    type Err = &'static str;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "argon2i" => Ok(Pbkdf::Argon2i),
+            "argon2id" => Ok(Pbkdf::Argon2id),
+            "pbkdf2" => Ok(Pbkdf::Pbkdf2),
+            _ => Err("PBKDF must be one of argon2i, argon2id, or pbkdf2"),
+        }
+    }
+}
+
+/// Options given to `cryptsetup luksFormat` when a LUKS2 volume is created.
+///
+/// Options which are not set are left to the defaults of cryptsetup, aside from the key size,
+/// which defaults to 512 bits.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LuksOptions {
+    /// The cipher specification, such as `aes-xts-plain64`.
+    pub cipher:           Option<String>,
+    /// The size of the volume key, in bits.
+    pub key_size:         u16,
+    pub pbkdf:            Option<Pbkdf>,
+    /// The memory cost of an Argon2 PBKDF, in kibibytes.
+    pub pbkdf_memory:     Option<u32>,
+    /// The number of iterations of the PBKDF, which replaces benchmarking.
+    pub pbkdf_iterations: Option<u32>,
+    /// The size of an encryption sector, in bytes.
+    pub sector_size:      Option<u16>,
+    pub label:            Option<String>,
+}
+
+impl Default for LuksOptions {
+    fn default() -> Self {
+        LuksOptions {
+            cipher:           None,
+            key_size:         512,
+            pbkdf:            None,
+            pbkdf_memory:     None,
+            pbkdf_iterations: None,
+            sector_size:      None,
+            label:            None,
+        }
+    }
+}
+
+impl LuksOptions {
+    /// Ensures that cryptsetup will accept these options, so that an install does not fail
+    /// after the disks have already been modified.
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if let Some(ref cipher) = self.cipher {
+            let valid_char = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == ':';
+            if cipher.is_empty() || !cipher.chars().all(valid_char) {
+                return Err("cipher must be a specification such as aes-xts-plain64");
+            }
+        }
+
+        if self.key_size == 0 || self.key_size > 1024 || self.key_size % 8 != 0 {
+            return Err("key size must be a multiple of 8 bits, and no larger than 1024 bits");
+        }
+
+        // When no PBKDF is given, cryptsetup defaults to argon2id.
+        let pbkdf = self.pbkdf.unwrap_or(Pbkdf::Argon2id);
+
+        if let Some(memory) = self.pbkdf_memory {
+            if !pbkdf.is_memory_hard() {
+                return Err("PBKDF memory cost may only be set for argon2i and argon2id");
+            }
+
+            if memory < 32 || memory > 4_194_304 {
+                return Err("PBKDF memory cost must be between 32 KiB and 4 GiB");
+            }
+        }
+
+        if let Some(iterations) = self.pbkdf_iterations {
+            if pbkdf.is_memory_hard() && iterations < 4 {
+                return Err("argon2 PBKDFs require at least 4 iterations");
+            } else if !pbkdf.is_memory_hard() && iterations < 1000 {
+                return Err("pbkdf2 requires at least 1000 iterations");
+            }
+        }
+
+        if let Some(size) = self.sector_size {
+            if size < 512 || size > 4096 || !size.is_power_of_two() {
+                return Err("sector size must be a power of two between 512 and 4096 bytes");
+            }
+        }
+
+        if let Some(ref label) = self.label {
+            if label.len() > 47 || label.contains('\0') {
+                return Err("label must be no longer than 47 bytes");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The arguments which precede the `luksFormat` command.
+    pub(crate) fn format_args(&self) -> Vec<OsString> {
+        let mut args: Vec<OsString> = vec!["-s".into(), self.key_size.to_string().into()];
+
+        if let Some(ref cipher) = self.cipher {
+            args.extend_from_slice(&["--cipher".into(), cipher.into()]);
+        }
+
+        if let Some(pbkdf) = self.pbkdf {
+            args.extend_from_slice(&["--pbkdf".into(), pbkdf.as_str().into()]);
+        }
+
+        if let Some(memory) = self.pbkdf_memory {
+            args.extend_from_slice(&["--pbkdf-memory".into(), memory.to_string().into()]);
+        }
+
+        if let Some(iterations) = self.pbkdf_iterations {
+            let iterations = iterations.to_string().into();
+            args.extend_from_slice(&["--pbkdf-force-iterations".into(), iterations]);
+        }
+
+        if let Some(size) = self.sector_size {
+            args.extend_from_slice(&["--sector-size".into(), size.to_string().into()]);
+        }
+
+        if let Some(ref label) = self.label {
+            args.extend_from_slice(&["--label".into(), label.into()]);
+        }
+
+        args
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn luks_validation() {
+        assert!(LuksOptions::default().validate().is_ok());
+
+        let options = LuksOptions {
+            cipher:       Some("serpent-xts-plain64".into()),
+            pbkdf:        Some(Pbkdf::Argon2id),
+            pbkdf_memory: Some(1_048_576),
+            sector_size:  Some(4096),
+            label:        Some("cryptdata".into()),
+            ..LuksOptions::default()
+        };
+        assert!(options.validate().is_ok());
+
+        let invalid = [
+            LuksOptions { cipher: Some("aes xts".into()), ..LuksOptions::default() },
+            LuksOptions { key_size: 500, ..LuksOptions::default() },
+            LuksOptions { sector_size: Some(1000), ..LuksOptions::default() },
+            LuksOptions { label: Some("x".repeat(48)), ..LuksOptions::default() },
+            LuksOptions {
+                pbkdf:        Some(Pbkdf::Pbkdf2),
+                pbkdf_memory: Some(65536),
+                ..LuksOptions::default()
+            },
+            LuksOptions {
+                pbkdf:            Some(Pbkdf::Pbkdf2),
+                pbkdf_iterations: Some(10),
+                ..LuksOptions::default()
+            },
+        ];
+
+        for options in &invalid {
+            assert!(options.validate().is_err(), "{:?} should be invalid", options);
+        }
+    }
+}
@@ -1,6 +1,12 @@
 mod encryption;
+mod enroll;
+mod luks;
 
-pub use self::encryption::LvmEncryption;
+pub use self::{
+    encryption::LvmEncryption,
+    enroll::{pcrs_from_str, pcrs_to_string, Enrollment},
+    luks::{LuksOptions, Pbkdf},
+};
 use super::{
     super::{
         DiskError, DiskExt, PartitionError, PartitionInfo, PartitionTable, PartitionType, FORMAT,
@@ -20,7 +26,7 @@ use std::{
     time::Duration,
 };
 
-#[derive(Debug, Default, Clone, PartialEq)]
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
 pub struct VgData {
     /// Size of a PE, measured in sectors.
     pe_size:  u64,
@@ -34,7 +40,7 @@ pub struct VgData {
 
 /// An LVM device acts similar to a Disk, but consists of one more block devices
 /// that comprise a volume group, and may optionally be encrypted.
-#[derive(Debug, Default, Clone, PartialEq)]
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
 pub struct LogicalDevice {
     pub model_name:   String,
     pub volume_group: String,
@@ -208,7 +214,7 @@ impl LogicalDevice {
 
                 let device_path = match path.canonicalize() {
                     Ok(resolved) => resolved,
-                    Err(why) => {
+                    Err(_) => {
                         eprintln!("LVM device path is not a symbolic link");
                         continue
                     }
@@ -240,6 +246,9 @@ impl LogicalDevice {
                     original_vg: None,
                     volume_group: None,
                     key_id: None,
+                    raid: None,
+                    esp_mirror: false,
+                    subvolumes: Vec::new(),
                     identifiers,
                 };
 
@@ -275,41 +284,47 @@ impl LogicalDevice {
         }
     }
 
-    /// Create & modify all logical volumes on the volume group, and format them.
-    pub fn modify_partitions(&self) -> Result<(), DiskError> {
+    /// Pairs each logical volume with the size that it will be created with, in bytes.
+    ///
+    /// The final volume has no size, as it will consume the remaining free space in the group.
+    pub(crate) fn volumes_with_sizes(&self) -> Vec<(Option<u64>, &PartitionInfo)> {
         let nparts = if self.partitions.is_empty() {
             if self.file_system.is_some() {
                 0
             } else {
-                return Ok(());
+                return Vec::new();
             }
         } else {
             self.partitions.len() - 1
         };
 
-        let partitions = self
-            .file_system
+        self.file_system
             .as_ref()
             .into_iter()
             .map(|part| (0, part))
-            .chain(self.partitions.iter().enumerate());
+            .chain(self.partitions.iter().enumerate())
+            .map(|(id, partition)| {
+                let size = if id == nparts {
+                    None
+                } else {
+                    Some(partition.get_sectors() * self.sector_size)
+                };
 
-        for (id, partition) in partitions {
+                (size, partition)
+            })
+            .collect()
+    }
+
+    /// Create & modify all logical volumes on the volume group, and format them.
+    pub fn modify_partitions(&self) -> Result<(), DiskError> {
+        for (size, partition) in self.volumes_with_sizes() {
             let label =
                 partition.name.as_ref().expect("logical partitions should have names").as_str();
 
             // Don't create a partition if it already exists.
             if !partition.flag_is_enabled(SOURCE) {
-                lvcreate(
-                    &self.volume_group,
-                    label,
-                    if id == nparts {
-                        None
-                    } else {
-                        Some(partition.get_sectors() * self.sector_size)
-                    },
-                )
-                .map_err(|why| DiskError::LogicalVolumeCreate { why })?;
+                lvcreate(&self.volume_group, label, size)
+                    .map_err(|why| DiskError::LogicalVolumeCreate { why })?;
             }
 
             if partition.flag_is_enabled(REMOVE) {
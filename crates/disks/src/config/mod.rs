@@ -6,6 +6,11 @@ mod disk_trait;
 mod disks;
 mod lvm;
 mod partitions;
+mod plan;
+mod raid;
+mod report;
+mod swap;
+mod tables;
 
 pub use self::{
     disk::*,
@@ -13,6 +18,11 @@ pub use self::{
     disks::*,
     lvm::*,
     partitions::*,
+    plan::*,
+    raid::{RaidDevice, RaidLevel},
+    report::*,
+    swap::SwapFile,
+    tables::{TableBackup, TableRegion},
 };
 pub use disk_types::{PartitionTable, Sector};
 
@@ -42,13 +52,15 @@ pub fn get_size(path: &Path) -> io::Result<u64> {
 
 #[cfg(test)]
 mod tests {
-    use super::*;
+    use super::{disks::MountKind, *};
+    use crate::Bootloader;
+    use libparted::PartitionFlag;
     use operations::*;
     use partition_identity::PartitionIdentifiers;
 
     fn get_default() -> Disks {
         Disks {
-            physical: vec![Disk {
+            physical:  vec![Disk {
                 mklabel:     false,
                 model_name:  "Test Disk".into(),
                 serial:      "Test Disk 123".into(),
@@ -74,7 +86,10 @@ mod tests {
                         ordering:     1,
                         part_type:    PartitionType::Primary,
                         key_id:       None,
+                        subvolumes:   Vec::new(),
                         original_vg:  None,
+                        raid:         None,
+                        esp_mirror:   false,
                         volume_group: None,
                         identifiers:  PartitionIdentifiers::default(),
                     },
@@ -92,7 +107,10 @@ mod tests {
                         ordering:     2,
                         part_type:    PartitionType::Primary,
                         key_id:       None,
+                        subvolumes:   Vec::new(),
                         original_vg:  None,
+                        raid:         None,
+                        esp_mirror:   false,
                         volume_group: None,
                         identifiers:  PartitionIdentifiers::default(),
                     },
@@ -110,7 +128,10 @@ mod tests {
                         ordering:     3,
                         part_type:    PartitionType::Primary,
                         key_id:       None,
+                        subvolumes:   Vec::new(),
                         original_vg:  None,
+                        raid:         None,
+                        esp_mirror:   false,
                         volume_group: None,
                         identifiers:  PartitionIdentifiers::default(),
                     },
@@ -128,19 +149,24 @@ mod tests {
                         ordering:     4,
                         part_type:    PartitionType::Primary,
                         key_id:       None,
+                        subvolumes:   Vec::new(),
                         original_vg:  None,
+                        raid:         None,
+                        esp_mirror:   false,
                         volume_group: None,
                         identifiers:  PartitionIdentifiers::default(),
                     },
                 ],
             }],
-            logical:  Vec::new(),
+            logical:   Vec::new(),
+            raid:      Vec::new(),
+            swap_file: None,
         }
     }
 
     fn get_empty() -> Disks {
         Disks {
-            physical: vec![Disk {
+            physical:  vec![Disk {
                 mklabel:     false,
                 file_system: None,
                 model_name:  "Test Disk".into(),
@@ -153,7 +179,9 @@ mod tests {
                 read_only:   false,
                 partitions:  Vec::new(),
             }],
-            logical:  Vec::new(),
+            logical:   Vec::new(),
+            raid:      Vec::new(),
+            swap_file: None,
         }
     }
 
@@ -161,7 +189,7 @@ mod tests {
 
     // 500 MiB Fat16 partition.
     fn boot_part(start: u64) -> PartitionBuilder {
-        PartitionBuilder::new(start, 1024_000 + start, FileSystem::Fat16)
+        PartitionBuilder::new(start, 1_024_000 + start, FileSystem::Fat16)
     }
 
     // 20 GiB Ext4 partition.
@@ -180,7 +208,7 @@ mod tests {
         new.resize_partition(3, start + GIB20).unwrap();
         new.remove_partition(4).unwrap();
         new.add_partition(boot_part(2048)).unwrap();
-        new.add_partition(root_part(1026_048)).unwrap();
+        new.add_partition(root_part(1_026_048)).unwrap();
         assert_eq!(
             source.diff(&new).unwrap(),
             DiskOps {
@@ -191,7 +219,7 @@ mod tests {
                 create_partitions: vec![
                     PartitionCreate {
                         start_sector: 420456448,
-                        end_sector:   420456447 + GIB20,
+                        end_sector:   420456448 + GIB20,
                         file_system:  Some(FileSystem::Xfs),
                         kind:         PartitionType::Primary,
                         flags:        vec![],
@@ -201,7 +229,7 @@ mod tests {
                     },
                     PartitionCreate {
                         start_sector: 2048,
-                        end_sector:   1024_000 + 2047,
+                        end_sector:   1_024_000 + 2047,
                         file_system:  Some(FileSystem::Fat16),
                         kind:         PartitionType::Primary,
                         flags:        vec![],
@@ -210,8 +238,8 @@ mod tests {
                         path:         PathBuf::from("/dev/sdz"),
                     },
                     PartitionCreate {
-                        start_sector: 1026_048,
-                        end_sector:   GIB20 + 1026_047,
+                        start_sector: 1_026_048,
+                        end_sector:   GIB20 + 1_026_047,
                         file_system:  Some(FileSystem::Ext4),
                         kind:         PartitionType::Primary,
                         flags:        vec![],
@@ -220,6 +248,7 @@ mod tests {
                         path:         PathBuf::from("/dev/sdz"),
                     },
                 ],
+                move_journal:      None,
             }
         )
     }
@@ -245,10 +274,10 @@ mod tests {
 
         // This should fail with an off by one error, due to the start
         // sector being located within the previous partition.
-        assert!(source.add_partition(root_part(1026_047)).is_err());
+        assert!(source.add_partition(root_part(1_026_047)).is_err());
 
         // Create 20GiB Ext4 partition after that.
-        source.add_partition(root_part(1026_048)).unwrap();
+        source.add_partition(root_part(1_026_048)).unwrap();
     }
 
     #[test]
@@ -268,8 +297,123 @@ mod tests {
         let mut duplicate = source.clone();
         assert!(source.validate_layout(&duplicate).is_ok());
         duplicate
-            .add_partition(PartitionBuilder::new(2048, 1024_000 + 2048, FileSystem::Fat16))
+            .add_partition(PartitionBuilder::new(2048, 1_024_000 + 2048, FileSystem::Fat16))
             .unwrap();
         assert!(source.validate_layout(&duplicate).is_ok());
     }
+
+    #[test]
+    fn subvolume_mounts() {
+        let mut disks = get_default();
+        disks.physical[0].partitions[1].mount_point = None;
+        disks.physical[0].partitions[1].set_subvolumes(Subvolume::default_layout());
+        assert!(disks.verify_partitions(Bootloader::Bios).is_ok());
+
+        let subvolume = |name: &str| MountKind::Direct {
+            device:  PathBuf::from("/dev/sdz2"),
+            fs:      "btrfs",
+            options: Some(["subvol=", name].concat()),
+        };
+
+        let mounts = disks.mount_targets(Path::new("/mnt"));
+        assert_eq!(mounts.get(Path::new("/mnt")), Some(&subvolume("@")));
+        assert_eq!(mounts.get(Path::new("/mnt/home")), Some(&subvolume("@home")));
+        assert_eq!(mounts.get(Path::new("/mnt/.snapshots")), Some(&subvolume("@snapshots")));
+        assert_eq!(
+            mounts.get(Path::new("/mnt/boot/efi")),
+            Some(&MountKind::Bind { source: PathBuf::from("/boot/efi") })
+        );
+
+        // The partition's target must be served by one of its subvolumes.
+        let home = Subvolume::new("@home", PathBuf::from("/home"));
+        disks.physical[0].partitions[1].set_subvolumes(vec![home]);
+        assert!(disks.verify_partitions(Bootloader::Bios).is_err());
+
+        disks.physical[0].partitions[1].set_subvolumes(Subvolume::default_layout());
+        disks.physical[0].partitions[1].filesystem = Some(FileSystem::Ext4);
+        assert!(disks.verify_partitions(Bootloader::Bios).is_err());
+    }
+
+    #[test]
+    fn swap_file_root() {
+        let mut disks = get_default();
+        disks.swap_file = Some(SwapFile::new(4_194_304));
+        assert!(disks.verify_partitions(Bootloader::Bios).is_ok());
+
+        let swap = disks.swap_file.as_ref().unwrap();
+        assert_eq!(swap.bytes(), 2_147_483_648);
+        assert_eq!(swap.path_within(Path::new("/mnt")), PathBuf::from("/mnt/swapfile"));
+
+        // Swap files are not supported by every file system.
+        disks.physical[0].partitions[1].filesystem = Some(FileSystem::F2fs);
+        assert!(disks.verify_partitions(Bootloader::Bios).is_err());
+
+        disks.swap_file = None;
+        assert!(disks.verify_partitions(Bootloader::Bios).is_ok());
+    }
+
+    #[test]
+    fn esp_mirrors_verified() {
+        let mut disks = get_default();
+        disks.physical[0].partitions[0].flags = vec![PartitionFlag::PED_PARTITION_ESP];
+
+        let mut mirror = get_empty().physical.into_iter().next().unwrap();
+        mirror.device_path = "/dev/sdy".into();
+        mirror.add_partition(boot_part(2048).esp_mirror()).unwrap();
+        disks.add(mirror);
+
+        assert_eq!(disks.get_esp_mirrors().len(), 1);
+        assert!(disks.verify_partitions(Bootloader::Efi).is_ok());
+        assert!(disks.verify_partitions(Bootloader::Bios).is_err());
+
+        // A mirror on the same disk as the EFI partition would not survive its failure.
+        {
+            let partition = &mut disks.physical[0].partitions[2];
+            partition.filesystem = Some(FileSystem::Fat32);
+            partition.set_esp_mirror();
+        }
+
+        assert_eq!(disks.get_esp_mirrors().len(), 2);
+        assert!(disks.verify_partitions(Bootloader::Efi).is_err());
+    }
+
+    #[test]
+    fn luks_options_verified() {
+        let mut disks = get_default();
+        let options = LuksOptions { sector_size: Some(4096), ..LuksOptions::default() };
+        let encryption = LvmEncryption::new("cryptdata".into(), Some("secret".into()), None);
+        disks.physical[0].partitions[2].volume_group =
+            Some(("data".into(), Some(encryption.with_luks(options))));
+        assert!(disks.verify_partitions(Bootloader::Bios).is_ok());
+
+        if let Some((_, Some(ref mut encryption))) = disks.physical[0].partitions[2].volume_group {
+            encryption.luks.sector_size = Some(3000);
+        }
+
+        assert!(disks.verify_partitions(Bootloader::Bios).is_err());
+    }
+
+    #[test]
+    fn serialized_disks() {
+        let mut disks = get_default();
+        {
+            let partition = &mut disks.physical[0].partitions[2];
+            partition.flags = vec![PartitionFlag::PED_PARTITION_LVM];
+            let encryption = LvmEncryption::new("cryptdata".into(), Some("secret".into()), None);
+            partition.volume_group = Some(("data".into(), Some(encryption)));
+        }
+
+        let json = serde_json::to_string(&disks).unwrap();
+        assert!(!json.contains("secret"));
+
+        let restored: Disks = serde_json::from_str(&json).unwrap();
+        let (_, encryption) = restored.physical[0].partitions[2].volume_group.clone().unwrap();
+        assert_eq!(encryption.unwrap().password, Some(String::new()));
+
+        // Apart from the password, nothing is lost.
+        disks.physical[0].partitions[2].volume_group = restored.physical[0].partitions[2]
+            .volume_group
+            .clone();
+        assert_eq!(disks, restored);
+    }
 }
@@ -1,6 +1,6 @@
 use super::{
     FileSystem, LvmEncryption, PartitionFlag, PartitionIdentifiers, PartitionInfo, PartitionType,
-    FORMAT,
+    Subvolume, FORMAT,
 };
 use std::path::PathBuf;
 
@@ -15,6 +15,9 @@ pub struct PartitionBuilder {
     pub mount:        Option<PathBuf>,
     pub volume_group: Option<(String, Option<LvmEncryption>)>,
     pub key_id:       Option<String>,
+    pub raid:         Option<String>,
+    pub esp_mirror:   bool,
+    pub subvolumes:   Vec<Subvolume>,
 }
 
 impl PartitionBuilder {
@@ -30,6 +33,9 @@ impl PartitionBuilder {
             mount:        None,
             volume_group: None,
             key_id:       None,
+            raid:         None,
+            esp_mirror:   false,
+            subvolumes:   Vec::new(),
         }
     }
 
@@ -74,6 +80,20 @@ impl PartitionBuilder {
         self
     }
 
+    /// Makes the new partition a member of the given software RAID array, in place of a file
+    /// system.
+    pub fn raid(mut self, array: String) -> PartitionBuilder {
+        self.raid = Some(array);
+        self
+    }
+
+    /// Makes the new partition a mirror of the EFI system partition, which the boot loader is
+    /// also installed to, and which is kept in sync with `/boot/efi` by the installed system.
+    pub fn esp_mirror(mut self) -> PartitionBuilder {
+        self.esp_mirror = true;
+        self
+    }
+
     /// Defines that this partition will store the keyfile of the given ID(s),
     /// at the target mount point.
     pub fn associate_keyfile(mut self, id: String) -> PartitionBuilder {
@@ -81,8 +101,26 @@ impl PartitionBuilder {
         self
     }
 
+    /// Creates the given btrfs subvolumes on the new partition, which must be formatted with
+    /// btrfs. The partition's mount target must be the target of one of the subvolumes.
+    pub fn subvolumes(mut self, subvolumes: Vec<Subvolume>) -> PartitionBuilder {
+        self.subvolumes = subvolumes;
+        self
+    }
+
     /// Builds a brand new Partition from the current state of the builder.
-    pub fn build(self) -> PartitionInfo {
+    pub fn build(mut self) -> PartitionInfo {
+        if self.raid.is_some() {
+            self.filesystem = None;
+            if !self.flags.contains(&PartitionFlag::PED_PARTITION_RAID) {
+                self.flags.push(PartitionFlag::PED_PARTITION_RAID);
+            }
+        }
+
+        if self.esp_mirror && !self.flags.contains(&PartitionFlag::PED_PARTITION_ESP) {
+            self.flags.push(PartitionFlag::PED_PARTITION_ESP);
+        }
+
         PartitionInfo {
             bitflags:     FORMAT,
             number:       -1,
@@ -107,6 +145,9 @@ impl PartitionBuilder {
             original_vg:  None,
             volume_group: self.volume_group.clone(),
             key_id:       self.key_id,
+            raid:         self.raid,
+            esp_mirror:   self.esp_mirror,
+            subvolumes:   self.subvolumes,
             identifiers:  PartitionIdentifiers::default(),
         }
     }
@@ -1,12 +1,14 @@
 mod builder;
+mod subvolume;
 
-pub use self::builder::PartitionBuilder;
+pub use self::{builder::PartitionBuilder, subvolume::Subvolume};
 use super::{
     super::{LvmEncryption, PartitionError},
     PVS,
 };
 pub use disk_types::{BlockDeviceExt, FileSystem, PartitionExt, PartitionType, SectorExt};
-use crate::external::{get_label, is_encrypted};
+use crate::external::{get_label, is_encrypted, FileSystemChecker};
+use disk_types::checker::{FsckError, FsckReport, Fscker};
 use fstab_generate::BlockInfo;
 use libparted::{Partition, PartitionFlag};
 pub use os_detect::OS;
@@ -42,7 +44,7 @@ pub const BUSY: u8 = 0b01_0000;
 pub const SWAPPED: u8 = 0b10_0000;
 
 /// Contains relevant information about a certain partition.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct PartitionInfo {
     pub bitflags:     u8,
     /// The partition number is the numeric value that follows the disk's device path.
@@ -64,6 +66,7 @@ pub struct PartitionInfo {
     pub filesystem:   Option<FileSystem>,
     /// Specifies optional flags that should be applied to the partition, if
     /// not already set.
+    #[serde(with = "crate::config::plan::flag_names")]
     pub flags:        Vec<PartitionFlag>,
     /// Specifies the name of the partition.
     pub name:         Option<String>,
@@ -81,7 +84,19 @@ pub struct PartitionInfo {
     pub volume_group: Option<(String, Option<LvmEncryption>)>,
     /// If the partition is associated with a keyfile, this will name the key.
     pub key_id:       Option<String>,
-    /// Possible identifiers for this partition.
+    /// The name of the software RAID array that this partition is, or will be, a member of.
+    #[serde(default)]
+    pub raid:         Option<String>,
+    /// Whether this partition mirrors the EFI system partition mounted at `/boot/efi`.
+    #[serde(default)]
+    pub esp_mirror:   bool,
+    /// Btrfs subvolumes to create on this partition, and where they will be mounted. The
+    /// partition's own target must be the target of one of its subvolumes.
+    #[serde(default)]
+    pub subvolumes:   Vec<Subvolume>,
+    /// Possible identifiers for this partition, which are not serialized, as they may be
+    /// reloaded from the device.
+    #[serde(skip)]
     pub identifiers:  PartitionIdentifiers,
 }
 
@@ -143,6 +158,9 @@ impl PartitionInfo {
             original_vg: None,
             volume_group: None,
             key_id: None,
+            raid: None,
+            esp_mirror: false,
+            subvolumes: Vec::new(),
             identifiers,
         }))
     }
@@ -196,6 +214,18 @@ impl PartitionInfo {
     // True if the partition contains an encrypted partition
     pub fn is_encrypted(&self) -> bool { is_encrypted(self.get_device_path()) }
 
+    /// Checks the file system on this partition without modifying it.
+    ///
+    /// Mounted file systems cannot be checked reliably, so they are reported as unsupported.
+    pub fn check_file_system(&self) -> Result<FsckReport, FsckError> {
+        match self.filesystem {
+            Some(fs) if self.mount_point.is_none() => {
+                FileSystemChecker::check(&self.device_path, fs)
+            }
+            _ => Ok(FsckReport::unsupported()),
+        }
+    }
+
     pub fn get_current_lvm_volume_group(&self) -> Option<&str> {
         self.original_vg.as_deref()
     }
@@ -225,6 +255,29 @@ impl PartitionInfo {
         self.volume_group = Some((group, encryption));
     }
 
+    /// Defines that the partition will be a member of the given software RAID array, in place
+    /// of its mount target or volume group.
+    pub fn set_raid(&mut self, array: String) {
+        self.raid = Some(array);
+        self.target = None;
+        self.volume_group = None;
+        self.subvolumes.clear();
+        if !self.flags.contains(&PartitionFlag::PED_PARTITION_RAID) {
+            self.flags.push(PartitionFlag::PED_PARTITION_RAID);
+        }
+    }
+
+    /// Defines that the partition will be a mirror of the EFI system partition, in place of
+    /// its mount target or volume group.
+    pub fn set_esp_mirror(&mut self) {
+        self.esp_mirror = true;
+        self.target = None;
+        self.volume_group = None;
+        if !self.flags.contains(&PartitionFlag::PED_PARTITION_ESP) {
+            self.flags.push(PartitionFlag::PED_PARTITION_ESP);
+        }
+    }
+
     /// Shrinks the partition, if possible.
     ///
     /// The provided value will be truncated to the nearest mebibyte, and returned.
@@ -261,6 +314,19 @@ impl PartitionInfo {
     /// Specifies to delete this partition from the partition table.
     pub fn remove(&mut self) { self.bitflags |= REMOVE; }
 
+    /// Defines the btrfs subvolumes that will be created on this partition.
+    pub fn set_subvolumes(&mut self, subvolumes: Vec<Subvolume>) { self.subvolumes = subvolumes; }
+
+    /// The subvolume of this partition which will be mounted at the given target.
+    pub fn get_subvolume(&self, target: &Path) -> Option<&Subvolume> {
+        self.subvolumes.iter().find(|subvolume| subvolume.is_mounted_at(target))
+    }
+
+    /// True if the partition, or one of its subvolumes, will be mounted at the given target.
+    pub fn is_mounted_at(&self, target: &Path) -> bool {
+        self.target.as_ref().map_or(false, |t| t == target) || self.get_subvolume(target).is_some()
+    }
+
     /// Obtains bock information for the partition, if possible, for use with
     /// generating entries in "/etc/fstab".
     pub fn get_block_info(&self) -> Option<BlockInfo> {
@@ -269,12 +335,44 @@ impl PartitionInfo {
             return None;
         }
 
-        Some(BlockInfo::new(
+        let info = BlockInfo::new(
             BlockInfo::get_partition_id(&self.device_path, fs)?,
             fs,
             self.target.as_deref(),
             get_preferred_options(fs),
-        ))
+        );
+
+        Some(match self.target.as_deref().and_then(|target| self.get_subvolume(target)) {
+            Some(subvolume) => info.with_subvolume(&subvolume.name),
+            None => info,
+        })
+    }
+
+    /// Obtains block information for every target that the partition will be mounted at, which
+    /// includes each of its subvolumes.
+    pub fn get_block_infos(&self) -> Vec<BlockInfo> {
+        if self.subvolumes.is_empty() {
+            return self.get_block_info().into_iter().collect();
+        }
+
+        let fs = match self.get_file_system() {
+            Some(fs) => fs,
+            None => return Vec::new(),
+        };
+
+        let id = match BlockInfo::get_partition_id(&self.device_path, fs) {
+            Some(id) => id,
+            None => return Vec::new(),
+        };
+
+        self.subvolumes
+            .iter()
+            .filter_map(|subvolume| {
+                let target = subvolume.target.as_deref()?;
+                let info = BlockInfo::new(id.clone(), fs, Some(target), get_preferred_options(fs));
+                Some(info.with_subvolume(&subvolume.name))
+            })
+            .collect()
     }
 }
 
@@ -310,6 +408,7 @@ fn get_flags(partition: &Partition) -> Vec<PartitionFlag> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::LuksOptions;
     use std::path::Path;
 
     fn efi_partition() -> PartitionInfo {
@@ -327,6 +426,9 @@ mod tests {
             ordering:     1,
             part_type:    PartitionType::Primary,
             key_id:       None,
+            raid:         None,
+            esp_mirror:   false,
+            subvolumes:   Vec::new(),
             original_vg:  None,
             volume_group: None,
             identifiers:  PartitionIdentifiers::default(),
@@ -348,6 +450,9 @@ mod tests {
             ordering:     2,
             part_type:    PartitionType::Primary,
             key_id:       None,
+            raid:         None,
+            esp_mirror:   false,
+            subvolumes:   Vec::new(),
             original_vg:  None,
             volume_group: None,
             identifiers:  PartitionIdentifiers::default(),
@@ -369,6 +474,9 @@ mod tests {
             ordering:     4,
             part_type:    PartitionType::Primary,
             key_id:       None,
+            raid:         None,
+            esp_mirror:   false,
+            subvolumes:   Vec::new(),
             original_vg:  None,
             identifiers:  PartitionIdentifiers::default(),
             volume_group: Some((
@@ -377,6 +485,8 @@ mod tests {
                     physical_volume: "LUKS_PV".into(),
                     password:        Some("password".into()),
                     keydata:         None,
+                    luks:            LuksOptions::default(),
+                    enrollments:     Vec::new(),
                 }),
             )),
         }
@@ -397,6 +507,9 @@ mod tests {
             ordering:     4,
             part_type:    PartitionType::Primary,
             key_id:       None,
+            raid:         None,
+            esp_mirror:   false,
+            subvolumes:   Vec::new(),
             original_vg:  None,
             volume_group: Some(("LVM_GROUP".into(), None)),
             identifiers:  PartitionIdentifiers::default(),
@@ -418,6 +531,9 @@ mod tests {
             ordering:     4,
             part_type:    PartitionType::Primary,
             key_id:       None,
+            raid:         None,
+            esp_mirror:   false,
+            subvolumes:   Vec::new(),
             original_vg:  None,
             volume_group: None,
             identifiers:  PartitionIdentifiers::default(),
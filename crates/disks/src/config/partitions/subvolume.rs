@@ -0,0 +1,36 @@
+use std::path::{Path, PathBuf};
+
+/// A btrfs subvolume, which permits a single btrfs partition to be mounted at several targets.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Subvolume {
+    /// The path of the subvolume, relative to the top level of the file system.
+    pub name:   String,
+    /// Where the subvolume will be mounted, if it will be mounted at all.
+    #[serde(default)]
+    pub target: Option<PathBuf>,
+}
+
+impl Subvolume {
+    pub fn new<S: Into<String>, T: Into<Option<PathBuf>>>(name: S, target: T) -> Subvolume {
+        Subvolume { name: name.into(), target: target.into() }
+    }
+
+    /// The conventional layout, which mounts `@` at `/`, `@home` at `/home`, and `@snapshots`
+    /// at `/.snapshots`.
+    pub fn default_layout() -> Vec<Subvolume> {
+        vec![
+            Subvolume::new("@", PathBuf::from("/")),
+            Subvolume::new("@home", PathBuf::from("/home")),
+            Subvolume::new("@snapshots", PathBuf::from("/.snapshots")),
+        ]
+    }
+
+    /// The mount option which selects this subvolume.
+    pub fn mount_option(&self) -> String { ["subvol=", &self.name].concat() }
+
+    /// True if this subvolume will be mounted at the given target.
+    pub fn is_mounted_at(&self, target: &Path) -> bool {
+        self.target.as_ref().map_or(false, |t| t == target)
+    }
+}
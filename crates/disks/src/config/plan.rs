@@ -0,0 +1,659 @@
+//! Declarative descriptions of a disk configuration, which may be deserialized from a plan file
+//! and applied to a `Disks` structure in place of constructing the layout by hand.
+
+use super::{
+    super::{DiskError, DiskExt, FileSystem, PartitionFlag, PartitionTable, PartitionType},
+    partitions::SOURCE,
+    Disk, Disks, Enrollment, LuksOptions, LvmEncryption, PartitionBuilder, RaidLevel, Sector,
+    Subvolume, SwapFile,
+};
+use disk_types::SectorExt;
+pub use disk_types::{partition_flag_from_str, partition_flag_to_str};
+use std::path::PathBuf;
+
+/// Describes the layout of every disk and logical device that the install will touch.
+///
+/// Actions are applied in the same order as the command line interface applies them: tables
+/// are written, partitions are removed, existing partitions are modified, new partitions are
+/// created, RAID arrays are defined, LUKS partitions are decrypted, and then volume groups are
+/// configured.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct DisksPlan {
+    #[serde(default)]
+    pub disks:         Vec<DiskPlan>,
+    /// Software RAID arrays to assemble from the partitions of the disks.
+    #[serde(default)]
+    pub raid:          Vec<RaidPlan>,
+    #[serde(default)]
+    pub decrypt:       Vec<DecryptPlan>,
+    #[serde(default)]
+    pub volume_groups: Vec<VolumeGroupPlan>,
+    /// Creates a swap file within the root file system.
+    #[serde(default)]
+    pub swap_file:     Option<SwapFilePlan>,
+}
+
+/// A swap file to create within the root file system.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SwapFilePlan {
+    /// The size of the swap file, as raw sectors, or as a `M` or `MiB` suffixed size.
+    pub size: String,
+    /// Where to create the swap file, which defaults to `/swapfile`.
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+}
+
+impl SwapFilePlan {
+    pub fn to_swap_file(&self) -> Result<SwapFile, DiskError> {
+        let mut swap = SwapFile::new(parse_size(&self.size)?);
+        if let Some(ref path) = self.path {
+            swap.path = path.clone();
+        }
+
+        Ok(swap)
+    }
+}
+
+/// A physical disk, and the changes to make to it.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct DiskPlan {
+    /// The block device path of the disk, such as `/dev/sda`.
+    pub path:       PathBuf,
+    /// Writes a new partition table to the disk, clobbering all existing partitions.
+    #[serde(default)]
+    pub table:      Option<TablePlan>,
+    /// Partition numbers to remove from the disk.
+    #[serde(default)]
+    pub remove:     Vec<i32>,
+    /// Existing partitions to move, resize, reformat, or assign a mount target to.
+    #[serde(default)]
+    pub modify:     Vec<ExistingPartitionPlan>,
+    /// New partitions to create on the disk.
+    #[serde(default)]
+    pub partitions: Vec<PartitionPlan>,
+}
+
+/// The kind of partition table to write to a disk.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TablePlan {
+    Gpt,
+    Msdos,
+}
+
+impl From<TablePlan> for PartitionTable {
+    fn from(table: TablePlan) -> Self {
+        match table {
+            TablePlan::Gpt => PartitionTable::Gpt,
+            TablePlan::Msdos => PartitionTable::Msdos,
+        }
+    }
+}
+
+/// The kind of partition to create.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PartitionKindPlan {
+    Primary,
+    Logical,
+    Extended,
+}
+
+impl Default for PartitionKindPlan {
+    fn default() -> Self { PartitionKindPlan::Primary }
+}
+
+impl From<PartitionKindPlan> for PartitionType {
+    fn from(kind: PartitionKindPlan) -> Self {
+        match kind {
+            PartitionKindPlan::Primary => PartitionType::Primary,
+            PartitionKindPlan::Logical => PartitionType::Logical,
+            PartitionKindPlan::Extended => PartitionType::Extended,
+        }
+    }
+}
+
+/// A new partition, which is the declarative equivalent of a `PartitionBuilder`.
+///
+/// Sectors are written in the same notation accepted by the command line: `start`, `end`,
+/// raw sector counts, `-` prefixed values counting from the end, `M` / `MiB` suffixed sizes,
+/// or `%` suffixed percentages.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PartitionPlan {
+    pub start:      String,
+    pub end:        String,
+    #[serde(default)]
+    pub kind:       PartitionKindPlan,
+    #[serde(default)]
+    pub filesystem: Option<String>,
+    #[serde(default)]
+    pub name:       Option<String>,
+    #[serde(default)]
+    pub mount:      Option<PathBuf>,
+    #[serde(default)]
+    pub flags:      Vec<String>,
+    #[serde(default)]
+    pub keyid:      Option<String>,
+    /// Formats the partition as a LVM physical volume, optionally wrapped in LUKS.
+    #[serde(default)]
+    pub lvm:        Option<LvmPlan>,
+    /// Makes the partition a member of the named RAID array, in place of a file system.
+    #[serde(default)]
+    pub raid:       Option<String>,
+    /// Makes the partition a mirror of the EFI partition mounted at `/boot/efi`.
+    #[serde(default)]
+    pub esp_mirror: bool,
+    /// Btrfs subvolumes to create on the partition, and where to mount them.
+    #[serde(default)]
+    pub subvolumes: Vec<Subvolume>,
+}
+
+/// Changes to make to a partition which already exists on the disk.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExistingPartitionPlan {
+    pub number:     i32,
+    /// Moves the start of the partition to this sector.
+    #[serde(default)]
+    pub start:      Option<String>,
+    /// Resizes the partition so that it ends at this sector.
+    #[serde(default)]
+    pub end:        Option<String>,
+    /// Reformats the partition with this file system.
+    #[serde(default)]
+    pub filesystem: Option<String>,
+    #[serde(default)]
+    pub mount:      Option<PathBuf>,
+    #[serde(default)]
+    pub flags:      Option<Vec<String>>,
+    #[serde(default)]
+    pub keyid:      Option<String>,
+    #[serde(default)]
+    pub lvm:        Option<LvmPlan>,
+    #[serde(default)]
+    pub raid:       Option<String>,
+    #[serde(default)]
+    pub esp_mirror: bool,
+    #[serde(default)]
+    pub subvolumes: Vec<Subvolume>,
+}
+
+/// A software RAID array, which is assembled from the partitions that name it in their `raid`
+/// field. An array which is already assembled is modified in place of being created.
+///
+/// The array may be given a partition table and partitions, as a disk would be, or its entire
+/// capacity may be formatted with a file system or used as a LVM physical volume.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RaidPlan {
+    pub name:       String,
+    pub level:      RaidLevel,
+    #[serde(default)]
+    pub table:      Option<TablePlan>,
+    /// Formats the entire array with this file system.
+    #[serde(default)]
+    pub filesystem: Option<String>,
+    #[serde(default)]
+    pub mount:      Option<PathBuf>,
+    /// Uses the entire array as a LVM physical volume, optionally wrapped in LUKS.
+    #[serde(default)]
+    pub lvm:        Option<LvmPlan>,
+    #[serde(default)]
+    pub partitions: Vec<PartitionPlan>,
+}
+
+/// Assigns a partition to a LVM volume group.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct LvmPlan {
+    pub volume_group: String,
+    #[serde(default)]
+    pub encryption:   Option<EncryptionPlan>,
+}
+
+/// LUKS settings for a physical volume.
+#[derive(Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct EncryptionPlan {
+    pub physical_volume: String,
+    #[serde(default)]
+    pub password:        Option<String>,
+    #[serde(default)]
+    pub keyfile:         Option<String>,
+    /// Options for formatting a new LUKS volume.
+    #[serde(default)]
+    pub luks:            LuksOptions,
+    /// Additional methods of unlocking a new LUKS volume, such as a TPM2 chip.
+    #[serde(default)]
+    pub enroll:          Vec<Enrollment>,
+}
+
+impl std::fmt::Debug for EncryptionPlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "EncryptionPlan {{ physical_volume: {}, password: hidden, keyfile: {:?}, luks: {:?}, \
+             enroll: {:?} }}",
+            self.physical_volume, self.keyfile, self.luks, self.enroll
+        )
+    }
+}
+
+impl EncryptionPlan {
+    fn to_encryption(&self) -> Result<LvmEncryption, DiskError> {
+        let empty = |value: &Option<String>| value.as_ref().map_or(false, String::is_empty);
+        if empty(&self.password)
+            || empty(&self.keyfile)
+            || (self.password.is_none() && self.keyfile.is_none())
+        {
+            return Err(DiskError::PlanEmptyKey { pv: self.physical_volume.clone() });
+        }
+
+        let encryption = LvmEncryption::new(
+            self.physical_volume.clone(),
+            self.password.clone(),
+            self.keyfile.clone(),
+        )
+        .with_luks(self.luks.clone())
+        .with_enrollments(self.enroll.clone());
+
+        encryption.validate()?;
+        Ok(encryption)
+    }
+}
+
+/// An existing LUKS partition which should be decrypted, so that its volume groups may be used.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct DecryptPlan {
+    pub device:     PathBuf,
+    pub encryption: EncryptionPlan,
+}
+
+/// Changes to make to a LVM volume group, whether it is newly-created or already exists.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct VolumeGroupPlan {
+    pub name:       String,
+    /// Removes all existing logical volumes from the group.
+    #[serde(default)]
+    pub remove_all: bool,
+    /// Names of logical volumes to remove from the group.
+    #[serde(default)]
+    pub remove:     Vec<String>,
+    /// Existing logical volumes to reformat or assign a mount target to.
+    #[serde(default)]
+    pub modify:     Vec<LogicalModifyPlan>,
+    /// New logical volumes to create in the group.
+    #[serde(default)]
+    pub volumes:    Vec<LogicalVolumePlan>,
+}
+
+/// Changes to make to a logical volume which already exists.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct LogicalModifyPlan {
+    pub name:       String,
+    #[serde(default)]
+    pub filesystem: Option<String>,
+    #[serde(default)]
+    pub mount:      Option<PathBuf>,
+    #[serde(default)]
+    pub subvolumes: Vec<Subvolume>,
+}
+
+/// A new logical volume to create in a volume group.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct LogicalVolumePlan {
+    pub name:       String,
+    pub size:       String,
+    #[serde(default)]
+    pub filesystem: Option<String>,
+    #[serde(default)]
+    pub mount:      Option<PathBuf>,
+    #[serde(default)]
+    pub flags:      Vec<String>,
+    #[serde(default)]
+    pub subvolumes: Vec<Subvolume>,
+}
+
+impl Disks {
+    /// Applies a declarative plan to this disk configuration.
+    ///
+    /// Disks referenced by the plan which are not already a part of the configuration will be
+    /// probed and added to it.
+    pub fn apply_plan(&mut self, plan: &DisksPlan) -> Result<(), DiskError> {
+        for disk in &plan.disks {
+            if self.find_disk(&disk.path).is_none() {
+                info!("adding {:?} to disks configuration", disk.path);
+                self.add(Disk::from_name(&disk.path)?);
+            }
+        }
+
+        for disk_plan in &plan.disks {
+            let disk = self
+                .find_disk_mut(&disk_plan.path)
+                .ok_or_else(|| DiskError::DiskGet { device: disk_plan.path.clone() })?;
+
+            if let Some(table) = disk_plan.table {
+                disk.mklabel(table.into())?;
+            }
+
+            for &partition in &disk_plan.remove {
+                disk.remove_partition(partition)?;
+            }
+
+            for modify in &disk_plan.modify {
+                apply_existing(disk, modify)?;
+            }
+
+            for partition in &disk_plan.partitions {
+                let builder = partition_builder(disk, partition)?;
+                disk.add_partition(builder)?;
+            }
+        }
+
+        let members = plan.disks.iter().flat_map(|disk| {
+            let new = disk.partitions.iter().filter_map(|p| p.raid.as_ref());
+            new.chain(disk.modify.iter().filter_map(|p| p.raid.as_ref()))
+        });
+
+        for name in members {
+            if !plan.raid.iter().any(|array| array.name == *name)
+                && self.get_raid_device(name).is_none()
+            {
+                return Err(DiskError::PlanRaidNotFound { name: name.clone() });
+            }
+        }
+
+        for array in &plan.raid {
+            apply_raid(self, array)?;
+        }
+
+        self.initialize_volume_groups()?;
+
+        for decrypt in &plan.decrypt {
+            self.decrypt_partition(&decrypt.device, &decrypt.encryption.to_encryption()?)?;
+        }
+
+        for group in &plan.volume_groups {
+            apply_volume_group(self, group)?;
+        }
+
+        if let Some(ref swap) = plan.swap_file {
+            self.swap_file = Some(swap.to_swap_file()?);
+        }
+
+        Ok(())
+    }
+}
+
+fn apply_existing(disk: &mut Disk, plan: &ExistingPartitionPlan) -> Result<(), DiskError> {
+    if let Some(ref start) = plan.start {
+        let start = disk.get_sector(parse_sector(start)?);
+        disk.move_partition(plan.number, start)?;
+    }
+
+    if let Some(ref end) = plan.end {
+        let end = disk.get_sector(parse_sector(end)?);
+        disk.resize_partition(plan.number, end)?;
+    }
+
+    let fs = plan.filesystem.as_ref().map(|fs| parse_fs(fs)).transpose()?;
+    let encryption =
+        plan.lvm.as_ref().and_then(|lvm| lvm.encryption.as_ref()).map(|enc| enc.to_encryption());
+    let encryption = encryption.transpose()?;
+    let flags = plan.flags.as_ref().map(|flags| parse_flags(flags)).transpose()?;
+
+    let partition = disk
+        .get_partition_mut(plan.number)
+        .ok_or(DiskError::PartitionNotFound { partition: plan.number })?;
+
+    if plan.keyid.is_some() && plan.mount.is_none() {
+        return Err(DiskError::KeyFileWithoutPath);
+    }
+
+    if let Some(ref keyid) = plan.keyid {
+        partition.associate_keyfile(keyid.clone());
+    }
+
+    if let Some(ref mount) = plan.mount {
+        partition.set_mount(mount.clone());
+    }
+
+    if let Some(ref raid) = plan.raid {
+        partition.set_raid(raid.clone());
+    } else if let Some(ref lvm) = plan.lvm {
+        partition.set_volume_group(lvm.volume_group.clone(), encryption);
+        partition.format_with(FileSystem::Lvm);
+    } else if let Some(fs) = fs {
+        partition.format_with(fs);
+    }
+
+    if let Some(flags) = flags {
+        partition.flags = flags;
+    }
+
+    if plan.esp_mirror {
+        partition.set_esp_mirror();
+    }
+
+    if !plan.subvolumes.is_empty() {
+        partition.set_subvolumes(plan.subvolumes.clone());
+    }
+
+    Ok(())
+}
+
+fn partition_builder(disk: &Disk, plan: &PartitionPlan) -> Result<PartitionBuilder, DiskError> {
+    let start = disk.get_sector(parse_sector(&plan.start)?);
+    let end = disk.get_sector(parse_sector(&plan.end)?);
+
+    let mut builder = match plan.lvm {
+        Some(ref lvm) => {
+            let encryption = lvm.encryption.as_ref().map(|enc| enc.to_encryption()).transpose()?;
+            PartitionBuilder::new(start, end, FileSystem::Lvm)
+                .logical_volume(lvm.volume_group.clone(), encryption)
+        }
+        None => {
+            let fs = plan.filesystem.as_ref().map(|fs| parse_fs(fs)).transpose()?;
+            PartitionBuilder::new(start, end, fs)
+        }
+    };
+
+    builder = builder.partition_type(plan.kind.into()).flags(parse_flags(&plan.flags)?);
+
+    if let Some(ref name) = plan.name {
+        builder = builder.name(name.clone());
+    }
+
+    match (plan.keyid.as_ref(), plan.mount.as_ref()) {
+        (Some(keyid), Some(mount)) => {
+            builder = builder.associate_keyfile(keyid.clone()).mount(mount.clone());
+        }
+        (Some(_), None) => return Err(DiskError::KeyFileWithoutPath),
+        (None, Some(mount)) => builder = builder.mount(mount.clone()),
+        (None, None) => (),
+    }
+
+    if let Some(ref raid) = plan.raid {
+        builder = builder.raid(raid.clone());
+    }
+
+    if plan.esp_mirror {
+        builder = builder.esp_mirror();
+    }
+
+    Ok(builder.subvolumes(plan.subvolumes.clone()))
+}
+
+fn apply_raid(disks: &mut Disks, plan: &RaidPlan) -> Result<(), DiskError> {
+    // An assembled array is only reused when none of its members are new partitions.
+    let existing = disks.raid.iter().position(|array| {
+        array.name == plan.name
+            && array.is_source
+            && disks.get_raid_members(&plan.name).all(|member| member.flag_is_enabled(SOURCE))
+    });
+
+    let array = match existing {
+        Some(id) => &mut disks.raid[id],
+        None => disks.add_raid_device(&plan.name, plan.level)?,
+    };
+
+    if let Some(table) = plan.table {
+        array.disk.mklabel(table.into())?;
+    }
+
+    if let Some(ref lvm) = plan.lvm {
+        let encryption = lvm.encryption.as_ref().map(|enc| enc.to_encryption()).transpose()?;
+        array.set_volume_group(lvm.volume_group.clone(), encryption);
+    } else if let Some(ref fs) = plan.filesystem {
+        array.format_with(parse_fs(fs)?);
+    }
+
+    if let (Some(mount), Some(fs)) = (plan.mount.as_ref(), array.get_file_system_mut()) {
+        fs.set_mount(mount.clone());
+    }
+
+    for partition in &plan.partitions {
+        let builder = partition_builder(&array.disk, partition)?;
+        array.add_partition(builder)?;
+    }
+
+    Ok(())
+}
+
+fn apply_volume_group(disks: &mut Disks, plan: &VolumeGroupPlan) -> Result<(), DiskError> {
+    let device = disks
+        .get_logical_device_mut(&plan.name)
+        .ok_or_else(|| DiskError::PlanVolumeGroupNotFound { group: plan.name.clone() })?;
+
+    if plan.remove_all {
+        device.clear_partitions();
+    } else {
+        for volume in &plan.remove {
+            device.remove_partition(volume)?;
+        }
+    }
+
+    for modify in &plan.modify {
+        let fs = modify.filesystem.as_ref().map(|fs| parse_fs(fs)).transpose()?;
+        let partition = device.get_partition_mut(&modify.name).ok_or_else(|| {
+            DiskError::LogicalPartitionNotFound {
+                group:  plan.name.clone(),
+                volume: modify.name.clone(),
+            }
+        })?;
+
+        if let Some(fs) = fs {
+            partition.format_and_keep_name(fs);
+        }
+
+        if let Some(ref mount) = modify.mount {
+            partition.set_mount(mount.clone());
+        }
+
+        if !modify.subvolumes.is_empty() {
+            partition.set_subvolumes(modify.subvolumes.clone());
+        }
+    }
+
+    for volume in &plan.volumes {
+        let start = device.get_last_sector();
+        let end = start + device.get_sector(parse_sector(&volume.size)?);
+        let fs = volume.filesystem.as_ref().map(|fs| parse_fs(fs)).transpose()?;
+
+        let mut builder = PartitionBuilder::new(start, end, fs)
+            .name(volume.name.clone())
+            .flags(parse_flags(&volume.flags)?)
+            .subvolumes(volume.subvolumes.clone());
+
+        if let Some(ref mount) = volume.mount {
+            builder = builder.mount(mount.clone());
+        }
+
+        device.add_partition(builder)?;
+    }
+
+    Ok(())
+}
+
+fn parse_fs(fs: &str) -> Result<FileSystem, DiskError> {
+    fs.parse::<FileSystem>().map_err(|_| DiskError::PlanInvalidFileSystem { fs: fs.into() })
+}
+
+fn parse_flags(flags: &[String]) -> Result<Vec<PartitionFlag>, DiskError> {
+    flags
+        .iter()
+        .map(|flag| {
+            partition_flag_from_str(flag)
+                .ok_or_else(|| DiskError::PlanInvalidFlag { flag: flag.clone() })
+        })
+        .collect()
+}
+
+/// Parses a sector value, additionally accepting sizes in mebibytes with a `MiB` suffix.
+pub fn parse_sector(sector: &str) -> Result<Sector, DiskError> {
+    let result = if sector.ends_with("MiB") {
+        sector[..sector.len() - 3].parse::<i64>().ok().and_then(|mebibytes| {
+            format!("{}M", (mebibytes * 1_048_576) / 1_000_000).parse::<Sector>().ok()
+        })
+    } else {
+        sector.parse::<Sector>().ok()
+    };
+
+    result.ok_or_else(|| DiskError::PlanInvalidSector { value: sector.into() })
+}
+
+/// Parses an absolute size into a number of 512-byte sectors, from either raw sectors or a `M`
+/// or `MiB` suffixed size. A size in mebibytes is converted exactly.
+pub fn parse_size(size: &str) -> Result<u64, DiskError> {
+    if size.ends_with("MiB") {
+        return size[..size.len() - 3]
+            .parse::<u64>()
+            .map(|mebibytes| mebibytes * 2048)
+            .map_err(|_| DiskError::PlanInvalidSector { value: size.into() });
+    }
+
+    match parse_sector(size)? {
+        Sector::Unit(sectors) => Ok(sectors),
+        Sector::Megabyte(megabytes) => Ok(megabytes * 1_000_000 / 512),
+        _ => Err(DiskError::PlanInvalidSector { value: size.into() }),
+    }
+}
+
+/// Serializes partition flags by their names, for use with `#[serde(with)]`.
+pub(crate) mod flag_names {
+    use super::{partition_flag_from_str, partition_flag_to_str, PartitionFlag};
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        flags: &[PartitionFlag],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(flags.iter().filter_map(|&flag| {
+            let name = partition_flag_to_str(flag);
+            if name.is_none() {
+                warn!("{:?} has no name, and will not be serialized", flag);
+            }
+            name
+        }))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<PartitionFlag>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|flag| {
+                partition_flag_from_str(flag)
+                    .ok_or_else(|| D::Error::custom(format!("invalid partition flag: {}", flag)))
+            })
+            .collect()
+    }
+}
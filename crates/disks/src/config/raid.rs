@@ -0,0 +1,283 @@
+use super::{
+    super::{DiskError, DiskExt, FileSystem, LvmEncryption, PartitionError, PartitionInfo},
+    partitions::FORMAT,
+    Disk, PartitionBuilder, PartitionTable,
+};
+use crate::external::{mdadm_create, mkfs};
+use disk_types::{BlockDeviceExt, PartitionTableExt, SectorExt};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+    thread,
+    time::Duration,
+};
+
+/// The sectors of each member which are not available to an array: mdadm reserves up to
+/// 128 MiB for its superblock and bitmap, and the remainder is rounded down to a whole chunk.
+const RESERVED_SECTORS: u64 = 262_144 + 1_024;
+
+/// The levels of software RAID that arrays may be created with.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RaidLevel {
+    Raid0,
+    Raid1,
+    Raid5,
+    Raid10,
+}
+
+impl RaidLevel {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RaidLevel::Raid0 => "raid0",
+            RaidLevel::Raid1 => "raid1",
+            RaidLevel::Raid5 => "raid5",
+            RaidLevel::Raid10 => "raid10",
+        }
+    }
+
+    /// The fewest members that an array of this level may be created with.
+    pub fn minimum_members(self) -> usize {
+        match self {
+            RaidLevel::Raid5 => 3,
+            _ => 2,
+        }
+    }
+
+    /// The sectors that an array of this level can store, given the number of its members and
+    /// the sectors of its smallest member.
+    pub fn capacity(self, members: usize, sectors: u64) -> u64 {
+        let sectors = sectors.saturating_sub(RESERVED_SECTORS);
+        let members = members as u64;
+        match self {
+            RaidLevel::Raid0 => sectors * members,
+            RaidLevel::Raid1 => sectors,
+            RaidLevel::Raid5 => sectors * members.saturating_sub(1),
+            RaidLevel::Raid10 => sectors * members / 2,
+        }
+    }
+}
+
+impl fmt::Display for RaidLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { f.write_str(self.as_str()) }
+}
+
+impl FromStr for RaidLevel {
+    type Err = &'static str;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "raid0" | "0" => Ok(RaidLevel::Raid0),
+            "raid1" | "1" => Ok(RaidLevel::Raid1),
+            "raid5" | "5" => Ok(RaidLevel::Raid5),
+            "raid10" | "10" => Ok(RaidLevel::Raid10),
+            _ => Err("RAID level must be one of raid0, raid1, raid5, or raid10"),
+        }
+    }
+}
+
+/// A software RAID array, which mdadm assembles from partitions of the physical disks.
+///
+/// Partitions become members of an array by naming it in their `raid` field. The array itself
+/// acts as a disk, so it may be given a partition table, or a file system or LVM physical
+/// volume which spans the entire array.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct RaidDevice {
+    /// The name of the array, which new arrays are created with at `/dev/md/<name>`.
+    pub name:      String,
+    pub level:     RaidLevel,
+    /// Whether the array was assembled before the install.
+    pub is_source: bool,
+    /// The block device of the array, and its partitions.
+    pub disk:      Disk,
+}
+
+impl BlockDeviceExt for RaidDevice {
+    fn get_device_path(&self) -> &Path { &self.disk.device_path }
+
+    fn get_mount_point(&self) -> Option<&Path> { self.disk.mount_point.as_deref() }
+}
+
+impl SectorExt for RaidDevice {
+    fn get_sectors(&self) -> u64 { self.disk.size }
+}
+
+impl PartitionTableExt for RaidDevice {
+    fn get_partition_table(&self) -> Option<PartitionTable> { self.disk.table_type }
+
+    fn get_partition_type_count(&self) -> (usize, usize, bool) {
+        self.disk.get_partition_type_count()
+    }
+}
+
+impl DiskExt for RaidDevice {
+    const LOGICAL: bool = false;
+
+    fn get_file_system(&self) -> Option<&PartitionInfo> { self.disk.file_system.as_ref() }
+
+    fn get_file_system_mut(&mut self) -> Option<&mut PartitionInfo> {
+        self.disk.file_system.as_mut()
+    }
+
+    fn set_file_system(&mut self, mut fs: PartitionInfo) {
+        fs.device_path = self.disk.device_path.clone();
+        self.disk.file_system = Some(fs);
+        self.disk.table_type = None;
+        self.disk.mklabel = false;
+        self.disk.partitions.clear();
+    }
+
+    fn get_model(&self) -> &str { &self.disk.model_name }
+
+    fn get_partitions_mut(&mut self) -> &mut [PartitionInfo] { &mut self.disk.partitions }
+
+    fn get_partitions(&self) -> &[PartitionInfo] { &self.disk.partitions }
+
+    fn push_partition(&mut self, partition: PartitionInfo) { self.disk.partitions.push(partition); }
+}
+
+impl RaidDevice {
+    /// Defines a new array of the given size, which will be created when the disks are
+    /// committed.
+    pub fn new(name: String, level: RaidLevel, sectors: u64) -> RaidDevice {
+        RaidDevice {
+            disk: Disk {
+                model_name:  [level.as_str(), " ", &name].concat(),
+                serial:      String::new(),
+                device_path: PathBuf::from(["/dev/md/", &name].concat()),
+                file_system: None,
+                mount_point: None,
+                size:        sectors,
+                device_type: "PED_DEVICE_MD".into(),
+                table_type:  None,
+                read_only:   false,
+                mklabel:     false,
+                partitions:  Vec::new(),
+            },
+            name,
+            level,
+            is_source: false,
+        }
+    }
+
+    /// An array which is already assembled, as it was probed.
+    pub fn from_disk(name: String, level: RaidLevel, disk: Disk) -> RaidDevice {
+        RaidDevice { name, level, is_source: true, disk }
+    }
+
+    /// Formats the entire array with a file system, in place of a partition table.
+    pub fn format_with(&mut self, fs: FileSystem) {
+        let partition = PartitionBuilder::new(0, self.get_sectors(), fs).build();
+        self.set_file_system(partition);
+    }
+
+    /// Uses the entire array as a LVM physical volume, which may optionally be encrypted.
+    pub fn set_volume_group(&mut self, group: String, encryption: Option<LvmEncryption>) {
+        let partition = PartitionBuilder::new(0, self.get_sectors(), None)
+            .logical_volume(group, encryption)
+            .build();
+        self.set_file_system(partition);
+    }
+
+    /// Creates the array from the given members if it does not exist yet, and then applies its
+    /// partition table, or the file system which spans it.
+    pub(crate) fn commit(&mut self, members: &[PathBuf]) -> Result<(), DiskError> {
+        if !self.is_source {
+            info!("creating {} array {} from {:?}", self.level, self.name, members);
+            let raid_error = |why| DiskError::RaidCreate { name: self.name.clone(), why };
+            mdadm_create(&self.disk.device_path, self.level.as_str(), members)
+                .map_err(raid_error)?;
+
+            // The partitions of the array are named after its kernel name, such as
+            // `/dev/md127p1`, so the symbolic link of the array is resolved.
+            let device_path = wait_for_device(&self.disk.device_path).map_err(raid_error)?;
+            self.disk.serial = Disk::from_name(&device_path)?.serial;
+            self.disk.device_path = device_path.clone();
+            if let Some(ref mut fs) = self.disk.file_system {
+                fs.device_path = device_path;
+            }
+        }
+
+        if self.disk.is_being_modified() {
+            if let Some(partitions) = self.disk.commit()? {
+                partitions.format().map_err(|why| DiskError::DiskCommit {
+                    device: self.disk.device_path.clone(),
+                    why,
+                })?;
+            }
+
+            self.disk.reload()?;
+        }
+
+        match self.disk.file_system {
+            // Physical volumes are created when the logical devices are committed.
+            Some(ref fs) if fs.volume_group.is_none() && fs.flag_is_enabled(FORMAT) => {
+                if let Some(kind) = fs.filesystem {
+                    mkfs(&fs.device_path, kind).map_err(|why| {
+                        DiskError::new_partition_error(
+                            fs.device_path.clone(),
+                            PartitionError::PartitionFormat { why },
+                        )
+                    })?;
+                }
+            }
+            _ => (),
+        }
+
+        Ok(())
+    }
+}
+
+/// Waits up to five seconds for udev to create the device of a new array, and resolves it.
+fn wait_for_device(path: &Path) -> std::io::Result<PathBuf> {
+    let mut attempt = 0;
+    loop {
+        match path.canonicalize() {
+            Ok(resolved) => return Ok(resolved),
+            Err(why) if attempt == 5 => return Err(why),
+            Err(_) => {
+                info!("waiting 1 second because {:?} does not exist yet", path);
+                attempt += 1;
+                thread::sleep(Duration::from_millis(1000));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn level_capacity() {
+        let sectors = 1_000_000 + RESERVED_SECTORS;
+        assert_eq!(RaidLevel::Raid0.capacity(3, sectors), 3_000_000);
+        assert_eq!(RaidLevel::Raid1.capacity(3, sectors), 1_000_000);
+        assert_eq!(RaidLevel::Raid5.capacity(3, sectors), 2_000_000);
+        assert_eq!(RaidLevel::Raid10.capacity(4, sectors), 2_000_000);
+        assert_eq!(RaidLevel::Raid1.capacity(2, 1_024), 0);
+    }
+
+    #[test]
+    fn level_names() {
+        for &level in &[RaidLevel::Raid0, RaidLevel::Raid1, RaidLevel::Raid5, RaidLevel::Raid10] {
+            assert_eq!(level.as_str().parse::<RaidLevel>(), Ok(level));
+        }
+
+        assert_eq!("1".parse::<RaidLevel>(), Ok(RaidLevel::Raid1));
+        assert!("raid6".parse::<RaidLevel>().is_err());
+    }
+
+    #[test]
+    fn whole_array_file_system() {
+        let mut array = RaidDevice::new("data".into(), RaidLevel::Raid1, 1_000_000);
+        assert_eq!(array.get_device_path(), Path::new("/dev/md/data"));
+
+        array.set_volume_group("data".into(), None);
+        let fs = array.get_file_system().unwrap();
+        assert_eq!(fs.device_path, Path::new("/dev/md/data"));
+        assert_eq!(fs.filesystem, Some(FileSystem::Lvm));
+        assert_eq!(fs.volume_group, Some(("data".into(), None)));
+    }
+}
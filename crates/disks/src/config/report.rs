@@ -0,0 +1,525 @@
+//! Dry runs of a disks configuration, which describe every change that committing the
+//! configuration would make, without modifying any block devices.
+
+use super::{
+    super::{DiskError, DiskExt, PartitionFlag, PartitionTable, PartitionType},
+    disks::MountKind,
+    partition_flag_to_str,
+    partitions::{FORMAT, REMOVE, SOURCE},
+    Disk, Disks, PartitionInfo, RaidDevice,
+};
+use disk_types::BlockDeviceExt;
+use std::path::{Path, PathBuf};
+
+/// Identifies a block device within a dry run report.
+///
+/// Partitions which have yet to be created do not have a device path, so they are identified
+/// by the disk that they will be created on, and the sector that they will start at.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum ReportDevice {
+    Path(PathBuf),
+    New { disk: PathBuf, start_sector: u64 },
+}
+
+/// A single action that committing a disks configuration will perform.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "action", rename_all = "kebab-case")]
+pub enum DiskAction {
+    /// Writes a new partition table, which clobbers all existing partitions.
+    Mklabel { disk: PathBuf, table: String },
+    /// Removes the partition which starts at the given sector.
+    RemovePartition { disk: PathBuf, start_sector: u64 },
+    /// Moves, resizes, relabels, or sets flags on an existing partition.
+    ChangePartition {
+        disk:             PathBuf,
+        partition:        PathBuf,
+        number:           i32,
+        old_start_sector: u64,
+        old_end_sector:   u64,
+        start_sector:     u64,
+        end_sector:       u64,
+        flags:            Vec<String>,
+        label:            Option<String>,
+    },
+    /// Creates a new partition.
+    CreatePartition {
+        disk:         PathBuf,
+        start_sector: u64,
+        end_sector:   u64,
+        kind:         String,
+        file_system:  Option<String>,
+        flags:        Vec<String>,
+        label:        Option<String>,
+    },
+    /// Creates a new file system on a device.
+    Format { device: ReportDevice, file_system: String },
+    /// Creates a software RAID array from the given members with `mdadm --create`.
+    RaidCreate { array: PathBuf, level: String, members: Vec<ReportDevice> },
+    /// Formats a device as a LUKS container.
+    LuksFormat { device: ReportDevice, physical_volume: String },
+    /// Opens a LUKS container as `/dev/mapper/{physical_volume}`.
+    LuksOpen { device: ReportDevice, physical_volume: String },
+    /// Creates a LVM physical volume.
+    PhysicalVolumeCreate { device: PathBuf },
+    /// Creates a LVM volume group from the given devices.
+    VolumeGroupCreate { volume_group: String, devices: Vec<ReportDevice> },
+    /// Creates a logical volume, which consumes the remaining space in the group if it has no
+    /// size.
+    LogicalVolumeCreate { volume_group: String, volume: String, size: Option<u64> },
+    /// Removes an existing logical volume.
+    LogicalVolumeRemove { volume_group: String, volume: String },
+    /// Creates a btrfs subvolume on a device, unless it already exists.
+    SubvolumeCreate { device: ReportDevice, subvolume: String },
+    /// Mounts a device to a target within the install's root, or bind mounts the location
+    /// where it is already mounted.
+    Mount {
+        device:      ReportDevice,
+        target:      PathBuf,
+        file_system: Option<String>,
+        options:     Option<String>,
+        bind:        bool,
+    },
+    /// Creates a swap file of the given number of sectors within the mounted root.
+    SwapFileCreate { path: PathBuf, sectors: u64 },
+}
+
+/// The ordered list of actions that committing a disks configuration will perform.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct DisksReport {
+    pub actions: Vec<DiskAction>,
+}
+
+impl Disks {
+    /// Computes every action that the installer would perform to commit and mount this
+    /// configuration, without modifying any block devices.
+    ///
+    /// The existing layout of each disk is probed so that it may be compared against the
+    /// configured layout, in the same manner as `Disk::commit`.
+    pub fn dry_run(&self) -> Result<DisksReport, DiskError> {
+        let mut disks = self.clone();
+        disks.remove_untouched_disks();
+
+        let mut actions = Vec::new();
+        let mut formats = Vec::new();
+
+        for disk in &disks.physical {
+            diff_actions(disk, &mut actions, &mut formats)?;
+        }
+
+        // Partitions are formatted after every disk has been partitioned.
+        actions.extend(formats);
+
+        // Arrays are assembled from the partitions of the physical disks once they are formatted.
+        for array in &disks.raid {
+            actions.extend(raid_actions(&disks, array)?);
+        }
+
+        for device in &disks.logical {
+            if device.encryption.is_some() && volume_devices(&disks, &device.volume_group).len() > 1
+            {
+                return Err(DiskError::SameGroup);
+            }
+            device.validate()?;
+        }
+
+        disks.assign_logical_device_paths();
+        disks.resolve_keyfile_paths()?;
+
+        for device in &disks.logical {
+            if !device.is_source {
+                let mut volumes = volume_devices(&disks, &device.volume_group);
+                if volumes.is_empty() {
+                    return Err(DiskError::VolumeGroupWithoutDevices {
+                        vg: device.volume_group.clone(),
+                    });
+                }
+
+                if let Some(encryption) = device.encryption.as_ref() {
+                    let pv = encryption.physical_volume.clone();
+                    let mapped = PathBuf::from(["/dev/mapper/", &pv].concat());
+                    let luks = volumes.swap_remove(0);
+                    actions.push(DiskAction::LuksFormat {
+                        device:          luks.clone(),
+                        physical_volume: pv.clone(),
+                    });
+                    actions.push(DiskAction::LuksOpen { device: luks, physical_volume: pv });
+                    actions.push(DiskAction::PhysicalVolumeCreate { device: mapped.clone() });
+                    volumes = vec![ReportDevice::Path(mapped)];
+                }
+
+                actions.push(DiskAction::VolumeGroupCreate {
+                    volume_group: device.volume_group.clone(),
+                    devices:      volumes,
+                });
+            }
+
+            for (size, partition) in device.volumes_with_sizes() {
+                let volume =
+                    partition.name.clone().expect("logical partitions should have names");
+
+                if !partition.flag_is_enabled(SOURCE) {
+                    actions.push(DiskAction::LogicalVolumeCreate {
+                        volume_group: device.volume_group.clone(),
+                        volume: volume.clone(),
+                        size,
+                    });
+                }
+
+                if partition.flag_is_enabled(REMOVE) {
+                    actions.push(DiskAction::LogicalVolumeRemove {
+                        volume_group: device.volume_group.clone(),
+                        volume,
+                    });
+                } else if partition.flag_is_enabled(FORMAT) {
+                    if let Some(fs) = partition.filesystem {
+                        actions.push(DiskAction::Format {
+                            device:      ReportDevice::Path(partition.device_path.clone()),
+                            file_system: Into::<&str>::into(fs).into(),
+                        });
+                    }
+                }
+            }
+        }
+
+        for partition in disks.get_partitions() {
+            if let Some(ref target) = partition.target {
+                for subvolume in &partition.subvolumes {
+                    actions.push(DiskAction::SubvolumeCreate {
+                        device:    report_device(&disks, &partition.device_path, target),
+                        subvolume: subvolume.name.clone(),
+                    });
+                }
+            }
+        }
+
+        for (target, kind) in disks.mount_targets(Path::new("/")) {
+            let action = match kind {
+                MountKind::Direct { device, fs, options } => DiskAction::Mount {
+                    device: report_device(&disks, &device, &target),
+                    target,
+                    file_system: Some(fs.into()),
+                    options,
+                    bind: false,
+                },
+                MountKind::Bind { source } => DiskAction::Mount {
+                    device: ReportDevice::Path(source),
+                    target,
+                    file_system: None,
+                    options: None,
+                    bind: true,
+                },
+            };
+
+            actions.push(action);
+        }
+
+        if let Some(ref swap) = disks.swap_file {
+            actions.push(DiskAction::SwapFileCreate {
+                path:    swap.path.clone(),
+                sectors: swap.sectors,
+            });
+        }
+
+        Ok(DisksReport { actions })
+    }
+}
+
+/// Compares the existing layout of a disk against its configured layout, and records the
+/// actions which committing the disk would perform.
+fn diff_actions(
+    disk: &Disk,
+    actions: &mut Vec<DiskAction>,
+    formats: &mut Vec<DiskAction>,
+) -> Result<(), DiskError> {
+    info!("generating dry run of {}", disk.path().display());
+    let source = Disk::from_name_with_serial(&disk.device_path, &disk.serial)?;
+    let ops = source.diff(disk)?;
+    let disk_path = disk.device_path.clone();
+
+    if let Some(table) = ops.mklabel {
+        actions.push(DiskAction::Mklabel {
+            disk:  disk_path.clone(),
+            table: table_name(table).into(),
+        });
+    }
+
+    for &start_sector in &ops.remove_partitions {
+        actions.push(DiskAction::RemovePartition { disk: disk_path.clone(), start_sector });
+    }
+
+    for change in &ops.change_partitions {
+        let (old_start_sector, old_end_sector) = source
+            .get_partition(change.num)
+            .map_or((change.start, change.end), |p| (p.start_sector, p.end_sector));
+
+        actions.push(DiskAction::ChangePartition {
+            disk: disk_path.clone(),
+            partition: change.path.clone(),
+            number: change.num,
+            old_start_sector,
+            old_end_sector,
+            start_sector: change.start,
+            end_sector: change.end,
+            flags: flag_names(&change.new_flags),
+            label: change.label.clone(),
+        });
+    }
+
+    for create in &ops.create_partitions {
+        actions.push(DiskAction::CreatePartition {
+            disk:         disk_path.clone(),
+            start_sector: create.start_sector,
+            end_sector:   create.end_sector,
+            kind:         kind_name(create.kind).into(),
+            file_system:  create.file_system.map(|fs| Into::<&str>::into(fs).to_owned()),
+            flags:        flag_names(&create.flags),
+            label:        create.label.clone(),
+        });
+
+        if create.kind != PartitionType::Extended {
+            if let Some(fs) = create.file_system {
+                formats.push(DiskAction::Format {
+                    device:      ReportDevice::New {
+                        disk:         disk_path.clone(),
+                        start_sector: create.start_sector,
+                    },
+                    file_system: Into::<&str>::into(fs).into(),
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The actions which committing an array performs: creating the array if it does not exist
+/// yet, partitioning it, and formatting the file system which spans it.
+fn raid_actions(disks: &Disks, array: &RaidDevice) -> Result<Vec<DiskAction>, DiskError> {
+    let mut actions = Vec::new();
+    let mut formats = Vec::new();
+    let disk = &array.disk;
+
+    if array.is_source {
+        if disk.is_being_modified() {
+            diff_actions(disk, &mut actions, &mut formats)?;
+        }
+    } else {
+        let members = disks
+            .physical
+            .iter()
+            .flat_map(|disk| {
+                disk.get_partitions()
+                    .iter()
+                    .filter(|p| p.raid.as_deref() == Some(array.name.as_str()))
+                    .map(move |p| partition_device(disk, p))
+            })
+            .collect();
+
+        actions.push(DiskAction::RaidCreate {
+            array: disk.device_path.clone(),
+            level: array.level.as_str().into(),
+            members,
+        });
+
+        // A new array has no existing layout to compare against.
+        if let Some(table) = disk.table_type {
+            actions.push(DiskAction::Mklabel {
+                disk:  disk.device_path.clone(),
+                table: table_name(table).into(),
+            });
+        }
+
+        for partition in disk.get_partitions() {
+            actions.push(DiskAction::CreatePartition {
+                disk:         disk.device_path.clone(),
+                start_sector: partition.start_sector,
+                end_sector:   partition.end_sector,
+                kind:         kind_name(partition.part_type).into(),
+                file_system:  partition.filesystem.map(|fs| Into::<&str>::into(fs).to_owned()),
+                flags:        flag_names(&partition.flags),
+                label:        partition.name.clone(),
+            });
+
+            if partition.part_type != PartitionType::Extended {
+                if let Some(fs) = partition.filesystem.filter(|_| partition.volume_group.is_none())
+                {
+                    formats.push(DiskAction::Format {
+                        device:      partition_device(disk, partition),
+                        file_system: Into::<&str>::into(fs).into(),
+                    });
+                }
+            }
+        }
+    }
+
+    actions.extend(formats);
+
+    // Physical volumes are created when the logical devices are committed.
+    if let Some(ref fs) = disk.file_system {
+        if fs.volume_group.is_none() && fs.flag_is_enabled(FORMAT) {
+            if let Some(kind) = fs.filesystem {
+                actions.push(DiskAction::Format {
+                    device:      ReportDevice::Path(fs.device_path.clone()),
+                    file_system: Into::<&str>::into(kind).into(),
+                });
+            }
+        }
+    }
+
+    Ok(actions)
+}
+
+/// Identifies the partitions and arrays that are members of the given volume group.
+fn volume_devices(disks: &Disks, volume_group: &str) -> Vec<ReportDevice> {
+    let mut devices = Vec::new();
+
+    let physical = disks.physical.iter().map(|disk| (disk, None));
+    let arrays = disks.raid.iter().map(|array| (&array.disk, array.get_file_system()));
+
+    for (disk, file_system) in physical.chain(arrays) {
+        for partition in file_system.into_iter().chain(disk.get_partitions()) {
+            let vg = partition
+                .volume_group
+                .as_ref()
+                .map(|x| &x.0)
+                .or_else(|| partition.original_vg.as_ref());
+
+            if vg.map_or(false, |vg| vg.as_str() == volume_group) {
+                devices.push(partition_device(disk, partition));
+            }
+        }
+    }
+
+    devices
+}
+
+/// Partitions which have yet to be created are identified by their disk and start sector.
+fn partition_device(disk: &Disk, partition: &PartitionInfo) -> ReportDevice {
+    if partition.device_path.as_os_str().is_empty() {
+        ReportDevice::New {
+            disk:         disk.get_device_path().to_path_buf(),
+            start_sector: partition.start_sector,
+        }
+    } else {
+        ReportDevice::Path(partition.device_path.clone())
+    }
+}
+
+/// New partitions lack a device path, so they are found by their mount target.
+fn report_device(disks: &Disks, device: &Path, target: &Path) -> ReportDevice {
+    if !device.as_os_str().is_empty() {
+        return ReportDevice::Path(device.to_path_buf());
+    }
+
+    disks
+        .physical
+        .iter()
+        .chain(disks.raid.iter().map(|array| &array.disk))
+        .find_map(|disk| {
+            disk.get_partitions()
+                .iter()
+                .find(|p| p.is_mounted_at(target))
+                .map(|p| ReportDevice::New {
+                    disk:         disk.get_device_path().to_path_buf(),
+                    start_sector: p.start_sector,
+                })
+        })
+        .unwrap_or_else(|| ReportDevice::Path(device.to_path_buf()))
+}
+
+fn flag_names(flags: &[PartitionFlag]) -> Vec<String> {
+    flags
+        .iter()
+        .map(|&flag| partition_flag_to_str(flag).map_or_else(|| format!("{:?}", flag), String::from))
+        .collect()
+}
+
+fn kind_name(kind: PartitionType) -> &'static str {
+    match kind {
+        PartitionType::Primary => "primary",
+        PartitionType::Logical => "logical",
+        PartitionType::Extended => "extended",
+    }
+}
+
+fn table_name(table: PartitionTable) -> &'static str {
+    match table {
+        PartitionTable::Gpt => "gpt",
+        PartitionTable::Msdos => "msdos",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::{FileSystem, PartitionBuilder, RaidLevel},
+        *,
+    };
+
+    const GIB: u64 = 2_097_152;
+
+    fn raid_disk(path: &str) -> Disk {
+        let mut disk = Disk {
+            mklabel:     true,
+            model_name:  "Test Disk".into(),
+            serial:      [path, " 123"].concat(),
+            device_path: path.into(),
+            file_system: None,
+            mount_point: None,
+            size:        100 * GIB,
+            device_type: "TEST".into(),
+            table_type:  Some(PartitionTable::Gpt),
+            read_only:   false,
+            partitions:  Vec::new(),
+        };
+
+        disk.add_partition(PartitionBuilder::new(2048, GIB / 2, FileSystem::Fat32)).unwrap();
+        disk.add_partition(
+            PartitionBuilder::new(GIB / 2, 100 * GIB - 34, None).raid("root".into()),
+        )
+        .unwrap();
+        disk
+    }
+
+    #[test]
+    fn volume_group_on_raid1() {
+        let mut disks = Disks::default();
+        disks.physical.push(raid_disk("/dev/sdy"));
+        disks.physical.push(raid_disk("/dev/sdz"));
+        disks
+            .add_raid_device("root", RaidLevel::Raid1)
+            .unwrap()
+            .set_volume_group("data".into(), None);
+
+        assert_eq!(volume_devices(&disks, "data"), vec![ReportDevice::Path("/dev/md/root".into())]);
+
+        let member =
+            |disk: &str| ReportDevice::New { disk: disk.into(), start_sector: GIB / 2 };
+        assert_eq!(
+            raid_actions(&disks, &disks.raid[0]).unwrap(),
+            vec![DiskAction::RaidCreate {
+                array:   "/dev/md/root".into(),
+                level:   "raid1".into(),
+                members: vec![member("/dev/sdy"), member("/dev/sdz")],
+            }]
+        );
+    }
+
+    #[test]
+    fn raid1_file_system() {
+        let mut disks = Disks::default();
+        disks.physical.push(raid_disk("/dev/sdy"));
+        disks.physical.push(raid_disk("/dev/sdz"));
+        disks.add_raid_device("root", RaidLevel::Raid1).unwrap().format_with(FileSystem::Ext4);
+
+        assert!(volume_devices(&disks, "data").is_empty());
+        assert_eq!(
+            raid_actions(&disks, &disks.raid[0]).unwrap()[1],
+            DiskAction::Format {
+                device:      ReportDevice::Path("/dev/md/root".into()),
+                file_system: "ext4".into(),
+            }
+        );
+    }
+}
@@ -0,0 +1,27 @@
+use std::path::{Path, PathBuf};
+
+/// A swap file within the root file system, which may be used in place of a swap partition.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SwapFile {
+    /// Where the swap file will be created, relative to the root of the install.
+    pub path:    PathBuf,
+    /// The size of the swap file, in 512-byte sectors.
+    pub sectors: u64,
+}
+
+impl SwapFile {
+    /// The default location of a swap file.
+    pub const PATH: &'static str = "/swapfile";
+
+    /// A swap file at the default location, which is `sectors` in size.
+    pub fn new(sectors: u64) -> SwapFile { SwapFile { path: PathBuf::from(Self::PATH), sectors } }
+
+    /// The size of the swap file, in bytes.
+    pub fn bytes(&self) -> u64 { self.sectors * 512 }
+
+    /// Where the swap file will be created, when the root of the install is mounted at `root`.
+    pub fn path_within(&self, root: &Path) -> PathBuf {
+        root.join(self.path.strip_prefix("/").unwrap_or(&self.path))
+    }
+}
@@ -0,0 +1,500 @@
+//! Backups of the partition tables of disks, which are taken before changes are committed to
+//! them, so that the partitions of a disk may be restored after an install has failed.
+//!
+//! The sectors which hold a table are copied as they are: for GPT, the protective MBR, both
+//! headers, and both partition entry arrays; and for MBR, the boot sector and every extended
+//! boot record in the chain of logical partitions.
+
+use super::{Disk, Disks};
+use crate::{external::blockdev, serial::get_serial, DiskError};
+use disk_types::{BlockDeviceExt, PartitionTable};
+use operations::native::sector_size;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    os::unix::fs::{FileTypeExt, OpenOptionsExt},
+    path::{Path, PathBuf},
+};
+
+const BOOT_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+const GPT_SIGNATURE: &[u8] = b"EFI PART";
+
+/// Partition types of the MBR which contain logical partitions.
+const EXTENDED_TYPES: [u8; 3] = [0x05, 0x0F, 0x85];
+
+/// Limits the chain of extended boot records that is followed, in case it loops.
+const MAX_LOGICAL_PARTITIONS: usize = 128;
+
+/// Larger partition entry arrays are assumed to be corrupt.
+const MAX_GPT_ENTRIES_SIZE: u64 = 1 << 20;
+
+/// A range of bytes of a disk which holds a part of its partition table.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct TableRegion {
+    /// The offset of the region from the start of the disk, in bytes.
+    pub offset: u64,
+    #[serde(with = "hex")]
+    pub data:   Vec<u8>,
+}
+
+/// A copy of the partition table of a disk.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct TableBackup {
+    pub device:      PathBuf,
+    /// The serial of the disk, which must match that of the disk that it is restored to.
+    pub serial:      String,
+    pub table:       PartitionTable,
+    /// The size of the disk in bytes, which must not have changed when it is restored.
+    pub size:        u64,
+    pub sector_size: u64,
+    pub regions:     Vec<TableRegion>,
+}
+
+impl TableBackup {
+    /// Reads the partition table of a disk, if it has one.
+    pub fn read(device: &Path, serial: &str) -> io::Result<Option<Self>> {
+        let mut file = File::open(device)?;
+        let size = file.seek(SeekFrom::End(0))?;
+        let sector_size = sector_size(&file)?;
+
+        let mbr = read_at(&mut file, 0, sector_size)?;
+        if mbr[510..512] != BOOT_SIGNATURE {
+            return Ok(None);
+        }
+
+        let header = read_at(&mut file, sector_size, sector_size)?;
+        let (table, regions) = if header.starts_with(GPT_SIGNATURE) {
+            (PartitionTable::Gpt, gpt_regions(&mut file, sector_size, mbr, header)?)
+        } else {
+            (PartitionTable::Msdos, mbr_regions(&mut file, sector_size, mbr)?)
+        };
+
+        Ok(Some(TableBackup {
+            device: device.to_path_buf(),
+            serial: serial.to_owned(),
+            table,
+            size,
+            sector_size,
+            regions,
+        }))
+    }
+
+    /// Writes the table back to its disk, and then has the kernel reread it.
+    ///
+    /// Partitions which were created after the backup are lost, though their file systems
+    /// are not erased. When an MBR is restored, the headers of a GPT which was written after
+    /// the backup are zeroed, as the kernel and partitioning tools would otherwise prefer it.
+    pub fn restore(&self) -> io::Result<()> {
+        let mut file = OpenOptions::new().read(true).write(true).open(&self.device)?;
+
+        let size = file.seek(SeekFrom::End(0))?;
+        if size != self.size {
+            let why = format!("disk is {} bytes, but was {} bytes when backed up", size, self.size);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, why));
+        }
+
+        if self.table == PartitionTable::Msdos {
+            let last = size / self.sector_size - 1;
+            for &lba in &[1, last] {
+                // Sectors which do not hold a GPT header may belong to a partition.
+                let offset = lba * self.sector_size;
+                if read_at(&mut file, offset, self.sector_size)?.starts_with(GPT_SIGNATURE) {
+                    file.seek(SeekFrom::Start(offset))?;
+                    file.write_all(&vec![0; self.sector_size as usize])?;
+                }
+            }
+        }
+
+        for region in &self.regions {
+            file.seek(SeekFrom::Start(region.offset))?;
+            file.write_all(&region.data)?;
+        }
+
+        file.sync_all()?;
+
+        if file.metadata()?.file_type().is_block_device() {
+            blockdev(&self.device, &["--flushbufs", "--rereadpt"])?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Disks {
+    /// Backs up the partition tables of the physical disks in this configuration to `path`,
+    /// before their changes are committed. Disks which will not be changed, or which do not
+    /// have a table, are skipped.
+    pub fn backup_tables<P: AsRef<Path>>(&self, path: P) -> Result<(), DiskError> {
+        let path = path.as_ref();
+        let mut backups = Vec::new();
+
+        for disk in &self.physical {
+            let device = disk.get_device_path();
+            let source = Disk::from_name_with_serial(&disk.device_path, &disk.serial)?;
+            let ops = source.diff(disk)?;
+            if ops.is_empty() && ops.mklabel.is_none() {
+                info!(
+                    "{} is not changed, so its partition table is not backed up",
+                    device.display()
+                );
+                continue;
+            }
+
+            info!("backing up the partition table of {}", device.display());
+            let backup = TableBackup::read(device, &disk.serial)
+                .map_err(|why| DiskError::TableBackup { device: device.to_path_buf(), why })?;
+            backups.extend(backup);
+        }
+
+        write_backups(path, &backups)
+            .map_err(|why| DiskError::TableBackupFile { path: path.to_path_buf(), why })
+    }
+
+    /// Restores the partition tables that were backed up to `path` by `backup_tables`, and
+    /// returns the result of each disk: the disk which was restored, or why it was not.
+    ///
+    /// Every disk is attempted, but a disk whose serial or size no longer matches is not
+    /// restored. Nothing is restored while the partition move journaled at `move_journal` is
+    /// unfinished, as the old table would point into partially moved data.
+    pub fn restore_tables<P: AsRef<Path>>(
+        path: P,
+        move_journal: Option<&Path>,
+    ) -> Result<Vec<Result<PathBuf, DiskError>>, DiskError> {
+        let path = path.as_ref();
+        if let Some(journal) = move_journal.filter(|journal| journal.exists()) {
+            return Err(DiskError::TableRestoreDuringMove { journal: journal.to_path_buf() });
+        }
+
+        let backups = read_backups(path)
+            .map_err(|why| DiskError::TableBackupFile { path: path.to_path_buf(), why })?;
+
+        let results = backups
+            .into_iter()
+            .map(|backup| {
+                info!("restoring the partition table of {}", backup.device.display());
+                restore_backup(&backup)
+                    .map(|_| backup.device.clone())
+                    .map_err(|why| DiskError::TableRestore { device: backup.device, why })
+            })
+            .collect();
+
+        Ok(results)
+    }
+}
+
+/// Restores a backup, if it is of the same disk that it was taken from.
+fn restore_backup(backup: &TableBackup) -> io::Result<()> {
+    if !backup.serial.is_empty() {
+        let serial = get_serial(&backup.device).unwrap_or_default();
+        if serial != backup.serial {
+            let why = format!("serial {:?} does not match {:?}", serial, backup.serial);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, why));
+        }
+    }
+
+    backup.restore()
+}
+
+fn read_backups(path: &Path) -> io::Result<Vec<TableBackup>> {
+    let data = fs::read(path)?;
+    serde_json::from_slice(&data).map_err(|why| io::Error::new(io::ErrorKind::InvalidData, why))
+}
+
+/// The backups are written to a temporary file that is then renamed, so that a partially
+/// written backup is never restored.
+fn write_backups(path: &Path, backups: &[TableBackup]) -> io::Result<()> {
+    let data = serde_json::to_vec_pretty(backups)
+        .map_err(|why| io::Error::new(io::ErrorKind::InvalidData, why))?;
+
+    let temporary = path.with_extension("partial");
+    let mut file =
+        OpenOptions::new().create(true).write(true).truncate(true).mode(0o600).open(&temporary)?;
+
+    file.write_all(&data).and_then(|_| file.sync_all()).and_then(|_| fs::rename(&temporary, path))
+}
+
+fn read_at(file: &mut File, offset: u64, length: u64) -> io::Result<Vec<u8>> {
+    let mut data = vec![0; length as usize];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut data)?;
+    Ok(data)
+}
+
+fn le_u32(data: &[u8]) -> u64 {
+    u64::from(u32::from_le_bytes([data[0], data[1], data[2], data[3]]))
+}
+
+fn le_u64(data: &[u8]) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&data[..8]);
+    u64::from_le_bytes(bytes)
+}
+
+fn gpt_regions(
+    file: &mut File,
+    sector_size: u64,
+    mbr: Vec<u8>,
+    header: Vec<u8>,
+) -> io::Result<Vec<TableRegion>> {
+    let entries = gpt_entries(file, sector_size, &header)?;
+    let backup_offset = le_u64(&header[32..40]) * sector_size;
+
+    let mut regions = vec![
+        TableRegion { offset: 0, data: mbr },
+        TableRegion { offset: sector_size, data: header },
+        entries,
+    ];
+
+    // The backup header at the end of the disk, which points to its own copy of the entries.
+    let backup = read_at(file, backup_offset, sector_size)?;
+    if backup.starts_with(GPT_SIGNATURE) {
+        regions.push(gpt_entries(file, sector_size, &backup)?);
+        regions.push(TableRegion { offset: backup_offset, data: backup });
+    } else {
+        warn!("backup GPT header is missing, so only the primary header is backed up");
+    }
+
+    Ok(regions)
+}
+
+fn gpt_entries(file: &mut File, sector_size: u64, header: &[u8]) -> io::Result<TableRegion> {
+    let offset = le_u64(&header[72..80]) * sector_size;
+    let length = le_u32(&header[80..84]) * le_u32(&header[84..88]);
+    if length == 0 || length > MAX_GPT_ENTRIES_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("GPT header has a partition entry array of {} bytes", length),
+        ));
+    }
+
+    // The entries are copied in whole sectors.
+    let length = (length + sector_size - 1) / sector_size * sector_size;
+    read_at(file, offset, length).map(|data| TableRegion { offset, data })
+}
+
+fn mbr_regions(file: &mut File, sector_size: u64, mbr: Vec<u8>) -> io::Result<Vec<TableRegion>> {
+    let extended = (0..4)
+        .map(|entry| &mbr[446 + entry * 16..446 + (entry + 1) * 16])
+        .find(|entry| EXTENDED_TYPES.contains(&entry[4]))
+        .map(|entry| le_u32(&entry[8..12]));
+
+    let mut regions = vec![TableRegion { offset: 0, data: mbr }];
+
+    if let Some(start) = extended {
+        // Each extended boot record links to the next, relative to the extended partition.
+        let mut next = 0;
+        for _ in 0..MAX_LOGICAL_PARTITIONS {
+            let offset = (start + next) * sector_size;
+            let ebr = read_at(file, offset, sector_size)?;
+            if ebr[510..512] != BOOT_SIGNATURE {
+                break;
+            }
+
+            let link = &ebr[462..478];
+            let (link_type, link_start) = (link[4], le_u32(&link[8..12]));
+            regions.push(TableRegion { offset, data: ebr });
+
+            if !EXTENDED_TYPES.contains(&link_type) || link_start == 0 {
+                break;
+            }
+
+            next = link_start;
+        }
+    }
+
+    Ok(regions)
+}
+
+/// Serializes bytes as a hexadecimal string.
+mod hex {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+    use std::fmt::Write;
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        let mut hex = String::with_capacity(data.len() * 2);
+        for byte in data {
+            let _ = write!(hex, "{:02x}", byte);
+        }
+
+        serializer.serialize_str(&hex)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        if hex.len() % 2 != 0 || !hex.is_ascii() {
+            return Err(D::Error::custom("invalid hex string"));
+        }
+
+        (0..hex.len())
+            .step_by(2)
+            .map(|at| u8::from_str_radix(&hex[at..at + 2], 16).map_err(D::Error::custom))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    const SECTOR: usize = 512;
+
+    fn gpt_header(current: u64, backup: u64, entries: u64) -> Vec<u8> {
+        let mut header = vec![0; SECTOR];
+        header[..8].copy_from_slice(GPT_SIGNATURE);
+        header[24..32].copy_from_slice(&current.to_le_bytes());
+        header[32..40].copy_from_slice(&backup.to_le_bytes());
+        header[72..80].copy_from_slice(&entries.to_le_bytes());
+        header[80..84].copy_from_slice(&128u32.to_le_bytes());
+        header[84..88].copy_from_slice(&128u32.to_le_bytes());
+        header
+    }
+
+    fn mbr_entry(sector: &mut [u8], index: usize, kind: u8, start: u32, sectors: u32) {
+        let entry = &mut sector[446 + index * 16..446 + (index + 1) * 16];
+        entry[4] = kind;
+        entry[8..12].copy_from_slice(&start.to_le_bytes());
+        entry[12..16].copy_from_slice(&sectors.to_le_bytes());
+    }
+
+    fn boot_sector() -> Vec<u8> {
+        let mut sector = vec![0; SECTOR];
+        sector[510..512].copy_from_slice(&BOOT_SIGNATURE);
+        sector
+    }
+
+    fn write_sector(image: &mut [u8], lba: u64, data: &[u8]) {
+        let offset = lba as usize * SECTOR;
+        image[offset..offset + data.len()].copy_from_slice(data);
+    }
+
+    #[test]
+    fn gpt_round_trip() {
+        let dir = TempDir::new("distinst-tables").unwrap();
+        let device = dir.path().join("disk.img");
+        let sectors = 2048u64;
+
+        let mut image = vec![0; sectors as usize * SECTOR];
+        let mut mbr = boot_sector();
+        mbr_entry(&mut mbr, 0, 0xEE, 1, sectors as u32 - 1);
+        write_sector(&mut image, 0, &mbr);
+        write_sector(&mut image, 1, &gpt_header(1, sectors - 1, 2));
+        write_sector(&mut image, 2, &[0xAB; 128 * 128]);
+        write_sector(&mut image, sectors - 33, &[0xCD; 128 * 128]);
+        write_sector(&mut image, sectors - 1, &gpt_header(sectors - 1, 1, sectors - 33));
+        fs::write(&device, &image).unwrap();
+
+        let backup = TableBackup::read(&device, "").unwrap().unwrap();
+        assert_eq!(backup.table, PartitionTable::Gpt);
+        let offsets = backup.regions.iter().map(|region| region.offset).collect::<Vec<_>>();
+        assert_eq!(offsets, vec![0, 512, 1024, (sectors - 33) * 512, (sectors - 1) * 512]);
+
+        // Overwrite the table, as a failed commit would have, and then restore it.
+        fs::write(&device, vec![0u8; image.len()]).unwrap();
+        backup.restore().unwrap();
+        assert_eq!(fs::read(&device).unwrap(), image);
+
+        // The backup survives serialization.
+        let path = dir.path().join("tables.json");
+        write_backups(&path, &[backup.clone()]).unwrap();
+        assert_eq!(read_backups(&path).unwrap(), vec![backup]);
+    }
+
+    #[test]
+    fn mbr_logical_chain() {
+        let dir = TempDir::new("distinst-tables").unwrap();
+        let device = dir.path().join("disk.img");
+        let mut image = vec![0; 4096 * SECTOR];
+
+        let mut mbr = boot_sector();
+        mbr_entry(&mut mbr, 0, 0x83, 2048, 1024);
+        mbr_entry(&mut mbr, 1, 0x0F, 3072, 1024);
+        write_sector(&mut image, 0, &mbr);
+
+        // Two logical partitions, the first of which links to the second.
+        let mut first = boot_sector();
+        mbr_entry(&mut first, 0, 0x83, 1, 255);
+        mbr_entry(&mut first, 1, 0x05, 256, 512);
+        write_sector(&mut image, 3072, &first);
+
+        let mut second = boot_sector();
+        mbr_entry(&mut second, 0, 0x83, 1, 511);
+        write_sector(&mut image, 3072 + 256, &second);
+        fs::write(&device, &image).unwrap();
+
+        let backup = TableBackup::read(&device, "").unwrap().unwrap();
+        assert_eq!(backup.table, PartitionTable::Msdos);
+        let offsets = backup.regions.iter().map(|region| region.offset).collect::<Vec<_>>();
+        assert_eq!(offsets, vec![0, 3072 * 512, (3072 + 256) * 512]);
+    }
+
+    #[test]
+    fn mbr_restore_clears_gpt() {
+        let dir = TempDir::new("distinst-tables").unwrap();
+        let device = dir.path().join("disk.img");
+        let sectors = 2048u64;
+
+        let mut image = vec![0; sectors as usize * SECTOR];
+        let mut mbr = boot_sector();
+        mbr_entry(&mut mbr, 0, 0x83, 2048, sectors as u32 - 2048);
+        write_sector(&mut image, 0, &mbr);
+        write_sector(&mut image, sectors - 1, &[0xAB; SECTOR]);
+        fs::write(&device, &image).unwrap();
+        let backup = TableBackup::read(&device, "").unwrap().unwrap();
+
+        // A failed commit had replaced the MBR with a GPT.
+        let mut gpt = image.clone();
+        write_sector(&mut gpt, 0, &boot_sector());
+        write_sector(&mut gpt, 1, &gpt_header(1, sectors - 1, 2));
+        write_sector(&mut gpt, sectors - 1, &gpt_header(sectors - 1, 1, sectors - 33));
+        fs::write(&device, &gpt).unwrap();
+
+        backup.restore().unwrap();
+        write_sector(&mut image, sectors - 1, &[0; SECTOR]);
+        assert_eq!(fs::read(&device).unwrap(), image);
+
+        // Data at the end of the disk is kept if it is not a GPT header.
+        write_sector(&mut image, sectors - 1, &[0xAB; SECTOR]);
+        fs::write(&device, &image).unwrap();
+        backup.restore().unwrap();
+        assert_eq!(fs::read(&device).unwrap(), image);
+    }
+
+    #[test]
+    fn restores_every_disk() {
+        let dir = TempDir::new("distinst-tables").unwrap();
+        let device = dir.path().join("disk.img");
+        let mut image = vec![0; 64 * SECTOR];
+        write_sector(&mut image, 0, &boot_sector());
+        fs::write(&device, &image).unwrap();
+
+        // The first disk has since been removed, which must not prevent the second's restore.
+        let backup = TableBackup::read(&device, "").unwrap().unwrap();
+        let missing = TableBackup { device: dir.path().join("missing.img"), ..backup.clone() };
+        let path = dir.path().join("tables.json");
+        write_backups(&path, &[missing, backup]).unwrap();
+
+        fs::write(&device, vec![0u8; image.len()]).unwrap();
+        let journal = dir.path().join("move.journal");
+        let results = Disks::restore_tables(&path, Some(&journal)).unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(results[0].is_err());
+        assert_eq!(results[1].as_ref().unwrap(), &device);
+        assert_eq!(fs::read(&device).unwrap(), image);
+
+        // Nothing is restored while a partition move is unfinished.
+        fs::write(&journal, b"").unwrap();
+        match Disks::restore_tables(&path, Some(&journal)) {
+            Err(DiskError::TableRestoreDuringMove { journal: found }) => assert_eq!(found, journal),
+            result => panic!("restored during a partition move: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn without_table() {
+        let dir = TempDir::new("distinst-tables").unwrap();
+        let device = dir.path().join("disk.img");
+        fs::write(&device, vec![0u8; 64 * SECTOR]).unwrap();
+        assert_eq!(TableBackup::read(&device, "").unwrap(), None);
+    }
+}
@@ -1,5 +1,6 @@
 pub use disk_types::PartitionSizeError;
 use disk_types::{FileSystem, PartitionTableError};
+use failure::Fail;
 use std::{io, path::PathBuf};
 
 /// Defines a variety of errors that may arise from configuring and committing changes to disks.
@@ -52,6 +53,8 @@ pub enum DiskError {
     LogicalVolumeCreate { why: io::Error },
     #[fail(display = "logical partition '{}-{}' does not exist", group, volume)]
     LogicalPartitionNotFound { group: String, volume: String },
+    #[fail(display = "invalid LUKS options for '{}': {}", pv, why)]
+    LuksOptions { pv: String, why: String },
     #[fail(display = "unable to get mount points: {}", why)]
     MountsObtain { why: io::Error },
     #[fail(display = "new partition could not be found")]
@@ -70,16 +73,48 @@ pub enum DiskError {
     PartitionTable { why: PartitionTableError },
     #[fail(display = "unable to create physical volume from '{}': {}", volume, why)]
     PhysicalVolumeCreate { volume: String, why: io::Error },
+    #[fail(display = "plan does not supply a key for the '{}' physical volume", pv)]
+    PlanEmptyKey { pv: String },
+    #[fail(display = "plan contains an invalid file system: {}", fs)]
+    PlanInvalidFileSystem { fs: String },
+    #[fail(display = "plan contains an invalid partition flag: {}", flag)]
+    PlanInvalidFlag { flag: String },
+    #[fail(display = "plan contains an invalid sector value: {}", value)]
+    PlanInvalidSector { value: String },
+    #[fail(display = "plan references RAID array '{}', which does not exist", name)]
+    PlanRaidNotFound { name: String },
+    #[fail(display = "plan references volume group '{}', which does not exist", group)]
+    PlanVolumeGroupNotFound { group: String },
+    #[fail(display = "unable to create RAID array '{}': {}", name, why)]
+    RaidCreate { name: String, why: io::Error },
+    #[fail(display = "{} array '{}' requires {} members, not {}", level, name, required, members)]
+    RaidMembers { name: String, level: String, members: usize, required: usize },
     #[fail(display = "multiple devices had the same volume group: currently unsupported")]
     SameGroup,
     #[fail(display = "sector overlaps partition {}", id)]
     SectorOverlaps { id: i32 },
     #[fail(display = "unable to get serial model of device: {}", why)]
     SerialGet { why: io::Error },
+    #[fail(display = "unable to create subvolume '{}' on {:?}: {}", subvolume, device, why)]
+    SubvolumeCreate { device: PathBuf, subvolume: String, why: io::Error },
+    #[fail(display = "unable to mount {:?} to create subvolumes: {}", device, why)]
+    SubvolumeMount { device: PathBuf, why: io::Error },
+    #[fail(display = "unable to create swap file at {:?}: {}", path, why)]
+    SwapFile { path: PathBuf, why: io::Error },
+    #[fail(display = "unable to back up the partition table of {:?}: {}", device, why)]
+    TableBackup { device: PathBuf, why: io::Error },
+    #[fail(display = "unable to access partition table backups at {:?}: {}", path, why)]
+    TableBackupFile { path: PathBuf, why: io::Error },
+    #[fail(display = "unable to restore the partition table of {:?}: {}", device, why)]
+    TableRestore { device: PathBuf, why: io::Error },
+    #[fail(display = "the partition move journaled at {:?} must be resumed first", journal)]
+    TableRestoreDuringMove { journal: PathBuf },
     #[fail(display = "unable to unmount partition(s) on {:?}: {}", device, why)]
     Unmount { device: PathBuf, why: io::Error },
     #[fail(display = "unable to create volume group '{}' on {:?}: {}", vg, device, why)]
     VolumeGroupCreate { device: PathBuf, vg: String, why: io::Error },
+    #[fail(display = "volume group '{}' has no physical volumes", vg)]
+    VolumeGroupWithoutDevices { vg: String },
     #[fail(display = "logical partition on {:?} lacks a label", device)]
     VolumePartitionLacksLabel { device: PathBuf },
 }
@@ -136,9 +171,7 @@ impl From<io::Error> for DiskError {
 }
 
 impl From<DiskError> for io::Error {
-    fn from(err: DiskError) -> io::Error {
-        io::Error::new(io::ErrorKind::Other, format!("an I/O error occurred: {}", err))
-    }
+    fn from(err: DiskError) -> io::Error { io::Error::new(io::ErrorKind::Other, err.compat()) }
 }
 
 impl From<PartitionSizeError> for PartitionError {
@@ -2,13 +2,13 @@
 
 pub use crate::config::deactivate_devices;
 pub use external_::*;
-use misc;
 use proc_mounts::{MountList, SwapList};
 use std::{
+    ffi::OsString,
     fs::Permissions,
     io::{self, Read, Write},
     os::unix::fs::PermissionsExt,
-    path::Path,
+    path::{Path, PathBuf},
 };
 use sys_mount::*;
 use tempdir::TempDir;
@@ -60,89 +60,132 @@ fn remove_encrypted_device(device: &Path) -> io::Result<()> {
 
 /// Creates a LUKS partition from a physical partition. This could be either a LUKS on LVM
 /// configuration, or a LVM on LUKS configurations.
+///
+/// When both a password and a keyfile are given, the partition is formatted with the password,
+/// and the keyfile is added to a second keyslot, so that either may be used to open it.
 pub fn cryptsetup_encrypt(device: &Path, enc: &LvmEncryption) -> io::Result<()> {
     remove_encrypted_device(device)?;
 
     info!("cryptsetup is encrypting {} with {:?}", device.display(), enc);
 
     match (enc.password.as_ref(), enc.keydata.as_ref()) {
-        (Some(_password), Some(_keydata)) => unimplemented!(),
+        (Some(password), Some(&(_, ref keydata))) => {
+            let password = append_newline(password.as_bytes());
+            let keydata = keydata.as_ref().expect("field should have been populated");
+            with_keyfile(enc, keydata, |keypath| {
+                luks_format_with_keyfile(device, enc, &password, keypath)
+            })
+        }
         (Some(password), None) => exec(
             "cryptsetup",
             Some(&append_newline(password.as_bytes())),
             None,
-            &[
-                "-s".into(),
-                "512".into(),
-                "luksFormat".into(),
-                "--type".into(),
-                "luks2".into(),
-                device.into(),
-            ],
+            &luks_format_args(enc, &[device.into()]),
         ),
         (None, Some(&(_, ref keydata))) => {
             let keydata = keydata.as_ref().expect("field should have been populated");
-            let tmpfs = TempDir::new("distinst")?;
-            let supported = SupportedFilesystems::new()?;
-            let _mount = Mount::new(&keydata.0, tmpfs.path(), &supported, MountFlags::BIND, None)?
-                .into_unmount_drop(UnmountFlags::DETACH);
-            let keypath = tmpfs.path().join(&enc.physical_volume);
-
-            generate_keyfile(&keypath)?;
-            info!("keypath exists: {}", keypath.is_file());
-
-            exec(
-                "cryptsetup",
-                None,
-                None,
-                &[
-                    "-s".into(),
-                    "512".into(),
-                    "luksFormat".into(),
-                    "--type".into(),
-                    "luks2".into(),
-                    device.into(),
-                    tmpfs.path().join(&enc.physical_volume).into(),
-                ],
-            )
+            with_keyfile(enc, keydata, |keypath| {
+                generate_keyfile(keypath)?;
+                let args = luks_format_args(enc, &[device.into(), keypath.into()]);
+                exec("cryptsetup", None, None, &args)
+            })
         }
-        (None, None) => unimplemented!(),
+        (None, None) => Err(no_key(&enc.physical_volume)),
     }
 }
 
+/// Formats the partition with the password, and then adds a newly-generated keyfile at
+/// `keypath` to a second keyslot.
+fn luks_format_with_keyfile(
+    device: &Path,
+    enc: &LvmEncryption,
+    password: &[u8],
+    keypath: &Path,
+) -> io::Result<()> {
+    exec("cryptsetup", Some(password), None, &luks_format_args(enc, &[device.into()]))?;
+    generate_keyfile(keypath)?;
+    exec("cryptsetup", Some(password), None, &["luksAddKey".into(), device.into(), keypath.into()])
+}
+
+/// The arguments of a `cryptsetup luksFormat` command, which are followed by `trailing`.
+fn luks_format_args(enc: &LvmEncryption, trailing: &[OsString]) -> Vec<OsString> {
+    let mut args = enc.luks.format_args();
+    args.extend_from_slice(&["luksFormat".into(), "--type".into(), "luks2".into()]);
+    args.extend_from_slice(trailing);
+    args
+}
+
 /// Opens an encrypted partition and maps it to the pv name.
+///
+/// When both a password and a keyfile are given, the password is tried first, and the keyfile
+/// is used if the password fails and the keyfile's partition is available.
 pub fn cryptsetup_open(device: &Path, enc: &LvmEncryption) -> io::Result<()> {
     deactivate_devices(&[device])?;
     let pv = &enc.physical_volume;
     info!("cryptsetup is opening {} with pv {} and {:?}", device.display(), pv, enc);
-    match (enc.password.as_ref(), enc.keydata.as_ref()) {
-        (Some(_password), Some(_keydata)) => unimplemented!(),
-        (Some(password), None) => exec(
+
+    let open_with_password = |password: &String| {
+        exec(
             "cryptsetup",
             Some(&append_newline(password.as_bytes())),
             None,
             &["open".into(), device.into(), pv.into()],
-        ),
-        (None, Some(&(_, ref keydata))) => {
-            let keydata = keydata.as_ref().expect("field should have been populated");
-            let tmpfs = TempDir::new("distinst")?;
-            let supported = SupportedFilesystems::new()?;
-            let _mount = Mount::new(&keydata.0, tmpfs.path(), &supported, MountFlags::BIND, None)?
-                .into_unmount_drop(UnmountFlags::DETACH);
-            let keypath = tmpfs.path().join(&enc.physical_volume);
-            info!("keypath exists: {}", keypath.is_file());
+        )
+    };
 
+    let open_with_keyfile = |keydata: &(PathBuf, PathBuf)| {
+        with_keyfile(enc, keydata, |keypath| {
             exec(
                 "cryptsetup",
                 None,
                 None,
                 &["open".into(), device.into(), pv.into(), "--key-file".into(), keypath.into()],
             )
+        })
+    };
+
+    match (enc.password.as_ref(), enc.keydata.as_ref()) {
+        (Some(password), Some(&(_, ref keydata))) => {
+            match (open_with_password(password), keydata.as_ref()) {
+                (Err(why), Some(keydata)) => {
+                    warn!("unable to open {} with password: {}", device.display(), why);
+                    open_with_keyfile(keydata)
+                }
+                (result, _) => result,
+            }
         }
-        (None, None) => unimplemented!(),
+        (Some(password), None) => open_with_password(password),
+        (None, Some(&(_, ref keydata))) => {
+            open_with_keyfile(keydata.as_ref().expect("field should have been populated"))
+        }
+        (None, None) => Err(no_key(pv)),
     }
 }
 
+/// Bind mounts the keyfile partition to a temporary directory, and calls `func` with the path
+/// of the volume's keyfile within it.
+fn with_keyfile<T, F: FnOnce(&Path) -> io::Result<T>>(
+    enc: &LvmEncryption,
+    keydata: &(PathBuf, PathBuf),
+    func: F,
+) -> io::Result<T> {
+    let tmpfs = TempDir::new("distinst")?;
+    let supported = SupportedFilesystems::new()?;
+    let _mount = Mount::new(&keydata.0, tmpfs.path(), &supported, MountFlags::BIND, None)?
+        .into_unmount_drop(UnmountFlags::DETACH);
+    let keypath = tmpfs.path().join(&enc.physical_volume);
+    info!("keypath exists: {}", keypath.is_file());
+
+    func(&keypath)
+}
+
+fn no_key(pv: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("neither a password nor a keyfile was supplied for {}", pv),
+    )
+}
+
 /// Append a newline to the input (used for the password)
 fn append_newline(input: &[u8]) -> Vec<u8> {
     let mut input = input.to_owned();
@@ -165,3 +208,116 @@ fn generate_keyfile(path: &Path) -> io::Result<()> {
     keyfile.write_all(&key)?;
     keyfile.sync_all()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LuksOptions, Pbkdf};
+    use executor::{with_executor, Output, RecordingExecutor};
+    use std::sync::Arc;
+
+    #[test]
+    fn cryptsetup_password_commands() {
+        let recorder = Arc::new(RecordingExecutor::new());
+        let enc = LvmEncryption::new("cryptdata".into(), Some("pass".into()), None);
+        let device = Path::new("/dev/distinst-test1");
+
+        with_executor(recorder.clone(), || {
+            cryptsetup_encrypt(device, &enc).unwrap();
+            cryptsetup_open(device, &enc).unwrap();
+        });
+
+        assert_eq!(
+            recorder.command_lines(),
+            vec![
+                "pvs",
+                "cryptsetup -s 512 luksFormat --type luks2 /dev/distinst-test1",
+                "cryptsetup open /dev/distinst-test1 cryptdata",
+            ]
+        );
+
+        let invocations = recorder.invocations();
+        assert_eq!(invocations[1].stdin, Some(b"pass\n".to_vec()));
+        assert_eq!(invocations[2].stdin, Some(b"pass\n".to_vec()));
+    }
+
+    #[test]
+    fn cryptsetup_password_and_keyfile() {
+        let recorder = Arc::new(RecordingExecutor::new());
+        let enc = LvmEncryption::new("cryptdata".into(), Some("pass".into()), Some("K".into()));
+        let device = Path::new("/dev/distinst-test1");
+
+        with_executor(recorder.clone(), || {
+            cryptsetup_open(device, &enc).unwrap();
+
+            // Without the keyfile's partition, a rejected password cannot be retried.
+            recorder.reply("cryptsetup", Output::status(2));
+            assert!(cryptsetup_open(device, &enc).is_err());
+
+            let keyless = LvmEncryption::new("cryptdata".into(), None, None);
+            assert!(cryptsetup_encrypt(device, &keyless).is_err());
+            assert!(cryptsetup_open(device, &keyless).is_err());
+        });
+
+        let lines = recorder.command_lines();
+        let open = "cryptsetup open /dev/distinst-test1 cryptdata";
+        assert_eq!(&lines[..2], &[open, open]);
+        assert!(!lines.iter().any(|line| line.contains("luksFormat")));
+    }
+
+    #[test]
+    fn cryptsetup_format_with_password_and_keyfile() {
+        let recorder = Arc::new(RecordingExecutor::new());
+        let enc = LvmEncryption::new("cryptdata".into(), Some("pass".into()), Some("K".into()));
+        let device = Path::new("/dev/distinst-test1");
+
+        // The keyfile's partition is bind mounted by `with_keyfile`, so a temporary directory
+        // stands in for it.
+        let keydir = TempDir::new("distinst-test").unwrap();
+        let keypath = keydir.path().join("cryptdata");
+
+        with_executor(recorder.clone(), || {
+            luks_format_with_keyfile(device, &enc, b"pass\n", &keypath).unwrap();
+        });
+
+        assert_eq!(
+            recorder.command_lines(),
+            vec![
+                "cryptsetup -s 512 luksFormat --type luks2 /dev/distinst-test1".to_owned(),
+                format!("cryptsetup luksAddKey /dev/distinst-test1 {}", keypath.display()),
+            ]
+        );
+
+        let invocations = recorder.invocations();
+        assert_eq!(invocations[0].stdin, Some(b"pass\n".to_vec()));
+        assert_eq!(invocations[1].stdin, Some(b"pass\n".to_vec()));
+        assert_eq!(keypath.metadata().unwrap().len(), 512);
+    }
+
+    #[test]
+    fn cryptsetup_luks_options() {
+        let recorder = Arc::new(RecordingExecutor::new());
+        let enc = LvmEncryption::new("cryptdata".into(), Some("pass".into()), None).with_luks(
+            LuksOptions {
+                cipher:           Some("aes-xts-plain64".into()),
+                key_size:         256,
+                pbkdf:            Some(Pbkdf::Pbkdf2),
+                pbkdf_iterations: Some(500_000),
+                sector_size:      Some(4096),
+                label:            Some("cryptdata".into()),
+                ..LuksOptions::default()
+            },
+        );
+
+        with_executor(recorder.clone(), || {
+            cryptsetup_encrypt(Path::new("/dev/distinst-test1"), &enc).unwrap();
+        });
+
+        assert_eq!(
+            recorder.command_lines()[1],
+            "cryptsetup -s 256 --cipher aes-xts-plain64 --pbkdf pbkdf2 \
+             --pbkdf-force-iterations 500000 --sector-size 4096 --label cryptdata luksFormat \
+             --type luks2 /dev/distinst-test1"
+        );
+    }
+}
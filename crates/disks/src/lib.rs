@@ -2,11 +2,11 @@ extern crate bitflags;
 extern crate derive_new;
 pub extern crate disk_types;
 extern crate distinst_bootloader as bootloader;
+extern crate distinst_executor as executor;
 extern crate distinst_external_commands as external_;
 extern crate distinst_utils as misc;
 extern crate envfile;
 extern crate failure;
-#[macro_use]
 extern crate failure_derive;
 extern crate fstab_generate;
 extern crate itertools;
@@ -20,6 +20,10 @@ extern crate partition_identity;
 extern crate proc_mounts;
 extern crate rand;
 extern crate rayon;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
 extern crate sys_mount;
 extern crate sysfs_class;
 extern crate tempdir;
@@ -0,0 +1,66 @@
+use std::{
+    cell::RefCell,
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+/// A token which is shared between the owner of an operation and the threads performing it,
+/// so that the operation may be cancelled from any thread.
+///
+/// Commands which are executed while a token is in scope of `with_cancellation` are refused
+/// once it has been cancelled, and a command which is already running is killed.
+#[derive(Debug, Clone, Default)]
+pub struct Cancellation(Arc<AtomicBool>);
+
+impl Cancellation {
+    pub fn new() -> Self { Self::default() }
+
+    /// Requests that the operation stops as soon as possible.
+    pub fn cancel(&self) { self.0.store(true, Ordering::SeqCst); }
+
+    pub fn is_cancelled(&self) -> bool { self.0.load(Ordering::SeqCst) }
+
+    /// The flag that is set on cancellation, for APIs which poll an `AtomicBool`.
+    pub fn flag(&self) -> &AtomicBool { &self.0 }
+
+    /// Returns an `Interrupted` error if the operation has been cancelled.
+    pub fn check(&self) -> io::Result<()> {
+        if self.is_cancelled() {
+            Err(io::Error::new(io::ErrorKind::Interrupted, "cancelled"))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+thread_local! {
+    static SCOPED: RefCell<Option<Cancellation>> = RefCell::new(None);
+}
+
+/// Cancels every command that the current thread runs within `func` with `token`.
+pub fn with_cancellation<T, F: FnOnce() -> T>(token: Cancellation, func: F) -> T {
+    struct Restore(Option<Cancellation>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let previous = self.0.take();
+            SCOPED.with(|scoped| *scoped.borrow_mut() = previous);
+        }
+    }
+
+    let _restore = Restore(SCOPED.with(|scoped| scoped.replace(Some(token))));
+    func()
+}
+
+/// The cancellation token of the current thread, if one is in scope.
+pub fn cancellation() -> Option<Cancellation> { SCOPED.with(|scoped| scoped.borrow().clone()) }
+
+/// Returns an `Interrupted` error if the token of the current thread has been cancelled.
+///
+/// Long operations which do not execute commands may call this between their stages.
+pub fn check_cancelled() -> io::Result<()> {
+    SCOPED.with(|scoped| scoped.borrow().as_ref().map_or(Ok(()), Cancellation::check))
+}
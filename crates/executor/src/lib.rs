@@ -0,0 +1,386 @@
+//! Pluggable execution of external commands.
+//!
+//! Every external command that distinst runs is described by an `Invocation`, which is handed
+//! to the active `Executor`. By default, this is the `SystemExecutor`, which spawns a process.
+//! Tests may substitute a `RecordingExecutor`, which records each invocation and replies with
+//! scripted output, so that the commands of an operation can be verified without root access
+//! or real disks.
+//!
+//! Commands may also be cancelled by a `Cancellation` token, which kills the command that is
+//! running and refuses to execute any more of them.
+
+extern crate libc;
+#[macro_use]
+extern crate log;
+extern crate once_cell;
+
+mod cancel;
+mod recording;
+mod system;
+
+pub use self::{
+    cancel::{cancellation, check_cancelled, with_cancellation, Cancellation},
+    recording::RecordingExecutor,
+    system::SystemExecutor,
+};
+
+use once_cell::sync::Lazy;
+use std::{
+    cell::RefCell,
+    ffi::{OsStr, OsString},
+    fmt, io,
+    process::Stdio,
+    sync::{Arc, RwLock},
+};
+
+/// Where the stdout or stderr of a command is directed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Redirect {
+    /// The stream is shared with the current process.
+    Inherit,
+    /// Everything written to the stream is discarded.
+    Null,
+    /// The stream is captured, and each line is handed to the caller as it is read.
+    Piped,
+}
+
+/// Identifies the stream that a line of output was read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// Describes an external command to execute.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Invocation {
+    pub program:   OsString,
+    pub args:      Vec<OsString>,
+    pub envs:      Vec<(OsString, OsString)>,
+    pub env_clear: bool,
+    /// Written to the stdin of the command. Otherwise, stdin is null.
+    pub stdin:     Option<Vec<u8>>,
+    pub stdout:    Redirect,
+    pub stderr:    Redirect,
+}
+
+impl Invocation {
+    pub fn new<S: AsRef<OsStr>>(program: S) -> Self {
+        Invocation {
+            program:   program.as_ref().to_owned(),
+            args:      Vec::new(),
+            envs:      Vec::new(),
+            env_clear: false,
+            stdin:     None,
+            stdout:    Redirect::Piped,
+            stderr:    Redirect::Piped,
+        }
+    }
+
+    pub fn arg<S: AsRef<OsStr>>(mut self, arg: S) -> Self {
+        self.args.push(arg.as_ref().to_owned());
+        self
+    }
+
+    pub fn args<S: AsRef<OsStr>, I: IntoIterator<Item = S>>(mut self, args: I) -> Self {
+        self.args.extend(args.into_iter().map(|arg| arg.as_ref().to_owned()));
+        self
+    }
+
+    pub fn stdin<B: Into<Vec<u8>>>(mut self, input: B) -> Self {
+        self.stdin = Some(input.into());
+        self
+    }
+
+    pub fn stdout(mut self, redirect: Redirect) -> Self {
+        self.stdout = redirect;
+        self
+    }
+
+    pub fn stderr(mut self, redirect: Redirect) -> Self {
+        self.stderr = redirect;
+        self
+    }
+}
+
+impl fmt::Display for Invocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.program.to_string_lossy())?;
+        for arg in &self.args {
+            write!(f, " {}", arg.to_string_lossy())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Handles which replace the streams of an invocation when it is spawned as a process.
+///
+/// These are for callers which were written against `std::process::Stdio`. Executors which do
+/// not spawn processes ignore them, and only see the streams that the invocation describes.
+#[derive(Debug, Default)]
+pub struct Handles {
+    /// Ignored if the invocation writes its own input to stdin.
+    pub stdin:  Option<Stdio>,
+    pub stdout: Option<Stdio>,
+    pub stderr: Option<Stdio>,
+}
+
+/// The result of a command which ran to completion.
+///
+/// Streams which were not piped are empty.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Output {
+    /// The exit code, which is `None` if the process was terminated by a signal.
+    pub code:   Option<i32>,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+impl Output {
+    /// An output with the given exit code, which wrote nothing.
+    pub fn status(code: i32) -> Self { Output { code: Some(code), ..Output::default() } }
+
+    pub fn with_stdout<B: Into<Vec<u8>>>(mut self, stdout: B) -> Self {
+        self.stdout = stdout.into();
+        self
+    }
+
+    pub fn with_stderr<B: Into<Vec<u8>>>(mut self, stderr: B) -> Self {
+        self.stderr = stderr.into();
+        self
+    }
+
+    pub fn success(&self) -> bool { self.code == Some(0) }
+}
+
+/// Executes external commands on behalf of distinst.
+pub trait Executor: Send + Sync {
+    /// Runs the command to completion, handing each line of piped output to `lines` as it is
+    /// read.
+    ///
+    /// An error is only returned if the command could not be executed. A command which exits
+    /// with a failure status is still an `Ok` output.
+    fn execute(
+        &self,
+        invocation: &Invocation,
+        lines: &mut dyn FnMut(Stream, &str),
+    ) -> io::Result<Output>;
+
+    /// Runs the command with the given handles in place of its streams. By default, the
+    /// handles are ignored.
+    fn execute_with_handles(
+        &self,
+        invocation: &Invocation,
+        _handles: Handles,
+        lines: &mut dyn FnMut(Stream, &str),
+    ) -> io::Result<Output> {
+        self.execute(invocation, lines)
+    }
+}
+
+static GLOBAL: Lazy<RwLock<Arc<dyn Executor>>> =
+    Lazy::new(|| RwLock::new(Arc::new(SystemExecutor)));
+
+thread_local! {
+    static SCOPED: RefCell<Option<Arc<dyn Executor>>> = RefCell::new(None);
+}
+
+/// Replaces the executor that is used by every thread, and returns the previous executor.
+pub fn set_executor(executor: Arc<dyn Executor>) -> Arc<dyn Executor> {
+    let mut global = GLOBAL.write().expect("executor lock is poisoned");
+    std::mem::replace(&mut *global, executor)
+}
+
+/// Executes every command that the current thread runs within `func` with `executor`.
+///
+/// This takes precedence over the executor set with `set_executor`, and does not affect
+/// other threads, so tests which run in parallel do not interfere with each other.
+pub fn with_executor<T, F: FnOnce() -> T>(executor: Arc<dyn Executor>, func: F) -> T {
+    struct Restore(Option<Arc<dyn Executor>>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let previous = self.0.take();
+            SCOPED.with(|scoped| *scoped.borrow_mut() = previous);
+        }
+    }
+
+    let _restore = Restore(SCOPED.with(|scoped| scoped.replace(Some(executor))));
+    func()
+}
+
+/// The executor that the current thread executes commands with.
+pub fn executor() -> Arc<dyn Executor> {
+    SCOPED.with(|scoped| scoped.borrow().clone()).unwrap_or_else(|| {
+        GLOBAL.read().expect("executor lock is poisoned").clone()
+    })
+}
+
+/// Executes a command with the current executor, unless the current cancellation token has
+/// been cancelled.
+pub fn execute(invocation: &Invocation, lines: &mut dyn FnMut(Stream, &str)) -> io::Result<Output> {
+    check_cancelled().map_err(|_| {
+        io::Error::new(io::ErrorKind::Interrupted, format!("{} was cancelled", invocation))
+    })?;
+
+    executor().execute(invocation, lines)
+}
+
+/// Executes a command with the current executor, with the given handles in place of its
+/// streams.
+pub fn execute_with_handles(
+    invocation: &Invocation,
+    handles: Handles,
+    lines: &mut dyn FnMut(Stream, &str),
+) -> io::Result<Output> {
+    check_cancelled().map_err(|_| {
+        io::Error::new(io::ErrorKind::Interrupted, format!("{} was cancelled", invocation))
+    })?;
+
+    executor().execute_with_handles(invocation, handles, lines)
+}
+
+/// The scoped executor and cancellation token of a thread, which are not inherited by the
+/// threads that it spawns.
+///
+/// A context is captured with `current` before work is handed to other threads, such as those
+/// of a thread pool, which then `enter` it.
+#[derive(Clone, Default)]
+pub struct Context {
+    executor:     Option<Arc<dyn Executor>>,
+    cancellation: Option<Cancellation>,
+}
+
+impl Context {
+    pub fn current() -> Self {
+        Context {
+            executor:     SCOPED.with(|scoped| scoped.borrow().clone()),
+            cancellation: cancellation(),
+        }
+    }
+
+    /// Runs `func` with the executor and cancellation token of this context.
+    pub fn enter<T, F: FnOnce() -> T>(&self, func: F) -> T {
+        match (self.executor.clone(), self.cancellation.clone()) {
+            (Some(executor), Some(token)) => {
+                with_executor(executor, || with_cancellation(token, func))
+            }
+            (Some(executor), None) => with_executor(executor, func),
+            (None, Some(token)) => with_cancellation(token, func),
+            (None, None) => func(),
+        }
+    }
+}
+
+/// Executes a command with the current executor, and collects its output.
+pub fn output(invocation: &Invocation) -> io::Result<Output> {
+    execute(invocation, &mut |_, _| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn system_output() {
+        let output = output(&Invocation::new("echo").arg("Hello,").arg("Executor!")).unwrap();
+        assert!(output.success());
+        assert_eq!(output.stdout, b"Hello, Executor!\n".to_vec());
+    }
+
+    #[test]
+    fn system_lines() {
+        let invocation = Invocation::new("sh").arg("-c").arg("echo one; echo two >&2; echo three");
+        let mut lines = Vec::new();
+        execute(&invocation, &mut |stream, line| lines.push((stream, line.to_owned()))).unwrap();
+
+        let stdout = lines.iter().filter(|(stream, _)| *stream == Stream::Stdout);
+        assert_eq!(stdout.map(|(_, line)| line.as_str()).collect::<Vec<_>>(), vec!["one", "three"]);
+        assert!(lines.contains(&(Stream::Stderr, "two".to_owned())));
+    }
+
+    #[test]
+    fn system_stdin() {
+        let invocation = Invocation::new("cat").stdin(&b"piped\n"[..]);
+        assert_eq!(output(&invocation).unwrap().stdout, b"piped\n".to_vec());
+    }
+
+    #[test]
+    fn scoped_recording() {
+        let recorder = Arc::new(RecordingExecutor::new());
+        recorder.reply("blkid", Output::status(2));
+
+        with_executor(recorder.clone(), || {
+            assert_eq!(output(&Invocation::new("blkid").arg("/dev/sda1")).unwrap().code, Some(2));
+            assert!(output(&Invocation::new("blkid").arg("/dev/sda2")).unwrap().success());
+        });
+
+        assert_eq!(recorder.command_lines(), vec!["blkid /dev/sda1", "blkid /dev/sda2"]);
+
+        // Outside of the scope, commands are executed by the system once more.
+        assert!(output(&Invocation::new("true")).unwrap().success());
+        assert_eq!(recorder.invocations().len(), 2);
+    }
+
+    #[test]
+    fn cancelled_commands_are_refused() {
+        let recorder = Arc::new(RecordingExecutor::new());
+        let token = Cancellation::new();
+
+        with_executor(recorder.clone(), || {
+            with_cancellation(token.clone(), || {
+                assert!(output(&Invocation::new("true")).is_ok());
+                token.cancel();
+
+                let why = output(&Invocation::new("mkfs.ext4").arg("/dev/sda2")).unwrap_err();
+                assert_eq!(why.kind(), io::ErrorKind::Interrupted);
+                assert_eq!(why.to_string(), "mkfs.ext4 /dev/sda2 was cancelled");
+            })
+        });
+
+        assert_eq!(recorder.command_lines(), vec!["true"]);
+        assert!(check_cancelled().is_ok());
+    }
+
+    #[test]
+    fn cancel_kills_running_command() {
+        let token = Cancellation::new();
+
+        let canceller = {
+            let token = token.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(100));
+                token.cancel();
+            })
+        };
+
+        let started = std::time::Instant::now();
+        let why = with_cancellation(token, || output(&Invocation::new("sleep").arg("10")));
+        canceller.join().unwrap();
+
+        assert_eq!(why.unwrap_err().kind(), io::ErrorKind::Interrupted);
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+    }
+
+    #[test]
+    fn context_is_entered_on_other_threads() {
+        let recorder = Arc::new(RecordingExecutor::new());
+        let token = Cancellation::new();
+        token.cancel();
+
+        let context = with_executor(recorder.clone(), || {
+            with_cancellation(token, Context::current)
+        });
+
+        let result = std::thread::spawn(move || {
+            context.enter(|| (check_cancelled().is_err(), output(&Invocation::new("true"))))
+        })
+        .join()
+        .unwrap();
+
+        assert!(result.0);
+        assert_eq!(result.1.unwrap_err().kind(), io::ErrorKind::Interrupted);
+        assert!(recorder.invocations().is_empty());
+    }
+}
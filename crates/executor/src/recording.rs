@@ -0,0 +1,79 @@
+use super::{Executor, Invocation, Output, Redirect, Stream};
+use std::{
+    collections::{HashMap, VecDeque},
+    ffi::{OsStr, OsString},
+    io,
+    sync::Mutex,
+};
+
+/// Records every invocation instead of executing it, and replies with scripted output.
+///
+/// Programs which do not have a scripted reply succeed without writing any output.
+#[derive(Debug, Default)]
+pub struct RecordingExecutor {
+    invocations: Mutex<Vec<Invocation>>,
+    replies:     Mutex<HashMap<OsString, VecDeque<Output>>>,
+}
+
+impl RecordingExecutor {
+    pub fn new() -> Self { Self::default() }
+
+    /// Queues `output` as the reply to the next invocation of `program`.
+    ///
+    /// Replies to the same program are consumed in the order that they were queued.
+    pub fn reply<S: AsRef<OsStr>>(&self, program: S, output: Output) -> &Self {
+        self.replies
+            .lock()
+            .expect("recorder lock is poisoned")
+            .entry(program.as_ref().to_owned())
+            .or_default()
+            .push_back(output);
+        self
+    }
+
+    /// Every invocation that has been recorded, in the order that they were executed.
+    pub fn invocations(&self) -> Vec<Invocation> {
+        self.invocations.lock().expect("recorder lock is poisoned").clone()
+    }
+
+    /// Every recorded invocation as a command line, for convenient comparisons.
+    pub fn command_lines(&self) -> Vec<String> {
+        self.invocations().iter().map(ToString::to_string).collect()
+    }
+
+    /// Forgets all recorded invocations.
+    pub fn clear(&self) { self.invocations.lock().expect("recorder lock is poisoned").clear(); }
+}
+
+impl Executor for RecordingExecutor {
+    fn execute(
+        &self,
+        invocation: &Invocation,
+        lines: &mut dyn FnMut(Stream, &str),
+    ) -> io::Result<Output> {
+        self.invocations.lock().expect("recorder lock is poisoned").push(invocation.clone());
+
+        let mut output = self
+            .replies
+            .lock()
+            .expect("recorder lock is poisoned")
+            .get_mut(&invocation.program)
+            .and_then(VecDeque::pop_front)
+            .unwrap_or_else(|| Output::status(0));
+
+        for (redirect, stream, data) in &mut [
+            (invocation.stdout, Stream::Stdout, &mut output.stdout),
+            (invocation.stderr, Stream::Stderr, &mut output.stderr),
+        ] {
+            if *redirect == Redirect::Piped {
+                for line in String::from_utf8_lossy(data).lines() {
+                    lines(*stream, line);
+                }
+            } else {
+                data.clear();
+            }
+        }
+
+        Ok(output)
+    }
+}
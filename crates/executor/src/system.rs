@@ -0,0 +1,147 @@
+use super::{cancellation, Cancellation, Executor, Handles, Invocation, Output, Redirect, Stream};
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Write},
+    os::unix::io::{FromRawFd, IntoRawFd},
+    process::{Command, Stdio},
+    thread,
+    time::Duration,
+};
+
+/// Executes commands by spawning them as processes on this system.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemExecutor;
+
+impl Executor for SystemExecutor {
+    fn execute(
+        &self,
+        invocation: &Invocation,
+        lines: &mut dyn FnMut(Stream, &str),
+    ) -> io::Result<Output> {
+        self.execute_with_handles(invocation, Handles::default(), lines)
+    }
+
+    fn execute_with_handles(
+        &self,
+        invocation: &Invocation,
+        handles: Handles,
+        lines: &mut dyn FnMut(Stream, &str),
+    ) -> io::Result<Output> {
+        debug!("spawning {}", invocation);
+
+        let stdin = match (&invocation.stdin, handles.stdin) {
+            (Some(_), _) => Stdio::piped(),
+            (None, Some(handle)) => handle,
+            (None, None) => Stdio::null(),
+        };
+
+        let mut command = Command::new(&invocation.program);
+        command
+            .args(&invocation.args)
+            .stdin(stdin)
+            .stdout(handles.stdout.unwrap_or_else(|| stdio(invocation.stdout)))
+            .stderr(handles.stderr.unwrap_or_else(|| stdio(invocation.stderr)));
+
+        if invocation.env_clear {
+            command.env_clear();
+        }
+
+        for (key, value) in &invocation.envs {
+            command.env(key, value);
+        }
+
+        let mut child = command.spawn()?;
+
+        if let Some(ref input) = invocation.stdin {
+            // Dropping stdin after writing closes it, so that the command receives an EOF.
+            child.stdin.take().expect("stdin not obtained").write_all(input)?;
+        }
+
+        let mut stdout = child.stdout.take().map(non_blocking).map(BufReader::new);
+        let mut stderr = child.stderr.take().map(non_blocking).map(BufReader::new);
+        let mut stdout_line = Vec::new();
+        let mut stderr_line = Vec::new();
+        let mut output = Output::default();
+        let token = cancellation();
+
+        loop {
+            let status = child.try_wait()?;
+
+            if let Some(ref mut reader) = stdout {
+                let captured = &mut output.stdout;
+                read_lines(reader, &mut stdout_line, captured, &mut |l| lines(Stream::Stdout, l))?;
+            }
+
+            if let Some(ref mut reader) = stderr {
+                let captured = &mut output.stderr;
+                read_lines(reader, &mut stderr_line, captured, &mut |l| lines(Stream::Stderr, l))?;
+            }
+
+            if let Some(status) = status {
+                for (line, stream) in
+                    &[(&stdout_line, Stream::Stdout), (&stderr_line, Stream::Stderr)]
+                {
+                    if !line.is_empty() {
+                        lines(*stream, &String::from_utf8_lossy(line));
+                    }
+                }
+
+                output.stdout.extend_from_slice(&stdout_line);
+                output.stderr.extend_from_slice(&stderr_line);
+                output.code = status.code();
+                return Ok(output);
+            }
+
+            if token.as_ref().map(Cancellation::is_cancelled) == Some(true) {
+                warn!("killing {}, which was cancelled", invocation);
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(io::Error::new(
+                    io::ErrorKind::Interrupted,
+                    format!("{} was cancelled", invocation),
+                ));
+            }
+
+            thread::sleep(Duration::from_millis(16));
+        }
+    }
+}
+
+fn stdio(redirect: Redirect) -> Stdio {
+    match redirect {
+        Redirect::Inherit => Stdio::inherit(),
+        Redirect::Null => Stdio::null(),
+        Redirect::Piped => Stdio::piped(),
+    }
+}
+
+fn non_blocking<F: IntoRawFd>(fd: F) -> File {
+    let fd = fd.into_raw_fd();
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFL, 0);
+        libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK);
+        File::from_raw_fd(fd)
+    }
+}
+
+/// Reads every complete line that is available without blocking. Incomplete lines are kept in
+/// `line` until the remainder has been written.
+fn read_lines<B: BufRead>(
+    reader: &mut B,
+    line: &mut Vec<u8>,
+    captured: &mut Vec<u8>,
+    callback: &mut dyn FnMut(&str),
+) -> io::Result<()> {
+    loop {
+        match reader.read_until(b'\n', line) {
+            Ok(0) => return Ok(()),
+            Ok(_) if line.last() == Some(&b'\n') => {
+                callback(&String::from_utf8_lossy(&line[..line.len() - 1]));
+                captured.append(line);
+            }
+            Ok(_) => (),
+            Err(ref why) if why.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+            Err(why) => return Err(why),
+        }
+    }
+}
@@ -1,12 +1,14 @@
 use self::FileSystem::*;
-use super::exec;
+use super::{exec, exec_output};
 use disk_types::FileSystem;
+use executor::Invocation;
 use crate::retry::Retry;
 use std::{
     ffi::{OsStr, OsString},
+    fs::{self, OpenOptions},
     io,
+    os::unix::fs::OpenOptionsExt,
     path::Path,
-    process::{Command, Stdio},
 };
 
 /// Erase all signatures on a disk
@@ -30,13 +32,7 @@ pub fn blockdev<P: AsRef<Path>, S: AsRef<OsStr>, I: IntoIterator<Item = S>>(
 
 /// Obtains the file system on a partition via blkid
 pub fn blkid_partition<P: AsRef<Path>>(part: P) -> Option<FileSystem> {
-    let output = Command::new("blkid")
-        .arg(part.as_ref())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::null())
-        .output()
-        .ok()?
-        .stdout;
+    let output = exec_output(Invocation::new("blkid").arg(part.as_ref())).ok()?.stdout;
 
     for field in String::from_utf8_lossy(&output).split_whitespace() {
         if field.starts_with("TYPE=") {
@@ -49,7 +45,7 @@ pub fn blkid_partition<P: AsRef<Path>>(part: P) -> Option<FileSystem> {
         }
     }
 
-    return None
+    None
 }
 
 /// Checks & corrects errors with partitions that have been moved / resized.
@@ -97,14 +93,7 @@ pub fn mkfs<P: AsRef<Path>>(part: P, kind: FileSystem) -> io::Result<()> {
 pub fn get_label<P: AsRef<Path>>(part: P, kind: FileSystem) -> Option<String> {
     let (cmd, args) = get_label_cmd(kind)?;
 
-    let output = Command::new(cmd)
-        .args(args)
-        .arg(part.as_ref())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::null())
-        .output()
-        .ok()?
-        .stdout;
+    let output = exec_output(Invocation::new(cmd).args(args).arg(part.as_ref())).ok()?.stdout;
 
     let output: String = String::from_utf8_lossy(&output).into();
 
@@ -141,10 +130,169 @@ fn get_label_cmd(kind: FileSystem) -> Option<(&'static str, &'static [&'static s
     Some(cmd)
 }
 
+/// Creates a subvolume at the given path, which must be within a mounted btrfs file system.
+pub fn btrfs_subvolume_create<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    exec("btrfs", None, None, &["subvolume".into(), "create".into(), path.as_ref().into()])
+}
+
+/// Makes the subvolume at the given path the default subvolume of its file system, which is the
+/// subvolume that is mounted when a subvolume is not specified.
+pub fn btrfs_subvolume_set_default<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    exec("btrfs", None, None, &["subvolume".into(), "set-default".into(), path.as_ref().into()])
+}
+
+/// Creates a swap file of `bytes` in size at `path`, which is on a `kind` file system.
+///
+/// Any file already at the path is replaced. Swap files on btrfs must not be copy-on-write,
+/// and that attribute may only be set on an empty file, so it is set before the file's space
+/// is allocated.
+pub fn mkswapfile<P: AsRef<Path>>(path: P, bytes: u64, kind: FileSystem) -> io::Result<()> {
+    let path = path.as_ref();
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+
+    OpenOptions::new().write(true).create_new(true).mode(0o600).open(path)?;
+
+    if kind == Btrfs {
+        exec("chattr", None, None, &["+C".into(), path.into()])?;
+    }
+
+    exec("fallocate", None, None, &["-l".into(), bytes.to_string().into(), path.into()])?;
+    exec("mkswap", None, None, &[path.into()])
+}
+
+/// Grows an ext2/3/4 file system to fill its partition.
+pub fn resize2fs<P: AsRef<Path>>(part: P) -> io::Result<()> {
+    exec("resize2fs", None, None, &[part.as_ref().into()])
+}
+
+/// Assigns a UUID to an ext2/3/4 file system, which must have been checked beforehand.
+pub fn set_ext_uuid<P: AsRef<Path>>(part: P, uuid: &str) -> io::Result<()> {
+    exec("tune2fs", None, None, &["-U".into(), uuid.into(), part.as_ref().into()])
+}
+
+/// Mounts a file system image read-only through a loop device, which is detached once the
+/// image is unmounted.
+pub fn mount_image_ro<P: AsRef<Path>, Q: AsRef<Path>>(
+    image: P,
+    target: Q,
+    kind: &str,
+) -> io::Result<()> {
+    let options: [OsString; 4] = ["-t".into(), kind.into(), "-o".into(), "loop,ro".into()];
+    let paths: [OsString; 2] = [image.as_ref().into(), target.as_ref().into()];
+    exec("mount", None, None, &[&options[..], &paths[..]].concat())
+}
+
+pub fn umount<P: AsRef<Path>>(target: P) -> io::Result<()> {
+    exec("umount", None, None, &[target.as_ref().into()])
+}
+
 pub fn remount_rw<P: AsRef<Path>>(path: P) -> io::Result<()> {
     exec("mount", None, None, &[path.as_ref().into(), "-o".into(), "remount,rw".into()])
 }
 
 fn swap_exists(path: &Path) -> bool {
-    Command::new("swaplabel").arg(path).status().ok().map_or(false, |stat| stat.success())
+    exec_output(Invocation::new("swaplabel").arg(path)).ok().map_or(false, |out| out.success())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use executor::{with_executor, Output, RecordingExecutor};
+    use std::sync::Arc;
+
+    #[test]
+    fn mkfs_commands() {
+        let recorder = Arc::new(RecordingExecutor::new());
+        with_executor(recorder.clone(), || {
+            mkfs("/dev/sda1", Fat32).unwrap();
+            mkfs("/dev/sda2", Ext4).unwrap();
+            mkfs("/dev/sda3", Lvm).unwrap();
+        });
+
+        assert_eq!(
+            recorder.command_lines(),
+            vec!["mkfs.fat -F 32 /dev/sda1", "mkfs.ext4 -F -q -E lazy_itable_init /dev/sda2"]
+        );
+    }
+
+    #[test]
+    fn mkswap_skips_existing_swap() {
+        let recorder = Arc::new(RecordingExecutor::new());
+        recorder.reply("swaplabel", Output::status(0));
+        recorder.reply("swaplabel", Output::status(1));
+
+        with_executor(recorder.clone(), || {
+            mkfs("/dev/sda4", Swap).unwrap();
+            mkfs("/dev/sda5", Swap).unwrap();
+        });
+
+        assert_eq!(
+            recorder.command_lines(),
+            vec!["swaplabel /dev/sda4", "swaplabel /dev/sda5", "mkswap -f /dev/sda5"]
+        );
+    }
+
+    #[test]
+    fn mkfs_failure() {
+        let recorder = Arc::new(RecordingExecutor::new());
+        recorder.reply("mkfs.xfs", Output::status(1));
+        assert!(with_executor(recorder, || mkfs("/dev/sda1", Xfs)).is_err());
+    }
+
+    #[test]
+    fn btrfs_subvolume_commands() {
+        let recorder = Arc::new(RecordingExecutor::new());
+        with_executor(recorder.clone(), || {
+            btrfs_subvolume_create("/tmp/distinst/@").unwrap();
+            btrfs_subvolume_set_default("/tmp/distinst/@").unwrap();
+        });
+
+        assert_eq!(
+            recorder.command_lines(),
+            vec![
+                "btrfs subvolume create /tmp/distinst/@",
+                "btrfs subvolume set-default /tmp/distinst/@"
+            ]
+        );
+    }
+
+    #[test]
+    fn swap_file_commands() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempdir::TempDir::new("distinst-swap").unwrap();
+        let path = dir.path().join("swapfile");
+        let display = path.display().to_string();
+
+        let recorder = Arc::new(RecordingExecutor::new());
+        with_executor(recorder.clone(), || {
+            mkswapfile(&path, 4_194_304, Btrfs).unwrap();
+            mkswapfile(&path, 4_194_304, Ext4).unwrap();
+        });
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        // Only btrfs requires copy-on-write to be disabled.
+        let chattr = format!("chattr +C {}", display);
+        let fallocate = format!("fallocate -l 4194304 {}", display);
+        let mkswap = format!("mkswap {}", display);
+        assert_eq!(
+            recorder.command_lines(),
+            vec![chattr, fallocate.clone(), mkswap.clone(), fallocate, mkswap]
+        );
+    }
+
+    #[test]
+    fn blkid_file_system() {
+        let recorder = Arc::new(RecordingExecutor::new());
+        recorder.reply(
+            "blkid",
+            Output::status(0).with_stdout(r#"/dev/sda1: UUID="1234-ABCD" TYPE="ext4""#),
+        );
+
+        assert_eq!(with_executor(recorder, || blkid_partition("/dev/sda1")), Some(Ext4));
+    }
 }
@@ -0,0 +1,281 @@
+use disk_types::{
+    checker::{FsckError, FsckReport, FsckStatus, Fscker},
+    FileSystem::{self, *},
+};
+use executor::{Invocation, Redirect};
+use std::path::Path;
+
+/// Checks file systems with the check and repair tools of each file system.
+///
+/// Checks never modify the file system; repairs are only made through `Fscker::repair`.
+pub struct FileSystemChecker;
+
+impl Fscker for FileSystemChecker {
+    fn check(path: &Path, fs: FileSystem) -> Result<FsckReport, FsckError> {
+        let (cmd, args) = match check_command(fs) {
+            Some(command) => command,
+            None => return Ok(FsckReport::unsupported()),
+        };
+
+        run(cmd, args, path, fs)
+    }
+
+    fn repair(path: &Path, fs: FileSystem) -> Result<FsckReport, FsckError> {
+        let (cmd, args) = match repair_command(fs) {
+            Some(command) => command,
+            None => return Self::check(path, fs),
+        };
+
+        info!("repairing {:?} file system at {}", fs, path.display());
+        let repair = run(cmd, args, path, fs)?;
+
+        // Repair tools do not all report whether their repairs were complete.
+        let mut report = Self::check(path, fs)?;
+        if report.status == FsckStatus::Clean && repair.status != FsckStatus::Clean {
+            report.status = FsckStatus::Repaired;
+        }
+
+        Ok(report)
+    }
+}
+
+/// Ensures that a file system is consistent before it is resized or moved.
+///
+/// File systems with errors are repaired when their tool is able to do so. An error is
+/// returned for a file system with errors which remain after the repair, whose output says how
+/// the file system is to be repaired if it cannot be repaired here.
+pub fn fsck_before_resize(path: &Path, fs: FileSystem) -> Result<FsckReport, FsckError> {
+    let mut report = FileSystemChecker::check(path, fs)?;
+    if report.status == FsckStatus::NeedsRepair && repair_command(fs).is_some() {
+        warn!("{:?} file system at {} has errors: {}", fs, path.display(), report.output);
+        report = FileSystemChecker::repair(path, fs)?;
+    }
+
+    match report.status {
+        FsckStatus::NeedsRepair => {
+            let output = match manual_repair(fs) {
+                Some(advice) => format!("{}\n{}", advice, report.output),
+                None => report.output,
+            };
+
+            Err(FsckError::NeedsRepair { path: path.to_path_buf(), fs, output })
+        }
+        _ => Ok(report),
+    }
+}
+
+/// The command which checks a file system without writing to it.
+fn check_command(fs: FileSystem) -> Option<(&'static str, &'static [&'static str])> {
+    let command: (&'static str, &'static [&'static str]) = match fs {
+        Btrfs => ("btrfs", &["check", "--readonly"]),
+        Exfat => ("fsck.exfat", &["-n"]),
+        Ext2 | Ext3 | Ext4 => ("e2fsck", &["-f", "-n"]),
+        F2fs => ("fsck.f2fs", &["--dry-run"]),
+        Fat16 | Fat32 => ("fsck.fat", &["-n"]),
+        Ntfs => ("ntfsfix", &["--no-action"]),
+        Xfs => ("xfs_repair", &["-n"]),
+        Swap | Luks | Lvm => return None,
+    };
+
+    Some(command)
+}
+
+/// The command which repairs a file system without prompting. Btrfs repairs are not
+/// considered safe to perform automatically, and ntfsfix only resets the journal of an NTFS
+/// file system rather than repairing it.
+fn repair_command(fs: FileSystem) -> Option<(&'static str, &'static [&'static str])> {
+    let command: (&'static str, &'static [&'static str]) = match fs {
+        Exfat => ("fsck.exfat", &["-p"]),
+        Ext2 | Ext3 | Ext4 => ("e2fsck", &["-f", "-p"]),
+        F2fs => ("fsck.f2fs", &["-a"]),
+        Fat16 | Fat32 => ("fsck.fat", &["-a"]),
+        Xfs => ("xfs_repair", &[]),
+        Btrfs | Ntfs | Swap | Luks | Lvm => return None,
+    };
+
+    Some(command)
+}
+
+/// How a file system is to be repaired, if it can only be repaired outside of the installer.
+fn manual_repair(fs: FileSystem) -> Option<&'static str> {
+    match fs {
+        Ntfs => Some("boot into Windows and run `chkdsk /f` on the volume to repair it"),
+        _ => None,
+    }
+}
+
+fn run(
+    cmd: &'static str,
+    args: &[&str],
+    path: &Path,
+    fs: FileSystem,
+) -> Result<FsckReport, FsckError> {
+    info!("executing {} with {:?} on {}", cmd, args, path.display());
+    let invocation =
+        Invocation::new(cmd).args(args).arg(path).stdout(Redirect::Piped).stderr(Redirect::Piped);
+
+    let output = executor::output(&invocation)?;
+    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+
+    match output.code.and_then(|code| parse_status(fs, code, &text)) {
+        Some(status) => Ok(FsckReport { status, code: output.code, output: text }),
+        None => Err(FsckError::BadStatus { command: cmd, status: output.code, output: text }),
+    }
+}
+
+/// Interprets the exit code of a check or repair tool. `None` is returned when the tool
+/// failed to operate on the file system at all.
+fn parse_status(fs: FileSystem, code: i32, output: &str) -> Option<FsckStatus> {
+    let status = match fs {
+        // e2fsck and fsck.exfat share the exit codes of fsck(8), which are bit flags.
+        Ext2 | Ext3 | Ext4 | Exfat => {
+            if code & (8 | 16 | 32 | 128) != 0 {
+                return None;
+            } else if code & 4 != 0 {
+                FsckStatus::NeedsRepair
+            } else if code & (1 | 2) != 0 {
+                FsckStatus::Repaired
+            } else {
+                FsckStatus::Clean
+            }
+        }
+        // btrfs check also exits with 1 when the device could not be opened.
+        Btrfs => match code {
+            0 => FsckStatus::Clean,
+            1 if !output.contains("cannot open") => FsckStatus::NeedsRepair,
+            _ => return None,
+        },
+        // xfs_repair -n exits with 1 when corruption was found, and 2 for a dirty log.
+        Xfs => match code {
+            0 => FsckStatus::Clean,
+            1 | 2 => FsckStatus::NeedsRepair,
+            _ => return None,
+        },
+        Fat16 | Fat32 | F2fs | Ntfs => match code {
+            0 => FsckStatus::Clean,
+            1 => FsckStatus::NeedsRepair,
+            _ => return None,
+        },
+        Swap | Luks | Lvm => FsckStatus::Unsupported,
+    };
+
+    Some(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use executor::{with_executor, Output, RecordingExecutor};
+    use std::sync::Arc;
+
+    #[test]
+    fn check_commands_are_read_only() {
+        let recorder = Arc::new(RecordingExecutor::new());
+        with_executor(recorder.clone(), || {
+            for &fs in &[Ext4, Fat32, Btrfs, Xfs, Ntfs, F2fs, Exfat] {
+                FileSystemChecker::check(Path::new("/dev/sda1"), fs).unwrap();
+            }
+
+            assert_eq!(
+                FileSystemChecker::check(Path::new("/dev/sda2"), Swap).unwrap(),
+                FsckReport::unsupported()
+            );
+        });
+
+        assert_eq!(
+            recorder.command_lines(),
+            vec![
+                "e2fsck -f -n /dev/sda1",
+                "fsck.fat -n /dev/sda1",
+                "btrfs check --readonly /dev/sda1",
+                "xfs_repair -n /dev/sda1",
+                "ntfsfix --no-action /dev/sda1",
+                "fsck.f2fs --dry-run /dev/sda1",
+                "fsck.exfat -n /dev/sda1",
+            ]
+        );
+    }
+
+    #[test]
+    fn exit_codes() {
+        assert_eq!(parse_status(Ext4, 0, ""), Some(FsckStatus::Clean));
+        assert_eq!(parse_status(Ext4, 1, ""), Some(FsckStatus::Repaired));
+        assert_eq!(parse_status(Ext4, 4, ""), Some(FsckStatus::NeedsRepair));
+        assert_eq!(parse_status(Ext4, 8, ""), None);
+        assert_eq!(parse_status(Fat32, 1, ""), Some(FsckStatus::NeedsRepair));
+        assert_eq!(parse_status(Fat32, 2, ""), None);
+        assert_eq!(parse_status(Btrfs, 1, "found 2 errors"), Some(FsckStatus::NeedsRepair));
+        assert_eq!(parse_status(Btrfs, 1, "ERROR: cannot open file system"), None);
+        assert_eq!(parse_status(Xfs, 2, ""), Some(FsckStatus::NeedsRepair));
+        assert_eq!(parse_status(Ntfs, 1, ""), Some(FsckStatus::NeedsRepair));
+    }
+
+    #[test]
+    fn gate_repairs_file_system() {
+        let recorder = Arc::new(RecordingExecutor::new());
+        recorder
+            .reply("e2fsck", Output::status(4).with_stdout("Inode 12 has illegal blocks"))
+            .reply("e2fsck", Output::status(1))
+            .reply("e2fsck", Output::status(0));
+
+        let report = with_executor(recorder.clone(), || {
+            fsck_before_resize(Path::new("/dev/sda2"), Ext4).unwrap()
+        });
+
+        assert_eq!(report.status, FsckStatus::Repaired);
+        assert_eq!(
+            recorder.command_lines(),
+            vec!["e2fsck -f -n /dev/sda2", "e2fsck -f -p /dev/sda2", "e2fsck -f -n /dev/sda2"]
+        );
+    }
+
+    #[test]
+    fn gate_rejects_unrepairable_file_system() {
+        let recorder = Arc::new(RecordingExecutor::new());
+        recorder.reply("btrfs", Output::status(1).with_stderr("found 1 errors"));
+
+        let result =
+            with_executor(recorder.clone(), || fsck_before_resize(Path::new("/dev/sda3"), Btrfs));
+
+        match result {
+            Err(FsckError::NeedsRepair { fs: Btrfs, .. }) => (),
+            other => panic!("expected the btrfs file system to need repair: {:?}", other),
+        }
+
+        assert_eq!(recorder.command_lines(), vec!["btrfs check --readonly /dev/sda3"]);
+    }
+
+    #[test]
+    fn gate_rejects_ntfs_with_errors() {
+        let recorder = Arc::new(RecordingExecutor::new());
+        recorder.reply("ntfsfix", Output::status(1).with_stdout("Volume is corrupt"));
+
+        let result =
+            with_executor(recorder.clone(), || fsck_before_resize(Path::new("/dev/sda4"), Ntfs));
+
+        match result {
+            Err(FsckError::NeedsRepair { fs: Ntfs, ref output, .. }) => {
+                assert!(output.contains("chkdsk"));
+                assert!(output.contains("Volume is corrupt"));
+            }
+            other => panic!("expected the NTFS file system to need repair: {:?}", other),
+        }
+
+        // ntfsfix is never run without `--no-action`.
+        assert_eq!(recorder.command_lines(), vec!["ntfsfix --no-action /dev/sda4"]);
+    }
+
+    #[test]
+    fn failed_check_is_an_error() {
+        let recorder = Arc::new(RecordingExecutor::new());
+        recorder.reply("e2fsck", Output::status(8).with_stderr("No such file or directory"));
+
+        let result =
+            with_executor(recorder, || FileSystemChecker::check(Path::new("/dev/sdz1"), Ext4));
+        match result {
+            Err(FsckError::BadStatus { command: "e2fsck", status: Some(8), .. }) => (),
+            other => panic!("expected the check to fail: {:?}", other),
+        }
+    }
+}
@@ -1,6 +1,7 @@
 //! A collection of external commands used throughout the program.
 
 extern crate disk_types;
+extern crate distinst_executor as executor;
 extern crate distinst_utils as misc;
 #[macro_use]
 extern crate log;
@@ -12,19 +13,20 @@ extern crate sys_mount;
 extern crate tempdir;
 
 pub mod block;
+pub mod check;
 pub mod luks;
 pub mod lvm;
+pub mod raid;
 pub(crate) mod retry;
 
-pub use self::{block::*, luks::*, lvm::*};
+pub use self::{block::*, check::*, luks::*, lvm::*, raid::*};
 
-use std::{
-    ffi::OsString,
-    io::{self, Write},
-    process::{Command, Stdio},
-};
+use executor::{Invocation, Redirect};
+use std::{ffi::OsString, io};
 
 /// A generic function for executing a variety of external commands.
+///
+/// Commands are executed by the current `distinst_executor::Executor`.
 pub fn exec(
     cmd: &str,
     stdin: Option<&[u8]>,
@@ -33,20 +35,13 @@ pub fn exec(
 ) -> io::Result<()> {
     info!("executing {} with {:?}", cmd, args);
 
-    let mut child = Command::new(cmd)
-        .args(args)
-        .stdin(if stdin.is_some() { Stdio::piped() } else { Stdio::null() })
-        .stdout(Stdio::null())
-        .spawn()?;
+    let mut invocation =
+        Invocation::new(cmd).args(args).stdout(Redirect::Null).stderr(Redirect::Inherit);
+    invocation.stdin = stdin.map(Vec::from);
 
-    if let Some(stdin) = stdin {
-        child.stdin.as_mut().expect("stdin not obtained").write_all(stdin)?;
-    }
-
-    let status = child.wait()?;
-    let success = status.success()
-        || valid_codes
-            .map_or(false, |codes| status.code().map_or(false, |code| codes.contains(&code)));
+    let code = executor::output(&invocation)?.code;
+    let success = code == Some(0)
+        || valid_codes.map_or(false, |codes| code.map_or(false, |code| codes.contains(&code)));
 
     if success {
         Ok(())
@@ -56,7 +51,7 @@ pub fn exec(
             format!(
                 "{} failed with status: {}",
                 cmd,
-                match status.code() {
+                match code {
                     Some(code) => format!("{} ({})", code, io::Error::from_raw_os_error(code)),
                     None => "unknown".into(),
                 }
@@ -65,4 +60,10 @@ pub fn exec(
     }
 }
 
+/// Executes a command with the current executor, capturing its stdout and discarding its
+/// stderr.
+fn exec_output(invocation: Invocation) -> io::Result<executor::Output> {
+    executor::output(&invocation.stdout(Redirect::Piped).stderr(Redirect::Null))
+}
+
 fn mebibytes(bytes: u64) -> String { format!("{}", bytes / (1024 * 1024)) }
@@ -1,67 +1,35 @@
 use super::*;
+use executor::{self, Invocation, Redirect};
 use std::{
-    io::{self, BufRead, BufReader},
+    io,
     path::{Path, PathBuf},
-    process::{Command, Stdio},
     thread,
     time::Duration,
 };
 
 /// Get a vector of encrypted devices
 pub fn encrypted_devices() -> io::Result<Vec<String>> {
-    let mut current_line = String::with_capacity(64);
-    let mut output = Vec::new();
+    let output = exec_output(Invocation::new("dmsetup").args(&["ls", "--target", "crypt"]))?;
+    let output = String::from_utf8_lossy(&output.stdout);
 
-    let mut reader = BufReader::new(
-        Command::new("dmsetup")
-            .args(&["ls", "--target", "crypt"])
-            .stdout(Stdio::piped())
-            .stderr(Stdio::null())
-            .spawn()?
-            .stdout
-            .expect("failed to execute dmsetup command"),
-    );
-
-    reader.read_line(&mut current_line)?;
-    if current_line.starts_with("No devices found") {
+    if output.starts_with("No devices found") {
         return Ok(Vec::new());
     }
 
-    {
-        let mut fields = current_line.split_whitespace();
-        if let Some(dm) = fields.next() {
-            output.push(dm.into());
-        }
-    }
-
-    current_line.clear();
-
-    while reader.read_line(&mut current_line)? != 0 {
-        {
-            let mut fields = current_line.split_whitespace();
-            if let Some(dm) = fields.next() {
-                output.push(dm.into());
-            }
-        }
-
-        current_line.clear();
-    }
-
-    Ok(output)
+    Ok(output.lines().filter_map(|line| line.split_whitespace().next()).map(Into::into).collect())
 }
 
 /// If `cryptsetup info DEV` has an exit status of 0, the partition is encrypted.
 pub fn is_encrypted(device: &Path) -> bool {
     let mut attempts = 0;
     loop {
-        let res = Command::new("cryptsetup")
-            .stdout(Stdio::null())
+        let invocation = Invocation::new("cryptsetup")
             .arg("luksDump")
             .arg(device)
-            .status()
-            .ok();
+            .stdout(Redirect::Null)
+            .stderr(Redirect::Inherit);
 
-        match res.and_then(|stat| stat.code()) {
+        match executor::output(&invocation).ok().and_then(|output| output.code) {
             Some(0) => return true,
             // An exit status of 4 can happen if the partition is scanned too hastily.
             Some(4) => {
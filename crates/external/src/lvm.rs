@@ -2,13 +2,13 @@ use super::*;
 use misc::{concat_osstr, device_maps, read_dirs};
 use proc_mounts::{MOUNTS, SWAPS};
 use rand::{self, distributions::Alphanumeric, Rng};
+use executor::Invocation;
 use std::{
     collections::BTreeMap,
     ffi::OsStr,
     fs::read_link,
-    io::{self, BufRead, BufReader},
+    io,
     path::{Path, PathBuf},
-    process::{Command, Stdio},
 };
 use sys_mount::{swapoff, unmount, UnmountFlags};
 
@@ -63,10 +63,12 @@ pub fn physical_volumes_to_deactivate<P: AsRef<Path>>(paths: &[P]) -> Vec<PathBu
                 let slave_path = slave.path();
                 let slave_path =
                     slave_path.file_name().expect("slave path does not have file name");
-                if paths.iter().any(|p| {
+                let is_slave = paths.iter().any(|p| {
                     p.as_ref().file_name().expect("slave path does not have file name")
                         == slave_path
-                }) {
+                });
+
+                if is_slave {
                     discovered.push(pv.to_path_buf());
                 }
             });
@@ -78,27 +80,14 @@ pub fn physical_volumes_to_deactivate<P: AsRef<Path>>(paths: &[P]) -> Vec<PathBu
 
 /// Get a vector of logical devices.
 pub fn dmlist() -> io::Result<Vec<String>> {
-    let mut current_line = String::with_capacity(64);
-    let mut output = Vec::new();
-
-    let mut reader = BufReader::new(
-        Command::new("dmsetup")
-            .arg("ls")
-            .stdout(Stdio::piped())
-            .stderr(Stdio::null())
-            .spawn()?
-            .stdout
-            .expect("failed to execute dmsetup command"),
-    );
+    let output = exec_output(Invocation::new("dmsetup").arg("ls"))?;
 
     // Parse the output of `dmsetup ls`, only taking the first field from each line.
-    while reader.read_line(&mut current_line)? != 0 {
-        if let Some(dm) = current_line.split_whitespace().next() {
-            output.push(dm.into());
-        }
-
-        current_line.clear();
-    }
+    let mut output: Vec<String> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .map(Into::into)
+        .collect();
 
     // Also add lvm volume groups from `vgdisplay`, which `dmsetup ls` does not list.
     output.extend_from_slice(&vgdisplay()?);
@@ -157,45 +146,28 @@ pub fn lvremove(group: &str, name: &str) -> io::Result<()> {
 /// Obtains a list of logical volumes associated with the given volume group.
 pub fn lvs(vg: &str) -> io::Result<Vec<PathBuf>> {
     info!("obtaining logical volumes on {}", vg);
-    let mut current_line = String::with_capacity(128);
-    let mut output = Vec::new();
-
-    let mut reader = BufReader::new(
-        Command::new("lvs")
-            .arg(vg)
-            .stdout(Stdio::piped())
-            .stderr(Stdio::null())
-            .spawn()?
-            .stdout
-            .expect("failed to execute lvs command"),
-    );
+    let output = exec_output(Invocation::new("lvs").arg(vg))?;
+    let mut volumes = Vec::new();
 
     // Skip the first line of output
-    let _ = reader.read_line(&mut current_line);
-    current_line.clear();
-
-    while reader.read_line(&mut current_line)? != 0 {
-        {
-            let line = &current_line[2..];
-            if let Some(pos) = line.find(' ') {
-                let dev = PathBuf::from(
-                    [
-                        "/dev/mapper/",
-                        &vg.replace("-", "--"),
-                        "-",
-                        &(&line[..pos].replace("-", "--")),
-                    ]
-                    .concat(),
-                );
-
-                output.push(dev);
-            }
-        }
+    for line in String::from_utf8_lossy(&output.stdout).lines().skip(1) {
+        let line = line.get(2..).unwrap_or("");
+        if let Some(pos) = line.find(' ') {
+            let dev = PathBuf::from(
+                [
+                    "/dev/mapper/",
+                    &vg.replace("-", "--"),
+                    "-",
+                    &(&line[..pos].replace("-", "--")),
+                ]
+                .concat(),
+            );
 
-        current_line.clear();
+            volumes.push(dev);
+        }
     }
 
-    Ok(output)
+    Ok(volumes)
 }
 
 /// Used to create a physical volume on a LUKS partition.
@@ -207,40 +179,24 @@ pub fn pvcreate<P: AsRef<Path>>(device: P) -> io::Result<()> {
 /// groups.
 pub fn pvs() -> io::Result<BTreeMap<PathBuf, Option<String>>> {
     info!("obtaining list of physical volumes");
-    let mut current_line = String::with_capacity(64);
-    let mut output = BTreeMap::new();
-
-    let mut reader = BufReader::new(
-        Command::new("pvs")
-            .stdout(Stdio::piped())
-            .stderr(Stdio::null())
-            .spawn()?
-            .stdout
-            .expect("failed to execute pvs command"),
-    );
+    let output = exec_output(Invocation::new("pvs"))?;
+    let mut volumes = BTreeMap::new();
 
     // Skip the first line of output
-    let _ = reader.read_line(&mut current_line);
-    current_line.clear();
-
-    while reader.read_line(&mut current_line)? != 0 {
-        {
-            let mut fields = current_line[2..].split_whitespace();
-            fields.next().map(|pv| {
-                fields.next().map(|vg| {
-                    eprintln!("Found PV {}: VG {}", pv, vg);
-                    output.insert(
-                        PathBuf::from(pv),
-                        if vg.is_empty() || vg == "lvm2" { None } else { Some(vg.into()) },
-                    )
-                })
-            });
-        }
-
-        current_line.clear();
+    for line in String::from_utf8_lossy(&output.stdout).lines().skip(1) {
+        let mut fields = line.get(2..).unwrap_or("").split_whitespace();
+        fields.next().map(|pv| {
+            fields.next().map(|vg| {
+                eprintln!("Found PV {}: VG {}", pv, vg);
+                volumes.insert(
+                    PathBuf::from(pv),
+                    if vg.is_empty() || vg == "lvm2" { None } else { Some(vg.into()) },
+                )
+            })
+        });
     }
 
-    Ok(output)
+    Ok(volumes)
 }
 
 /// Deactivates all logical volumes in the supplied volume group
@@ -270,28 +226,13 @@ pub fn vgdeactivate(volume_group: &str) -> io::Result<()> {
 
 /// Get a list of all volume groups.
 fn vgdisplay() -> io::Result<Vec<String>> {
-    let mut current_line = String::with_capacity(64);
-    let mut output = Vec::new();
-
-    let mut reader = BufReader::new(
-        Command::new("vgdisplay")
-            .arg("-s")
-            .stdout(Stdio::piped())
-            .stderr(Stdio::null())
-            .spawn()?
-            .stdout
-            .expect("failed to execute vgdisplay command"),
-    );
-
-    while reader.read_line(&mut current_line)? != 0 {
-        if let Some(dm) = current_line.split_whitespace().next() {
-            output.push(dm[1..dm.len() - 1].into());
-        }
+    let output = exec_output(Invocation::new("vgdisplay").arg("-s"))?;
 
-        current_line.clear();
-    }
-
-    Ok(output)
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .map(|dm| dm[1..dm.len() - 1].into())
+        .collect())
 }
 
 /// Removes the given volume group from the system.
@@ -304,3 +245,48 @@ pub fn pvremove(physical_volume: &Path) -> io::Result<()> {
     let args = &["-ffy".into(), physical_volume.into()];
     exec("pvremove", None, None, args)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use executor::{with_executor, Output, RecordingExecutor};
+    use std::sync::Arc;
+
+    #[test]
+    fn vgcreate_commands() {
+        let recorder = Arc::new(RecordingExecutor::new());
+        with_executor(recorder.clone(), || {
+            pvcreate("/dev/mapper/cryptdata").unwrap();
+            vgcreate("data", ["/dev/mapper/cryptdata"].iter()).unwrap();
+            lvcreate("data", "root", Some(20 * 1024 * 1024 * 1024)).unwrap();
+            lvcreate("data", "home", None).unwrap();
+        });
+
+        assert_eq!(
+            recorder.command_lines(),
+            vec![
+                "pvcreate -ffy /dev/mapper/cryptdata",
+                "vgcreate -ffy data /dev/mapper/cryptdata",
+                "lvcreate -y -L 20480 data -n root",
+                "lvcreate -y -l 100%FREE data -n home",
+            ]
+        );
+    }
+
+    #[test]
+    fn pvs_output() {
+        let recorder = Arc::new(RecordingExecutor::new());
+        recorder.reply(
+            "pvs",
+            Output::status(0).with_stdout(
+                "  PV                    VG   Fmt  Attr PSize   PFree\n  \
+                 /dev/mapper/cryptdata data lvm2 a--  464.74g    0 \n  \
+                 /dev/sdb1                  lvm2 ---  100.00g 100.00g\n",
+            ),
+        );
+
+        let pvs = with_executor(recorder, pvs).unwrap();
+        assert_eq!(pvs.get(Path::new("/dev/mapper/cryptdata")), Some(&Some("data".to_owned())));
+        assert_eq!(pvs.get(Path::new("/dev/sdb1")), Some(&None));
+    }
+}
@@ -0,0 +1,186 @@
+use super::*;
+use executor::Invocation;
+use std::{
+    ffi::OsString,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// A software RAID array which is currently assembled, as listed by `/proc/mdstat`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MdArray {
+    /// The block device of the array, such as `/dev/md127`.
+    pub device:  PathBuf,
+    /// The name of the array within `/dev/md/`, or the kernel name if it has none.
+    pub name:    String,
+    /// The RAID level of the array, such as `raid1`.
+    pub level:   String,
+    /// The block devices which are members of the array.
+    pub members: Vec<PathBuf>,
+}
+
+/// Creates a new array at `device` from the given members, which is assembled immediately.
+pub fn mdadm_create<P: AsRef<Path>>(device: &Path, level: &str, members: &[P]) -> io::Result<()> {
+    let mut args: Vec<OsString> = vec![
+        "--create".into(),
+        device.into(),
+        // Members which appear to hold a file system would otherwise require confirmation.
+        "--run".into(),
+        "--metadata=1.2".into(),
+        ["--level=", level].concat().into(),
+        format!("--raid-devices={}", members.len()).into(),
+    ];
+
+    args.extend(members.iter().map(|member| member.as_ref().into()));
+    exec("mdadm", None, None, &args)
+}
+
+/// Stops an assembled array, which releases its members.
+pub fn mdadm_stop<P: AsRef<Path>>(device: P) -> io::Result<()> {
+    info!("stopping RAID array at {}", device.as_ref().display());
+    exec("mdadm", None, None, &["--stop".into(), device.as_ref().into()])
+}
+
+/// The `ARRAY` lines which identify each assembled array, as written to `mdadm.conf`.
+pub fn mdadm_detail_scan() -> io::Result<String> {
+    let output = exec_output(Invocation::new("mdadm").args(&["--detail", "--scan"]))?;
+    if !output.success() {
+        return Err(io::Error::new(io::ErrorKind::Other, "mdadm --detail --scan failed"));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Obtains the arrays which are currently assembled. Arrays which are inactive are skipped,
+/// as their level is not known until they have been assembled.
+pub fn mdstat() -> io::Result<Vec<MdArray>> {
+    let mdstat = match fs::read_to_string("/proc/mdstat") {
+        Ok(mdstat) => mdstat,
+        // The md driver has not been loaded, so there can be no arrays.
+        Err(ref why) if why.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(why) => return Err(why),
+    };
+
+    let names = fs::read_dir("/dev/md")
+        .map(|entries| {
+            entries
+                .filter_map(Result::ok)
+                .filter_map(|entry| {
+                    let device = entry.path().canonicalize().ok()?;
+                    Some((device, entry.file_name().to_string_lossy().into_owned()))
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    let mut arrays = parse_mdstat(&mdstat);
+    for array in &mut arrays {
+        if let Some((_, name)) = names.iter().find(|(device, _)| *device == array.device) {
+            array.name = name.clone();
+        }
+    }
+
+    Ok(arrays)
+}
+
+fn parse_mdstat(mdstat: &str) -> Vec<MdArray> {
+    let mut arrays = Vec::new();
+
+    for line in mdstat.lines() {
+        let (kernel_name, fields) = match line.find(" : ") {
+            Some(pos) if line.starts_with("md") => (&line[..pos], &line[pos + 3..]),
+            _ => continue,
+        };
+
+        let mut fields = fields.split_whitespace().filter(|field| !field.starts_with('('));
+        if fields.next() != Some("active") {
+            continue;
+        }
+
+        let level = match fields.next() {
+            Some(level) => level.to_owned(),
+            None => continue,
+        };
+
+        let members = fields
+            .map(|member| {
+                let name = member.find('[').map_or(member, |pos| &member[..pos]);
+                Path::new("/dev").join(name)
+            })
+            .collect();
+
+        arrays.push(MdArray {
+            device: Path::new("/dev").join(kernel_name),
+            name: kernel_name.to_owned(),
+            level,
+            members,
+        });
+    }
+
+    arrays
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use executor::{with_executor, RecordingExecutor};
+    use std::sync::Arc;
+
+    #[test]
+    fn mdadm_create_command() {
+        let recorder = Arc::new(RecordingExecutor::new());
+        with_executor(recorder.clone(), || {
+            mdadm_create(Path::new("/dev/md/root"), "raid1", &["/dev/sda2", "/dev/sdb2"]).unwrap();
+            mdadm_stop("/dev/md/root").unwrap();
+        });
+
+        assert_eq!(
+            recorder.command_lines(),
+            vec![
+                "mdadm --create /dev/md/root --run --metadata=1.2 --level=raid1 --raid-devices=2 \
+                 /dev/sda2 /dev/sdb2",
+                "mdadm --stop /dev/md/root",
+            ]
+        );
+    }
+
+    #[test]
+    fn mdstat_arrays() {
+        let mdstat = concat!(
+            "Personalities : [raid1] [raid0] [raid6] [raid5] [raid4] [raid10]\n",
+            "md126 : active (auto-read-only) raid10 sdd1[3] sdc1[2] sdb1[1] sda1[0]\n",
+            "      2093056 blocks super 1.2 512K chunks 2 near-copies [4/4] [UUUU]\n",
+            "\n",
+            "md127 : active raid1 nvme1n1p2[1] nvme0n1p2[0](F)\n",
+            "      487252992 blocks super 1.2 [2/1] [U_]\n",
+            "\n",
+            "md0 : inactive sde1[0](S)\n",
+            "      1046528 blocks super 1.2\n",
+            "\n",
+            "unused devices: <none>\n",
+        );
+
+        assert_eq!(
+            parse_mdstat(mdstat),
+            vec![
+                MdArray {
+                    device:  "/dev/md126".into(),
+                    name:    "md126".into(),
+                    level:   "raid10".into(),
+                    members: vec![
+                        "/dev/sdd1".into(),
+                        "/dev/sdc1".into(),
+                        "/dev/sdb1".into(),
+                        "/dev/sda1".into(),
+                    ],
+                },
+                MdArray {
+                    device:  "/dev/md127".into(),
+                    name:    "md127".into(),
+                    level:   "raid1".into(),
+                    members: vec!["/dev/nvme1n1p2".into(), "/dev/nvme0n1p2".into()],
+                },
+            ]
+        );
+    }
+}
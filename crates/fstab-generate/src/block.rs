@@ -8,12 +8,14 @@ use disk_types::FileSystem;
 /// partition.
 #[derive(Debug, PartialEq)]
 pub struct BlockInfo<'a> {
-    pub uid:     PartitionID,
-    mount:       Option<PathBuf>,
-    pub fs:      &'static str,
-    pub options: &'a str,
-    pub dump:    bool,
-    pub pass:    bool,
+    pub uid:       PartitionID,
+    mount:         Option<PathBuf>,
+    pub fs:        &'static str,
+    pub options:   &'a str,
+    /// The btrfs subvolume to mount, which is appended to the options.
+    pub subvolume: Option<&'a str>,
+    pub dump:      bool,
+    pub pass:      bool,
 }
 
 impl<'a> BlockInfo<'a> {
@@ -36,11 +38,18 @@ impl<'a> BlockInfo<'a> {
                 _ => fs.into(),
             },
             options,
+            subvolume: None,
             dump: false,
             pass: false,
         }
     }
 
+    /// Mounts the given btrfs subvolume, rather than the default subvolume.
+    pub fn with_subvolume(mut self, subvolume: &'a str) -> Self {
+        self.subvolume = Some(subvolume);
+        self
+    }
+
     /// Writes a single line to the fstab buffer for this file system.
     pub fn write_entry(&self, fstab: &mut OsString) {
         let mount_variant = match self.uid.variant {
@@ -60,6 +69,10 @@ impl<'a> BlockInfo<'a> {
         fstab.push(&self.fs);
         fstab.push("  ");
         fstab.push(&self.options);
+        if let Some(subvolume) = self.subvolume {
+            fstab.push(",subvol=");
+            fstab.push(subvolume);
+        }
         fstab.push("  ");
         fstab.push(if self.dump { "1" } else { "0" });
         fstab.push("  ");
@@ -115,6 +128,25 @@ UUID=ROOT  /  ext4  defaults  0  0
         );
     }
 
+    #[test]
+    fn fstab_subvolume_entries() {
+        let root_id = PartitionID { id: "ROOT".into(), variant: PartitionSource::UUID };
+        let (root, home) = (Some(Path::new("/")), Some(Path::new("/home")));
+        let root = BlockInfo::new(root_id.clone(), FileSystem::Btrfs, root, "defaults");
+        let home = BlockInfo::new(root_id, FileSystem::Btrfs, home, "defaults");
+
+        let fstab = &mut OsString::new();
+        root.with_subvolume("@").write_entry(fstab);
+        home.with_subvolume("@home").write_entry(fstab);
+
+        assert_eq!(
+            *fstab,
+            OsString::from(r#"UUID=ROOT  /  btrfs  defaults,subvol=@  0  0
+UUID=ROOT  /home  btrfs  defaults,subvol=@home  0  0
+"#)
+        );
+    }
+
     #[test]
     fn block_info_swap() {
         let id = PartitionID {
@@ -132,6 +164,7 @@ UUID=ROOT  /  ext4  defaults  0  0
                 mount: None,
                 fs: "swap",
                 options: "sw",
+                subvolume: None,
                 dump: false,
                 pass: false,
             }
@@ -156,6 +189,7 @@ UUID=ROOT  /  ext4  defaults  0  0
                 mount: Some(PathBuf::from("/boot/efi")),
                 fs: "vfat",
                 options: "defaults",
+                subvolume: None,
                 dump: false,
                 pass: false,
             }
@@ -180,6 +214,7 @@ UUID=ROOT  /  ext4  defaults  0  0
                 mount: Some(PathBuf::from("/")),
                 fs: FileSystem::Ext4.into(),
                 options: "defaults",
+                subvolume: None,
                 dump: false,
                 pass: false,
             }
@@ -3,7 +3,6 @@
 //! associated with a language (if any exist at all).
 
 use super::get_main_country;
-use misc;
 use std::{
     collections::{btree_map::Entry, BTreeMap},
     io::{self, BufRead, BufReader},
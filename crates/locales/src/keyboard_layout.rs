@@ -1,4 +1,3 @@
-use misc;
 use serde_xml_rs as xml;
 use std::io::{self, BufReader};
 
@@ -1,4 +1,3 @@
-use misc;
 use std::{
     collections::BTreeMap,
     io::{BufRead, BufReader},
@@ -22,6 +22,10 @@ extern crate partition_identity;
 extern crate sys_mount;
 extern crate tempdir;
 
+mod windows;
+
+pub use windows::{is_hibernated, volume_state, WindowsState};
+
 use std::fs::File;
 use std::io::{self, BufRead, BufReader};
 use std::path::Path;
@@ -33,12 +37,16 @@ use sys_mount::*;
 
 /// Describes the OS found on a partition.
 #[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
 pub enum OS {
-    Windows(String),
+    /// The name of the install, and the state of its volume.
+    Windows(String, WindowsState),
     Linux {
         info: OsRelease,
         partitions: Vec<PartitionID>,
         targets: Vec<PathBuf>,
+        /// The btrfs subvolume mounted at each target, if any.
+        subvolumes: Vec<Option<String>>,
     },
     MacOs(String)
 }
@@ -46,21 +54,88 @@ pub enum OS {
 /// Mounts the partition to a temporary directory and checks for the existence of an
 /// installed operating system.
 ///
+/// The partition is mounted with `mount_for_probe`, so that it is never written to. The
+/// state of a Windows volume is also read from the device.
+///
 /// If the installed operating system is Linux, it will also report back the location
 /// of the home partition.
-pub fn detect_os_from_device<'a, F: Into<FilesystemType<'a>>>(device: &Path, fs: F) -> Option<OS> {
+pub fn detect_os_from_device(device: &Path, fs: &str) -> Option<OS> {
     info!("detecting OS from device: {:?}", device);
     // Create a temporary directoy where we will mount the FS.
     TempDir::new("distinst").ok().and_then(|tempdir| {
         // Mount the FS to the temporary directory
         let base = tempdir.path();
-        Mount::new(device, base, fs, MountFlags::empty(), None)
-            .map(|m| m.into_unmount_drop(UnmountFlags::DETACH))
+        let os = mount_for_probe(device, base, fs, None)
             .ok()
-            .and_then(|_mount| detect_os_from_path(base))
+            .and_then(|_mount| detect_os_from_path(base))?;
+
+        Some(match os {
+            OS::Windows(name, state) => {
+                let volume = volume_state(device);
+                OS::Windows(name, WindowsState { hibernated: state.hibernated, ..volume })
+            }
+            os => os,
+        })
     })
 }
 
+/// Mounts a foreign file system read-only at `target`, so that it may be probed without
+/// being modified.
+///
+/// Journals and logs are not replayed, as a replay writes to the device even when the file
+/// system is mounted read-only. Any `options` are given in addition to those which prevent
+/// the replay. NTFS is mounted with the `ntfs3` driver where it is available.
+pub fn mount_for_probe(
+    device: &Path,
+    target: &Path,
+    fs: &str,
+    options: Option<&str>,
+) -> io::Result<UnmountDrop<Mount>> {
+    let mut result = Err(io::Error::new(io::ErrorKind::InvalidInput, "no file system type"));
+    for (driver, data) in probe_attempts(fs, options) {
+        info!("mounting {:?} read-only as {} with {:?}", device, driver, data);
+        result = Mount::new(device, target, driver, probe_flags(), data.as_deref())
+            .map(|mount| mount.into_unmount_drop(UnmountFlags::DETACH));
+
+        if result.is_ok() {
+            break;
+        }
+    }
+
+    result
+}
+
+/// The flags that a file system is mounted with when it is being probed.
+pub fn probe_flags() -> MountFlags {
+    MountFlags::RDONLY | MountFlags::NOSUID | MountFlags::NODEV | MountFlags::NOEXEC
+}
+
+/// The options which prevent a file system from replaying its journal or log when mounted.
+pub fn no_replay_options(fs: &str) -> Option<&'static str> {
+    match fs {
+        "ext3" | "ext4" => Some("noload"),
+        "f2fs" | "xfs" => Some("norecovery"),
+        "btrfs" => Some("nologreplay"),
+        _ => None,
+    }
+}
+
+/// The driver and mount options of each attempt to mount a file system for probing.
+fn probe_attempts<'a>(fs: &'a str, options: Option<&str>) -> Vec<(&'a str, Option<String>)> {
+    let data = match (no_replay_options(fs), options) {
+        (Some(no_replay), Some(options)) => Some([no_replay, ",", options].concat()),
+        (Some(no_replay), None) => Some(no_replay.to_owned()),
+        (None, options) => options.map(String::from),
+    };
+
+    let drivers = match fs {
+        "ntfs" => vec!["ntfs3", "ntfs"],
+        fs => vec![fs],
+    };
+
+    drivers.into_iter().map(|driver| (driver, data.clone())).collect()
+}
+
 /// Detects the existence of an OS at a defined path.
 ///
 /// This function is called by `detect_os_from_device`, after having temporarily mounted it.
@@ -83,8 +158,8 @@ pub fn detect_linux(base: &Path) -> Option<OS> {
     if path.exists() {
         info!("found OS Release: {}", std::fs::read_to_string(&path).unwrap());
         if let Ok(info) = OsRelease::new_from(path) {
-            let (partitions, targets) = find_linux_parts(base);
-            return Some(OS::Linux { info, partitions, targets });
+            let (partitions, targets, subvolumes) = find_linux_parts(base);
+            return Some(OS::Linux { info, partitions, targets, subvolumes });
         }
     }
 
@@ -105,14 +180,16 @@ pub fn detect_macos(base: &Path) -> Option<OS> {
 /// Detect if Windows is installed at the given path.
 pub fn detect_windows(base: &Path) -> Option<OS> {
     // TODO: More advanced version-specific detection is possible.
-    base.join("Windows/System32/ntoskrnl.exe")
-        .exists()
-        .map(|| OS::Windows("Windows".into()))
+    base.join("Windows/System32/ntoskrnl.exe").exists().map(|| {
+        let state = WindowsState { hibernated: is_hibernated(base), ..WindowsState::default() };
+        OS::Windows("Windows".into(), state)
+    })
 }
 
-fn find_linux_parts(base: &Path) -> (Vec<PartitionID>, Vec<PathBuf>) {
+fn find_linux_parts(base: &Path) -> (Vec<PartitionID>, Vec<PathBuf>, Vec<Option<String>>) {
     let mut partitions = Vec::new();
     let mut targets = Vec::new();
+    let mut subvolumes = Vec::new();
 
     if let Ok(fstab) = open(base.join("etc/fstab")) {
         for entry in BufReader::new(fstab).lines() {
@@ -125,18 +202,28 @@ fn find_linux_parts(base: &Path) -> (Vec<PartitionID>, Vec<PathBuf>) {
                 let mut fields = entry.split_whitespace();
                 let source = fields.next();
                 let target = fields.next();
+                let options = fields.nth(1);
 
                 if let Some(target) = target {
                     if let Some(Ok(path)) = source.map(|s| s.parse::<PartitionID>()) {
                         partitions.push(path);
                         targets.push(PathBuf::from(String::from(target)));
+                        subvolumes.push(options.and_then(parse_subvolume));
                     }
                 }
             }
         }
     }
 
-    (partitions, targets)
+    (partitions, targets, subvolumes)
+}
+
+/// Obtains the name of the btrfs subvolume from the options of a fstab entry.
+fn parse_subvolume(options: &str) -> Option<String> {
+    options
+        .split(',')
+        .find(|option| option.starts_with("subvol="))
+        .map(|option| option["subvol=".len()..].trim_start_matches('/').to_owned())
 }
 
 fn parse_plist<R: BufRead>(file: R) -> Option<String> {
@@ -145,7 +232,7 @@ fn parse_plist<R: BufRead>(file: R) -> Option<String> {
     let mut version: Option<String> = None;
     let mut flags = 0;
 
-    for entry in file.lines().flat_map(|line| line) {
+    for entry in file.lines().flatten() {
         let entry = entry.trim();
         match flags {
             0 => match entry {
@@ -206,7 +293,13 @@ impl BoolExt for bool {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::io::Cursor;
+    use std::{
+        collections::hash_map::DefaultHasher,
+        fs,
+        hash::Hasher,
+        io::{Cursor, Read},
+        process::Command,
+    };
 
     const MAC_PLIST: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
 <!DOCTYPE plist PUBLIC "Apple Stuff">
@@ -223,6 +316,120 @@ mod tests {
 </dict>
 </plist>"#;
 
+    #[test]
+    fn probes_are_read_only() {
+        assert!(probe_flags().contains(MountFlags::RDONLY));
+
+        let matrix: &[(&str, &[&str], Option<&str>)] = &[
+            ("btrfs", &["btrfs"], Some("nologreplay")),
+            ("exfat", &["exfat"], None),
+            ("ext2", &["ext2"], None),
+            ("ext3", &["ext3"], Some("noload")),
+            ("ext4", &["ext4"], Some("noload")),
+            ("f2fs", &["f2fs"], Some("norecovery")),
+            ("ntfs", &["ntfs3", "ntfs"], None),
+            ("vfat", &["vfat"], None),
+            ("xfs", &["xfs"], Some("norecovery")),
+        ];
+
+        for &(fs, drivers, data) in matrix {
+            let attempts = probe_attempts(fs, None);
+            assert_eq!(attempts.iter().map(|a| a.0).collect::<Vec<_>>(), drivers, "{}", fs);
+            for (_, options) in attempts {
+                assert_eq!(options.as_deref(), data, "{}", fs);
+            }
+        }
+    }
+
+    fn run(program: &str, args: &[&str]) -> String {
+        let output = Command::new(program).args(args).output().unwrap();
+        assert!(output.status.success(), "{} {:?}: {:?}", program, args, output);
+        String::from_utf8(output.stdout).unwrap().trim().to_owned()
+    }
+
+    fn checksum(path: &Path) -> u64 {
+        let mut file = File::open(path).unwrap();
+        let mut buffer = vec![0; 1024 * 1024];
+        let mut hasher = DefaultHasher::new();
+        loop {
+            match file.read(&mut buffer).unwrap() {
+                0 => return hasher.finish(),
+                read => hasher.write(&buffer[..read]),
+            }
+        }
+    }
+
+    /// Creates an image of the file system which was copied while it was mounted, so that
+    /// its journal or log must be replayed, and NTFS is marked as dirty.
+    fn dirty_image(dir: &Path, fs: &str, mkfs: &[&str], megabytes: u64) -> PathBuf {
+        let clean = dir.join([fs, ".clean"].concat());
+        let dirty = dir.join([fs, ".dirty"].concat());
+        File::create(&clean).unwrap().set_len(megabytes * 1024 * 1024).unwrap();
+        run(mkfs[0], &[&mkfs[1..], &[clean.to_str().unwrap()]].concat());
+
+        let loop_device =
+            PathBuf::from(run("losetup", &["--find", "--show", clean.to_str().unwrap()]));
+        let target = dir.join("target");
+        fs::create_dir_all(&target).unwrap();
+        {
+            let driver = if fs == "ntfs" { "ntfs3" } else { fs };
+            let _mount = Mount::new(&loop_device, &target, driver, MountFlags::empty(), None)
+                .unwrap()
+                .into_unmount_drop(UnmountFlags::DETACH);
+            fs::create_dir_all(target.join("etc")).unwrap();
+            fs::create_dir_all(target.join("Windows/System32")).unwrap();
+            fs::write(target.join("etc/os-release"), "NAME=\"Probe\"\nID=probe\n").unwrap();
+            fs::write(target.join("Windows/System32/ntoskrnl.exe"), "").unwrap();
+            run("sync", &[]);
+            fs::copy(&clean, &dirty).unwrap();
+        }
+
+        run("losetup", &["--detach", loop_device.to_str().unwrap()]);
+        dirty
+    }
+
+    /// Requires root, loop devices, and the mkfs of each file system, so it must be run
+    /// explicitly with `sudo cargo test -- --ignored`.
+    #[test]
+    #[ignore]
+    fn probes_do_not_write_to_devices() {
+        let dir = TempDir::new("distinst-probe").unwrap();
+        let images: &[(&str, &[&str], u64)] = &[
+            ("ext4", &["mkfs.ext4", "-F", "-q"], 64),
+            ("xfs", &["mkfs.xfs", "-f", "-q"], 320),
+            ("ntfs", &["mkntfs", "-F", "-f", "-q"], 64),
+        ];
+
+        for &(fs, mkfs, megabytes) in images {
+            let image = dirty_image(dir.path(), fs, mkfs, megabytes);
+            let before = checksum(&image);
+
+            let loop_device =
+                PathBuf::from(run("losetup", &["--find", "--show", image.to_str().unwrap()]));
+            let os = detect_os_from_device(&loop_device, fs);
+            info!("detected {:?} on the {} image", os, fs);
+            run("losetup", &["--detach", loop_device.to_str().unwrap()]);
+
+            assert_eq!(before, checksum(&image), "{} image was modified by the probe", fs);
+        }
+    }
+
+    #[test]
+    fn probe_options_are_appended() {
+        assert_eq!(
+            probe_attempts("btrfs", Some("subvol=@home")),
+            vec![("btrfs", Some("nologreplay,subvol=@home".to_owned()))]
+        );
+        assert_eq!(probe_attempts("vfat", Some("utf8")), vec![("vfat", Some("utf8".to_owned()))]);
+    }
+
+    #[test]
+    fn fstab_subvolumes() {
+        assert_eq!(parse_subvolume("defaults,subvol=@home"), Some("@home".into()));
+        assert_eq!(parse_subvolume("subvol=/@,compress=zstd"), Some("@".into()));
+        assert_eq!(parse_subvolume("defaults,subvolid=256"), None);
+    }
+
     #[test]
     fn mac_plist_parsing() {
         assert_eq!(
@@ -0,0 +1,308 @@
+//! Detects conditions of a Windows volume which make it unsafe to resize.
+
+use std::{
+    fmt,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+/// The OEM ID in the boot sector of a volume encrypted with BitLocker.
+const BITLOCKER_OEM_ID: &[u8] = b"-FVE-FS-";
+/// The OEM ID in the boot sector of an NTFS volume.
+const NTFS_OEM_ID: &[u8] = b"NTFS    ";
+
+/// The MFT record of the `$Volume` metadata file.
+const VOLUME_RECORD: u64 = 3;
+/// The type of the `$VOLUME_INFORMATION` attribute, which holds the volume flags.
+const VOLUME_INFORMATION: u32 = 0x70;
+const END_OF_ATTRIBUTES: u32 = 0xFFFF_FFFF;
+/// Set while the volume is in use by Windows, and cleared when it is shut down cleanly.
+const VOLUME_IS_DIRTY: u16 = 0x0001;
+/// The stride of the update sequence array, which is independent of the sector size.
+const FIXUP_STRIDE: usize = 512;
+
+/// Conditions of a Windows volume which make it unsafe to resize.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WindowsState {
+    /// `hiberfil.sys` holds a hibernation image, which Windows will resume from. This is
+    /// also the case after a shutdown with Fast Startup enabled.
+    pub hibernated: bool,
+    /// The volume is marked as dirty, as it was not cleanly unmounted by Windows, which is
+    /// the case after a shutdown with Fast Startup enabled.
+    pub dirty:      bool,
+    /// The volume is encrypted with BitLocker.
+    pub bitlocker:  bool,
+}
+
+impl WindowsState {
+    /// True if the volume may be resized without losing data.
+    pub fn is_safe_to_resize(&self) -> bool { !(self.hibernated || self.dirty || self.bitlocker) }
+}
+
+impl fmt::Display for WindowsState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut reasons = Vec::new();
+        if self.hibernated {
+            reasons.push("Windows is hibernated");
+        }
+
+        if self.dirty {
+            reasons.push("the volume was not shut down cleanly, which Fast Startup causes");
+        }
+
+        if self.bitlocker {
+            reasons.push("the volume is encrypted with BitLocker");
+        }
+
+        if reasons.is_empty() {
+            f.write_str("the volume is safe to resize")
+        } else {
+            f.write_str(&reasons.join(", "))
+        }
+    }
+}
+
+/// Reads the state of the volume on `device` from its boot sector and `$Volume` record.
+///
+/// `hibernated` is never set, as that requires the volume to be mounted.
+pub fn volume_state(device: &Path) -> WindowsState {
+    let mut state = WindowsState::default();
+    if let Err(why) = File::open(device).and_then(|mut file| inspect_volume(&mut file, &mut state))
+    {
+        warn!("unable to inspect the volume on {:?}: {}", device, why);
+    }
+
+    state
+}
+
+/// True if `hiberfil.sys` within the mounted volume at `base` holds a hibernation image.
+pub fn is_hibernated(base: &Path) -> bool {
+    let mut signature = [0; 4];
+    File::open(base.join("hiberfil.sys"))
+        .and_then(|mut file| file.read_exact(&mut signature))
+        .map(|_| is_hibernation_signature(&signature))
+        .unwrap_or(false)
+}
+
+/// The hibernation file begins with `hibr` when it holds an image, or `rstr` while the
+/// image is being restored. The signature is zeroed, or `wake`, once Windows has resumed.
+fn is_hibernation_signature(signature: &[u8; 4]) -> bool {
+    signature.eq_ignore_ascii_case(b"hibr") || signature.eq_ignore_ascii_case(b"rstr")
+}
+
+fn inspect_volume<R: Read + Seek>(volume: &mut R, state: &mut WindowsState) -> io::Result<()> {
+    let mut boot = [0; 512];
+    volume.seek(SeekFrom::Start(0))?;
+    volume.read_exact(&mut boot)?;
+
+    let oem_id = &boot[3..11];
+    if oem_id == BITLOCKER_OEM_ID {
+        state.bitlocker = true;
+        return Ok(());
+    } else if oem_id != NTFS_OEM_ID {
+        return Ok(());
+    }
+
+    let bytes_per_sector = u64::from(u16::from_le_bytes([boot[0x0B], boot[0x0C]]));
+    // Values above 128 are the negated power of two of clusters larger than 64 KiB.
+    let sectors_per_cluster = match boot[0x0D] {
+        value if value > 128 => 1u64.checked_shl(256 - u32::from(value)).unwrap_or(0),
+        value => u64::from(value),
+    };
+
+    let cluster_size = bytes_per_sector * sectors_per_cluster;
+    // Negative values are the power of two of records smaller than a cluster.
+    let record_size = match boot[0x40] as i8 {
+        value if value < 0 && value > -32 => 1 << -i32::from(value),
+        value => u64::from(value as u8) * cluster_size,
+    };
+
+    if cluster_size == 0 || record_size < FIXUP_STRIDE as u64 || record_size > 64 * 1024 {
+        return Err(invalid_volume("invalid NTFS boot sector"));
+    }
+
+    let mut mft_lcn = [0; 8];
+    mft_lcn.copy_from_slice(&boot[0x30..0x38]);
+    let volume_record = u64::from_le_bytes(mft_lcn)
+        .checked_mul(cluster_size)
+        .and_then(|mft| mft.checked_add(VOLUME_RECORD * record_size))
+        .ok_or_else(|| invalid_volume("invalid MFT location"))?;
+
+    let mut record = vec![0; record_size as usize];
+    volume.seek(SeekFrom::Start(volume_record))?;
+    volume.read_exact(&mut record)?;
+
+    let flags = apply_fixups(&mut record)
+        .and_then(|_| volume_flags(&record))
+        .ok_or_else(|| invalid_volume("invalid $Volume record"))?;
+
+    state.dirty = flags & VOLUME_IS_DIRTY != 0;
+    Ok(())
+}
+
+/// Restores the last two bytes of each stride of the record, which are replaced by the
+/// update sequence number when written to disk.
+fn apply_fixups(record: &mut [u8]) -> Option<()> {
+    if record.get(0..4)? != b"FILE" {
+        return None;
+    }
+
+    let offset = usize::from(read_u16(record, 0x04)?);
+    let count = usize::from(read_u16(record, 0x06)?);
+    let usn = read_u16(record, offset)?;
+
+    for stride in 1..count {
+        let end = stride * FIXUP_STRIDE - 2;
+        if read_u16(record, end)? != usn {
+            return None;
+        }
+
+        let original = read_u16(record, offset + stride * 2)?;
+        record.get_mut(end..end + 2)?.copy_from_slice(&original.to_le_bytes());
+    }
+
+    Some(())
+}
+
+/// Finds the flags of the resident `$VOLUME_INFORMATION` attribute within the record.
+fn volume_flags(record: &[u8]) -> Option<u16> {
+    let mut offset = usize::from(read_u16(record, 0x14)?);
+    loop {
+        let kind = read_u32(record, offset)?;
+        if kind == END_OF_ATTRIBUTES {
+            return None;
+        }
+
+        let length = read_u32(record, offset + 4)? as usize;
+        if length == 0 {
+            return None;
+        }
+
+        if kind == VOLUME_INFORMATION && *record.get(offset + 8)? == 0 {
+            let value = offset + usize::from(read_u16(record, offset + 0x14)?);
+            return read_u16(record, value + 10);
+        }
+
+        offset += length;
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset + 2)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn invalid_volume(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const SECTOR: usize = 512;
+    const CLUSTER: usize = 4096;
+    const RECORD: usize = 1024;
+    const MFT_LCN: usize = 4;
+
+    /// Builds an NTFS volume which holds only a boot sector and the `$Volume` record.
+    fn ntfs_volume(flags: u16) -> Vec<u8> {
+        let mft = MFT_LCN * CLUSTER;
+        let mut volume = vec![0; mft + 4 * RECORD];
+
+        volume[3..11].copy_from_slice(NTFS_OEM_ID);
+        volume[0x0B..0x0D].copy_from_slice(&(SECTOR as u16).to_le_bytes());
+        volume[0x0D] = (CLUSTER / SECTOR) as u8;
+        volume[0x30..0x38].copy_from_slice(&(MFT_LCN as u64).to_le_bytes());
+        volume[0x40] = -10i8 as u8;
+
+        let record = &mut volume[mft + 3 * RECORD..mft + 4 * RECORD];
+        record[0..4].copy_from_slice(b"FILE");
+        // The update sequence array follows the header, with one entry for each stride.
+        record[0x04..0x06].copy_from_slice(&0x30u16.to_le_bytes());
+        record[0x06..0x08].copy_from_slice(&3u16.to_le_bytes());
+        record[0x14..0x16].copy_from_slice(&0x38u16.to_le_bytes());
+
+        // A resident $VOLUME_NAME attribute, which precedes $VOLUME_INFORMATION.
+        record[0x38..0x3C].copy_from_slice(&0x60u32.to_le_bytes());
+        record[0x3C..0x40].copy_from_slice(&0x18u32.to_le_bytes());
+
+        let info = 0x50;
+        record[info..info + 4].copy_from_slice(&VOLUME_INFORMATION.to_le_bytes());
+        record[info + 4..info + 8].copy_from_slice(&0x28u32.to_le_bytes());
+        record[info + 0x14..info + 0x16].copy_from_slice(&0x18u16.to_le_bytes());
+        record[info + 0x18 + 8] = 3;
+        record[info + 0x18 + 9] = 1;
+        record[info + 0x18 + 10..info + 0x18 + 12].copy_from_slice(&flags.to_le_bytes());
+        record[info + 0x28..info + 0x2C].copy_from_slice(&END_OF_ATTRIBUTES.to_le_bytes());
+
+        // Replace the end of each stride with the update sequence number.
+        record[0x30..0x32].copy_from_slice(&7u16.to_le_bytes());
+        for stride in 1..3 {
+            let end = stride * FIXUP_STRIDE - 2;
+            let original = [record[end], record[end + 1]];
+            record[0x30 + stride * 2..0x32 + stride * 2].copy_from_slice(&original);
+            record[end..end + 2].copy_from_slice(&7u16.to_le_bytes());
+        }
+
+        volume
+    }
+
+    fn inspect(volume: Vec<u8>) -> io::Result<WindowsState> {
+        let mut state = WindowsState::default();
+        inspect_volume(&mut Cursor::new(volume), &mut state).map(|_| state)
+    }
+
+    #[test]
+    fn clean_volume() {
+        let state = inspect(ntfs_volume(0)).unwrap();
+        assert_eq!(state, WindowsState::default());
+        assert!(state.is_safe_to_resize());
+    }
+
+    #[test]
+    fn dirty_volume() {
+        let state = inspect(ntfs_volume(VOLUME_IS_DIRTY)).unwrap();
+        assert!(state.dirty);
+        assert!(!state.is_safe_to_resize());
+    }
+
+    #[test]
+    fn corrupted_fixups() {
+        let mut volume = ntfs_volume(0);
+        volume[MFT_LCN * CLUSTER + 3 * RECORD + FIXUP_STRIDE - 2] = 0;
+        assert_eq!(inspect(volume).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn bitlocker_volume() {
+        let mut volume = vec![0; SECTOR];
+        volume[3..11].copy_from_slice(BITLOCKER_OEM_ID);
+        let state = inspect(volume).unwrap();
+        assert!(state.bitlocker);
+        assert!(!state.is_safe_to_resize());
+    }
+
+    #[test]
+    fn other_volume() {
+        let mut volume = vec![0; SECTOR];
+        volume[3..11].copy_from_slice(b"mkfs.fat");
+        assert_eq!(inspect(volume).unwrap(), WindowsState::default());
+    }
+
+    #[test]
+    fn hibernation_signatures() {
+        assert!(is_hibernation_signature(b"hibr"));
+        assert!(is_hibernation_signature(b"HIBR"));
+        assert!(is_hibernation_signature(b"RSTR"));
+        assert!(!is_hibernation_signature(b"wake"));
+        assert!(!is_hibernation_signature(&[0; 4]));
+    }
+}
@@ -0,0 +1,315 @@
+//! Extracts tar archives and OCI layers, which may be compressed with gzip, xz, or zstd.
+
+use crate::{
+    unpack::{self, Metadata},
+    Progress,
+};
+use flate2::read::GzDecoder;
+use std::{
+    cell::Cell,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Read, Result},
+    path::{Component, Path, PathBuf},
+    rc::Rc,
+};
+use tar::{Archive, Entry, EntryType};
+use xz2::read::XzDecoder;
+
+const GZIP_MAGIC: &[u8] = &[0x1F, 0x8B];
+const XZ_MAGIC: &[u8] = &[0xFD, b'7', b'z', b'X', b'Z', 0x00];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xB5, 0x2F, 0xFD];
+const WHITEOUT: &str = ".wh.";
+const OPAQUE_WHITEOUT: &str = ".wh..wh..opq";
+/// The prefix of PAX extensions which hold extended attributes.
+const XATTR_PREFIX: &[u8] = b"SCHILY.xattr.";
+
+/// Counts the bytes which are read from the archive file, so that progress can be measured
+/// against its size regardless of how it was compressed.
+struct CountingReader<R> {
+    inner: R,
+    count: Rc<Cell<u64>>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize> {
+        let read = self.inner.read(buffer)?;
+        self.count.set(self.count.get() + read as u64);
+        Ok(read)
+    }
+}
+
+/// Detects the compression of the archive from its magic number.
+fn decoder<'a, R: BufRead + 'a>(mut reader: R) -> Result<Box<dyn Read + 'a>> {
+    let magic = reader.fill_buf()?;
+    let decoder: Box<dyn Read + 'a> = if magic.starts_with(GZIP_MAGIC) {
+        Box::new(GzDecoder::new(reader))
+    } else if magic.starts_with(XZ_MAGIC) {
+        Box::new(XzDecoder::new(reader))
+    } else if magic.starts_with(ZSTD_MAGIC) {
+        Box::new(zstd::stream::read::Decoder::with_buffer(reader)?)
+    } else {
+        Box::new(reader)
+    };
+
+    Ok(decoder)
+}
+
+/// The path of an entry within the directory, provided that it does not escape the directory.
+fn entry_path(directory: &Path, path: &Path) -> Option<PathBuf> {
+    let mut target = directory.to_path_buf();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => target.push(part),
+            Component::CurDir => (),
+            _ => return None,
+        }
+    }
+
+    Some(target)
+}
+
+/// Applies an OCI whiteout entry, which removes a file of a lower layer from the target.
+///
+/// Returns `false` if the entry is not a whiteout, and should be unpacked.
+fn whiteout(directory: &Path, path: &Path) -> Result<bool> {
+    let name = match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) if name.starts_with(WHITEOUT) => name,
+        _ => return Ok(false),
+    };
+
+    let parent = match path.parent().and_then(|parent| entry_path(directory, parent)) {
+        Some(parent) => parent,
+        None => {
+            let why = format!("{:?} is outside of the archive", path);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, why));
+        }
+    };
+
+    if name == OPAQUE_WHITEOUT {
+        // An opaque directory hides every file that the lower layers placed within it.
+        if parent.is_dir() {
+            for entry in fs::read_dir(&parent)? {
+                remove(&entry?.path())?;
+            }
+        }
+    } else {
+        remove(&parent.join(&name[WHITEOUT.len()..]))?;
+    }
+
+    Ok(true)
+}
+
+fn remove(path: &Path) -> Result<()> {
+    let result = match fs::symlink_metadata(path) {
+        Ok(ref metadata) if metadata.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(why) => Err(why),
+    };
+
+    match result {
+        Err(ref why) if why.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// The metadata of an entry, including the extended attributes of its PAX extensions.
+fn metadata<R: Read>(entry: &mut Entry<R>) -> Result<Metadata> {
+    let header = entry.header();
+    let mut metadata = Metadata {
+        mode:   header.mode()?,
+        uid:    header.uid()? as u32,
+        gid:    header.gid()? as u32,
+        mtime:  header.mtime()? as i64,
+        xattrs: Vec::new(),
+    };
+
+    if let Some(extensions) = entry.pax_extensions()? {
+        for extension in extensions {
+            let extension = extension?;
+            let key = extension.key_bytes();
+            if key.starts_with(XATTR_PREFIX) {
+                let name = key[XATTR_PREFIX.len()..].to_vec();
+                metadata.xattrs.push((name, extension.value_bytes().to_vec()));
+            }
+        }
+    }
+
+    Ok(metadata)
+}
+
+/// Unpacks a tar archive into the directory, reporting the bytes read from the file as the
+/// progress beyond `offset`.
+///
+/// The whiteouts of OCI layers are applied if `whiteouts` is set.
+pub(crate) fn unpack<F: FnMut(i32)>(
+    file: File,
+    directory: &Path,
+    offset: u64,
+    whiteouts: bool,
+    progress: &mut Progress<F>,
+) -> Result<()> {
+    let count = Rc::new(Cell::new(0));
+    let reader = BufReader::new(CountingReader { inner: file, count: count.clone() });
+
+    // Ownership and extended attributes are applied once each entry has been unpacked, as tar
+    // does not change the owner, and changing it clears the `security.capability` attribute.
+    let mut archive = Archive::new(decoder(reader)?);
+    archive.set_preserve_permissions(true);
+    archive.set_preserve_mtime(true);
+    archive.set_unpack_xattrs(false);
+    archive.set_overwrite(true);
+
+    for entry in archive.entries()? {
+        progress.check()?;
+        let mut entry = entry?;
+        let kind = entry.header().entry_type();
+        if kind == EntryType::XGlobalHeader {
+            continue;
+        }
+
+        let path = entry.path()?.into_owned();
+        if whiteouts && whiteout(directory, &path)? {
+            continue;
+        }
+
+        let target = entry_path(directory, &path).ok_or_else(|| {
+            let why = format!("{:?} is outside of the archive", path);
+            io::Error::new(io::ErrorKind::InvalidData, why)
+        })?;
+
+        match kind {
+            // Device nodes and FIFOs are created here, as tar does not create them.
+            EntryType::Block | EntryType::Char | EntryType::Fifo => {
+                let header = entry.header();
+                let mode = match kind {
+                    EntryType::Block => libc::S_IFBLK,
+                    EntryType::Char => libc::S_IFCHR,
+                    _ => libc::S_IFIFO,
+                };
+
+                let major = header.device_major()?.unwrap_or(0);
+                let minor = header.device_minor()?.unwrap_or(0);
+                unpack::create_node(&target, mode, major, minor)?;
+                unpack::apply(&target, false, &metadata(&mut entry)?)?;
+            }
+            // Hard links share the metadata of the file that they link to.
+            EntryType::Link => {
+                entry.unpack_in(directory)?;
+            }
+            _ => {
+                let metadata = metadata(&mut entry)?;
+                if entry.unpack_in(directory)? {
+                    unpack::apply(&target, kind == EntryType::Symlink, &metadata)?;
+                }
+            }
+        }
+
+        progress.set(offset + count.get());
+    }
+
+    Ok(())
+}
+
+pub(crate) fn extract<F: FnMut(i32)>(
+    archive: &Path,
+    directory: &Path,
+    progress: &mut Progress<F>,
+) -> Result<()> {
+    let file = File::open(archive)?;
+    progress.total = file.metadata()?.len();
+    unpack(file, directory, 0, false, progress)?;
+    progress.set(progress.total);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        os::unix::fs::MetadataExt,
+        sync::atomic::{AtomicBool, Ordering},
+    };
+    use tar::{Builder, Header};
+    use tempdir::TempDir;
+
+    /// A header which is owned by the current user, so that its ownership may be preserved.
+    fn header(kind: EntryType, size: u64) -> Header {
+        let mut header = Header::new_gnu();
+        header.set_entry_type(kind);
+        header.set_size(size);
+        header.set_mode(0o640);
+        header.set_mtime(1_500_000_000);
+        header.set_uid(u64::from(unsafe { libc::getuid() }));
+        header.set_gid(u64::from(unsafe { libc::getgid() }));
+        header
+    }
+
+    fn archive(path: &Path) {
+        let mut builder = Builder::new(File::create(path).unwrap());
+
+        let mut file = header(EntryType::Regular, 8);
+        builder.append_data(&mut file, "etc/hostname", &b"distinst"[..]).unwrap();
+
+        let mut link = header(EntryType::Link, 0);
+        builder.append_link(&mut link, "etc/hostname.link", "etc/hostname").unwrap();
+
+        let mut symlink = header(EntryType::Symlink, 0);
+        builder.append_link(&mut symlink, "hostname", "etc/hostname").unwrap();
+
+        builder.finish().unwrap();
+    }
+
+    #[test]
+    fn tar_extraction() {
+        let tempdir = TempDir::new("distinst-squashfs").unwrap();
+        let path = tempdir.path().join("image.tar");
+        let directory = tempdir.path().join("target");
+        fs::create_dir(&directory).unwrap();
+        archive(&path);
+
+        let cancel = AtomicBool::new(false);
+        let mut percents = Vec::new();
+        crate::extract(&path, &directory, &cancel, |percent| percents.push(percent)).unwrap();
+
+        let hostname = directory.join("etc/hostname");
+        assert_eq!(fs::read(&hostname).unwrap(), b"distinst");
+
+        let metadata = fs::metadata(&hostname).unwrap();
+        assert_eq!(metadata.mode() & 0o7777, 0o640);
+        assert_eq!(metadata.mtime(), 1_500_000_000);
+        let link = fs::metadata(directory.join("etc/hostname.link")).unwrap();
+        assert_eq!(metadata.ino(), link.ino());
+        assert_eq!(fs::read_link(directory.join("hostname")).unwrap(), Path::new("etc/hostname"));
+        assert_eq!(percents.last(), Some(&100));
+
+        cancel.store(true, Ordering::SeqCst);
+        let error = crate::extract(&path, &directory, &cancel, |_| ()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::Interrupted);
+    }
+
+    #[test]
+    fn whiteouts() {
+        let tempdir = TempDir::new("distinst-squashfs").unwrap();
+        let directory = tempdir.path();
+        fs::create_dir_all(directory.join("etc/skel")).unwrap();
+        fs::write(directory.join("etc/skel/.bashrc"), b"").unwrap();
+        fs::write(directory.join("etc/hostname"), b"").unwrap();
+
+        assert!(!whiteout(directory, Path::new("etc/hosts")).unwrap());
+        assert!(whiteout(directory, Path::new("etc/.wh.hostname")).unwrap());
+        assert!(!directory.join("etc/hostname").exists());
+        assert!(whiteout(directory, Path::new("etc/skel/.wh..wh..opq")).unwrap());
+        assert!(directory.join("etc/skel").exists());
+        assert!(!directory.join("etc/skel/.bashrc").exists());
+        assert!(whiteout(directory, Path::new("../.wh.etc")).is_err());
+    }
+
+    #[test]
+    fn escaping_paths() {
+        let directory = Path::new("/target");
+        let target = entry_path(directory, Path::new("./dev/null"));
+        assert_eq!(target, Some("/target/dev/null".into()));
+        assert_eq!(entry_path(directory, Path::new("../dev/null")), None);
+        assert_eq!(entry_path(directory, Path::new("/dev/null")), None);
+    }
+}
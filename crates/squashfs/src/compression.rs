@@ -0,0 +1,83 @@
+use flate2::read::ZlibDecoder;
+use std::io::{Error, ErrorKind, Read, Result};
+use xz2::{read::XzDecoder, stream::Stream};
+
+/// The compressor which a squashfs image was created with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Lzma,
+    Lzo,
+    Xz,
+    Lz4,
+    Zstd,
+}
+
+impl Compression {
+    pub fn from_id(id: u16) -> Result<Self> {
+        let compression = match id {
+            1 => Compression::Gzip,
+            2 => Compression::Lzma,
+            3 => Compression::Lzo,
+            4 => Compression::Xz,
+            5 => Compression::Lz4,
+            6 => Compression::Zstd,
+            _ => {
+                let why = format!("unknown compression type {}", id);
+                return Err(Error::new(ErrorKind::InvalidData, why));
+            }
+        };
+
+        Ok(compression)
+    }
+
+    /// Decompresses a block, which may be no larger than `capacity` once decompressed.
+    pub fn decompress(self, input: &[u8], capacity: usize) -> Result<Vec<u8>> {
+        let mut output = Vec::with_capacity(capacity);
+        // Reading one byte beyond the capacity detects a block which decompresses too large.
+        let limit = capacity as u64 + 1;
+
+        match self {
+            Compression::Gzip => ZlibDecoder::new(input).take(limit).read_to_end(&mut output)?,
+            Compression::Xz => XzDecoder::new(input).take(limit).read_to_end(&mut output)?,
+            Compression::Lzma => {
+                let stream = Stream::new_lzma_decoder(u64::max_value())?;
+                XzDecoder::new_stream(input, stream).take(limit).read_to_end(&mut output)?
+            }
+            Compression::Zstd => {
+                zstd::stream::read::Decoder::new(input)?.take(limit).read_to_end(&mut output)?
+            }
+            Compression::Lzo | Compression::Lz4 => {
+                let why = format!("{:?} compressed images are not supported", self);
+                return Err(Error::new(ErrorKind::InvalidData, why));
+            }
+        };
+
+        if output.len() > capacity {
+            return Err(Error::new(ErrorKind::InvalidData, "block is larger than its capacity"));
+        }
+
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::ZlibEncoder, Compression as Level};
+    use std::io::Write;
+
+    #[test]
+    fn decompression() {
+        let data = b"distinst".repeat(64);
+        let mut encoder = ZlibEncoder::new(Vec::new(), Level::default());
+        encoder.write_all(&data).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let gzip = Compression::from_id(1).unwrap();
+        assert_eq!(gzip.decompress(&compressed, data.len()).unwrap(), data);
+        assert!(gzip.decompress(&compressed, data.len() - 1).is_err());
+        assert!(Compression::Lz4.decompress(&compressed, data.len()).is_err());
+        assert!(Compression::from_id(7).is_err());
+    }
+}
@@ -0,0 +1,155 @@
+//! Copies a directory tree into the target, in the manner of `rsync -aHAX`.
+
+use crate::{
+    unpack::{self, Metadata},
+    Progress,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::{Read, Result, Write},
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    path::{Path, PathBuf},
+};
+
+/// The size of the regular files in the tree, counting hard links once.
+fn total_size(source: &Path, seen: &mut HashSet<(u64, u64)>) -> Result<u64> {
+    let mut total = 0;
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            total += total_size(&entry.path(), seen)?;
+        } else if metadata.is_file()
+            && (metadata.nlink() <= 1 || seen.insert((metadata.dev(), metadata.ino())))
+        {
+            total += metadata.len();
+        }
+    }
+
+    Ok(total)
+}
+
+fn copy_file<F: FnMut(i32)>(
+    source: &Path,
+    target: &Path,
+    progress: &mut Progress<F>,
+) -> Result<()> {
+    let mut input = File::open(source)?;
+    let mut output = unpack::create_file(target)?;
+    let mut buffer = vec![0; 1024 * 1024];
+
+    loop {
+        progress.check()?;
+        let read = input.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(());
+        }
+
+        output.write_all(&buffer[..read])?;
+        progress.advance(read as u64);
+    }
+}
+
+fn copy_tree<F: FnMut(i32)>(
+    source: &Path,
+    target: &Path,
+    links: &mut HashMap<(u64, u64), PathBuf>,
+    progress: &mut Progress<F>,
+) -> Result<()> {
+    for entry in fs::read_dir(source)? {
+        progress.check()?;
+
+        let entry = entry?;
+        let path = entry.path();
+        let destination = target.join(entry.file_name());
+        let metadata = entry.metadata()?;
+        let kind = metadata.file_type();
+
+        if !kind.is_dir() && metadata.nlink() > 1 {
+            let inode = (metadata.dev(), metadata.ino());
+            if let Some(existing) = links.get(&inode) {
+                unpack::create_hard_link(existing, &destination)?;
+                continue;
+            }
+
+            links.insert(inode, destination.clone());
+        }
+
+        if kind.is_dir() {
+            unpack::create_dir(&destination)?;
+            copy_tree(&path, &destination, links, progress)?;
+        } else if kind.is_file() {
+            copy_file(&path, &destination, progress)?;
+        } else if kind.is_symlink() {
+            unpack::create_symlink(fs::read_link(&path)?.as_os_str().as_bytes(), &destination)?;
+        } else {
+            let rdev = metadata.rdev();
+            let (major, minor) = unsafe { (libc::major(rdev), libc::minor(rdev)) };
+            let kind = metadata.mode() & libc::S_IFMT;
+            unpack::create_node(&destination, kind, major, minor)?;
+        }
+
+        apply_metadata(&path, &destination, &metadata)?;
+    }
+
+    Ok(())
+}
+
+fn apply_metadata(source: &Path, target: &Path, metadata: &fs::Metadata) -> Result<()> {
+    let attributes = Metadata {
+        mode:   metadata.mode(),
+        uid:    metadata.uid(),
+        gid:    metadata.gid(),
+        mtime:  metadata.mtime(),
+        xattrs: unpack::read_xattrs(source)?,
+    };
+
+    unpack::apply(target, metadata.file_type().is_symlink(), &attributes)
+}
+
+/// Copies the contents of the source directory into the target directory, retaining ownership,
+/// permissions, extended attributes (including ACLs), hard links, and device nodes.
+pub(crate) fn copy<F: FnMut(i32)>(source: &Path, target: &Path, progress: &mut Progress<F>) -> Result<()> {
+    progress.total = total_size(source, &mut HashSet::new())?;
+    copy_tree(source, target, &mut HashMap::new(), progress)?;
+    apply_metadata(source, target, &fs::symlink_metadata(source)?)?;
+    progress.set(progress.total);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        os::unix::fs::{symlink, PermissionsExt},
+        sync::atomic::AtomicBool,
+    };
+    use tempdir::TempDir;
+
+    #[test]
+    fn directory_copy() {
+        let tempdir = TempDir::new("distinst-squashfs").unwrap();
+        let source = tempdir.path().join("source");
+        let target = tempdir.path().join("target");
+        fs::create_dir_all(source.join("usr/bin")).unwrap();
+        fs::create_dir(&target).unwrap();
+
+        fs::write(source.join("usr/bin/distinst"), b"distinst").unwrap();
+        fs::set_permissions(source.join("usr/bin/distinst"), fs::Permissions::from_mode(0o755))
+            .unwrap();
+        fs::hard_link(source.join("usr/bin/distinst"), source.join("usr/bin/installer")).unwrap();
+        symlink("usr/bin", source.join("bin")).unwrap();
+
+        let cancel = AtomicBool::new(false);
+        let mut percents = Vec::new();
+        crate::extract(&source, &target, &cancel, |percent| percents.push(percent)).unwrap();
+
+        let binary = fs::metadata(target.join("usr/bin/distinst")).unwrap();
+        assert_eq!(binary.mode() & 0o7777, 0o755);
+        assert_eq!(binary.ino(), fs::metadata(target.join("usr/bin/installer")).unwrap().ino());
+        assert_eq!(fs::read(target.join("bin/distinst")).unwrap(), b"distinst");
+        assert_eq!(fs::read_link(target.join("bin")).unwrap(), Path::new("usr/bin"));
+        assert_eq!(percents.last(), Some(&100));
+    }
+}
@@ -0,0 +1,642 @@
+//! An in-process reader of squashfs 4.0 images, which extracts them without `unsquashfs`.
+
+use crate::{
+    compression::Compression,
+    unpack::{self, Metadata},
+    verify::{Superblock, TABLE_ABSENT},
+    Progress,
+};
+use std::{
+    cmp,
+    collections::HashMap,
+    ffi::OsStr,
+    fs::File,
+    io::{Error, ErrorKind, Result, Seek, SeekFrom, Write},
+    os::unix::{ffi::OsStrExt, fs::FileExt},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+const METADATA_SIZE: usize = 8192;
+const METADATA_UNCOMPRESSED: u16 = 1 << 15;
+const BLOCK_UNCOMPRESSED: u32 = 1 << 24;
+const NO_FRAGMENT: u32 = !0;
+const NO_XATTR: u32 = !0;
+/// Values of extended attributes with this type flag are stored elsewhere in the table.
+const XATTR_OUT_OF_LINE: u16 = 0x100;
+
+fn invalid<T>(why: &str) -> Result<T> { Err(Error::new(ErrorKind::InvalidData, why.to_owned())) }
+
+/// A position within a metadata table: the position of a metadata block in the image, and an
+/// offset within that block once it is decompressed.
+#[derive(Debug, Clone, Copy)]
+struct Cursor {
+    block:  u64,
+    offset: usize,
+}
+
+impl Cursor {
+    /// Locates a reference within a table, which contains the offset of its metadata block from
+    /// the start of the table in its upper bits, and the offset within the block in its lower
+    /// 16 bits.
+    fn new(table: u64, reference: u64) -> Self {
+        Cursor { block: table + (reference >> 16), offset: (reference & 0xFFFF) as usize }
+    }
+}
+
+#[derive(Debug)]
+enum InodeKind {
+    Directory { block: u32, offset: u16, size: u32 },
+    File { blocks_start: u64, size: u64, fragment: u32, fragment_offset: u32, blocks: Vec<u32> },
+    Symlink { target: Vec<u8> },
+    BlockDevice { rdev: u32 },
+    CharDevice { rdev: u32 },
+    Fifo,
+    Socket,
+}
+
+#[derive(Debug)]
+struct Inode {
+    kind:   InodeKind,
+    mode:   u16,
+    uid:    u32,
+    gid:    u32,
+    mtime:  u32,
+    number: u32,
+    nlink:  u32,
+    xattr:  u32,
+}
+
+struct DirEntry {
+    name:  Vec<u8>,
+    inode: u64,
+}
+
+/// A squashfs image which is opened for reading.
+pub struct Image {
+    file:        File,
+    superblock:  Superblock,
+    compression: Compression,
+    /// Decompressed metadata blocks, and the position of the block which follows each of them.
+    metadata:    HashMap<u64, (Rc<Vec<u8>>, u64)>,
+    ids:         Vec<u32>,
+    fragments:   Vec<(u64, u32)>,
+    fragment:    Option<(u32, Rc<Vec<u8>>)>,
+    xattr_start: u64,
+    xattr_ids:   Vec<(u64, u32)>,
+}
+
+impl Image {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path)?;
+        let superblock = Superblock::read(&file)?;
+        superblock.validate(file.metadata()?.len())?;
+
+        let mut image = Image {
+            compression: Compression::from_id(superblock.compression)?,
+            file,
+            superblock,
+            metadata: HashMap::new(),
+            ids: Vec::new(),
+            fragments: Vec::new(),
+            fragment: None,
+            xattr_start: 0,
+            xattr_ids: Vec::new(),
+        };
+
+        let ids = image.lookup_table(
+            image.superblock.id_table_start,
+            usize::from(image.superblock.ids),
+            4,
+        )?;
+        image.ids = ids.chunks(4).map(u32_from).collect();
+
+        if image.superblock.fragment_table_start != TABLE_ABSENT {
+            let table = image.lookup_table(
+                image.superblock.fragment_table_start,
+                image.superblock.fragments as usize,
+                16,
+            )?;
+            image.fragments = table.chunks(16).map(table_entry).collect();
+        }
+
+        if image.superblock.xattr_id_table_start != TABLE_ABSENT {
+            let mut header = [0; 16];
+            image.file.read_exact_at(&mut header, image.superblock.xattr_id_table_start)?;
+            image.xattr_start = u64_from(&header[..8]);
+            let count = u32_from(&header[8..12]) as usize;
+
+            let table =
+                image.lookup_table(image.superblock.xattr_id_table_start + 16, count, 16)?;
+            image.xattr_ids = table.chunks(16).map(table_entry).collect();
+        }
+
+        Ok(image)
+    }
+
+    /// The total size of the regular files in the image, counting hard links once.
+    pub fn total_size(&mut self) -> Result<u64> {
+        let root = self.inode(self.superblock.root_inode)?;
+        let mut seen = HashMap::new();
+        self.directory_size(&root, &mut seen)
+    }
+
+    /// Extracts every file in the image into the directory.
+    pub(crate) fn extract<F: FnMut(i32)>(
+        &mut self,
+        directory: &Path,
+        progress: &mut Progress<F>,
+    ) -> Result<()> {
+        let root = self.inode(self.superblock.root_inode)?;
+        let mut links = HashMap::new();
+        self.extract_directory(&root, directory, &mut links, progress)?;
+        self.apply_metadata(&root, directory)
+    }
+
+    fn directory_size(&mut self, directory: &Inode, seen: &mut HashMap<u32, ()>) -> Result<u64> {
+        let mut total = 0;
+        for entry in self.read_directory(directory)? {
+            let inode = self.inode(entry.inode)?;
+            match inode.kind {
+                InodeKind::Directory { .. } => total += self.directory_size(&inode, seen)?,
+                InodeKind::File { size, .. } => {
+                    if inode.nlink <= 1 || seen.insert(inode.number, ()).is_none() {
+                        total += size;
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        Ok(total)
+    }
+
+    fn extract_directory<F: FnMut(i32)>(
+        &mut self,
+        directory: &Inode,
+        path: &Path,
+        links: &mut HashMap<u32, PathBuf>,
+        progress: &mut Progress<F>,
+    ) -> Result<()> {
+        for entry in self.read_directory(directory)? {
+            progress.check()?;
+
+            let inode = self.inode(entry.inode)?;
+            let target = path.join(OsStr::from_bytes(&entry.name));
+
+            let is_directory = matches!(inode.kind, InodeKind::Directory { .. });
+
+            if inode.nlink > 1 && !is_directory {
+                if let Some(existing) = links.get(&inode.number) {
+                    unpack::create_hard_link(existing, &target)?;
+                    continue;
+                }
+
+                links.insert(inode.number, target.clone());
+            }
+
+            match inode.kind {
+                InodeKind::Directory { .. } => {
+                    unpack::create_dir(&target)?;
+                    self.extract_directory(&inode, &target, links, progress)?;
+                }
+                InodeKind::File { .. } => {
+                    let mut file = unpack::create_file(&target)?;
+                    self.extract_file(&inode, &mut file, progress)?;
+                }
+                InodeKind::Symlink { target: ref link } => unpack::create_symlink(link, &target)?,
+                InodeKind::BlockDevice { rdev } => {
+                    let (major, minor) = unpack::decode_device(rdev);
+                    unpack::create_node(&target, libc::S_IFBLK, major, minor)?;
+                }
+                InodeKind::CharDevice { rdev } => {
+                    let (major, minor) = unpack::decode_device(rdev);
+                    unpack::create_node(&target, libc::S_IFCHR, major, minor)?;
+                }
+                InodeKind::Fifo => unpack::create_node(&target, libc::S_IFIFO, 0, 0)?,
+                InodeKind::Socket => unpack::create_node(&target, libc::S_IFSOCK, 0, 0)?,
+            }
+
+            // The metadata of a directory is applied after its contents have been extracted,
+            // so that its modification time is retained.
+            self.apply_metadata(&inode, &target)?;
+        }
+
+        Ok(())
+    }
+
+    fn extract_file<F: FnMut(i32)>(
+        &mut self,
+        inode: &Inode,
+        file: &mut File,
+        progress: &mut Progress<F>,
+    ) -> Result<()> {
+        let (blocks_start, size, fragment, fragment_offset, blocks) = match inode.kind {
+            InodeKind::File { blocks_start, size, fragment, fragment_offset, ref blocks } => {
+                (blocks_start, size, fragment, fragment_offset, blocks)
+            }
+            _ => return invalid("inode is not a file"),
+        };
+
+        let block_size = u64::from(self.superblock.block_size);
+        let mut position = blocks_start;
+        let mut written = 0;
+
+        for &block in blocks {
+            progress.check()?;
+
+            let length = cmp::min(block_size, size - written);
+            let stored = block & !BLOCK_UNCOMPRESSED;
+
+            if stored == 0 {
+                // A sparse block, which is left as a hole.
+                file.seek(SeekFrom::Current(length as i64))?;
+            } else {
+                let mut data = vec![0; stored as usize];
+                self.file.read_exact_at(&mut data, position)?;
+                position += u64::from(stored);
+
+                if block & BLOCK_UNCOMPRESSED == 0 {
+                    data = self.compression.decompress(&data, block_size as usize)?;
+                }
+
+                if (data.len() as u64) < length {
+                    return invalid("data block is shorter than its file");
+                }
+
+                file.write_all(&data[..length as usize])?;
+            }
+
+            written += length;
+            progress.advance(length);
+        }
+
+        if fragment != NO_FRAGMENT {
+            let tail = (size - written) as usize;
+            let data = self.fragment(fragment)?;
+            let start = fragment_offset as usize;
+
+            match data.get(start..start + tail) {
+                Some(tail) => file.write_all(tail)?,
+                None => return invalid("fragment is shorter than its file"),
+            }
+
+            written += tail as u64;
+            progress.advance(tail as u64);
+        }
+
+        if written != size {
+            return invalid("file is missing data blocks");
+        }
+
+        file.set_len(size)
+    }
+
+    fn apply_metadata(&mut self, inode: &Inode, path: &Path) -> Result<()> {
+        let is_symlink = matches!(inode.kind, InodeKind::Symlink { .. });
+
+        let metadata = Metadata {
+            mode:   u32::from(inode.mode),
+            uid:    inode.uid,
+            gid:    inode.gid,
+            mtime:  i64::from(inode.mtime),
+            xattrs: self.xattrs(inode.xattr)?,
+        };
+
+        unpack::apply(path, is_symlink, &metadata)
+    }
+
+    /// Reads a metadata block, which is cached after it has been decompressed.
+    fn metadata_block(&mut self, position: u64) -> Result<(Rc<Vec<u8>>, u64)> {
+        if let Some(block) = self.metadata.get(&position) {
+            return Ok(block.clone());
+        }
+
+        let mut header = [0; 2];
+        self.file.read_exact_at(&mut header, position)?;
+        let header = u16::from_le_bytes(header);
+        let stored = usize::from(header & !METADATA_UNCOMPRESSED);
+
+        let mut data = vec![0; stored];
+        self.file.read_exact_at(&mut data, position + 2)?;
+
+        if header & METADATA_UNCOMPRESSED == 0 {
+            data = self.compression.decompress(&data, METADATA_SIZE)?;
+        }
+
+        if data.is_empty() {
+            return invalid("metadata block is empty");
+        }
+
+        let block = (Rc::new(data), position + 2 + stored as u64);
+        self.metadata.insert(position, block.clone());
+        Ok(block)
+    }
+
+    /// Reads from a metadata table, continuing into the blocks which follow as necessary.
+    fn read_metadata(&mut self, cursor: &mut Cursor, buffer: &mut [u8]) -> Result<()> {
+        let mut filled = 0;
+        while filled < buffer.len() {
+            let (data, next) = self.metadata_block(cursor.block)?;
+            if cursor.offset >= data.len() {
+                cursor.offset -= data.len();
+                cursor.block = next;
+                continue;
+            }
+
+            let count = cmp::min(buffer.len() - filled, data.len() - cursor.offset);
+            buffer[filled..filled + count]
+                .copy_from_slice(&data[cursor.offset..cursor.offset + count]);
+            filled += count;
+            cursor.offset += count;
+        }
+
+        Ok(())
+    }
+
+    fn read_bytes(&mut self, cursor: &mut Cursor, length: usize) -> Result<Vec<u8>> {
+        let mut buffer = vec![0; length];
+        self.read_metadata(cursor, &mut buffer)?;
+        Ok(buffer)
+    }
+
+    fn read_u16(&mut self, cursor: &mut Cursor) -> Result<u16> {
+        let mut buffer = [0; 2];
+        self.read_metadata(cursor, &mut buffer)?;
+        Ok(u16::from_le_bytes(buffer))
+    }
+
+    fn read_u32(&mut self, cursor: &mut Cursor) -> Result<u32> {
+        let mut buffer = [0; 4];
+        self.read_metadata(cursor, &mut buffer)?;
+        Ok(u32::from_le_bytes(buffer))
+    }
+
+    fn read_u64(&mut self, cursor: &mut Cursor) -> Result<u64> {
+        let mut buffer = [0; 8];
+        self.read_metadata(cursor, &mut buffer)?;
+        Ok(u64::from_le_bytes(buffer))
+    }
+
+    /// Reads a table of fixed-size entries, which is located by an index of the positions of
+    /// its metadata blocks. The blocks are consecutive, so only the first position is read.
+    fn lookup_table(&mut self, index: u64, count: usize, entry_size: usize) -> Result<Vec<u8>> {
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut first = [0; 8];
+        self.file.read_exact_at(&mut first, index)?;
+        let mut cursor = Cursor { block: u64::from_le_bytes(first), offset: 0 };
+        self.read_bytes(&mut cursor, count * entry_size)
+    }
+
+    fn id(&self, index: u16) -> Result<u32> {
+        match self.ids.get(usize::from(index)) {
+            Some(&id) => Ok(id),
+            None => invalid("inode references an ID which does not exist"),
+        }
+    }
+
+    fn inode(&mut self, reference: u64) -> Result<Inode> {
+        let cursor = &mut Cursor::new(self.superblock.inode_table_start, reference);
+
+        let kind = self.read_u16(cursor)?;
+        let mode = self.read_u16(cursor)?;
+        let uid = self.read_u16(cursor)?;
+        let gid = self.read_u16(cursor)?;
+        let mtime = self.read_u32(cursor)?;
+        let number = self.read_u32(cursor)?;
+
+        let mut nlink = 1;
+        let mut xattr = NO_XATTR;
+
+        let kind = match kind {
+            1 => {
+                let block = self.read_u32(cursor)?;
+                nlink = self.read_u32(cursor)?;
+                let size = u32::from(self.read_u16(cursor)?);
+                let offset = self.read_u16(cursor)?;
+                InodeKind::Directory { block, offset, size }
+            }
+            8 => {
+                nlink = self.read_u32(cursor)?;
+                let size = self.read_u32(cursor)?;
+                let block = self.read_u32(cursor)?;
+                let _parent = self.read_u32(cursor)?;
+                let _index_count = self.read_u16(cursor)?;
+                let offset = self.read_u16(cursor)?;
+                xattr = self.read_u32(cursor)?;
+                InodeKind::Directory { block, offset, size }
+            }
+            2 | 9 => {
+                let (blocks_start, fragment, fragment_offset, size) = if kind == 2 {
+                    let blocks_start = u64::from(self.read_u32(cursor)?);
+                    let fragment = self.read_u32(cursor)?;
+                    let fragment_offset = self.read_u32(cursor)?;
+                    let size = u64::from(self.read_u32(cursor)?);
+                    (blocks_start, fragment, fragment_offset, size)
+                } else {
+                    let blocks_start = self.read_u64(cursor)?;
+                    let size = self.read_u64(cursor)?;
+                    let _sparse = self.read_u64(cursor)?;
+                    nlink = self.read_u32(cursor)?;
+                    let fragment = self.read_u32(cursor)?;
+                    let fragment_offset = self.read_u32(cursor)?;
+                    xattr = self.read_u32(cursor)?;
+                    (blocks_start, fragment, fragment_offset, size)
+                };
+
+                let block_size = u64::from(self.superblock.block_size);
+                let count = if fragment == NO_FRAGMENT {
+                    (size + block_size - 1) / block_size
+                } else {
+                    size / block_size
+                };
+
+                let mut blocks = Vec::new();
+                for _ in 0..count {
+                    blocks.push(self.read_u32(cursor)?);
+                }
+
+                InodeKind::File { blocks_start, size, fragment, fragment_offset, blocks }
+            }
+            3 | 10 => {
+                nlink = self.read_u32(cursor)?;
+                let length = self.read_u32(cursor)? as usize;
+                if length > 4096 {
+                    return invalid("symlink target is too long");
+                }
+
+                let target = self.read_bytes(cursor, length)?;
+                if kind == 10 {
+                    xattr = self.read_u32(cursor)?;
+                }
+
+                InodeKind::Symlink { target }
+            }
+            4 | 5 | 11 | 12 => {
+                nlink = self.read_u32(cursor)?;
+                let rdev = self.read_u32(cursor)?;
+                if kind > 10 {
+                    xattr = self.read_u32(cursor)?;
+                }
+
+                if kind == 4 || kind == 11 {
+                    InodeKind::BlockDevice { rdev }
+                } else {
+                    InodeKind::CharDevice { rdev }
+                }
+            }
+            6 | 7 | 13 | 14 => {
+                nlink = self.read_u32(cursor)?;
+                if kind > 10 {
+                    xattr = self.read_u32(cursor)?;
+                }
+
+                if kind == 6 || kind == 13 {
+                    InodeKind::Fifo
+                } else {
+                    InodeKind::Socket
+                }
+            }
+            _ => return invalid("inode has an unknown type"),
+        };
+
+        let (uid, gid) = (self.id(uid)?, self.id(gid)?);
+        Ok(Inode { kind, mode, uid, gid, mtime, number, nlink, xattr })
+    }
+
+    fn read_directory(&mut self, directory: &Inode) -> Result<Vec<DirEntry>> {
+        let (block, offset, size) = match directory.kind {
+            InodeKind::Directory { block, offset, size } => (block, offset, size),
+            _ => return invalid("inode is not a directory"),
+        };
+
+        let mut entries = Vec::new();
+        // The size includes three bytes for the `.` and `..` entries, which are not stored.
+        let mut remaining = (size as usize).saturating_sub(3);
+        let cursor = &mut Cursor {
+            block:  self.superblock.directory_table_start + u64::from(block),
+            offset: usize::from(offset),
+        };
+
+        while remaining > 0 {
+            let count = self.read_u32(cursor)? + 1;
+            let start = self.read_u32(cursor)?;
+            let _number = self.read_u32(cursor)?;
+            remaining = remaining.checked_sub(12).ok_or_else(directory_overrun)?;
+
+            for _ in 0..count {
+                let inode_offset = self.read_u16(cursor)?;
+                let _number_delta = self.read_u16(cursor)?;
+                let _kind = self.read_u16(cursor)?;
+                let length = usize::from(self.read_u16(cursor)?) + 1;
+                let name = self.read_bytes(cursor, length)?;
+                remaining = remaining.checked_sub(8 + length).ok_or_else(directory_overrun)?;
+
+                if name == b"." || name == b".." || name.contains(&b'/') || name.contains(&0) {
+                    return invalid("directory contains an invalid file name");
+                }
+
+                entries.push(DirEntry {
+                    name,
+                    inode: u64::from(start) << 16 | u64::from(inode_offset),
+                });
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// Reads a fragment block, which is retained as consecutive files share fragments.
+    fn fragment(&mut self, index: u32) -> Result<Rc<Vec<u8>>> {
+        if let Some((cached, data)) = &self.fragment {
+            if *cached == index {
+                return Ok(data.clone());
+            }
+        }
+
+        let (start, size) = match self.fragments.get(index as usize) {
+            Some(&fragment) => fragment,
+            None => return invalid("file references a fragment which does not exist"),
+        };
+
+        let stored = size & !BLOCK_UNCOMPRESSED;
+        let mut data = vec![0; stored as usize];
+        self.file.read_exact_at(&mut data, start)?;
+
+        if size & BLOCK_UNCOMPRESSED == 0 {
+            data = self.compression.decompress(&data, self.superblock.block_size as usize)?;
+        }
+
+        let data = Rc::new(data);
+        self.fragment = Some((index, data.clone()));
+        Ok(data)
+    }
+
+    /// Reads the extended attributes at the given index of the xattr ID table.
+    fn xattrs(&mut self, index: u32) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        if index == NO_XATTR {
+            return Ok(Vec::new());
+        }
+
+        let (reference, count) = match self.xattr_ids.get(index as usize) {
+            Some(&entry) => entry,
+            None => return invalid("inode references xattrs which do not exist"),
+        };
+
+        let cursor = &mut Cursor::new(self.xattr_start, reference);
+        let mut xattrs = Vec::new();
+
+        for _ in 0..count {
+            let kind = self.read_u16(cursor)?;
+            let length = usize::from(self.read_u16(cursor)?);
+
+            let prefix: &[u8] = match kind & 0xFF {
+                0 => b"user.",
+                1 => b"trusted.",
+                2 => b"security.",
+                _ => return invalid("xattr has an unknown prefix"),
+            };
+
+            let mut name = prefix.to_vec();
+            name.extend_from_slice(&self.read_bytes(cursor, length)?);
+
+            let length = self.read_u32(cursor)? as usize;
+            let value = if kind & XATTR_OUT_OF_LINE != 0 {
+                let reference = self.read_u64(cursor)?;
+                let value_cursor = &mut Cursor::new(self.xattr_start, reference);
+                let length = self.read_u32(value_cursor)? as usize;
+                self.read_bytes(value_cursor, length)?
+            } else {
+                self.read_bytes(cursor, length)?
+            };
+
+            xattrs.push((name, value));
+        }
+
+        Ok(xattrs)
+    }
+}
+
+fn directory_overrun() -> Error {
+    Error::new(ErrorKind::InvalidData, "directory listing is larger than its directory")
+}
+
+/// Fragment and xattr ID entries begin with a position, followed by a size or count.
+fn table_entry(entry: &[u8]) -> (u64, u32) {
+    (u64_from(&entry[..8]), u32_from(&entry[8..12]))
+}
+
+fn u32_from(bytes: &[u8]) -> u32 {
+    let mut buffer = [0; 4];
+    buffer.copy_from_slice(&bytes[..4]);
+    u32::from_le_bytes(buffer)
+}
+
+fn u64_from(bytes: &[u8]) -> u64 {
+    let mut buffer = [0; 8];
+    buffer.copy_from_slice(&bytes[..8]);
+    u64::from_le_bytes(buffer)
+}
@@ -2,204 +2,140 @@ extern crate libc;
 #[macro_use]
 extern crate log;
 
+mod archive;
+mod compression;
+mod directory;
+mod image;
+mod oci;
+mod unpack;
+pub mod raw;
+pub mod verify;
+
+use self::{image::Image, verify::SQUASHFS_MAGIC};
 use std::{
     fs::File,
     io::{Error, ErrorKind, Read, Result},
-    os::unix::{
-        io::{AsRawFd, FromRawFd, RawFd},
-        process::CommandExt,
-    },
     path::Path,
-    process::{Command, Stdio},
-    str,
+    sync::atomic::{AtomicBool, Ordering},
 };
 
-fn getpty(columns: u32, lines: u32) -> (RawFd, String) {
-    use std::{
-        ffi::CStr,
-        fs::OpenOptions,
-        io,
-        os::unix::{fs::OpenOptionsExt, io::IntoRawFd},
-    };
+/// Reports the progress of an extraction, and checks whether it has been cancelled.
+pub(crate) struct Progress<'a, F> {
+    pub total: u64,
+    done:      u64,
+    last:      i32,
+    cancel:    &'a AtomicBool,
+    callback:  F,
+}
 
-    extern "C" {
-        fn ptsname(fd: libc::c_int) -> *const libc::c_char;
-        fn grantpt(fd: libc::c_int) -> libc::c_int;
-        fn unlockpt(fd: libc::c_int) -> libc::c_int;
+impl<'a, F: FnMut(i32)> Progress<'a, F> {
+    pub(crate) fn new(cancel: &'a AtomicBool, callback: F) -> Self {
+        Progress { total: 0, done: 0, last: -1, cancel, callback }
     }
 
-    let master_fd = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .custom_flags(libc::O_CLOEXEC)
-        .open("/dev/ptmx")
-        .unwrap()
-        .into_raw_fd();
-    unsafe {
-        if grantpt(master_fd) < 0 {
-            panic!("grantpt: {:?}", Error::last_os_error());
-        }
-        if unlockpt(master_fd) < 0 {
-            panic!("unlockpt: {:?}", Error::last_os_error());
+    pub fn advance(&mut self, bytes: u64) { self.set(self.done + bytes); }
+
+    pub fn set(&mut self, done: u64) {
+        self.done = done;
+        let percent =
+            if self.total == 0 { 100 } else { (done.min(self.total) * 100 / self.total) as i32 };
+
+        if percent != self.last {
+            self.last = percent;
+            (self.callback)(percent);
         }
     }
 
-    unsafe {
-        let size = libc::winsize {
-            ws_row:    lines as libc::c_ushort,
-            ws_col:    columns as libc::c_ushort,
-            ws_xpixel: 0,
-            ws_ypixel: 0,
-        };
-        if libc::ioctl(master_fd, libc::TIOCSWINSZ, &size as *const libc::winsize) < 0 {
-            panic!("ioctl: {:?}", io::Error::last_os_error());
+    pub fn check(&self) -> Result<()> {
+        if self.cancel.load(Ordering::SeqCst) {
+            Err(Error::new(ErrorKind::Interrupted, "operation was cancelled"))
+        } else {
+            Ok(())
         }
     }
-
-    let tty_path = unsafe { CStr::from_ptr(ptsname(master_fd)).to_string_lossy().into_owned() };
-    (master_fd, tty_path)
 }
 
-fn slave_stdio(tty_path: &str) -> Result<(File, File, File)> {
-    use libc::{O_CLOEXEC, O_RDONLY, O_WRONLY};
-    use std::ffi::CString;
+/// The kinds of image that may be extracted into a directory.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// A squashfs image.
+    Squashfs,
+    /// A tar archive, which may be compressed with gzip, xz, or zstd.
+    Tar,
+    /// A directory tree, which is copied.
+    Directory,
+    /// A directory in the OCI image layout, whose layers are unpacked in order.
+    Oci,
+}
 
-    let cvt = |res: i32| -> Result<i32> {
-        if res < 0 {
-            Err(Error::last_os_error())
+impl Format {
+    /// Detects the format of an image from its contents, rather than from its file name.
+    pub fn detect<P: AsRef<Path>>(image: P) -> Result<Self> {
+        let image = image.as_ref();
+        let format = if image.is_dir() {
+            if image.join("oci-layout").exists() {
+                Format::Oci
+            } else {
+                Format::Directory
+            }
+        } else if is_squashfs(image)? {
+            Format::Squashfs
         } else {
-            Ok(res)
-        }
-    };
-
-    let tty_c = CString::new(tty_path).unwrap();
-    let stdin =
-        unsafe { File::from_raw_fd(cvt(libc::open(tty_c.as_ptr(), O_CLOEXEC | O_RDONLY))?) };
-    let stdout =
-        unsafe { File::from_raw_fd(cvt(libc::open(tty_c.as_ptr(), O_CLOEXEC | O_WRONLY))?) };
-    let stderr =
-        unsafe { File::from_raw_fd(cvt(libc::open(tty_c.as_ptr(), O_CLOEXEC | O_WRONLY))?) };
-
-    Ok((stdin, stdout, stderr))
-}
+            Format::Tar
+        };
 
-fn before_exec() -> Result<()> {
-    unsafe {
-        if libc::setsid() < 0 {
-            panic!("setsid: {:?}", Error::last_os_error());
-        }
-        if libc::ioctl(0, libc::TIOCSCTTY, 1) < 0 {
-            panic!("ioctl: {:?}", Error::last_os_error());
-        }
+        Ok(format)
     }
-
-    Ok(())
 }
 
-fn handle<F: FnMut(i32)>(mut master: File, mut callback: F) -> Result<()> {
-    let mut last_progress = 0;
-    loop {
-        let mut data = [0; 0x1000];
-        let count = master.read(&mut data)?;
-        if count == 0 {
-            return Ok(());
-        }
-        if let Ok(string) = str::from_utf8(&data[..count]) {
-            for line in string.split(|c| c == '\r' || c == '\n') {
-                let len = line.len();
-                if line.starts_with('[') && line.ends_with('%') && len >= 4 {
-                    if let Ok(progress) = line[len - 4..len - 1].trim().parse::<i32>() {
-                        if last_progress != progress {
-                            callback(progress);
-                            last_progress = progress;
-                        }
-                    }
-                }
-            }
-        }
+fn is_squashfs(archive: &Path) -> Result<bool> {
+    let mut magic = [0; 4];
+    match File::open(archive)?.read_exact(&mut magic) {
+        Ok(()) => Ok(u32::from_le_bytes(magic) == SQUASHFS_MAGIC),
+        Err(ref why) if why.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(why) => Err(why),
     }
 }
 
-enum ExtractFormat {
-    Tar,
-    Squashfs,
+/// Extracts an image into the directory, detecting its format from its contents.
+pub fn extract<P: AsRef<Path>, Q: AsRef<Path>, F: FnMut(i32)>(
+    archive: P,
+    directory: Q,
+    cancel: &AtomicBool,
+    callback: F,
+) -> Result<()> {
+    let format = Format::detect(archive.as_ref())?;
+    extract_as(format, archive, directory, cancel, callback)
 }
 
-/// Extracts an image using either unsquashfs or tar.
-pub fn extract<P: AsRef<Path>, Q: AsRef<Path>, F: FnMut(i32)>(
+/// Extracts an image of the given format into the directory.
+///
+/// Ownership, permissions, extended attributes, hard links, and device nodes are retained.
+/// Squashfs images cannot contain the `system.` namespace of extended attributes, so POSIX ACLs
+/// are not retained from them. The extraction stops with an `Interrupted` error once `cancel`
+/// is set.
+pub fn extract_as<P: AsRef<Path>, Q: AsRef<Path>, F: FnMut(i32)>(
+    format: Format,
     archive: P,
     directory: Q,
+    cancel: &AtomicBool,
     callback: F,
 ) -> Result<()> {
     let archive = archive.as_ref().canonicalize()?;
     let directory = directory.as_ref().canonicalize()?;
-
-    let directory = directory
-        .to_str()
-        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Invalid directory path"))?
-        .replace("'", "'\"'\"'");
-
-    let format = if archive.extension().map_or(false, |ext| ext == "squashfs") {
-        ExtractFormat::Squashfs
-    } else {
-        ExtractFormat::Tar
-    };
-
-    let archive = archive
-        .to_str()
-        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Invalid archive path"))?
-        .replace("'", "'\"'\"'");
-
-    let mut command = match format {
-        ExtractFormat::Squashfs => {
-            let mut command = Command::new("unsquashfs");
-            command.arg("-f").arg("-d").arg(directory).arg(archive);
-            command
-        }
-        ExtractFormat::Tar => {
-            let mut command = Command::new("tar");
-            command.arg("--overwrite").arg("-xf").arg(archive).arg("-C").arg(directory);
-            command
-        }
-    };
-
-    debug!("{:?}", command);
-
-    let (master_fd, tty_path) = getpty(80, 30);
-    let mut child = {
-        let (slave_stdin, slave_stdout, slave_stderr) = slave_stdio(&tty_path)?;
-
-        unsafe {
-            command
-                .stdin(Stdio::from_raw_fd(slave_stdin.as_raw_fd()))
-                .stdout(Stdio::from_raw_fd(slave_stdout.as_raw_fd()))
-                .stderr(Stdio::from_raw_fd(slave_stderr.as_raw_fd()))
-                .env("COLUMNS", "")
-                .env("LINES", "")
-                .env("TERM", "xterm-256color")
-                .pre_exec(before_exec)
-                .spawn()?
+    let mut progress = Progress::new(cancel, callback);
+
+    debug!("extracting {:?} image {:?} to {:?}", format, archive, directory);
+    match format {
+        Format::Squashfs => {
+            let mut image = Image::open(&archive)?;
+            progress.total = image.total_size()?;
+            image.extract(&directory, &mut progress)?;
+            progress.set(progress.total);
+            Ok(())
         }
-    };
-
-    let master = unsafe { File::from_raw_fd(master_fd) };
-    match handle(master, callback) {
-        Ok(()) => (),
-        Err(err) => match err.raw_os_error() {
-            // EIO happens when slave end is closed
-            Some(libc::EIO) => (),
-            // Log other errors, use status code below to return
-            _ => error!("handle error: {}", err),
-        },
-    }
-
-    let status = child.wait()?;
-    if status.success() {
-        Ok(())
-    } else {
-        Err(Error::new(
-            ErrorKind::Other,
-            format!("archive extraction failed with status: {}", status),
-        ))
+        Format::Tar => archive::extract(&archive, &directory, &mut progress),
+        Format::Directory => directory::copy(&archive, &directory, &mut progress),
+        Format::Oci => oci::extract(&archive, &directory, &mut progress),
     }
 }
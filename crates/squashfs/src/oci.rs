@@ -0,0 +1,135 @@
+//! Extracts the root file system of an image in the OCI image layout, by unpacking the layers
+//! of its manifest in order.
+
+use crate::{archive, Progress};
+use serde_json::Value;
+use std::{
+    fs::{self, File},
+    io::{Error, ErrorKind, Result},
+    path::{Path, PathBuf},
+};
+
+const INDEX_MEDIA_TYPE: &str = "application/vnd.oci.image.index.v1+json";
+
+fn invalid<T>(why: String) -> Result<T> { Err(Error::new(ErrorKind::InvalidData, why)) }
+
+/// The OCI name of the architecture which distinst was built for.
+fn architecture() -> &'static str {
+    match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        "x86" => "386",
+        arch => arch,
+    }
+}
+
+/// The path of a blob within the layout, which is validated so that it cannot escape it.
+fn blob(layout: &Path, descriptor: &Value) -> Result<PathBuf> {
+    let digest = descriptor["digest"].as_str().unwrap_or("");
+    let mut parts = digest.splitn(2, ':');
+    let (algorithm, encoded) = match (parts.next(), parts.next()) {
+        (Some(algorithm), Some(encoded)) => (algorithm, encoded),
+        _ => return invalid(format!("{:?} is not a valid digest", digest)),
+    };
+
+    fn valid(part: &str, allowed: fn(char) -> bool) -> bool {
+        !part.is_empty() && part.chars().all(allowed)
+    }
+
+    if !valid(algorithm, |c| c.is_ascii_lowercase() || c.is_ascii_digit())
+        || !valid(encoded, |c| c.is_ascii_hexdigit())
+    {
+        return invalid(format!("{:?} is not a valid digest", digest));
+    }
+
+    Ok(layout.join("blobs").join(algorithm).join(encoded))
+}
+
+fn read_json(path: &Path) -> Result<Value> {
+    let contents = fs::read(path)?;
+    serde_json::from_slice(&contents)
+        .or_else(|why| invalid(format!("{:?} is not valid JSON: {}", path, why)))
+}
+
+/// Selects the manifest of an index which matches the architecture of the host, or the first
+/// manifest if none of them declare a platform. Nested indexes are followed.
+fn manifest(layout: &Path, index: &Value) -> Result<Value> {
+    let manifests = index["manifests"].as_array().map_or(&[][..], |manifests| manifests);
+
+    let descriptor = manifests
+        .iter()
+        .find(|manifest| {
+            let platform = &manifest["platform"];
+            platform["os"] == "linux" && platform["architecture"] == architecture()
+        })
+        .or_else(|| manifests.iter().find(|manifest| manifest["platform"].is_null()))
+        .or_else(|| manifests.first().filter(|_| manifests.len() == 1));
+
+    let descriptor = match descriptor {
+        Some(descriptor) => descriptor,
+        None => return invalid(format!("image has no manifest for linux/{}", architecture())),
+    };
+
+    let document = read_json(&blob(layout, descriptor)?)?;
+    if descriptor["mediaType"] == INDEX_MEDIA_TYPE || document["manifests"].is_array() {
+        manifest(layout, &document)
+    } else {
+        Ok(document)
+    }
+}
+
+pub(crate) fn extract<F: FnMut(i32)>(
+    layout: &Path,
+    directory: &Path,
+    progress: &mut Progress<F>,
+) -> Result<()> {
+    if !layout.join("oci-layout").exists() {
+        return invalid(format!("{:?} is not an OCI image layout", layout));
+    }
+
+    let manifest = manifest(layout, &read_json(&layout.join("index.json"))?)?;
+    let layers = match manifest["layers"].as_array() {
+        Some(layers) => layers,
+        None => return invalid("image manifest has no layers".into()),
+    };
+
+    let mut paths = Vec::with_capacity(layers.len());
+    for layer in layers {
+        let media_type = layer["mediaType"].as_str().unwrap_or("");
+        if !media_type.contains(".tar") {
+            return invalid(format!("layer of type {:?} is not supported", media_type));
+        }
+
+        let path = blob(layout, layer)?;
+        progress.total += fs::metadata(&path)?.len();
+        paths.push(path);
+    }
+
+    let mut offset = 0;
+    for path in paths {
+        let file = File::open(&path)?;
+        let size = file.metadata()?.len();
+        info!("unpacking layer {:?}", path);
+        archive::unpack(file, directory, offset, true, progress)?;
+        offset += size;
+    }
+
+    progress.set(progress.total);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blob_paths() {
+        let layout = Path::new("/image");
+        let descriptor = serde_json::json!({ "digest": "sha256:0123abcd" });
+        assert_eq!(blob(layout, &descriptor).unwrap(), Path::new("/image/blobs/sha256/0123abcd"));
+
+        for digest in &["sha256:../../etc/shadow", "sha256", "../sha256:0123", ""] {
+            assert!(blob(layout, &serde_json::json!({ "digest": digest })).is_err());
+        }
+    }
+}
@@ -0,0 +1,95 @@
+//! Raw file system images, which are copied block by block onto the root partition.
+
+use crate::Progress;
+use std::{
+    fs::{File, OpenOptions},
+    io::{Error, ErrorKind, Read, Result, Seek, SeekFrom, Write},
+    os::unix::fs::FileExt,
+    path::Path,
+    sync::atomic::AtomicBool,
+};
+
+const EXT_MAGIC_OFFSET: u64 = 1080;
+const EXT_MAGIC: u16 = 0xEF53;
+const EROFS_MAGIC_OFFSET: u64 = 1024;
+const EROFS_MAGIC: u32 = 0xE0F5_E1E2;
+
+/// The file system of a raw image.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RawFilesystem {
+    /// An ext2, ext3, or ext4 file system, which may be grown once it has been copied.
+    Ext,
+    /// A read-only EROFS file system, which cannot be grown or modified once it has been
+    /// copied, so its contents are copied instead.
+    Erofs,
+}
+
+/// Detects the file system of a raw image from its superblock.
+pub fn detect<P: AsRef<Path>>(image: P) -> Result<RawFilesystem> {
+    let file = File::open(image.as_ref())?;
+
+    let mut ext = [0; 2];
+    if file.read_exact_at(&mut ext, EXT_MAGIC_OFFSET).is_ok()
+        && u16::from_le_bytes(ext) == EXT_MAGIC
+    {
+        return Ok(RawFilesystem::Ext);
+    }
+
+    let mut erofs = [0; 4];
+    if file.read_exact_at(&mut erofs, EROFS_MAGIC_OFFSET).is_ok()
+        && u32::from_le_bytes(erofs) == EROFS_MAGIC
+    {
+        return Ok(RawFilesystem::Erofs);
+    }
+
+    Err(Error::new(
+        ErrorKind::InvalidData,
+        format!("{:?} is not an ext2/3/4 or EROFS image", image.as_ref()),
+    ))
+}
+
+/// Copies a raw image onto the beginning of a block device, which must be at least as large
+/// as the image. The device is synced before returning.
+pub fn copy<P: AsRef<Path>, Q: AsRef<Path>, F: FnMut(i32)>(
+    image: P,
+    device: Q,
+    cancel: &AtomicBool,
+    callback: F,
+) -> Result<()> {
+    let (image, device) = (image.as_ref(), device.as_ref());
+    let mut input = File::open(image)?;
+    let mut output = OpenOptions::new().write(true).open(device)?;
+
+    let size = input.metadata()?.len();
+    let capacity = output.seek(SeekFrom::End(0))?;
+    output.seek(SeekFrom::Start(0))?;
+
+    if size > capacity {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "{:?} is {} bytes, which is larger than the {} bytes of {:?}",
+                image, size, capacity, device
+            ),
+        ));
+    }
+
+    let mut progress = Progress::new(cancel, callback);
+    progress.total = size;
+
+    let mut buffer = vec![0; 4 * 1024 * 1024];
+    loop {
+        progress.check()?;
+        let read = input.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+
+        output.write_all(&buffer[..read])?;
+        progress.advance(read as u64);
+    }
+
+    output.sync_all()?;
+    progress.set(size);
+    Ok(())
+}
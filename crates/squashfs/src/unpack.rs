@@ -0,0 +1,190 @@
+//! Creates the files of an extracted image, and applies their ownership, permissions, extended
+//! attributes, and modification times.
+
+use std::{
+    ffi::{CString, OsStr},
+    fs::{self, File, OpenOptions},
+    io::{Error, ErrorKind, Result},
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{symlink, OpenOptionsExt},
+    },
+    path::Path,
+};
+
+/// The attributes of an extracted file.
+#[derive(Debug, Default)]
+pub struct Metadata {
+    /// The permission bits of the file, including the setuid, setgid, and sticky bits.
+    pub mode:   u32,
+    pub uid:    u32,
+    pub gid:    u32,
+    pub mtime:  i64,
+    pub xattrs: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+fn cstr(path: &Path) -> Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, "path contains a null byte"))
+}
+
+fn cvt(result: libc::c_int) -> Result<()> {
+    if result < 0 {
+        Err(Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Removes a file which is to be replaced, as existing files are overwritten.
+fn remove_existing(path: &Path) -> Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(ref metadata) if !metadata.is_dir() => fs::remove_file(path),
+        _ => Ok(()),
+    }
+}
+
+pub fn create_dir(path: &Path) -> Result<()> {
+    remove_existing(path)?;
+    match fs::create_dir(path) {
+        Err(ref why) if why.kind() == ErrorKind::AlreadyExists => Ok(()),
+        result => result,
+    }
+}
+
+/// Creates a file that only the owner may access until its metadata is applied.
+pub fn create_file(path: &Path) -> Result<File> {
+    remove_existing(path)?;
+    OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(path)
+}
+
+pub fn create_symlink(target: &[u8], path: &Path) -> Result<()> {
+    remove_existing(path)?;
+    symlink(OsStr::from_bytes(target), path)
+}
+
+pub fn create_hard_link(existing: &Path, path: &Path) -> Result<()> {
+    remove_existing(path)?;
+    fs::hard_link(existing, path)
+}
+
+/// Creates a device node, FIFO, or socket, where `kind` is one of the `S_IF*` file types.
+pub fn create_node(path: &Path, kind: libc::mode_t, major: u32, minor: u32) -> Result<()> {
+    remove_existing(path)?;
+    let path = cstr(path)?;
+    cvt(unsafe { libc::mknod(path.as_ptr(), kind | 0o600, libc::makedev(major, minor)) })
+}
+
+/// Applies the metadata to an extracted file.
+///
+/// The owner is changed first, as changing it clears the setuid and setgid bits, and the
+/// `security.capability` attribute.
+pub fn apply(path: &Path, is_symlink: bool, metadata: &Metadata) -> Result<()> {
+    let cpath = cstr(path)?;
+
+    cvt(unsafe { libc::lchown(cpath.as_ptr(), metadata.uid, metadata.gid) })?;
+
+    if !is_symlink {
+        cvt(unsafe { libc::chmod(cpath.as_ptr(), metadata.mode & 0o7777) })?;
+    }
+
+    for (name, value) in &metadata.xattrs {
+        let name = CString::new(name.as_slice())
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "xattr name contains a null byte"))?;
+
+        let result = cvt(unsafe {
+            libc::lsetxattr(
+                cpath.as_ptr(),
+                name.as_ptr(),
+                value.as_ptr() as *const libc::c_void,
+                value.len(),
+                0,
+            )
+        });
+
+        if let Err(why) = result {
+            // File systems which do not support extended attributes should not fail the install.
+            if why.raw_os_error() == Some(libc::EOPNOTSUPP) {
+                warn!("unable to set {:?} on {:?}: {}", name, path, why);
+            } else {
+                return Err(why);
+            }
+        }
+    }
+
+    let time = libc::timespec { tv_sec: metadata.mtime as libc::time_t, tv_nsec: 0 };
+    let times = [time, time];
+    cvt(unsafe {
+        libc::utimensat(libc::AT_FDCWD, cpath.as_ptr(), times.as_ptr(), libc::AT_SYMLINK_NOFOLLOW)
+    })
+}
+
+/// Reads the extended attributes of a file, without following symlinks.
+pub fn read_xattrs(path: &Path) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let cpath = cstr(path)?;
+    let unsupported = |why: &Error| why.raw_os_error() == Some(libc::EOPNOTSUPP);
+
+    let names = match read_sized(|buffer, size| unsafe {
+        libc::llistxattr(cpath.as_ptr(), buffer as *mut libc::c_char, size)
+    }) {
+        Ok(names) => names,
+        Err(ref why) if unsupported(why) => return Ok(Vec::new()),
+        Err(why) => return Err(why),
+    };
+
+    let mut xattrs = Vec::new();
+    for name in names.split(|&byte| byte == 0).filter(|name| !name.is_empty()) {
+        let cname = CString::new(name).expect("xattr names are separated by null bytes");
+        let value = read_sized(|buffer, size| unsafe {
+            libc::lgetxattr(cpath.as_ptr(), cname.as_ptr(), buffer, size)
+        });
+
+        match value {
+            Ok(value) => xattrs.push((name.to_vec(), value)),
+            // The attribute was removed after the names were listed.
+            Err(ref why) if why.raw_os_error() == Some(libc::ENODATA) => (),
+            Err(why) => return Err(why),
+        }
+    }
+
+    Ok(xattrs)
+}
+
+/// Calls a function that fills a buffer, after calling it once to query the size that the
+/// buffer must be, and retrying if the size grows between the calls.
+fn read_sized<F: FnMut(*mut libc::c_void, usize) -> libc::ssize_t>(mut func: F) -> Result<Vec<u8>> {
+    loop {
+        let size = func(std::ptr::null_mut(), 0);
+        if size < 0 {
+            return Err(Error::last_os_error());
+        }
+
+        let mut buffer = vec![0u8; size as usize];
+        let read = func(buffer.as_mut_ptr() as *mut libc::c_void, buffer.len());
+        if read >= 0 {
+            buffer.truncate(read as usize);
+            return Ok(buffer);
+        }
+
+        let why = Error::last_os_error();
+        if why.raw_os_error() != Some(libc::ERANGE) {
+            return Err(why);
+        }
+    }
+}
+
+/// Splits a device number, as squashfs encodes it, into its major and minor numbers.
+pub fn decode_device(rdev: u32) -> (u32, u32) {
+    ((rdev & 0xf_ff00) >> 8, (rdev & 0xff) | ((rdev >> 12) & 0xf_ff00))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn device_numbers() {
+        assert_eq!(decode_device(0x0801), (8, 1));
+        assert_eq!(decode_device(0x0100_0000 | 0x0300), (3, 0x1000));
+    }
+}
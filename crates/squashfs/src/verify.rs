@@ -0,0 +1,321 @@
+//! Verification of an image before it is extracted, so that a corrupted image is found before
+//! any disk has been modified.
+
+use crate::Progress;
+use md5::Md5;
+use sha2::{digest::DynDigest, Sha256};
+use std::{
+    fs::File,
+    io::{Error, ErrorKind, Read, Result},
+    path::Path,
+    sync::atomic::AtomicBool,
+};
+
+pub(crate) const SQUASHFS_MAGIC: u32 = 0x7371_7368;
+const SUPERBLOCK_SIZE: usize = 96;
+/// Marks an optional table which the image does not contain.
+pub(crate) const TABLE_ABSENT: u64 = !0;
+
+/// The superblock at the start of a squashfs 4.0 image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Superblock {
+    pub inodes:                u32,
+    pub block_size:            u32,
+    pub fragments:             u32,
+    pub compression:           u16,
+    pub block_log:             u16,
+    pub flags:                 u16,
+    pub ids:                   u16,
+    pub version:               (u16, u16),
+    /// A reference to the inode of the root directory.
+    pub root_inode:            u64,
+    /// The size of the image, which may be followed by padding.
+    pub bytes_used:            u64,
+    pub id_table_start:        u64,
+    pub xattr_id_table_start:  u64,
+    pub inode_table_start:     u64,
+    pub directory_table_start: u64,
+    pub fragment_table_start:  u64,
+    pub export_table_start:    u64,
+}
+
+impl Superblock {
+    pub fn read<R: Read>(mut reader: R) -> Result<Self> {
+        let mut data = [0u8; SUPERBLOCK_SIZE];
+        reader.read_exact(&mut data).map_err(|why| {
+            let why = format!("image is too small for a superblock: {}", why);
+            Error::new(ErrorKind::InvalidData, why)
+        })?;
+
+        let u16_at = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]);
+        let u32_at = |at: usize| {
+            let mut bytes = [0; 4];
+            bytes.copy_from_slice(&data[at..at + 4]);
+            u32::from_le_bytes(bytes)
+        };
+        let u64_at = |at: usize| {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(&data[at..at + 8]);
+            u64::from_le_bytes(bytes)
+        };
+
+        if u32_at(0) != SQUASHFS_MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "image is not a squashfs image"));
+        }
+
+        Ok(Superblock {
+            inodes:                u32_at(4),
+            block_size:            u32_at(12),
+            fragments:             u32_at(16),
+            compression:           u16_at(20),
+            block_log:             u16_at(22),
+            flags:                 u16_at(24),
+            ids:                   u16_at(26),
+            version:               (u16_at(28), u16_at(30)),
+            root_inode:            u64_at(32),
+            bytes_used:            u64_at(40),
+            id_table_start:        u64_at(48),
+            xattr_id_table_start:  u64_at(56),
+            inode_table_start:     u64_at(64),
+            directory_table_start: u64_at(72),
+            fragment_table_start:  u64_at(80),
+            export_table_start:    u64_at(88),
+        })
+    }
+
+    /// Ensures that the superblock is consistent, and that the image is not truncated.
+    pub fn validate(&self, image_size: u64) -> Result<()> {
+        let invalid = |why: String| Err(Error::new(ErrorKind::InvalidData, why));
+
+        if self.version != (4, 0) {
+            let (major, minor) = self.version;
+            return invalid(format!("unsupported squashfs version {}.{}", major, minor));
+        }
+
+        let valid_block_size = self.block_size.is_power_of_two()
+            && self.block_size >= 4096
+            && self.block_size <= 1_048_576
+            && 1u32.checked_shl(u32::from(self.block_log)) == Some(self.block_size);
+
+        if !valid_block_size {
+            return invalid(format!("invalid block size of {} bytes", self.block_size));
+        }
+
+        if self.compression == 0 || self.compression > 6 {
+            return invalid(format!("unknown compression type {}", self.compression));
+        }
+
+        if self.bytes_used > image_size {
+            return invalid(format!(
+                "image is truncated: expected {} bytes, but found {} bytes",
+                self.bytes_used, image_size
+            ));
+        }
+
+        let required = [self.inode_table_start, self.directory_table_start, self.id_table_start];
+        let optional = [
+            self.xattr_id_table_start,
+            self.fragment_table_start,
+            self.export_table_start,
+        ];
+
+        let out_of_bounds = required
+            .iter()
+            .chain(optional.iter().filter(|&&start| start != TABLE_ABSENT))
+            .any(|&start| start < SUPERBLOCK_SIZE as u64 || start >= self.bytes_used);
+
+        if out_of_bounds || self.inode_table_start >= self.directory_table_start {
+            return invalid("superblock contains an invalid table offset".into());
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads and validates the superblock of a squashfs image.
+pub fn check_superblock<P: AsRef<Path>>(image: P) -> Result<Superblock> {
+    let file = File::open(image.as_ref())?;
+    let image_size = file.metadata()?.len();
+    let superblock = Superblock::read(&file)?;
+    superblock.validate(image_size)?;
+    Ok(superblock)
+}
+
+/// A digest which an image may be verified against.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Digest {
+    Md5,
+    Sha256,
+}
+
+impl Digest {
+    fn hasher(self) -> Box<dyn DynDigest> {
+        match self {
+            Digest::Md5 => Box::new(Md5::default()),
+            Digest::Sha256 => Box::new(Sha256::default()),
+        }
+    }
+
+    /// The length of this digest in hexadecimal characters.
+    pub fn hex_len(self) -> usize {
+        match self {
+            Digest::Md5 => 32,
+            Digest::Sha256 => 64,
+        }
+    }
+
+    /// True if the value is a hexadecimal digest of this kind.
+    pub fn is_valid(self, value: &str) -> bool {
+        value.len() == self.hex_len() && value.chars().all(|c| c.is_ascii_hexdigit())
+    }
+}
+
+/// Computes the digest of a file as it is read, so that the progress of reading it may be
+/// reported to the callback. Reading stops with an `Interrupted` error once `cancel` is set.
+pub fn checksum<P: AsRef<Path>, F: FnMut(i32)>(
+    path: P,
+    digest: Digest,
+    cancel: &AtomicBool,
+    callback: F,
+) -> Result<String> {
+    let mut file = File::open(path.as_ref())?;
+    let mut progress = Progress::new(cancel, callback);
+    progress.total = file.metadata()?.len();
+
+    let mut hasher = digest.hasher();
+    let mut buffer = vec![0; 1024 * 1024];
+    loop {
+        progress.check()?;
+        let count = file.read(&mut buffer)?;
+        if count == 0 {
+            break;
+        }
+
+        hasher.update(&buffer[..count]);
+        progress.advance(count as u64);
+    }
+
+    progress.set(progress.total);
+    Ok(hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Verifies that the digest of a file matches the expected digest.
+pub fn verify<P: AsRef<Path>, F: FnMut(i32)>(
+    path: P,
+    digest: Digest,
+    expected: &str,
+    cancel: &AtomicBool,
+    callback: F,
+) -> Result<()> {
+    let actual = checksum(path, digest, cancel, callback)?;
+    if actual.eq_ignore_ascii_case(expected.trim()) {
+        Ok(())
+    } else {
+        Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("checksum mismatch: expected {}, but found {}", expected.trim(), actual),
+        ))
+    }
+}
+
+/// Finds the MD5 digest of a file within the contents of an `md5sum.txt`, whose entries are
+/// relative to the root of the install media, such as `./casper/filesystem.squashfs`.
+pub fn md5sum_entry(md5sums: &str, relative_path: &Path) -> Option<String> {
+    let relative_path = relative_path.strip_prefix("./").unwrap_or(relative_path);
+    md5sums.lines().find_map(|line| {
+        let mut fields = line.splitn(2, char::is_whitespace);
+        let digest = fields.next()?;
+        let path = Path::new(fields.next()?.trim_start().trim_start_matches('*'));
+        let path = path.strip_prefix("./").unwrap_or(path);
+
+        if path == relative_path && Digest::Md5.is_valid(digest) {
+            Some(digest.to_ascii_lowercase())
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, sync::atomic::Ordering};
+    use tempdir::TempDir;
+
+    fn superblock() -> Vec<u8> {
+        let mut data = vec![0u8; SUPERBLOCK_SIZE];
+        let mut put = |at: usize, bytes: &[u8]| data[at..at + bytes.len()].copy_from_slice(bytes);
+        put(0, &SQUASHFS_MAGIC.to_le_bytes());
+        put(4, &100u32.to_le_bytes());
+        put(12, &131_072u32.to_le_bytes());
+        put(20, &4u16.to_le_bytes());
+        put(22, &17u16.to_le_bytes());
+        put(28, &4u16.to_le_bytes());
+        put(40, &8192u64.to_le_bytes());
+        put(48, &8000u64.to_le_bytes());
+        put(56, &TABLE_ABSENT.to_le_bytes());
+        put(64, &4096u64.to_le_bytes());
+        put(72, &6000u64.to_le_bytes());
+        put(80, &7000u64.to_le_bytes());
+        put(88, &TABLE_ABSENT.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn superblock_validation() {
+        let data = superblock();
+        let superblock = Superblock::read(data.as_slice()).unwrap();
+        assert_eq!(superblock.block_size, 131_072);
+        assert_eq!(superblock.bytes_used, 8192);
+        assert!(superblock.validate(8192).is_ok());
+        assert!(superblock.validate(8191).is_err(), "truncated images are invalid");
+
+        let mut corrupt = data.clone();
+        corrupt[22] = 16;
+        assert!(Superblock::read(corrupt.as_slice()).unwrap().validate(8192).is_err());
+
+        let mut corrupt = data.clone();
+        corrupt[72..80].copy_from_slice(&9000u64.to_le_bytes());
+        assert!(Superblock::read(corrupt.as_slice()).unwrap().validate(8192).is_err());
+
+        assert!(Superblock::read(&data[..50]).is_err());
+        assert!(Superblock::read(&[0u8; SUPERBLOCK_SIZE][..]).is_err());
+    }
+
+    #[test]
+    fn md5sum_entries() {
+        let md5sums = "d41d8cd98f00b204e9800998ecf8427e  ./.disk/info\n\
+                       0CC175B9C0F1B6A831C399E269772661  ./casper/filesystem.squashfs\n";
+
+        assert_eq!(
+            md5sum_entry(md5sums, Path::new("casper/filesystem.squashfs")).as_deref(),
+            Some("0cc175b9c0f1b6a831c399e269772661")
+        );
+        assert_eq!(md5sum_entry(md5sums, Path::new("./casper/vmlinuz")), None);
+        assert!(Digest::Sha256.is_valid(&"a".repeat(64)));
+        assert!(!Digest::Md5.is_valid("not a digest"));
+    }
+
+    #[test]
+    fn verify_digests() {
+        let tempdir = TempDir::new("distinst-squashfs").unwrap();
+        let path = tempdir.path().join("image");
+        fs::write(&path, b"abc").unwrap();
+
+        let cancel = AtomicBool::new(false);
+        let md5 = "900150983CD24FB0D6963F7D28E17F72";
+        assert!(verify(&path, Digest::Md5, md5, &cancel, |_| ()).is_ok());
+
+        let sha256 = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        let mut percents = Vec::new();
+        verify(&path, Digest::Sha256, sha256, &cancel, |percent| percents.push(percent)).unwrap();
+        assert_eq!(percents.last(), Some(&100));
+
+        let error = verify(&path, Digest::Sha256, &"0".repeat(64), &cancel, |_| ()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+        cancel.store(true, Ordering::SeqCst);
+        let error = checksum(&path, Digest::Sha256, &cancel, |_| ()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Interrupted);
+    }
+}
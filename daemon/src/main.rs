@@ -0,0 +1,33 @@
+//! A D-Bus service which exposes the installer to frontends that do not run as root.
+
+extern crate clap;
+extern crate dbus;
+extern crate distinst;
+#[macro_use]
+extern crate log;
+extern crate serde_json;
+
+mod options;
+mod polkit;
+mod service;
+
+use clap::{App, Arg};
+use std::process::exit;
+
+fn main() {
+    let matches = App::new("distinst-daemon")
+        .arg(Arg::with_name("session").long("session").help(
+            "serves on the session bus without polkit authorization, such as a private bus \
+             started by dbus-run-session for testing",
+        ))
+        .get_matches();
+
+    if let Err(err) = distinst::log(|_level, _message| {}) {
+        eprintln!("Failed to initialize logging: {}", err);
+    }
+
+    if let Err(why) = service::run(matches.is_present("session")) {
+        eprintln!("distinst-daemon: {}", why);
+        exit(1);
+    }
+}
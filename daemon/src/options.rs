@@ -0,0 +1,107 @@
+//! Describes the install options to frontends as JSON, and selects the option to apply.
+
+use dbus::tree::MethodErr;
+use distinst::auto::{AlongsideMethod, InstallOption, InstallOptions};
+use serde_json::{json, Value};
+
+/// Lists the options as JSON objects, each of which has the `kind` and `index` that selects
+/// it, and a `title` to display.
+pub fn list(options: &InstallOptions) -> Value {
+    let mut list = Vec::new();
+
+    for (index, option) in options.erase_options.iter().enumerate() {
+        list.push(json!({
+            "kind": "erase",
+            "index": index,
+            "title": option.to_string(),
+            "device": option.device,
+            "model": option.model,
+            "sectors": option.sectors,
+            "meets_requirements": option.meets_requirements(),
+            "rotational": option.is_rotational(),
+            "removable": option.is_removable(),
+        }));
+    }
+
+    for (index, option) in options.refresh_options.iter().enumerate() {
+        list.push(json!({
+            "kind": "refresh",
+            "index": index,
+            "title": option.to_string(),
+            "os": option.os_release.name,
+            "version": option.os_release.version_id,
+            "can_retain_old": option.can_retain_old,
+        }));
+    }
+
+    for (index, option) in options.alongside_options.iter().enumerate() {
+        let (sectors_free, sectors_total) = match option.method {
+            AlongsideMethod::Shrink { sectors_free, sectors_total, .. } => {
+                (sectors_free, sectors_total)
+            }
+            AlongsideMethod::Free(ref region) => (region.size(), region.size()),
+        };
+
+        list.push(json!({
+            "kind": "alongside",
+            "index": index,
+            "title": option.to_string(),
+            "os": option.get_os(),
+            "device": option.device,
+            "sectors_free": sectors_free,
+            "sectors_total": sectors_total,
+        }));
+    }
+
+    if let Some(ref option) = options.recovery_option {
+        list.push(json!({
+            "kind": "recovery",
+            "index": 0,
+            "title": format!("Recovery install of {}", option.hostname),
+            "hostname": option.hostname,
+            "oem_mode": option.oem_mode,
+        }));
+    }
+
+    Value::Array(list)
+}
+
+/// Selects an option by the `kind` and `index` that it was listed with. The password
+/// encrypts the new root when it is not empty, and `sectors` is the size of an install
+/// alongside another OS.
+pub fn select<'a>(
+    options: &'a InstallOptions,
+    kind: &str,
+    index: usize,
+    password: &str,
+    sectors: u64,
+    swap_file: bool,
+) -> Result<InstallOption<'a>, MethodErr> {
+    let password = if password.is_empty() { None } else { Some(password.to_owned()) };
+    let missing = || MethodErr::invalid_arg(&format!("there is no {} option {}", kind, index));
+
+    let option = match kind {
+        "erase" => InstallOption::Erase {
+            option: options.erase_options.get(index).ok_or_else(missing)?,
+            password,
+            swap_file,
+        },
+        "refresh" => {
+            InstallOption::Refresh(options.refresh_options.get(index).ok_or_else(missing)?)
+        }
+        "alongside" => InstallOption::Alongside {
+            option: options.alongside_options.get(index).ok_or_else(missing)?,
+            password,
+            sectors,
+            swap_file,
+            force_shrink: false,
+        },
+        "recovery" => {
+            let option = options.recovery_option.as_ref().filter(|_| index == 0);
+            InstallOption::Recovery { option: option.ok_or_else(missing)?, password }
+        }
+        _ => return Err(MethodErr::invalid_arg(&format!("{:?} is not a kind of option", kind))),
+    };
+
+    Ok(option)
+}
@@ -0,0 +1,42 @@
+//! Authorizes the callers of the service with polkit.
+
+use dbus::{arg::Variant, blocking::Connection, Error};
+use std::{collections::HashMap, time::Duration};
+
+const ALLOW_USER_INTERACTION: u32 = 1;
+
+/// Callers may take some time to authenticate when they are prompted to.
+const TIMEOUT: Duration = Duration::from_secs(300);
+
+pub struct Authority {
+    connection: Connection,
+}
+
+impl Authority {
+    pub fn new() -> Result<Self, Error> {
+        Connection::new_system().map(|connection| Authority { connection })
+    }
+
+    /// Whether the caller with the given unique bus name may perform an action, which may
+    /// prompt them to authenticate.
+    pub fn check(&self, sender: &str, action: &str) -> Result<bool, Error> {
+        let proxy = self.connection.with_proxy(
+            "org.freedesktop.PolicyKit1",
+            "/org/freedesktop/PolicyKit1/Authority",
+            TIMEOUT,
+        );
+
+        let mut subject = HashMap::new();
+        subject.insert("name", Variant(sender));
+        let details: HashMap<&str, &str> = HashMap::new();
+
+        let ((authorized, _challenge, _details),): ((bool, bool, HashMap<String, String>),) =
+            proxy.method_call(
+                "org.freedesktop.PolicyKit1.Authority",
+                "CheckAuthorization",
+                (("system-bus-name", subject), action, details, ALLOW_USER_INTERACTION, ""),
+            )?;
+
+        Ok(authorized)
+    }
+}
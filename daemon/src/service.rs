@@ -0,0 +1,384 @@
+//! The `com.system76.Distinst` interface, which is served at `/com/system76/Distinst`.
+//!
+//! Frontends probe the disks, list the install options for them, apply one of those options,
+//! and then start the install with the configuration of a plan. The install runs on its own
+//! thread, and reports its progress with the `Status`, `Error`, and `Finished` signals.
+//!
+//! Callers are authorized with polkit on threads of their own, as they may be prompted to
+//! authenticate. A call is only handled once it has been authorized, so that the service
+//! continues to handle other calls, such as to cancel an install, in the meantime.
+
+use crate::{options, polkit::Authority};
+use dbus::{
+    blocking::{stdintf::org_freedesktop_dbus::RequestNameReply, LocalConnection},
+    channel::{MatchingReceiver, Sender},
+    message::MatchRule,
+    strings::{Interface, Path},
+    tree::{Factory, MethodErr},
+    Message,
+};
+use distinst::{
+    auto::InstallOptions, plan::ConfigPlan, Cancellation, Config, Disks, ErrorCode, Installer,
+    Status, Step,
+};
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    sync::{mpsc, Arc},
+    thread,
+    time::Duration,
+};
+
+pub const BUS_NAME: &str = "com.system76.Distinst";
+pub const OBJECT_PATH: &str = "/com/system76/Distinst";
+pub const INTERFACE: &str = "com.system76.Distinst";
+
+/// Probing disks and configuring an install, which does not modify any disk.
+const PROBE_ACTION: &str = "com.system76.distinst.probe";
+/// Starting or cancelling an install.
+const INSTALL_ACTION: &str = "com.system76.distinst.install";
+
+const BUSY_ERROR: &str = "com.system76.Distinst.Error.Busy";
+const NOT_PROBED: &str = "ProbeDisks must be called first";
+
+/// Events of the install and authorization threads, which are handled by the service.
+enum Event {
+    /// A method call, and whether its caller is authorized to make it.
+    Authorized(Message, Result<(), MethodErr>),
+    Status(Status),
+    Error { step: Step, code: ErrorCode, message: String },
+    Finished(Option<(ErrorCode, String)>),
+}
+
+#[derive(Default)]
+struct State {
+    disks:        Option<Disks>,
+    options:      Option<InstallOptions>,
+    /// Cancels the install which is in progress.
+    cancellation: Option<Cancellation>,
+}
+
+impl State {
+    fn idle(&self) -> Result<(), MethodErr> {
+        if self.cancellation.is_some() {
+            Err((BUSY_ERROR, "an install is in progress").into())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn disks(&self) -> Result<&Disks, MethodErr> {
+        self.disks.as_ref().ok_or_else(|| MethodErr::failed(&NOT_PROBED))
+    }
+}
+
+/// The name of the D-Bus error for an error code, such as
+/// `com.system76.Distinst.Error.InvalidConfig`.
+fn error_name(code: ErrorCode) -> String {
+    let mut name = String::from("com.system76.Distinst.Error.");
+    for word in code.as_str().split('-') {
+        let mut chars = word.chars();
+        if let Some(first) = chars.next() {
+            name.push(first.to_ascii_uppercase());
+            name.extend(chars);
+        }
+    }
+
+    name
+}
+
+fn failed(code: ErrorCode, message: String) -> MethodErr { (error_name(code), message).into() }
+
+fn to_json(disks: &Disks) -> Result<String, MethodErr> {
+    serde_json::to_string(disks).map_err(|why| MethodErr::failed(&why))
+}
+
+/// The polkit action which a call to a method of the interface requires, if any.
+fn action(msg: &Message) -> Option<&'static str> {
+    if msg.interface().as_deref() != Some(INTERFACE) {
+        return None;
+    }
+
+    match msg.member().as_deref() {
+        Some("ProbeDisks") | Some("InstallOptions") | Some("ApplyOption") => Some(PROBE_ACTION),
+        Some("Install") | Some("Cancel") => Some(INSTALL_ACTION),
+        _ => None,
+    }
+}
+
+/// Checks that the caller is authorized for the action on a thread with a connection of its
+/// own, and sends the call back to the service with the result.
+fn authorize(msg: Message, action: &'static str, events: mpsc::Sender<Event>) {
+    thread::spawn(move || {
+        let result = match msg.sender() {
+            Some(sender) => match Authority::new().and_then(|a| a.check(&sender, action)) {
+                Ok(true) => Ok(()),
+                Ok(false) => Err((
+                    "org.freedesktop.DBus.Error.AccessDenied",
+                    format!("{} is not authorized to perform {}", &*sender, action),
+                )
+                    .into()),
+                Err(why) => {
+                    error!("polkit authorization failed: {}", why);
+                    Err(MethodErr::failed(&why))
+                }
+            },
+            None => Err(MethodErr::failed(&"message has no sender")),
+        };
+
+        let _ = events.send(Event::Authorized(msg, result));
+    });
+}
+
+/// Installs on a thread of its own, so that the service may continue to handle calls, such
+/// as to cancel the install with the returned token.
+fn install(disks: Disks, config: Config, events: mpsc::Sender<Event>) -> Cancellation {
+    let (cancellation_tx, cancellation_rx) = mpsc::sync_channel(1);
+
+    thread::spawn(move || {
+        let mut installer = Installer::default();
+        let _ = cancellation_tx.send(installer.cancellation());
+
+        {
+            let events = events.clone();
+            installer.on_status(move |status| {
+                let _ = events.send(Event::Status(*status));
+            });
+        }
+
+        {
+            let events = events.clone();
+            installer.on_error(move |error| {
+                let _ = events.send(Event::Error {
+                    step:    error.step,
+                    code:    error.code(),
+                    message: error.err.to_string(),
+                });
+            });
+        }
+
+        let result = installer.install(disks, &config);
+        let failure = result.err().map(|why| (ErrorCode::of(&why), why.to_string()));
+        let _ = events.send(Event::Finished(failure));
+    });
+
+    cancellation_rx.recv().expect("install thread exited before it started")
+}
+
+pub fn run(session: bool) -> Result<(), dbus::Error> {
+    let connection =
+        if session { LocalConnection::new_session()? } else { LocalConnection::new_system()? };
+
+    match connection.request_name(BUS_NAME, false, true, true)? {
+        RequestNameReply::PrimaryOwner => (),
+        _ => {
+            return Err(dbus::Error::new_custom(
+                "org.freedesktop.DBus.Error.AddressInUse",
+                &format!("{} is already owned", BUS_NAME),
+            ))
+        }
+    }
+
+    let state = Rc::new(RefCell::new(State::default()));
+    let (events_tx, events_rx) = mpsc::channel();
+
+    let f = Factory::new_fn::<()>();
+
+    let status_signal =
+        Arc::new(f.signal("Status", ()).sarg::<&str, _>("step").sarg::<i32, _>("percent"));
+
+    let error_signal = Arc::new(
+        f.signal("Error", ())
+            .sarg::<&str, _>("step")
+            .sarg::<&str, _>("code")
+            .sarg::<&str, _>("message"),
+    );
+
+    let finished_signal = Arc::new(
+        f.signal("Finished", ())
+            .sarg::<bool, _>("success")
+            .sarg::<&str, _>("code")
+            .sarg::<&str, _>("message"),
+    );
+
+    let probe_disks = {
+        let state = state.clone();
+        f.method("ProbeDisks", (), move |m| {
+            let mut state = state.borrow_mut();
+            state.idle()?;
+
+            info!("probing disks for {:?}", m.msg.sender());
+            let disks = Disks::probe_devices()
+                .map_err(|why| failed(ErrorCode::from(&why), why.to_string()))?;
+
+            let json = to_json(&disks)?;
+            state.disks = Some(disks);
+            state.options = None;
+            Ok(vec![m.msg.method_return().append1(json)])
+        })
+        .outarg::<&str, _>("disks")
+    };
+
+    let install_options = {
+        let state = state.clone();
+        f.method("InstallOptions", (), move |m| {
+            let (required_space, shrink_overhead): (u64, u64) = m.msg.read2()?;
+            let mut state = state.borrow_mut();
+            state.idle()?;
+
+            let options = InstallOptions::new(state.disks()?, required_space, shrink_overhead);
+            let json = options::list(&options).to_string();
+            state.options = Some(options);
+            Ok(vec![m.msg.method_return().append1(json)])
+        })
+        .inarg::<u64, _>("required_space")
+        .inarg::<u64, _>("shrink_overhead")
+        .outarg::<&str, _>("options")
+    };
+
+    let apply_option = {
+        let state = state.clone();
+        f.method("ApplyOption", (), move |m| {
+            let (kind, index, password, sectors, swap_file): (&str, u32, &str, u64, bool) =
+                m.msg.read5()?;
+
+            let state = &mut *state.borrow_mut();
+            state.idle()?;
+
+            let options = state
+                .options
+                .as_ref()
+                .ok_or_else(|| MethodErr::failed(&"InstallOptions must be called first"))?;
+            let disks = state.disks.as_mut().ok_or_else(|| MethodErr::failed(&NOT_PROBED))?;
+
+            options::select(options, kind, index as usize, password, sectors, swap_file)?
+                .apply(disks)
+                .map_err(|why| failed(ErrorCode::from(&why), why.to_string()))?;
+
+            Ok(vec![m.msg.method_return().append1(to_json(disks)?)])
+        })
+        .inarg::<&str, _>("kind")
+        .inarg::<u32, _>("index")
+        .inarg::<&str, _>("password")
+        .inarg::<u64, _>("sectors")
+        .inarg::<bool, _>("swap_file")
+        .outarg::<&str, _>("disks")
+    };
+
+    let install_method = {
+        let (state, events_tx) = (state.clone(), events_tx.clone());
+        f.method("Install", (), move |m| {
+            let config: &str = m.msg.read1()?;
+            let config = serde_json::from_str::<ConfigPlan>(config).map(Config::from).map_err(
+                |why| failed(ErrorCode::InvalidConfig, format!("invalid config: {}", why)),
+            )?;
+
+            let mut state = state.borrow_mut();
+            state.idle()?;
+            let disks = state.disks.take().ok_or_else(|| MethodErr::failed(&NOT_PROBED))?;
+
+            info!("starting an install for {:?}", m.msg.sender());
+            state.options = None;
+            state.cancellation = Some(install(disks, config, events_tx.clone()));
+            Ok(vec![m.msg.method_return()])
+        })
+        .inarg::<&str, _>("config")
+    };
+
+    let cancel = {
+        let state = state.clone();
+        f.method("Cancel", (), move |m| {
+            if let Some(ref cancellation) = state.borrow().cancellation {
+                info!("cancelling the install for {:?}", m.msg.sender());
+                cancellation.cancel();
+            }
+
+            Ok(vec![m.msg.method_return()])
+        })
+    };
+
+    let interface = f
+        .interface(INTERFACE, ())
+        .add_m(probe_disks)
+        .add_m(install_options)
+        .add_m(apply_option)
+        .add_m(install_method)
+        .add_m(cancel)
+        .add_s(status_signal.clone())
+        .add_s(error_signal.clone())
+        .add_s(finished_signal.clone());
+
+    let tree =
+        Rc::new(f.tree(()).add(f.object_path(OBJECT_PATH, ()).introspectable().add(interface)));
+
+    // Calls are handled by the tree once they have been authorized. On the session bus, the
+    // service has no more privileges than its callers, so they are not authorized.
+    {
+        let tree = tree.clone();
+        connection.start_receive(
+            MatchRule::new_method_call(),
+            Box::new(move |msg, connection| {
+                match action(&msg) {
+                    Some(action) if !session => authorize(msg, action, events_tx.clone()),
+                    _ => {
+                        for reply in tree.handle(&msg).unwrap_or_default() {
+                            let _ = connection.send(reply);
+                        }
+                    }
+                }
+
+                true
+            }),
+        );
+    }
+
+    let (path, interface) = (Path::from(OBJECT_PATH), Interface::from(INTERFACE));
+
+    info!("serving {} on the {} bus", BUS_NAME, if session { "session" } else { "system" });
+    loop {
+        connection.process(Duration::from_millis(100))?;
+
+        for event in events_rx.try_iter() {
+            let message = match event {
+                Event::Authorized(msg, Ok(())) => {
+                    for reply in tree.handle(&msg).unwrap_or_default() {
+                        let _ = connection.send(reply);
+                    }
+
+                    continue;
+                }
+                Event::Authorized(msg, Err(why)) => why.to_message(&msg),
+                Event::Status(status) => status_signal
+                    .msg(&path, &interface)
+                    .append2(status.step.as_str(), status.percent),
+                Event::Error { step, code, message } => error_signal
+                    .msg(&path, &interface)
+                    .append3(step.as_str(), code.as_str(), message),
+                Event::Finished(failure) => {
+                    state.borrow_mut().cancellation = None;
+                    let (success, code, message) = match failure {
+                        None => (true, "", String::new()),
+                        Some((code, message)) => (false, code.as_str(), message),
+                    };
+
+                    finished_signal.msg(&path, &interface).append3(success, code, message)
+                }
+            };
+
+            let _ = connection.send(message);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_names() {
+        let name = |code| error_name(code).replace("com.system76.Distinst.Error.", "");
+        assert_eq!(name(ErrorCode::InvalidConfig), "InvalidConfig");
+        assert_eq!(name(ErrorCode::ImageNotFound), "ImageNotFound");
+        assert_eq!(name(ErrorCode::Lvm), "Lvm");
+    }
+}
@@ -35,6 +35,9 @@ fn main() {
         lang:             "en_US.UTF-8".into(),
         remove:           "/cdrom/casper/filesystem.manifest-remove".into(),
         squashfs:         "/cdrom/casper/filesystem.squashfs".into(),
+        squashfs_sha256:  None,
+        image_format:     ImageFormat::Auto,
+        move_journal:     None,
     };
 
     eprintln!("Options: {:#?}", options);
@@ -85,6 +88,8 @@ fn main() {
                         } else {
                             0
                         },
+                        swap_file: false,
+                        force_shrink: false,
                     };
 
                     match option.apply(&mut disks) {
@@ -107,7 +112,8 @@ fn main() {
 
             match options.erase_options.iter().find(|opt| opt.device == disk) {
                 Some(option) => {
-                    let option = InstallOption::Erase { option, password: args.next() };
+                    let option =
+                        InstallOption::Erase { option, password: args.next(), swap_file: false };
 
                     match option.apply(&mut disks) {
                         Ok(()) => (),
@@ -183,7 +189,6 @@ fn main() {
     }
 
     {
-        let pb_opt = pb_opt.clone();
         let mut step_opt = None;
         installer.on_status(move |status| {
             if step_opt != Some(status.step) {
@@ -203,6 +208,7 @@ fn main() {
                     Step::Extract => "Extracting filesystem ",
                     Step::Configure => "Configuring installation",
                     Step::Bootloader => "Installing bootloader ",
+                    Step::Verify => "Verifying image ",
                 });
                 *pb_opt.borrow_mut() = Some(pb);
             }
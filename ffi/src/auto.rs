@@ -1,4 +1,3 @@
-use libc;
 
 use super::{gen_object_ptr, get_str, null_check, DistinstDisks, DistinstOsRelease};
 use distinst::{
@@ -58,11 +57,7 @@ pub unsafe extern "C" fn distinst_alongside_option_is_linux(
     option: *const DistinstAlongsideOption,
 ) -> bool {
     let option = &*(option as *const AlongsideOption);
-    if let Some(OS::Linux { .. }) = option.alongside {
-        true
-    } else {
-        false
-    }
+    matches!(option.alongside, Some(OS::Linux { .. }))
 }
 
 #[no_mangle]
@@ -70,11 +65,7 @@ pub unsafe extern "C" fn distinst_alongside_option_is_mac_os(
     option: *const DistinstAlongsideOption,
 ) -> bool {
     let option = &*(option as *const AlongsideOption);
-    if let Some(OS::MacOs(_)) = option.alongside {
-        true
-    } else {
-        false
-    }
+    matches!(option.alongside, Some(OS::MacOs(_)))
 }
 
 #[no_mangle]
@@ -82,11 +73,39 @@ pub unsafe extern "C" fn distinst_alongside_option_is_windows(
     option: *const DistinstAlongsideOption,
 ) -> bool {
     let option = &*(option as *const AlongsideOption);
-    if let Some(OS::Windows(_)) = option.alongside {
-        true
-    } else {
-        false
-    }
+    matches!(option.alongside, Some(OS::Windows(..)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn distinst_alongside_option_is_unsafe_to_shrink(
+    option: *const DistinstAlongsideOption,
+) -> bool {
+    let option = &*(option as *const AlongsideOption);
+    option.is_unsafe_to_shrink()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn distinst_alongside_option_is_hibernated(
+    option: *const DistinstAlongsideOption,
+) -> bool {
+    let option = &*(option as *const AlongsideOption);
+    option.windows_state.map_or(false, |state| state.hibernated)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn distinst_alongside_option_is_dirty(
+    option: *const DistinstAlongsideOption,
+) -> bool {
+    let option = &*(option as *const AlongsideOption);
+    option.windows_state.map_or(false, |state| state.dirty)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn distinst_alongside_option_is_bitlocker(
+    option: *const DistinstAlongsideOption,
+) -> bool {
+    let option = &*(option as *const AlongsideOption);
+    option.windows_state.map_or(false, |state| state.bitlocker)
 }
 
 #[no_mangle]
@@ -149,7 +168,7 @@ pub unsafe extern "C" fn distinst_refresh_option_can_retain_old(
         return false;
     }
 
-    (&*(option as *const RefreshOption)).can_retain_old
+    (*(option as *const RefreshOption)).can_retain_old
 }
 
 #[no_mangle]
@@ -504,7 +523,7 @@ pub unsafe extern "C" fn distinst_recovery_option_mode(
         return ptr::null();
     }
 
-    (&*(option as *const RecoveryOption)).mode.as_ref().map_or(ptr::null(), |mode| {
+    (*(option as *const RecoveryOption)).mode.as_ref().map_or(ptr::null(), |mode| {
         *len = mode.len() as libc::c_int;
         mode.as_bytes().as_ptr()
     })
@@ -525,6 +544,8 @@ pub struct DistinstInstallOption {
     option:       *const libc::c_void,
     encrypt_pass: *const libc::c_char,
     sectors:      u64,
+    swap_file:    bool,
+    force_shrink: bool,
 }
 
 impl<'a> From<&'a DistinstInstallOption> for InstallOption<'a> {
@@ -540,9 +561,11 @@ impl<'a> From<&'a DistinstInstallOption> for InstallOption<'a> {
         unsafe {
             match opt.tag {
                 DISTINST_INSTALL_OPTION_VARIANT::ALONGSIDE => InstallOption::Alongside {
-                    option:   &*(opt.option as *const AlongsideOption),
-                    password: get_passwd(),
-                    sectors:  opt.sectors,
+                    option:       &*(opt.option as *const AlongsideOption),
+                    password:     get_passwd(),
+                    sectors:      opt.sectors,
+                    swap_file:    opt.swap_file,
+                    force_shrink: opt.force_shrink,
                 },
                 DISTINST_INSTALL_OPTION_VARIANT::RECOVERY => InstallOption::Recovery {
                     option:   &*(opt.option as *const RecoveryOption),
@@ -552,8 +575,9 @@ impl<'a> From<&'a DistinstInstallOption> for InstallOption<'a> {
                     InstallOption::Refresh(&*(opt.option as *const RefreshOption))
                 }
                 DISTINST_INSTALL_OPTION_VARIANT::ERASE => InstallOption::Erase {
-                    option:   &*(opt.option as *const EraseOption),
-                    password: get_passwd(),
+                    option:    &*(opt.option as *const EraseOption),
+                    password:  get_passwd(),
+                    swap_file: opt.swap_file,
                 },
                 DISTINST_INSTALL_OPTION_VARIANT::UPGRADE => {
                     InstallOption::Upgrade(&*(opt.option as *const RecoveryOption))
@@ -570,6 +594,8 @@ pub unsafe extern "C" fn distinst_install_option_new() -> *mut DistinstInstallOp
         option:       ptr::null(),
         encrypt_pass: ptr::null(),
         sectors:      0,
+        swap_file:    false,
+        force_shrink: false,
     }))
 }
 
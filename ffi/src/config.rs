@@ -1,7 +1,32 @@
-use distinst::{Config, UserAccountCreate};
+use distinst::{Config, ImageFormat, UserAccountCreate};
 use crate::get_str;
-use libc;
-use std::io;
+use std::{io, path::PathBuf};
+
+/// The format of the image that an install is performed from.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub enum DISTINST_IMAGE_FORMAT {
+    AUTO,
+    SQUASHFS,
+    TAR,
+    DIRECTORY,
+    OCI,
+    RAW,
+}
+
+impl From<DISTINST_IMAGE_FORMAT> for ImageFormat {
+    fn from(format: DISTINST_IMAGE_FORMAT) -> Self {
+        use DISTINST_IMAGE_FORMAT::*;
+        match format {
+            AUTO => ImageFormat::Auto,
+            SQUASHFS => ImageFormat::Squashfs,
+            TAR => ImageFormat::Tar,
+            DIRECTORY => ImageFormat::Directory,
+            OCI => ImageFormat::Oci,
+            RAW => ImageFormat::Raw,
+        }
+    }
+}
 
 /// Installer configuration
 #[repr(C)]
@@ -16,6 +41,9 @@ pub struct DistinstConfig {
     remove:           *const libc::c_char,
     squashfs:         *const libc::c_char,
     flags:            u8,
+    squashfs_sha256:  *const libc::c_char,
+    image_format:     DISTINST_IMAGE_FORMAT,
+    move_journal:     *const libc::c_char,
 }
 
 impl DistinstConfig {
@@ -30,6 +58,9 @@ impl DistinstConfig {
             old_root:         get_str(self.old_root).ok().map(String::from),
             remove:           get_str(self.remove)?.to_string(),
             flags:            self.flags,
+            squashfs_sha256:  get_str(self.squashfs_sha256).ok().map(String::from),
+            image_format:     self.image_format.into(),
+            move_journal:     get_str(self.move_journal).ok().map(PathBuf::from),
         })
     }
 }
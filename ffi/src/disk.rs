@@ -1,4 +1,3 @@
-use libc;
 
 use std::{
     ffi::{CStr, CString, OsStr},
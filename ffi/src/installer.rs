@@ -1,11 +1,13 @@
-use libc;
 
 use std::io;
 
 use crate::config::DistinstConfig;
 use crate::disk::DistinstDisks;
-use distinst::{timezones::Region, Disks, Error, Installer, Status, Step};
-use crate::gen_object_ptr;
+use distinst::{
+    timezones::Region, Cancellation, Disks, Error, ErrorCode, HookTime, InstallError, Installer,
+    Status, Step,
+};
+use crate::{gen_object_ptr, get_str};
 use crate::DistinstRegion;
 use crate::DistinstUserAccountCreate;
 
@@ -19,6 +21,7 @@ pub enum DISTINST_STEP {
     EXTRACT,
     CONFIGURE,
     BOOTLOADER,
+    VERIFY,
 }
 
 impl From<DISTINST_STEP> for Step {
@@ -31,6 +34,7 @@ impl From<DISTINST_STEP> for Step {
             EXTRACT => Step::Extract,
             CONFIGURE => Step::Configure,
             BOOTLOADER => Step::Bootloader,
+            VERIFY => Step::Verify,
         }
     }
 }
@@ -45,6 +49,131 @@ impl From<Step> for DISTINST_STEP {
             Step::Extract => EXTRACT,
             Step::Configure => CONFIGURE,
             Step::Bootloader => BOOTLOADER,
+            Step::Verify => VERIFY,
+        }
+    }
+}
+
+/// When a hook runs, relative to its step.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub enum DISTINST_HOOK_TIME {
+    BEFORE,
+    AFTER,
+}
+
+impl From<DISTINST_HOOK_TIME> for HookTime {
+    fn from(time: DISTINST_HOOK_TIME) -> Self {
+        match time {
+            DISTINST_HOOK_TIME::BEFORE => HookTime::Before,
+            DISTINST_HOOK_TIME::AFTER => HookTime::After,
+        }
+    }
+}
+
+/// Identifies the cause of an installer error.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+#[allow(non_camel_case_types)]
+pub enum DISTINST_ERROR_CODE {
+    UNKNOWN = 1,
+    INVALID_CONFIG = 2,
+    INVALID_HOSTNAME = 3,
+    INTERRUPTED = 4,
+    PERMISSION_DENIED = 5,
+    NOT_FOUND = 6,
+    RESUME_UNAVAILABLE = 7,
+    DISK_PROBE = 10,
+    INVALID_LAYOUT = 11,
+    LAYOUT_CHANGED = 12,
+    DEVICE_NOT_FOUND = 13,
+    PARTITION_TABLE = 14,
+    FORMAT = 15,
+    ENCRYPTION = 16,
+    LVM = 17,
+    MOUNT = 18,
+    SWAP_FILE = 19,
+    EXTERNAL_COMMAND = 20,
+    RAID = 21,
+    UNSAFE_SHRINK = 22,
+    IMAGE_NOT_FOUND = 30,
+    EXTRACT = 31,
+    CONFIGURE = 32,
+    BOOTLOADER = 33,
+    IMAGE_CORRUPT = 34,
+    RETAIN_HOME = 40,
+    RECOVERY_OPTION = 41,
+    UPGRADE = 42,
+}
+
+impl From<DISTINST_ERROR_CODE> for ErrorCode {
+    fn from(code: DISTINST_ERROR_CODE) -> Self {
+        use DISTINST_ERROR_CODE::*;
+        match code {
+            UNKNOWN => ErrorCode::Unknown,
+            INVALID_CONFIG => ErrorCode::InvalidConfig,
+            INVALID_HOSTNAME => ErrorCode::InvalidHostname,
+            INTERRUPTED => ErrorCode::Interrupted,
+            PERMISSION_DENIED => ErrorCode::PermissionDenied,
+            NOT_FOUND => ErrorCode::NotFound,
+            RESUME_UNAVAILABLE => ErrorCode::ResumeUnavailable,
+            DISK_PROBE => ErrorCode::DiskProbe,
+            INVALID_LAYOUT => ErrorCode::InvalidLayout,
+            LAYOUT_CHANGED => ErrorCode::LayoutChanged,
+            DEVICE_NOT_FOUND => ErrorCode::DeviceNotFound,
+            PARTITION_TABLE => ErrorCode::PartitionTable,
+            FORMAT => ErrorCode::Format,
+            ENCRYPTION => ErrorCode::Encryption,
+            LVM => ErrorCode::Lvm,
+            MOUNT => ErrorCode::Mount,
+            SWAP_FILE => ErrorCode::SwapFile,
+            EXTERNAL_COMMAND => ErrorCode::ExternalCommand,
+            RAID => ErrorCode::Raid,
+            UNSAFE_SHRINK => ErrorCode::UnsafeShrink,
+            IMAGE_NOT_FOUND => ErrorCode::ImageNotFound,
+            EXTRACT => ErrorCode::Extract,
+            CONFIGURE => ErrorCode::Configure,
+            BOOTLOADER => ErrorCode::Bootloader,
+            IMAGE_CORRUPT => ErrorCode::ImageCorrupt,
+            RETAIN_HOME => ErrorCode::RetainHome,
+            RECOVERY_OPTION => ErrorCode::RecoveryOption,
+            UPGRADE => ErrorCode::Upgrade,
+        }
+    }
+}
+
+impl From<ErrorCode> for DISTINST_ERROR_CODE {
+    fn from(code: ErrorCode) -> Self {
+        use DISTINST_ERROR_CODE::*;
+        match code {
+            ErrorCode::Unknown => UNKNOWN,
+            ErrorCode::InvalidConfig => INVALID_CONFIG,
+            ErrorCode::InvalidHostname => INVALID_HOSTNAME,
+            ErrorCode::Interrupted => INTERRUPTED,
+            ErrorCode::PermissionDenied => PERMISSION_DENIED,
+            ErrorCode::NotFound => NOT_FOUND,
+            ErrorCode::ResumeUnavailable => RESUME_UNAVAILABLE,
+            ErrorCode::DiskProbe => DISK_PROBE,
+            ErrorCode::InvalidLayout => INVALID_LAYOUT,
+            ErrorCode::LayoutChanged => LAYOUT_CHANGED,
+            ErrorCode::DeviceNotFound => DEVICE_NOT_FOUND,
+            ErrorCode::PartitionTable => PARTITION_TABLE,
+            ErrorCode::Format => FORMAT,
+            ErrorCode::Encryption => ENCRYPTION,
+            ErrorCode::Lvm => LVM,
+            ErrorCode::Mount => MOUNT,
+            ErrorCode::SwapFile => SWAP_FILE,
+            ErrorCode::ExternalCommand => EXTERNAL_COMMAND,
+            ErrorCode::Raid => RAID,
+            ErrorCode::UnsafeShrink => UNSAFE_SHRINK,
+            ErrorCode::ImageNotFound => IMAGE_NOT_FOUND,
+            ErrorCode::Extract => EXTRACT,
+            ErrorCode::Configure => CONFIGURE,
+            ErrorCode::Bootloader => BOOTLOADER,
+            ErrorCode::ImageCorrupt => IMAGE_CORRUPT,
+            ErrorCode::RetainHome => RETAIN_HOME,
+            ErrorCode::RecoveryOption => RECOVERY_OPTION,
+            ErrorCode::Upgrade => UPGRADE,
         }
     }
 }
@@ -55,6 +184,7 @@ impl From<Step> for DISTINST_STEP {
 pub struct DistinstError {
     step: DISTINST_STEP,
     err:  libc::c_int,
+    code: DISTINST_ERROR_CODE,
 }
 
 /// Installer error callback
@@ -97,10 +227,13 @@ pub unsafe extern "C" fn distinst_installer_emit_error(
     installer: *mut DistinstInstaller,
     error: *const DistinstError,
 ) {
-    (*(installer as *mut Installer)).emit_error(&Error {
-        step: (*error).step.into(),
-        err:  io::Error::from_raw_os_error((*error).err),
-    });
+    let mut err = io::Error::from_raw_os_error((*error).err);
+    let code = ErrorCode::from((*error).code);
+    if code != ErrorCode::Unknown {
+        err = InstallError::new(code, err.to_string()).into_io_error(err.kind());
+    }
+
+    (*(installer as *mut Installer)).emit_error(&Error { step: (*error).step.into(), err });
 }
 
 /// Set the installer status callback
@@ -115,6 +248,7 @@ pub unsafe extern "C" fn distinst_installer_on_error(
             &DistinstError {
                 step: error.step.into(),
                 err:  error.err.raw_os_error().unwrap_or(libc::EIO),
+                code: error.code().into(),
             } as *const DistinstError,
             user_data,
         )
@@ -168,6 +302,70 @@ pub unsafe extern "C" fn distinst_installer_set_user_callback(
     });
 }
 
+/// Run an executable before or after a step of the install.
+///
+/// Returns `EIO` if the command is not a valid string.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_installer_add_hook(
+    installer: *mut DistinstInstaller,
+    step: DISTINST_STEP,
+    time: DISTINST_HOOK_TIME,
+    command: *const libc::c_char,
+) -> libc::c_int {
+    if installer.is_null() {
+        return libc::EIO;
+    }
+
+    match get_str(command) {
+        Ok(command) => {
+            (*(installer as *mut Installer)).add_hook_command(step.into(), time.into(), command);
+            0
+        }
+        Err(why) => {
+            info!("Hook error: {}", why);
+            libc::EIO
+        }
+    }
+}
+
+/// A token which cancels the install of an installer
+#[repr(C)]
+pub struct DistinstCancellation;
+
+/// Get the token which cancels the install of this installer, so that the install may be
+/// cancelled from another thread. The token must be destroyed with
+/// `distinst_cancellation_destroy`.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_installer_cancellation(
+    installer: *const DistinstInstaller,
+) -> *mut DistinstCancellation {
+    if installer.is_null() {
+        return std::ptr::null_mut();
+    }
+
+    let cancellation = (*(installer as *const Installer)).cancellation();
+    gen_object_ptr(cancellation) as *mut DistinstCancellation
+}
+
+/// Cancel the install, which kills the command that it is running, and then unmounts the
+/// target and closes its logical volumes and encrypted partitions, before the install fails.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_cancellation_cancel(cancellation: *const DistinstCancellation) {
+    if !cancellation.is_null() {
+        (*(cancellation as *const Cancellation)).cancel();
+    }
+}
+
+/// Destroy a cancellation token
+#[no_mangle]
+pub unsafe extern "C" fn distinst_cancellation_destroy(cancellation: *mut DistinstCancellation) {
+    if cancellation.is_null() {
+        error!("DistinstCancellation was to be destroyed even though it is null");
+    } else {
+        Box::from_raw(cancellation as *mut Cancellation);
+    }
+}
+
 /// Install using this installer, whilst retaining home & user accounts.
 #[no_mangle]
 pub unsafe extern "C" fn distinst_installer_install(
@@ -198,6 +396,34 @@ pub unsafe extern "C" fn distinst_installer_install(
     }
 }
 
+/// Resume an install which failed after its image was extracted, using the journal that
+/// the failed install wrote.
+#[no_mangle]
+pub unsafe extern "C" fn distinst_installer_resume(
+    installer: *mut DistinstInstaller,
+    config: *const DistinstConfig,
+) -> libc::c_int {
+    if installer.is_null() || config.is_null() {
+        return libc::EIO;
+    }
+
+    match (*config).as_config() {
+        Ok(config) => match (*(installer as *mut Installer)).resume(&config) {
+            Ok(()) => 0,
+            Err(err) => {
+                info!("Resume error: {}", err);
+                err.raw_os_error().unwrap_or(libc::EIO)
+            }
+        },
+        Err(err) => {
+            info!("Config error: {}", err);
+            let errno = err.raw_os_error().unwrap_or(libc::EIO);
+            (*(installer as *mut Installer)).emit_error(&Error { step: Step::Init, err });
+            errno
+        }
+    }
+}
+
 /// Destroy an installer object
 #[no_mangle]
 pub unsafe extern "C" fn distinst_installer_destroy(installer: *mut DistinstInstaller) {
@@ -1,5 +1,5 @@
 #![allow(unknown_lints)]
-#![allow(cast_ptr_alignment)]
+#![allow(clippy::cast_ptr_alignment, clippy::missing_safety_doc)]
 
 extern crate distinst;
 extern crate distinst_external_commands as external;
@@ -22,6 +22,7 @@ pub const DISTINST_MODIFY_BOOT_ORDER: u8 = 0b1;
 pub const DISTINST_INSTALL_HARDWARE_SUPPORT: u8 = 0b10;
 pub const DISTINST_KEEP_OLD_ROOT: u8 = 0b100;
 pub const DISTINST_RUN_UBUNTU_DRIVERS: u8 = 0b1000;
+pub const DISTINST_VERIFY_IMAGE: u8 = 0b10000;
 
 use std::io;
 
@@ -1,6 +1,5 @@
 use super::{get_str, null_check, to_cstr};
 use distinst::locale;
-use libc;
 use std::ptr;
 
 #[no_mangle]
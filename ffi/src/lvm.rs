@@ -1,10 +1,9 @@
 use distinst::{
-    BlockDeviceExt, DiskExt, Disks, LogicalDevice, PartitionBuilder, PartitionInfo, Sector,
-    SectorExt,
+    BlockDeviceExt, DiskExt, Disks, Enrollment, LogicalDevice, LuksOptions, PartitionBuilder,
+    PartitionInfo, Pbkdf, Sector, SectorExt,
 };
 use external::luks::deactivate_logical_devices;
 use crate::ffi::AsMutPtr;
-use libc;
 
 use super::{
     get_str, null_check, DistinstDisks, DistinstPartition, DistinstPartitionBuilder, DistinstSector,
@@ -297,11 +296,105 @@ pub unsafe extern "C" fn distinst_lvm_device_contains_mount(
 #[repr(C)]
 pub struct DistinstLvmEncryption {
     /// The PV field is not optional
-    pub physical_volume: *mut libc::c_char,
+    pub physical_volume:  *mut libc::c_char,
     /// The password field is optional
-    pub password:        *mut libc::c_char,
+    pub password:         *mut libc::c_char,
     /// The keydata field is optional
-    pub keydata:         *mut libc::c_char,
+    pub keydata:          *mut libc::c_char,
+    /// The cipher is optional
+    pub cipher:           *mut libc::c_char,
+    /// The key size, in bits, where 0 is the default
+    pub key_size:         u16,
+    pub pbkdf:            DISTINST_PBKDF,
+    /// The memory cost of an Argon2 PBKDF in KiB, where 0 is the default
+    pub pbkdf_memory:     u32,
+    /// The PBKDF iterations, where 0 is the default
+    pub pbkdf_iterations: u32,
+    /// The encryption sector size in bytes, where 0 is the default
+    pub sector_size:      u16,
+    /// The label is optional
+    pub label:            *mut libc::c_char,
+    /// Enrolls a TPM2 device, which may be "auto", if it is not null
+    pub tpm2_device:      *mut libc::c_char,
+    /// A bitmask of the PCRs that a TPM2 enrollment is bound to, where 0 is PCR 7
+    pub tpm2_pcrs:        u32,
+    /// Enrolls a FIDO2 device, which may be "auto", if it is not null
+    pub fido2_device:     *mut libc::c_char,
+}
+
+impl DistinstLvmEncryption {
+    /// The options for formatting the volume, where zeroed and null fields use the defaults.
+    pub unsafe fn luks_options(&self) -> Result<LuksOptions, ()> {
+        let optional_str = |ptr: *mut libc::c_char| {
+            if ptr.is_null() {
+                Ok(None)
+            } else {
+                get_str(ptr).map(|string| Some(string.to_owned())).map_err(|_| ())
+            }
+        };
+
+        let nonzero_u32 = |value: u32| if value == 0 { None } else { Some(value) };
+        let defaults = LuksOptions::default();
+
+        Ok(LuksOptions {
+            cipher:           optional_str(self.cipher)?,
+            key_size:         if self.key_size == 0 { defaults.key_size } else { self.key_size },
+            pbkdf:            self.pbkdf.into(),
+            pbkdf_memory:     nonzero_u32(self.pbkdf_memory),
+            pbkdf_iterations: nonzero_u32(self.pbkdf_iterations),
+            sector_size:      if self.sector_size == 0 { None } else { Some(self.sector_size) },
+            label:            optional_str(self.label)?,
+        })
+    }
+
+    /// The unlock methods to enroll, where a null device is not enrolled.
+    pub unsafe fn enrollments(&self) -> Result<Vec<Enrollment>, ()> {
+        let device = |ptr: *mut libc::c_char| match get_str(ptr).map_err(|_| ())? {
+            "auto" => Ok(None),
+            device => Ok(Some(device.to_owned())),
+        };
+
+        let mut enrollments = Vec::new();
+
+        if !self.tpm2_device.is_null() {
+            let mut enrollment = Enrollment::tpm2();
+            if let Enrollment::Tpm2 { device: ref mut tpm, ref mut pcrs } = enrollment {
+                *tpm = device(self.tpm2_device)?;
+                if self.tpm2_pcrs != 0 {
+                    *pcrs = (0..24).filter(|pcr| self.tpm2_pcrs & (1 << pcr) != 0).collect();
+                }
+            }
+
+            enrollments.push(enrollment);
+        }
+
+        if !self.fido2_device.is_null() {
+            enrollments.push(Enrollment::Fido2 { device: device(self.fido2_device)? });
+        }
+
+        Ok(enrollments)
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[allow(non_camel_case_types)]
+pub enum DISTINST_PBKDF {
+    DEFAULT = 0,
+    ARGON2I = 1,
+    ARGON2ID = 2,
+    PBKDF2 = 3,
+}
+
+impl From<DISTINST_PBKDF> for Option<Pbkdf> {
+    fn from(pbkdf: DISTINST_PBKDF) -> Option<Pbkdf> {
+        match pbkdf {
+            DISTINST_PBKDF::DEFAULT => None,
+            DISTINST_PBKDF::ARGON2I => Some(Pbkdf::Argon2i),
+            DISTINST_PBKDF::ARGON2ID => Some(Pbkdf::Argon2id),
+            DISTINST_PBKDF::PBKDF2 => Some(Pbkdf::Pbkdf2),
+        }
+    }
 }
 
 #[no_mangle]
@@ -319,4 +412,14 @@ pub unsafe extern "C" fn distinst_lvm_encryption_copy(
     dst.physical_volume = src.physical_volume;
     dst.password = src.password;
     dst.keydata = src.keydata;
+    dst.cipher = src.cipher;
+    dst.key_size = src.key_size;
+    dst.pbkdf = src.pbkdf;
+    dst.pbkdf_memory = src.pbkdf_memory;
+    dst.pbkdf_iterations = src.pbkdf_iterations;
+    dst.sector_size = src.sector_size;
+    dst.label = src.label;
+    dst.tpm2_device = src.tpm2_device;
+    dst.tpm2_pcrs = src.tpm2_pcrs;
+    dst.fido2_device = src.fido2_device;
 }
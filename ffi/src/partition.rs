@@ -1,4 +1,3 @@
-use libc;
 
 use std::{ffi::CString, io, os::unix::ffi::OsStrExt, path::PathBuf, ptr};
 
@@ -284,7 +283,17 @@ pub unsafe extern "C" fn distinst_partition_builder_logical_volume(
             }
         };
 
-        Some(LvmEncryption::new(pv, password, keydata))
+        let encryption = &*encryption;
+        let (luks, enrollments) = match (encryption.luks_options(), encryption.enrollments()) {
+            (Ok(luks), Ok(enrollments)) => (luks, enrollments),
+            _ => return builder,
+        };
+
+        Some(
+            LvmEncryption::new(pv, password, keydata)
+                .with_luks(luks)
+                .with_enrollments(enrollments),
+        )
     };
 
     builder_action(builder, |builder| builder.logical_volume(group, encryption))
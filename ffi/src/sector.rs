@@ -1,6 +1,5 @@
 use distinst::Sector;
 use crate::get_str;
-use libc;
 use std::ptr;
 use crate::to_cstr;
 
@@ -1,6 +1,5 @@
 use distinst::timezones::*;
 use crate::gen_object_ptr;
-use libc;
 use std::ptr;
 
 #[repr(C)]
@@ -12,7 +11,7 @@ pub unsafe extern "C" fn distinst_timezones_new() -> *mut DistinstTimezones {
         Ok(timezones) => gen_object_ptr(timezones) as *mut Timezones as *mut DistinstTimezones,
         Err(why) => {
             eprintln!("distinst: timezone error: {}", why);
-            return ptr::null_mut();
+            ptr::null_mut()
         }
     }
 }
@@ -26,7 +25,7 @@ pub unsafe extern "C" fn distinst_timezones_zones(
         return ptr::null_mut();
     }
     let boxed: Box<dyn Iterator<Item = &Zone>> =
-        Box::new((&*(tz as *const Timezones)).zones().into_iter());
+        Box::new((&*(tz as *const Timezones)).zones().iter());
     gen_object_ptr(boxed) as *mut DistinstZones
 }
 
@@ -45,7 +44,7 @@ pub struct DistinstZones;
 #[no_mangle]
 pub unsafe extern "C" fn distinst_zones_next(tz: *mut DistinstZones) -> *const DistinstZone {
     let zones = &mut *(tz as *mut Box<dyn Iterator<Item = &Zone>>);
-    zones.next().map_or_else(|| ptr::null(), |zone| zone as *const Zone as *const DistinstZone)
+    zones.next().map_or_else(ptr::null, |zone| zone as *const Zone as *const DistinstZone)
 }
 
 #[no_mangle]
@@ -56,13 +55,13 @@ pub unsafe extern "C" fn distinst_zones_nth(
     let zones = &mut *(tz as *mut Box<dyn Iterator<Item = &Zone>>);
     zones
         .nth(nth as usize)
-        .map_or_else(|| ptr::null(), |zone| zone as *const Zone as *const DistinstZone)
+        .map_or_else(ptr::null, |zone| zone as *const Zone as *const DistinstZone)
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn distinst_zones_destroy(tz: *mut DistinstZones) {
     if !tz.is_null() {
-        Box::from_raw(tz as *mut Box<dyn Iterator<Item = &Zone>>);
+        drop(Box::from_raw(tz as *mut Box<dyn Iterator<Item = &Zone>>));
     } else {
         error!("distinst_zones_destroy: tz input was null");
     }
@@ -94,7 +93,7 @@ pub unsafe extern "C" fn distinst_zone_regions(zone: *const DistinstZone) -> *mu
     }
 
     let boxed: Box<dyn Iterator<Item = &Region>> =
-        Box::new((&*(zone as *const Zone)).regions().into_iter());
+        Box::new((&*(zone as *const Zone)).regions().iter());
     gen_object_ptr(boxed) as *mut DistinstRegions
 }
 
@@ -108,7 +107,7 @@ pub unsafe extern "C" fn distinst_regions_next(
     let regions = &mut *(regions as *mut Box<dyn Iterator<Item = &Region>>);
     regions
         .next()
-        .map_or_else(|| ptr::null(), |region| region as *const Region as *const DistinstRegion)
+        .map_or_else(ptr::null, |region| region as *const Region as *const DistinstRegion)
 }
 
 #[no_mangle]
@@ -119,13 +118,13 @@ pub unsafe extern "C" fn distinst_regions_nth(
     let regions = &mut *(regions as *mut Box<dyn Iterator<Item = &Region>>);
     regions
         .nth(nth as usize)
-        .map_or_else(|| ptr::null(), |region| region as *const Region as *const DistinstRegion)
+        .map_or_else(ptr::null, |region| region as *const Region as *const DistinstRegion)
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn distinst_regions_destroy(tz: *mut DistinstRegions) {
     if !tz.is_null() {
-        Box::from_raw(tz as *mut Box<dyn Iterator<Item = &Region>>);
+        drop(Box::from_raw(tz as *mut Box<dyn Iterator<Item = &Region>>));
     } else {
         error!("distinst_regions_destroy: tz input was null");
     }
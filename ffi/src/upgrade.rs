@@ -1,6 +1,5 @@
 use super::{DistinstDisks, DistinstRecoveryOption};
 use distinst::{self, auto::RecoveryOption, Disks, RecoveryEnv, UpgradeEvent};
-use libc;
 use std::ptr;
 
 #[repr(C)]
@@ -1,6 +1,6 @@
 //! User account information will be collected here.
 
-use super::{mount_and_then, ReinstallError};
+use super::{probe_and_then, ReinstallError};
 use disk_types::FileSystem;
 use std::{collections::HashMap, ffi::OsStr, os::unix::ffi::OsStrExt, path::Path};
 
@@ -30,9 +30,13 @@ pub(crate) fn lines<T: ::std::iter::FromIterator<(Vec<u8>, Vec<u8>)>>(input: &[u
 }
 
 impl AccountFiles {
-    pub fn new(device: &Path, fs: FileSystem) -> Result<AccountFiles, ReinstallError> {
+    pub fn new(
+        device: &Path,
+        fs: FileSystem,
+        subvolume: Option<&str>,
+    ) -> Result<AccountFiles, ReinstallError> {
         info!("retrieving user account data");
-        mount_and_then(device, fs, |base| {
+        probe_and_then(device, fs, subvolume, |base| {
             read(base.join("etc/passwd"))
                 .and_then(|p| read(base.join("etc/group")).map(|g| (p, g)))
                 .and_then(|(p, g)| read(base.join("etc/shadow")).map(|s| (p, g, s)))
@@ -11,6 +11,7 @@ pub(crate) use self::{
 pub use self::{options::*, retain::delete_old_install};
 
 use disk_types::FileSystem;
+use os_detect::mount_for_probe;
 use std::{
     io,
     path::{Path, PathBuf},
@@ -50,7 +51,44 @@ impl From<io::Error> for ReinstallError {
     fn from(why: io::Error) -> ReinstallError { ReinstallError::IO { why } }
 }
 
-fn mount_and_then<T, F>(device: &Path, fs: FileSystem, mut action: F) -> Result<T, ReinstallError>
+/// Mounts the device to a temporary directory, and applies `action` to it.
+///
+/// If a btrfs `subvolume` is given, that subvolume will be mounted instead of the default.
+fn mount_and_then<T, F>(
+    device: &Path,
+    fs: FileSystem,
+    subvolume: Option<&str>,
+    action: F,
+) -> Result<T, ReinstallError>
+where
+    F: FnMut(&Path) -> Result<T, ReinstallError>,
+{
+    with_mount(device, fs, subvolume, false, action)
+}
+
+/// Mounts the device read-only to a temporary directory, and applies `action` to it.
+///
+/// This is used by actions that only read from the device, which is mounted with
+/// `os_detect::mount_for_probe` so that it will not be modified.
+fn probe_and_then<T, F>(
+    device: &Path,
+    fs: FileSystem,
+    subvolume: Option<&str>,
+    action: F,
+) -> Result<T, ReinstallError>
+where
+    F: FnMut(&Path) -> Result<T, ReinstallError>,
+{
+    with_mount(device, fs, subvolume, true, action)
+}
+
+fn with_mount<T, F>(
+    device: &Path,
+    fs: FileSystem,
+    subvolume: Option<&str>,
+    read_only: bool,
+    mut action: F,
+) -> Result<T, ReinstallError>
 where
     F: FnMut(&Path) -> Result<T, ReinstallError>,
 {
@@ -61,11 +99,17 @@ where
         fs => fs.into(),
     };
 
+    let options = subvolume.map(|subvolume| ["subvol=", subvolume].concat());
+
     TempDir::new("distinst").map_err(|why| ReinstallError::TempDir { why }).and_then(|tempdir| {
         let base = tempdir.path();
-        Mount::new(device, base, fs, MountFlags::empty(), None)
-            .map(|m| m.into_unmount_drop(UnmountFlags::DETACH))
-            .map_err(|why| ReinstallError::PartitionMount { why })
-            .and_then(|_mount| action(base))
+        let mount = if read_only {
+            mount_for_probe(device, base, fs, options.as_deref())
+        } else {
+            Mount::new(device, base, fs, MountFlags::empty(), options.as_deref())
+                .map(|m| m.into_unmount_drop(UnmountFlags::DETACH))
+        };
+
+        mount.map_err(|why| ReinstallError::PartitionMount { why }).and_then(|_mount| action(base))
     })
 }
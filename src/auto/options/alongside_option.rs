@@ -1,4 +1,4 @@
-use os_detect::OS;
+use os_detect::{WindowsState, OS};
 use std::{fmt, path::PathBuf};
 
 #[derive(Debug)]
@@ -9,20 +9,28 @@ pub enum AlongsideMethod {
 
 #[derive(Debug)]
 pub struct AlongsideOption {
-    pub alongside: Option<OS>,
-    pub device:    PathBuf,
-    pub method:    AlongsideMethod,
+    pub alongside:     Option<OS>,
+    pub device:        PathBuf,
+    pub method:        AlongsideMethod,
+    /// The state of the NTFS volume that would be shrunk, which may be unsafe to resize.
+    pub windows_state: Option<WindowsState>,
 }
 
 impl AlongsideOption {
     pub fn get_os(&self) -> &str {
         match self.alongside {
             Some(OS::Linux { ref info, .. }) => info.pretty_name.as_str(),
-            Some(OS::Windows(ref name)) => name.as_str(),
+            Some(OS::Windows(ref name, _)) => name.as_str(),
             Some(OS::MacOs(ref name)) => name.as_str(),
             None => "none",
         }
     }
+
+    /// True if the partition that would be shrunk is hibernated, was left dirty by Fast
+    /// Startup, or is encrypted with BitLocker.
+    pub fn is_unsafe_to_shrink(&self) -> bool {
+        self.windows_state.map_or(false, |state| !state.is_safe_to_resize())
+    }
 }
 
 impl fmt::Display for AlongsideOption {
@@ -31,15 +39,22 @@ impl fmt::Display for AlongsideOption {
         let device = self.device.display();
 
         match self.method {
-            AlongsideMethod::Shrink { sectors_total, sectors_free, ref path, .. } => write!(
-                f,
-                "alongside {:?} ({}) by shrinking {}: {} of {} MiB free",
-                os,
-                device,
-                path.display(),
-                sectors_free / 2048,
-                sectors_total / 2048
-            ),
+            AlongsideMethod::Shrink { sectors_total, sectors_free, ref path, .. } => {
+                write!(
+                    f,
+                    "alongside {:?} ({}) by shrinking {}: {} of {} MiB free",
+                    os,
+                    device,
+                    path.display(),
+                    sectors_free / 2048,
+                    sectors_total / 2048
+                )?;
+
+                match self.windows_state {
+                    Some(state) if !state.is_safe_to_resize() => write!(f, " (unsafe: {})", state),
+                    _ => Ok(()),
+                }
+            }
             AlongsideMethod::Free(ref region) => write!(
                 f,
                 "alongside {:?} ({}) using free space: {} MiB free",
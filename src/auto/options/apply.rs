@@ -11,10 +11,23 @@ use crate::misc;
 use partition_identity::PartitionID;
 use proc_mounts::MountIter;
 
+/// An installation option to apply to a disks configuration.
+///
+/// Options which create a new root may use a swap file within the root, in place of a swap
+/// partition, by setting `swap_file`.
+///
+/// Installing alongside Windows by shrinking a volume that is hibernated, left dirty by Fast
+/// Startup, or encrypted with BitLocker is refused, unless `force_shrink` is set.
 pub enum InstallOption<'a> {
-    Alongside { option: &'a AlongsideOption, password: Option<String>, sectors: u64 },
+    Alongside {
+        option:       &'a AlongsideOption,
+        password:     Option<String>,
+        sectors:      u64,
+        swap_file:    bool,
+        force_shrink: bool,
+    },
     Refresh(&'a RefreshOption),
-    Erase { option: &'a EraseOption, password: Option<String> },
+    Erase { option: &'a EraseOption, password: Option<String>, swap_file: bool },
     Recovery { option: &'a RecoveryOption, password: Option<String> },
     Upgrade(&'a RecoveryOption),
 }
@@ -78,8 +91,8 @@ impl<'a> InstallOption<'a> {
     pub fn apply(self, disks: &mut Disks) -> Result<(), InstallOptionError> {
         match self {
             // Install alongside another OS, taking `sectors` from the largest free partition.
-            InstallOption::Alongside { option, password, sectors } => {
-                alongside_config(disks, option, password, sectors)
+            InstallOption::Alongside { option, password, sectors, swap_file, force_shrink } => {
+                alongside_config(disks, option, password, sectors, swap_file, force_shrink)
             }
             // Reuse existing partitions, without making any modifications.
             InstallOption::Refresh(option) => refresh_config(disks, option),
@@ -88,7 +101,9 @@ impl<'a> InstallOption<'a> {
                 recovery_config(disks, option, password)
             }
             // Reset the `disks` object and designate a disk to be wiped and installed.
-            InstallOption::Erase { option, password } => erase_config(disks, option, password),
+            InstallOption::Erase { option, password, swap_file } => {
+                erase_config(disks, option, password, swap_file)
+            }
             InstallOption::Upgrade(option) => upgrade_config(disks, option),
         }
     }
@@ -99,7 +114,21 @@ fn alongside_config(
     option: &AlongsideOption,
     password: Option<String>,
     sectors: u64,
+    swap_file: bool,
+    force_shrink: bool,
 ) -> Result<(), InstallOptionError> {
+    if let (AlongsideMethod::Shrink { path, .. }, Some(state)) =
+        (&option.method, option.windows_state)
+    {
+        if !state.is_safe_to_resize() {
+            if !force_shrink {
+                return Err(InstallOptionError::UnsafeWindowsShrink { path: path.clone(), state });
+            }
+
+            warn!("shrinking {:?} regardless of its state: {}", path, state);
+        }
+    }
+
     let mut tmp = Disks::default();
     mem::swap(&mut tmp, disks);
 
@@ -183,7 +212,8 @@ fn alongside_config(
         let swap = end - DEFAULT_SWAP_SECTORS;
 
         // Only create a new unencrypted swap partition if a swap partition does not already exist.
-        let end = if !device.get_partitions().iter().any(|p| p.filesystem == Some(Swap)) {
+        let has_swap = device.get_partitions().iter().any(|p| p.filesystem == Some(Swap));
+        let end = if !swap_file && !has_swap {
             device.add_partition(PartitionBuilder::new(swap, end, Swap))?;
             swap
         } else {
@@ -202,21 +232,39 @@ fn alongside_config(
             .ok_or(InstallOptionError::LogicalDeviceNotFound { vg: root_vg })?;
 
         let start = lvm_device.get_sector(Sector::Start);
-        let swap = lvm_device.get_sector(Sector::UnitFromEnd(DEFAULT_SWAP_SECTORS));
         let end = lvm_device.get_sector(Sector::End);
 
-        lvm_device
-            .add_partition(
-                PartitionBuilder::new(start, swap, Ext4).name("root".into()).mount("/".into()),
-            )
-            .and_then(|_| {
-                lvm_device.add_partition(PartitionBuilder::new(swap, end, Swap).name("swap".into()))
-            })?;
+        if swap_file {
+            lvm_device.add_partition(
+                PartitionBuilder::new(start, end, Ext4).name("root".into()).mount("/".into()),
+            )?;
+        } else {
+            let swap = lvm_device.get_sector(Sector::UnitFromEnd(DEFAULT_SWAP_SECTORS));
+            lvm_device
+                .add_partition(
+                    PartitionBuilder::new(start, swap, Ext4).name("root".into()).mount("/".into()),
+                )
+                .and_then(|_| {
+                    let swap_partition = PartitionBuilder::new(swap, end, Swap).name("swap".into());
+                    lvm_device.add_partition(swap_partition)
+                })?;
+        }
     }
 
+    disks.swap_file = default_swap_file(swap_file);
+
     Ok(())
 }
 
+/// The swap file to create in place of a swap partition, if one was requested.
+fn default_swap_file(swap_file: bool) -> Option<SwapFile> {
+    if swap_file {
+        Some(SwapFile::new(DEFAULT_SWAP_SECTORS))
+    } else {
+        None
+    }
+}
+
 fn upgrade_config(disks: &mut Disks, option: &RecoveryOption) -> Result<(), InstallOptionError> {
     info!("applying upgrade config");
     set_mount_by_identity(disks, &PartitionID::new_uuid(option.root_uuid.clone()), "/")?;
@@ -235,7 +283,15 @@ fn upgrade_config(disks: &mut Disks, option: &RecoveryOption) -> Result<(), Inst
 /// Apply a `refresh` config to `disks`.
 fn refresh_config(disks: &mut Disks, option: &RefreshOption) -> Result<(), InstallOptionError> {
     info!("applying refresh install config");
-    set_mount_by_identity(disks, &PartitionID::new_uuid(option.root_part.clone()), "/")?;
+    let root = PartitionID::new_uuid(option.root_part.clone());
+    set_mount_by_identity(disks, &root, "/")?;
+
+    if !option.subvolumes.is_empty() {
+        disks
+            .get_partition_by_id_mut(&root)
+            .ok_or_else(|| InstallOptionError::PartitionIDNotFound { id: root.clone() })?
+            .set_subvolumes(option.subvolumes.clone());
+    }
 
     if let Some(ref home) = option.home_part {
         set_mount_by_identity(disks, home, "/home")?;
@@ -404,6 +460,7 @@ fn erase_config(
     disks: &mut Disks,
     option: &EraseOption,
     password: Option<String>,
+    swap_file: bool,
 ) -> Result<(), InstallOptionError> {
     let mut tmp = Disks::default();
     mem::swap(&mut tmp, disks);
@@ -413,8 +470,9 @@ fn erase_config(
     let start_sector = Sector::Start;
     let boot_sector = Sector::Unit(DEFAULT_ESP_SECTORS);
     let recovery_sector = Sector::Unit(DEFAULT_ESP_SECTORS + DEFAULT_RECOVER_SECTORS);
-    let swap_sector = Sector::UnitFromEnd(DEFAULT_SWAP_SECTORS);
     let end_sector = Sector::End;
+    let swap_sector =
+        if swap_file { end_sector } else { Sector::UnitFromEnd(DEFAULT_SWAP_SECTORS) };
 
     let (lvm, root_vg) = match generate_encryption(password)? {
         Some((enc, root)) => (Some((enc, root.clone())), Some(root)),
@@ -488,8 +546,12 @@ fn erase_config(
                     PartitionBuilder::new(start, end, Ext4).mount("/".into())
                 })
             })
-            // Configure swap partition
+            // Configure swap partition, unless a swap file will be used.
             .and_then(|_| {
+                if swap_file {
+                    return Ok(());
+                }
+
                 let start = device.get_sector(swap_sector);
                 let end = device.get_sector(end_sector);
                 device.add_partition(PartitionBuilder::new(start, end, Swap))
@@ -498,6 +560,8 @@ fn erase_config(
         disks.add(device);
     }
 
+    disks.swap_file = default_swap_file(swap_file);
+
     disks.initialize_volume_groups()?;
 
     if let Some(root_vg) = root_vg {
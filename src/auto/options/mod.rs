@@ -49,20 +49,36 @@ impl InstallOptions {
                         "found OS on {:?}: {}",
                         part.get_device_path(),
                         match os {
-                            OS::Windows(ref version) => format!("Windows ({})", version),
+                            OS::Windows(ref version, _) => format!("Windows ({})", version),
                             OS::Linux { ref info, .. } => format!("Linux ({})", info.pretty_name),
                             OS::MacOs(ref version) => format!("Mac OS ({})", version),
                         }
                     );
 
                     // Only consider Linux installs for refreshing.
-                    if let OS::Linux { ref info, ref partitions, ref targets } = os {
+                    if let OS::Linux { ref info, ref partitions, ref targets, ref subvolumes } =
+                        os
+                    {
                         // Only consider versions of Linux that are the same as the installer's
                         // version.
                         if info.version_id == os_release.version_id {
-                            let home = targets.iter().position(|t| t == Path::new("/home"));
-                            let efi = targets.iter().position(|t| t == Path::new("/boot/efi"));
-                            let recovery = targets.iter().position(|t| t == Path::new("/recovery"));
+                            let root = PartitionID::get_uuid(part.get_device_path())
+                                .expect("root device did not have uuid");
+
+                            // Subvolumes of the root partition are mounted alongside the root,
+                            // rather than being separate partitions.
+                            let subvolumes =
+                                root_subvolumes(&root, partitions, targets, subvolumes);
+                            let separate = |target: &str| {
+                                (0..targets.len()).find(|&pos| {
+                                    targets[pos] == Path::new(target)
+                                        && (subvolumes.is_empty() || partitions[pos] != root)
+                                })
+                            };
+
+                            let home = separate("/home");
+                            let efi = separate("/boot/efi");
+                            let recovery = separate("/recovery");
 
                             info!(
                                 "found refresh option {}on {:?}",
@@ -72,12 +88,11 @@ impl InstallOptions {
 
                             refresh_options.push(RefreshOption {
                                 os_release:     info.clone(),
-                                root_part:      PartitionID::get_uuid(part.get_device_path())
-                                    .expect("root device did not have uuid")
-                                    .id,
+                                root_part:      root.id,
                                 home_part:      home.map(|pos| partitions[pos].clone()),
                                 efi_part:       efi.map(|pos| partitions[pos].clone()),
                                 recovery_part:  recovery.map(|pos| partitions[pos].clone()),
+                                subvolumes,
                                 can_retain_old: if let Ok(used) = part.sectors_used() {
                                     part.get_sectors() - used > required_space
                                 } else {
@@ -114,15 +129,34 @@ impl InstallOptions {
                                 free,
                                 sectors
                             );
+                            let windows_state = match os {
+                                Some(OS::Windows(_, state)) => Some(state),
+                                _ if part.filesystem == Some(FileSystem::Ntfs) => {
+                                    Some(os_detect::volume_state(part.get_device_path()))
+                                }
+                                _ => None,
+                            };
+
+                            let unsafe_state =
+                                windows_state.filter(|state| !state.is_safe_to_resize());
+                            if let Some(state) = unsafe_state {
+                                warn!(
+                                    "shrinking {:?} is unsafe: {}",
+                                    part.get_device_path(),
+                                    state
+                                );
+                            }
+
                             alongside_options.push(AlongsideOption {
-                                device:    device.get_device_path().to_path_buf(),
+                                device: device.get_device_path().to_path_buf(),
                                 alongside: os,
-                                method:    AlongsideMethod::Shrink {
+                                method: AlongsideMethod::Shrink {
                                     path:          part.get_device_path().to_path_buf(),
                                     partition:     part.number,
                                     sectors_free:  free,
                                     sectors_total: sectors,
                                 },
+                                windows_state,
                             });
                         }
                     }
@@ -137,12 +171,13 @@ impl InstallOptions {
                             part.start_sector - 1
                         );
                         alongside_options.push(AlongsideOption {
-                            device:    device.get_device_path().to_path_buf(),
-                            alongside: None,
-                            method:    AlongsideMethod::Free(Region::new(
+                            device:        device.get_device_path().to_path_buf(),
+                            alongside:     None,
+                            method:        AlongsideMethod::Free(Region::new(
                                 last_end_sector + 1,
                                 part.start_sector - 1,
                             )),
+                            windows_state: None,
                         })
                     }
 
@@ -158,12 +193,13 @@ impl InstallOptions {
                         last_sector
                     );
                     alongside_options.push(AlongsideOption {
-                        device:    device.get_device_path().to_path_buf(),
-                        alongside: None,
-                        method:    AlongsideMethod::Free(Region::new(
+                        device:        device.get_device_path().to_path_buf(),
+                        alongside:     None,
+                        method:        AlongsideMethod::Free(Region::new(
                             last_end_sector + 1,
                             last_sector,
                         )),
+                        windows_state: None,
                     })
                 }
 
@@ -214,6 +250,32 @@ impl InstallOptions {
     }
 }
 
+/// Collects the subvolumes of the root partition from the fstab of an existing install.
+///
+/// Subvolumes are only considered if the root itself is mounted from a subvolume.
+fn root_subvolumes(
+    root: &PartitionID,
+    partitions: &[PartitionID],
+    targets: &[PathBuf],
+    subvolumes: &[Option<String>],
+) -> Vec<Subvolume> {
+    let subvolumes = partitions
+        .iter()
+        .zip(targets)
+        .zip(subvolumes)
+        .filter(|((partition, _), _)| *partition == root)
+        .filter_map(|((_, target), subvolume)| {
+            subvolume.as_ref().map(|name| Subvolume::new(name.as_str(), target.clone()))
+        })
+        .collect::<Vec<_>>();
+
+    if subvolumes.iter().any(|subvolume| subvolume.is_mounted_at(Path::new("/"))) {
+        subvolumes
+    } else {
+        Vec::new()
+    }
+}
+
 #[derive(Debug, Fail)]
 pub enum InstallOptionError {
     #[fail(display = "partition ID ({:?}) was not found", id)]
@@ -240,6 +302,8 @@ pub enum InstallOptionError {
     ProcMounts { why: io::Error },
     #[fail(display = "could not remount /cdrom as rewriteable: {}", _0)]
     RemountCdrom(io::Error),
+    #[fail(display = "refusing to shrink the Windows partition at {:?}: {}", path, state)]
+    UnsafeWindowsShrink { path: PathBuf, state: os_detect::WindowsState },
 }
 
 impl From<DiskError> for InstallOptionError {
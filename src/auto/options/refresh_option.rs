@@ -1,3 +1,4 @@
+use crate::disks::Subvolume;
 use os_release::OsRelease;
 use partition_identity::PartitionID;
 use std::fmt;
@@ -9,6 +10,8 @@ pub struct RefreshOption {
     pub home_part:      Option<PartitionID>,
     pub efi_part:       Option<PartitionID>,
     pub recovery_part:  Option<PartitionID>,
+    /// Btrfs subvolumes of the root partition, if the root is mounted from a subvolume.
+    pub subvolumes:     Vec<Subvolume>,
     pub can_retain_old: bool,
 }
 
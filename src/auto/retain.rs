@@ -4,7 +4,7 @@ use crate::bootloader::Bootloader;
 use disk_types::FileSystem;
 use crate::disks::Disks;
 
-use super::{mount_and_then, AccountFiles, ReinstallError, UserData};
+use super::{mount_and_then, probe_and_then, AccountFiles, ReinstallError, UserData};
 
 use crate::misc;
 use std::{
@@ -19,9 +19,13 @@ use std::{
 };
 
 /// Removes all files in the chroot at `/`, except for `/home`.
-pub fn remove_root(root_path: &Path, root_fs: FileSystem) -> Result<(), ReinstallError> {
+pub fn remove_root(
+    root_path: &Path,
+    root_fs: FileSystem,
+    subvolume: Option<&str>,
+) -> Result<(), ReinstallError> {
     info!("removing all files except /home. This may take a while...");
-    mount_and_then(root_path, root_fs, |base| {
+    mount_and_then(root_path, root_fs, subvolume, |base| {
         read_and_exclude(base, &[OsStr::new("home")], |entry| {
             if entry.is_dir() {
                 fs::remove_dir_all(entry)?;
@@ -35,8 +39,12 @@ pub fn remove_root(root_path: &Path, root_fs: FileSystem) -> Result<(), Reinstal
 }
 
 /// Migrate the original system to the `/linux.old/` directory, excluding `/home`.
-pub fn move_root(root_path: &Path, root_fs: FileSystem) -> Result<(), ReinstallError> {
-    mount_and_then(root_path, root_fs, |base| {
+pub fn move_root(
+    root_path: &Path,
+    root_fs: FileSystem,
+    subvolume: Option<&str>,
+) -> Result<(), ReinstallError> {
+    mount_and_then(root_path, root_fs, subvolume, |base| {
         let old_root = base.join("linux.old");
 
         // Remove an old, old root if it already exists.
@@ -59,9 +67,13 @@ pub fn move_root(root_path: &Path, root_fs: FileSystem) -> Result<(), ReinstallE
 }
 
 /// If a refresh install fails, this can be used to restore the original system.
-pub fn recover_root(root_path: &Path, root_fs: FileSystem) -> Result<(), ReinstallError> {
+pub fn recover_root(
+    root_path: &Path,
+    root_fs: FileSystem,
+    subvolume: Option<&str>,
+) -> Result<(), ReinstallError> {
     info!("attempting to restore the original system");
-    mount_and_then(root_path, root_fs, |base| {
+    mount_and_then(root_path, root_fs, subvolume, |base| {
         // Remove files installed by the installer.
         read_and_exclude(base, &[OsStr::new("home"), OsStr::new("linux.old")], |entry| {
             if entry.is_dir() {
@@ -84,9 +96,13 @@ pub fn recover_root(root_path: &Path, root_fs: FileSystem) -> Result<(), Reinsta
 }
 
 /// Delete the /linux.old directory withint the given device.
-pub fn delete_old_install(root_path: &Path, root_fs: FileSystem) -> Result<(), ReinstallError> {
+pub fn delete_old_install(
+    root_path: &Path,
+    root_fs: FileSystem,
+    subvolume: Option<&str>,
+) -> Result<(), ReinstallError> {
     info!("removing the /linux.old directory at {:?}. This may take a while...", root_path);
-    mount_and_then(root_path, root_fs, |base| {
+    mount_and_then(root_path, root_fs, subvolume, |base| {
         let old_root = base.join("linux.old");
 
         // Remove an old, old root if it already exists.
@@ -157,10 +173,11 @@ impl<'a> Backup<'a> {
     pub fn new(
         device: &Path,
         fs: FileSystem,
+        subvolume: Option<&str>,
         is_root: bool,
         account_files: &'a AccountFiles,
     ) -> Result<Backup<'a>, ReinstallError> {
-        mount_and_then(device, fs, |base| {
+        probe_and_then(device, fs, subvolume, |base| {
             info!("collecting list of user accounts");
             let dir = if is_root { base.join("home").read_dir() } else { base.read_dir() };
 
@@ -201,9 +218,14 @@ impl<'a> Backup<'a> {
     }
 
     /// Restores the backup to the given device. The device will be opened using the specified file
-    /// system.
-    pub fn restore(&self, device: &Path, fs: FileSystem) -> Result<(), ReinstallError> {
-        mount_and_then(device, fs, |base| {
+    /// system, and subvolume if the root is a btrfs subvolume.
+    pub fn restore(
+        &self,
+        device: &Path,
+        fs: FileSystem,
+        subvolume: Option<&str>,
+    ) -> Result<(), ReinstallError> {
+        mount_and_then(device, fs, subvolume, |base| {
             info!("appending user account data to new install");
             let (passwd, group, shadow, gshadow) = (
                 base.join("etc/passwd"),
@@ -1,11 +1,11 @@
 use crate::bootloader::Bootloader;
 use crate::chroot::Chroot;
+use crate::executor::{self, Invocation};
 use crate::installer::{bitflags::FileSystemSupport, traits::InstallerDiskOps};
 use os_release::OsRelease;
 use std::{
     collections::HashSet,
     io::{self, BufRead},
-    process::Command,
 };
 
 pub fn check_language_support(lang: &str, chroot: &Chroot) -> io::Result<Option<String>> {
@@ -67,7 +67,7 @@ pub fn get_dependencies_from_list<P: AsRef<str>>(deps: &[P]) -> Option<Vec<Strin
 }
 
 fn get_dependencies_from_package<A: FnMut(&str), P: AsRef<str>>(dep: P, mut action: A) {
-    let output = Command::new("apt-cache").args(&["show", dep.as_ref()]).output().ok();
+    let output = executor::output(&Invocation::new("apt-cache").args(&["show", dep.as_ref()])).ok();
 
     if let Some(output) = output {
         for line in io::Cursor::new(output.stdout).lines() {
@@ -167,11 +167,8 @@ pub fn get_required_packages<D: InstallerDiskOps>(
 
     if flags.contains(FileSystemSupport::LUKS) {
         retain.extend_from_slice(&["cryptsetup", "cryptsetup-bin"]);
-        match (release.id.as_str(), release.version.as_str()) {
-            ("ubuntu", "18.10") => {
-                retain.extend_from_slice(&["cryptsetup-initramfs", "cryptsetup-run"])
-            }
-            _ => (),
+        if let ("ubuntu", "18.10") = (release.id.as_str(), release.version.as_str()) {
+            retain.extend_from_slice(&["cryptsetup-initramfs", "cryptsetup-run"]);
         }
     }
 
@@ -179,5 +176,9 @@ pub fn get_required_packages<D: InstallerDiskOps>(
         retain.extend_from_slice(&["lvm2", "dmeventd", "dmraid", "kpartx", "kpartx-boot"]);
     }
 
+    if flags.contains(FileSystemSupport::RAID) {
+        retain.push("mdadm");
+    }
+
     retain
 }
@@ -1,6 +1,314 @@
-use crate::disks::DiskError;
+//! The typed errors of an install, and the codes which identify them.
+//!
+//! Installer steps return `io::Result`, so a typed error is carried within an `io::Error` as an
+//! `InstallError`. `ErrorCode::of` recovers the code of an error, which is reported through
+//! `installer::Error`, the FFI, and the exit status of the CLI.
+
+use crate::auto::{InstallOptionError, ReinstallError};
+use crate::disks::{DecryptionError, DiskError, PartitionError};
+use crate::installer::Step;
+use crate::upgrade::UpgradeError;
+use failure::{Compat, Fail};
 use std::{error::Error, fmt::Display, io};
 
+/// Identifies the cause of an install failure, so that a frontend may suggest a remedy
+/// without parsing error messages.
+///
+/// The numeric values are stable, and are used as the exit status of the CLI.
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorCode {
+    /// The cause of the error is not known.
+    Unknown           = 1,
+    /// The configuration of the install is invalid.
+    InvalidConfig     = 2,
+    InvalidHostname   = 3,
+    /// The install was cancelled.
+    Interrupted       = 4,
+    PermissionDenied  = 5,
+    NotFound          = 6,
+    /// The install journal is missing, invalid, or describes an install which cannot resume.
+    ResumeUnavailable = 7,
+    DiskProbe         = 10,
+    /// The disks configuration contains partitions which cannot be applied.
+    InvalidLayout     = 11,
+    /// The partitions on a disk changed after the disks were probed.
+    LayoutChanged     = 12,
+    DeviceNotFound    = 13,
+    /// Writing the partition table, or creating, moving, or resizing a partition failed.
+    PartitionTable    = 14,
+    Format            = 15,
+    Encryption        = 16,
+    Lvm               = 17,
+    Mount             = 18,
+    SwapFile          = 19,
+    ExternalCommand   = 20,
+    /// Creating a software RAID array failed.
+    Raid              = 21,
+    /// A Windows partition is hibernated, was shut down with Fast Startup, or is encrypted
+    /// with BitLocker, and would be damaged by shrinking it.
+    UnsafeShrink      = 22,
+    /// The image to install does not exist.
+    ImageNotFound     = 30,
+    Extract           = 31,
+    Configure         = 32,
+    Bootloader        = 33,
+    /// The image failed verification, and may be corrupted.
+    ImageCorrupt      = 34,
+    /// Home could not be retained from the previous install.
+    RetainHome        = 40,
+    RecoveryOption    = 41,
+    Upgrade           = 42,
+}
+
+impl ErrorCode {
+    /// The machine-readable name of this code, such as `invalid-hostname`.
+    pub fn as_str(self) -> &'static str {
+        use self::ErrorCode::*;
+        match self {
+            Unknown => "unknown",
+            InvalidConfig => "invalid-config",
+            InvalidHostname => "invalid-hostname",
+            Interrupted => "interrupted",
+            PermissionDenied => "permission-denied",
+            NotFound => "not-found",
+            ResumeUnavailable => "resume-unavailable",
+            DiskProbe => "disk-probe",
+            InvalidLayout => "invalid-layout",
+            LayoutChanged => "layout-changed",
+            DeviceNotFound => "device-not-found",
+            PartitionTable => "partition-table",
+            Format => "format",
+            Encryption => "encryption",
+            Lvm => "lvm",
+            Mount => "mount",
+            SwapFile => "swap-file",
+            ExternalCommand => "external-command",
+            Raid => "raid",
+            UnsafeShrink => "unsafe-shrink",
+            ImageNotFound => "image-not-found",
+            Extract => "extract",
+            Configure => "configure",
+            Bootloader => "bootloader",
+            ImageCorrupt => "image-corrupt",
+            RetainHome => "retain-home",
+            RecoveryOption => "recovery-option",
+            Upgrade => "upgrade",
+        }
+    }
+
+    /// The exit status of the CLI when an install fails with this code.
+    pub fn exit_status(self) -> i32 { i32::from(self as u8) }
+
+    /// The code of an I/O error, which is the code of the typed error that it carries, or is
+    /// otherwise derived from its kind.
+    pub fn of(err: &io::Error) -> ErrorCode {
+        ErrorCode::carried_by(err).unwrap_or_else(|| match err.kind() {
+            io::ErrorKind::Interrupted => ErrorCode::Interrupted,
+            io::ErrorKind::PermissionDenied => ErrorCode::PermissionDenied,
+            io::ErrorKind::NotFound => ErrorCode::NotFound,
+            io::ErrorKind::InvalidInput => ErrorCode::InvalidConfig,
+            _ => ErrorCode::Unknown,
+        })
+    }
+
+    /// The code of an error which occurred in the given step, when its cause is not known.
+    pub fn for_step(step: Step) -> ErrorCode {
+        match step {
+            Step::Backup => ErrorCode::RetainHome,
+            Step::Init => ErrorCode::Unknown,
+            Step::Partition => ErrorCode::PartitionTable,
+            Step::Extract => ErrorCode::Extract,
+            Step::Configure => ErrorCode::Configure,
+            Step::Bootloader => ErrorCode::Bootloader,
+            Step::Verify => ErrorCode::ImageCorrupt,
+        }
+    }
+
+    fn carried_by(err: &io::Error) -> Option<ErrorCode> {
+        let inner = err.get_ref()?;
+        inner
+            .downcast_ref::<Compat<InstallError>>()
+            .map(|err| err.get_ref().code)
+            .or_else(|| {
+                inner.downcast_ref::<Compat<DiskError>>().map(|err| ErrorCode::from(err.get_ref()))
+            })
+    }
+}
+
+impl<'a> From<&'a DiskError> for ErrorCode {
+    fn from(err: &'a DiskError) -> ErrorCode {
+        match err {
+            DiskError::Decryption { why } => why.into(),
+            DiskError::DeviceProbe => ErrorCode::DiskProbe,
+            DiskError::DeviceGet { .. }
+            | DiskError::DiskGet { .. }
+            | DiskError::DiskNew { .. }
+            | DiskError::InvalidSerial
+            | DiskError::LogicalPartitionNotFound { .. }
+            | DiskError::NewPartNotFound
+            | DiskError::PartitionNotFound { .. }
+            | DiskError::SerialGet { .. } => ErrorCode::DeviceNotFound,
+            DiskError::DiskCommit { .. }
+            | DiskError::DiskFresh { .. }
+            | DiskError::DiskSync { .. }
+            | DiskError::GeometryCreate { .. }
+            | DiskError::GeometryDuplicate
+            | DiskError::GeometrySet
+            | DiskError::PartitionRemove { .. }
+            | DiskError::PartitionRemoveBySector { .. }
+            | DiskError::PartitionTable { .. }
+            | DiskError::TableBackup { .. }
+            | DiskError::TableBackupFile { .. }
+            | DiskError::TableRestore { .. }
+            | DiskError::TableRestoreDuringMove { .. } => ErrorCode::PartitionTable,
+            DiskError::Encryption { .. } | DiskError::EncryptionOpen { .. } => {
+                ErrorCode::Encryption
+            }
+            DiskError::ExternalCommand { .. } => ErrorCode::ExternalCommand,
+            DiskError::IO { why } => ErrorCode::of(why),
+            DiskError::KeyContainsRoot
+            | DiskError::KeyPathAlreadySet { .. }
+            | DiskError::KeyWithoutPath
+            | DiskError::KeyFileWithoutPath
+            | DiskError::LuksOptions { .. }
+            | DiskError::PartitionOOB
+            | DiskError::PlanEmptyKey { .. }
+            | DiskError::PlanInvalidFileSystem { .. }
+            | DiskError::PlanInvalidFlag { .. }
+            | DiskError::PlanInvalidSector { .. }
+            | DiskError::PlanRaidNotFound { .. }
+            | DiskError::PlanVolumeGroupNotFound { .. }
+            | DiskError::RaidMembers { .. }
+            | DiskError::SameGroup
+            | DiskError::SectorOverlaps { .. }
+            | DiskError::VolumeGroupWithoutDevices { .. }
+            | DiskError::VolumePartitionLacksLabel { .. } => ErrorCode::InvalidLayout,
+            DiskError::LayoutChanged => ErrorCode::LayoutChanged,
+            DiskError::LogicalVolumeCreate { .. }
+            | DiskError::PhysicalVolumeCreate { .. }
+            | DiskError::VolumeGroupCreate { .. } => ErrorCode::Lvm,
+            DiskError::MountsObtain { .. }
+            | DiskError::SubvolumeMount { .. }
+            | DiskError::Unmount { .. } => ErrorCode::Mount,
+            DiskError::PartitionError { why, .. } => why.into(),
+            DiskError::RaidCreate { .. } => ErrorCode::Raid,
+            DiskError::SubvolumeCreate { .. } => ErrorCode::Format,
+            DiskError::SwapFile { .. } => ErrorCode::SwapFile,
+        }
+    }
+}
+
+impl<'a> From<&'a PartitionError> for ErrorCode {
+    fn from(err: &'a PartitionError) -> ErrorCode {
+        use crate::disks::PartitionError::*;
+        match err {
+            NoFilesystem | PartitionFormat { .. } => ErrorCode::Format,
+            PartitionCreate { .. } | PartitionMove { .. } | PartitionResize { .. } => {
+                ErrorCode::PartitionTable
+            }
+            PartitionOverlaps
+            | PartitionTooLarge { .. }
+            | PartitionTooSmall { .. }
+            | ResizeTooSmall
+            | ShrinkValueTooHigh
+            | UnsupportedShrinking { .. } => ErrorCode::InvalidLayout,
+        }
+    }
+}
+
+impl<'a> From<&'a DecryptionError> for ErrorCode {
+    fn from(err: &'a DecryptionError) -> ErrorCode {
+        match err {
+            DecryptionError::LuksNotFound { .. } => ErrorCode::DeviceNotFound,
+            _ => ErrorCode::Encryption,
+        }
+    }
+}
+
+impl<'a> From<&'a ReinstallError> for ErrorCode {
+    fn from(err: &'a ReinstallError) -> ErrorCode {
+        match err {
+            ReinstallError::DiskProbe { why } => why.into(),
+            ReinstallError::MissingSquashfs { .. } => ErrorCode::ImageNotFound,
+            ReinstallError::Install { why } => ErrorCode::of(why),
+            _ => ErrorCode::RetainHome,
+        }
+    }
+}
+
+impl<'a> From<&'a InstallOptionError> for ErrorCode {
+    fn from(err: &'a InstallOptionError) -> ErrorCode {
+        match err {
+            InstallOptionError::DiskError { why } => why.into(),
+            InstallOptionError::PartitionError { why } => why.into(),
+            InstallOptionError::UnsafeWindowsShrink { .. } => ErrorCode::UnsafeShrink,
+            _ => ErrorCode::RecoveryOption,
+        }
+    }
+}
+
+impl<'a> From<&'a UpgradeError> for ErrorCode {
+    fn from(err: &'a UpgradeError) -> ErrorCode {
+        match err {
+            UpgradeError::Configure(why) => why.into(),
+            _ => ErrorCode::Upgrade,
+        }
+    }
+}
+
+/// An install error, which pairs the code that identifies its cause with a description.
+#[derive(Debug, Fail)]
+#[fail(display = "{}", message)]
+pub struct InstallError {
+    pub code:    ErrorCode,
+    pub message: String,
+}
+
+impl InstallError {
+    pub fn new<S: Into<String>>(code: ErrorCode, message: S) -> Self {
+        InstallError { code, message: message.into() }
+    }
+
+    /// Converts this into an I/O error of the given kind, which carries this error.
+    pub fn into_io_error(self, kind: io::ErrorKind) -> io::Error {
+        io::Error::new(kind, self.compat())
+    }
+}
+
+impl From<InstallError> for io::Error {
+    fn from(err: InstallError) -> io::Error {
+        let kind = match err.code {
+            ErrorCode::Interrupted => io::ErrorKind::Interrupted,
+            ErrorCode::PermissionDenied => io::ErrorKind::PermissionDenied,
+            ErrorCode::NotFound | ErrorCode::DeviceNotFound | ErrorCode::ImageNotFound => {
+                io::ErrorKind::NotFound
+            }
+            ErrorCode::InvalidConfig | ErrorCode::InvalidHostname | ErrorCode::InvalidLayout => {
+                io::ErrorKind::InvalidInput
+            }
+            _ => io::ErrorKind::Other,
+        };
+
+        err.into_io_error(kind)
+    }
+}
+
+macro_rules! coded_error {
+    ($($error:ty),+) => {
+        $(
+            impl From<$error> for InstallError {
+                fn from(err: $error) -> InstallError {
+                    InstallError::new(ErrorCode::from(&err), err.to_string())
+                }
+            }
+        )+
+    };
+}
+
+coded_error!(DiskError, ReinstallError, InstallOptionError, UpgradeError);
+
 /// Extends `Option<T>` to be converted into an `io::Result<T>`.
 pub trait IntoIoResult<T> {
     fn into_io_result<E, F>(self, error: F) -> io::Result<T>
@@ -20,20 +328,31 @@ impl<T> IntoIoResult<T> for Option<T> {
 }
 
 /// Extends `io::Result<T>` to enable supplying additional context to an I/O error.
+///
+/// The code of an error which carries a typed error is retained.
 pub trait IoContext<T> {
     fn with_context<F: FnMut(Box<dyn Display>) -> String>(self, func: F) -> io::Result<T>;
 }
 
 impl<T> IoContext<T> for io::Result<T> {
     fn with_context<F: FnMut(Box<dyn Display>) -> String>(self, mut func: F) -> io::Result<T> {
-        self.map_err(|why| io::Error::new(why.kind(), func(Box::new(why))))
+        self.map_err(|why| match ErrorCode::carried_by(&why) {
+            Some(code) => {
+                let kind = why.kind();
+                InstallError::new(code, func(Box::new(why))).into_io_error(kind)
+            }
+            None => io::Error::new(why.kind(), func(Box::new(why))),
+        })
     }
 }
 
 // NOTE: This can be removed once RFC #1210 is implemented.
 impl<T> IoContext<T> for Result<T, DiskError> {
     fn with_context<F: FnMut(Box<dyn Display>) -> String>(self, mut func: F) -> io::Result<T> {
-        self.map_err(|why| io::Error::new(io::ErrorKind::Other, func(Box::new(why))))
+        self.map_err(|why| {
+            let code = ErrorCode::from(&why);
+            InstallError::new(code, func(Box::new(why))).into_io_error(io::ErrorKind::Other)
+        })
     }
 }
 
@@ -43,3 +362,51 @@ impl<T> IoContext<T> for Result<T, DiskError> {
 //         self.map_err(|why| io::Error::new(io::ErrorKind::Other, func(Box::new(why))))
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn error_codes() {
+        let err: Result<(), DiskError> = Err(DiskError::LayoutChanged);
+        let err = err
+            .with_context(|why| format!("partitioning: {}", why))
+            .with_context(|why| format!("install: {}", why))
+            .unwrap_err();
+        assert_eq!(ErrorCode::of(&err), ErrorCode::LayoutChanged);
+        assert_eq!(err.to_string(), "install: partitioning: partition layout on disk has changed");
+
+        let err = io::Error::from(DiskError::SwapFile {
+            path: PathBuf::from("/swapfile"),
+            why:  io::Error::new(io::ErrorKind::Other, "no space"),
+        });
+        assert_eq!(ErrorCode::of(&err), ErrorCode::SwapFile);
+
+        let err = io::Error::from(ReinstallError::MissingSquashfs { path: "/cdrom".into() });
+        assert_eq!(ErrorCode::of(&err), ErrorCode::ImageNotFound);
+        assert_eq!(ErrorCode::of(&ReinstallError::ReformattingHome.into()), ErrorCode::RetainHome);
+
+        let err = InstallOptionError::UnsafeWindowsShrink {
+            path:  "/dev/sda3".into(),
+            state: os_detect::WindowsState { hibernated: true, ..Default::default() },
+        };
+        assert_eq!(ErrorCode::from(&err), ErrorCode::UnsafeShrink);
+
+        let err = io::Error::from(InstallError::new(ErrorCode::InvalidHostname, "invalid"));
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(ErrorCode::of(&err), ErrorCode::InvalidHostname);
+
+        let err = io::Error::new(io::ErrorKind::NotFound, "missing");
+        assert_eq!(ErrorCode::of(&err), ErrorCode::NotFound);
+        let err = io::Error::new(io::ErrorKind::Other, "other");
+        assert_eq!(ErrorCode::of(&err), ErrorCode::Unknown);
+
+        assert_eq!(ErrorCode::InvalidHostname.exit_status(), 3);
+        assert_eq!(
+            serde_json::to_string(&ErrorCode::InvalidHostname).unwrap(),
+            format!("\"{}\"", ErrorCode::InvalidHostname.as_str())
+        );
+    }
+}
@@ -1,5 +1,5 @@
 bitflags! {
-    pub struct FileSystemSupport: u8 {
+    pub struct FileSystemSupport: u16 {
         const LVM = 1;
         const LUKS = 2;
         const FAT = 4;
@@ -8,5 +8,6 @@ bitflags! {
         const BTRFS = 32;
         const NTFS = 64;
         const F2FS = 128;
+        const RAID = 256;
     }
 }
@@ -0,0 +1,255 @@
+//! Hooks which run site-specific logic before and after each step of an install.
+
+use super::Step;
+use crate::chroot::{Chroot, Command};
+use std::{
+    fs, io,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// The directory within the target that executable hooks are copied to, to run in the chroot.
+const CHROOT_HOOK_DIR: &str = "tmp/distinst-hooks";
+
+/// When a hook runs, relative to its step.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum HookTime {
+    Before,
+    After,
+}
+
+impl HookTime {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HookTime::Before => "before",
+            HookTime::After => "after",
+        }
+    }
+}
+
+/// The step and target that a hook is run for.
+#[derive(Debug)]
+pub struct HookContext<'a> {
+    pub step:   Step,
+    pub time:   HookTime,
+    /// The directory that the target is mounted at, once it has been mounted.
+    pub target: Option<&'a Path>,
+}
+
+enum Action {
+    Command(PathBuf),
+    Closure(Box<dyn FnMut(&HookContext) -> io::Result<()>>),
+}
+
+struct Hook {
+    step:   Step,
+    time:   HookTime,
+    action: Action,
+}
+
+/// A hook which is given on the command line as `<time>-<step>=<command>`, such as
+/// `after-configure=/usr/lib/site/configure.sh`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HookCommand {
+    pub step:    Step,
+    pub time:    HookTime,
+    pub command: PathBuf,
+}
+
+impl FromStr for HookCommand {
+    type Err = io::Error;
+
+    fn from_str(input: &str) -> io::Result<Self> {
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{:?} is not a hook of the form <before|after>-<step>=<command>", input),
+            )
+        };
+
+        let mut fields = input.splitn(2, '=');
+        let point = fields.next().ok_or_else(invalid)?;
+        let command = fields.next().filter(|command| !command.is_empty()).ok_or_else(invalid)?;
+
+        let (time, step) = if point.starts_with("before-") {
+            (HookTime::Before, &point[7..])
+        } else if point.starts_with("after-") {
+            (HookTime::After, &point[6..])
+        } else {
+            return Err(invalid());
+        };
+
+        let step = step.parse().map_err(|_| invalid())?;
+        Ok(HookCommand { step, time, command: command.into() })
+    }
+}
+
+/// The hooks which are registered on an installer.
+#[derive(Default)]
+pub(crate) struct Hooks(Vec<Hook>);
+
+impl Hooks {
+    pub fn add_command(&mut self, step: Step, time: HookTime, command: PathBuf) {
+        self.0.push(Hook { step, time, action: Action::Command(command) });
+    }
+
+    pub fn add_closure(
+        &mut self,
+        step: Step,
+        time: HookTime,
+        closure: Box<dyn FnMut(&HookContext) -> io::Result<()>>,
+    ) {
+        self.0.push(Hook { step, time, action: Action::Closure(closure) });
+    }
+
+    /// Runs the hooks of a step in the order that they were registered, stopping at the first
+    /// hook which fails.
+    pub fn run(&mut self, step: Step, time: HookTime, target: Option<&Path>) -> io::Result<()> {
+        let context = HookContext { step, time, target };
+
+        for hook in self.0.iter_mut().filter(|hook| hook.step == step && hook.time == time) {
+            let result = match hook.action {
+                Action::Closure(ref mut closure) => closure(&context),
+                // A hook which expects to modify the target is never run against the host.
+                Action::Command(ref command) if runs_in_chroot(step, time) => match target {
+                    Some(target) => run_in_chroot(command, target, &context),
+                    None => Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("{:?} cannot run, as the target is not mounted", command),
+                    )),
+                },
+                Action::Command(ref command) => run_on_host(command, &context),
+            };
+
+            result.map_err(|why| {
+                let (step, time) = (step.as_str(), time.as_str());
+                io::Error::new(why.kind(), format!("{} {} hook failed: {}", time, step, why))
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Hooks which run after the image has been extracted, and after it has been configured, run
+/// within the target.
+fn runs_in_chroot(step: Step, time: HookTime) -> bool {
+    time == HookTime::After && (step == Step::Extract || step == Step::Configure)
+}
+
+fn run_on_host(command: &Path, context: &HookContext) -> io::Result<()> {
+    info!("running {} {} hook {:?}", context.time.as_str(), context.step.as_str(), command);
+
+    let mut cmd = Command::new(command);
+    cmd.env("DISTINST_STEP", context.step.as_str());
+    cmd.env("DISTINST_HOOK", context.time.as_str());
+    if let Some(target) = context.target.and_then(Path::to_str) {
+        cmd.env("DISTINST_TARGET", target);
+    }
+
+    cmd.run()
+}
+
+/// Copies the hook into the target, and runs it with a clean environment.
+fn run_in_chroot(command: &Path, target: &Path, context: &HookContext) -> io::Result<()> {
+    info!(
+        "running {} {} hook {:?} in the target",
+        context.time.as_str(),
+        context.step.as_str(),
+        command
+    );
+
+    let name = command.file_name().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("{:?} is not a file", command))
+    })?;
+
+    let directory = target.join(CHROOT_HOOK_DIR);
+    let path = directory.join(name);
+    fs::create_dir_all(&directory)?;
+    fs::copy(command, &path)?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+
+    let result = chroot_command(target, &Path::new("/").join(CHROOT_HOOK_DIR).join(name), context);
+    let _ = fs::remove_dir_all(&directory);
+    result
+}
+
+fn chroot_command(target: &Path, command: &Path, context: &HookContext) -> io::Result<()> {
+    let mut chroot = cascade! {
+        Chroot::new(target)?;
+        ..clear_envs(true);
+        ..env("DEBIAN_FRONTEND", "noninteractive");
+        ..env("HOME", "/root");
+        ..env("PATH", "/usr/sbin:/usr/bin:/sbin:/bin");
+        ..env("DISTINST_STEP", context.step.as_str());
+        ..env("DISTINST_HOOK", context.time.as_str());
+        ..env("DISTINST_TARGET", "/");
+    };
+
+    let result = chroot.command(command, &[] as &[&str]).run();
+    chroot.unmount(false).and(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, rc::Rc};
+
+    #[test]
+    fn hook_commands() {
+        let hook = "after-configure=/usr/lib/site/hook.sh".parse::<HookCommand>().unwrap();
+        assert_eq!(hook, HookCommand {
+            step:    Step::Configure,
+            time:    HookTime::After,
+            command: "/usr/lib/site/hook.sh".into(),
+        });
+
+        assert!("during-configure=/hook.sh".parse::<HookCommand>().is_err());
+        assert!("before-unpack=/hook.sh".parse::<HookCommand>().is_err());
+        assert!("before-extract=".parse::<HookCommand>().is_err());
+    }
+
+    #[test]
+    fn closures() {
+        let ran = Rc::new(RefCell::new(Vec::new()));
+        let mut hooks = Hooks::default();
+
+        for &(step, time) in &[(Step::Extract, HookTime::After), (Step::Init, HookTime::Before)] {
+            let ran = ran.clone();
+            hooks.add_closure(
+                step,
+                time,
+                Box::new(move |context| {
+                    ran.borrow_mut().push((context.step, context.time));
+                    Ok(())
+                }),
+            );
+        }
+
+        hooks.add_closure(
+            Step::Bootloader,
+            HookTime::Before,
+            Box::new(|_| Err(io::Error::new(io::ErrorKind::Other, "no bootloader"))),
+        );
+
+        hooks.run(Step::Init, HookTime::Before, None).unwrap();
+        hooks.run(Step::Init, HookTime::After, None).unwrap();
+        assert_eq!(*ran.borrow(), vec![(Step::Init, HookTime::Before)]);
+
+        let error = hooks.run(Step::Bootloader, HookTime::Before, None).unwrap_err();
+        assert_eq!(error.to_string(), "before bootloader hook failed: no bootloader");
+    }
+
+    #[test]
+    fn chroot_hooks_require_a_target() {
+        let mut hooks = Hooks::default();
+        hooks.add_command(Step::Extract, HookTime::After, "/bin/true".into());
+
+        let error = hooks.run(Step::Extract, HookTime::After, None).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "after extract hook failed: \"/bin/true\" cannot run, as the target is not mounted"
+        );
+    }
+}
@@ -0,0 +1,126 @@
+//! A journal of the steps that an install has completed, which permits an install that failed
+//! after its image was extracted to be resumed with `Installer::resume`.
+
+use super::Step;
+use crate::disks::Disks;
+use crate::errors::IoContext;
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+};
+
+/// The location that the journal of the current install is written to.
+pub const JOURNAL_PATH: &str = "/tmp/distinst-journal.json";
+
+/// Records the progress of an install.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct InstallJournal {
+    /// Steps which have completed, in the order that they completed.
+    pub completed:   Vec<Step>,
+    /// The configuration of the disks after they were partitioned, with the device paths of
+    /// every new partition and logical volume assigned. Passwords are not retained.
+    pub disks:       Option<Disks>,
+    /// The image which was extracted to the target.
+    pub squashfs:    PathBuf,
+    /// Packages which will be removed from the install.
+    pub remove_pkgs: Vec<String>,
+    /// The UUID of the old root partition, if home is being retained.
+    pub old_root:    Option<String>,
+}
+
+impl InstallJournal {
+    /// Reads the journal that was written by a previous install.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let data = fs::read(path)
+            .with_context(|why| format!("failed to read journal at {:?}: {}", path, why))?;
+
+        serde_json::from_slice(&data).map_err(|why| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("journal at {:?} is invalid: {}", path, why),
+            )
+        })
+    }
+
+    /// Writes the journal, which only the owner may read.
+    ///
+    /// The journal is written to a temporary file that is then renamed, so that a partially
+    /// written journal is never read.
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let data = serde_json::to_vec_pretty(self)
+            .map_err(|why| io::Error::new(io::ErrorKind::InvalidData, why))?;
+
+        let temporary = path.with_extension("partial");
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&temporary)
+            .with_context(|why| format!("failed to create journal at {:?}: {}", temporary, why))?;
+
+        file.write_all(&data)
+            .and_then(|_| file.sync_all())
+            .and_then(|_| fs::rename(&temporary, path))
+            .with_context(|why| format!("failed to write journal to {:?}: {}", path, why))
+    }
+
+    /// Removes a journal, if it exists.
+    pub fn remove<P: AsRef<Path>>(path: P) -> io::Result<()> {
+        match fs::remove_file(path.as_ref()) {
+            Err(ref why) if why.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Records that the given step has completed.
+    pub fn complete(&mut self, step: Step) {
+        if !self.is_complete(step) {
+            self.completed.push(step);
+        }
+    }
+
+    pub fn is_complete(&self, step: Step) -> bool { self.completed.contains(&step) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use tempdir::TempDir;
+
+    #[test]
+    fn journal_round_trip() {
+        let dir = TempDir::new("distinst-journal").unwrap();
+        let path = dir.path().join("journal.json");
+
+        let mut journal = InstallJournal {
+            disks:       Some(Disks::default()),
+            squashfs:    "/cdrom/casper/filesystem.squashfs".into(),
+            remove_pkgs: vec!["distinst".into()],
+            ..InstallJournal::default()
+        };
+
+        journal.complete(Step::Init);
+        journal.complete(Step::Partition);
+        journal.complete(Step::Partition);
+        journal.write(&path).unwrap();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let loaded = InstallJournal::load(&path).unwrap();
+        assert_eq!(loaded.completed, vec![Step::Init, Step::Partition]);
+        assert!(loaded.is_complete(Step::Partition));
+        assert!(!loaded.is_complete(Step::Extract));
+        assert_eq!(loaded, journal);
+
+        InstallJournal::remove(&path).unwrap();
+        assert!(!path.exists());
+        InstallJournal::remove(&path).unwrap();
+    }
+}
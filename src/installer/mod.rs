@@ -2,31 +2,39 @@ pub mod bitflags;
 pub mod traits;
 
 mod conf;
+mod hooks;
+mod journal;
 mod state;
 
 pub(crate) mod steps;
 
-pub use self::{conf::RecoveryEnv, steps::Step};
+pub use self::{
+    conf::RecoveryEnv,
+    hooks::{HookCommand, HookContext, HookTime},
+    journal::JOURNAL_PATH,
+    steps::{ImageFormat, Step, TABLES_PATH},
+};
 
-use self::state::InstallerState;
+use self::{hooks::Hooks, journal::InstallJournal, state::InstallerState};
 
 use crate::auto::{
     delete_old_install, move_root, recover_root, remove_root, validate_backup_conditions,
     AccountFiles, Backup, ReinstallError,
 };
 use disk_types::BlockDeviceExt;
-use crate::disks::{Bootloader, Disks};
-use crate::errors::IoContext;
+use crate::disks::{Bootloader, Disks, DisksReport, PartitionInfo};
+use crate::errors::{ErrorCode, InstallError, IoContext};
+use crate::executor::{self, Cancellation};
 use crate::external::luks::deactivate_logical_devices;
 use crate::hostname;
 use os_release::OsRelease;
 use partition_identity::PartitionID;
-use crate::squashfs;
 use std::{
     io,
     path::{Path, PathBuf},
     sync::atomic::Ordering,
 };
+use sys_mount::Mounts;
 use tempdir::TempDir;
 use crate::timezones::Region;
 use crate::PARTITIONING_TEST;
@@ -35,6 +43,7 @@ pub const MODIFY_BOOT_ORDER: u8 = 0b01;
 pub const INSTALL_HARDWARE_SUPPORT: u8 = 0b10;
 pub const KEEP_OLD_ROOT: u8 = 0b100;
 pub const RUN_UBUNTU_DRIVERS: u8 = 0b1000;
+pub const VERIFY_IMAGE: u8 = 0b10000;
 
 macro_rules! percent {
     ($steps:expr) => {
@@ -62,8 +71,18 @@ pub struct Config {
     pub lang:             String,
     /// The file that contains a list of packages to remove.
     pub remove:           String,
-    /// The archive (`tar` or `squashfs`) which contains the base system.
+    /// The image which contains the base system, which is a squashfs image, a tar archive, a
+    /// directory, an OCI image layout, or a raw file system image.
     pub squashfs:         String,
+    /// The format of the image, which is detected from its contents if it is `Auto`.
+    pub image_format:     ImageFormat,
+    /// The SHA-256 digest that the archive is verified against before any disk is modified.
+    /// If it is not set, and `VERIFY_IMAGE` is set, the digest is read from `/cdrom/md5sum.txt`,
+    /// and the install fails if the image is not listed in it.
+    pub squashfs_sha256:  Option<String>,
+    /// Where the journals of partition moves are kept, which must be on persistent storage
+    /// that is not being partitioned. Moves cannot be resumed if they are not journaled.
+    pub move_journal:     Option<PathBuf>,
     /// Some flags to control the behavior of the installation.
     pub flags:            u8,
 }
@@ -84,6 +103,11 @@ pub struct Error {
     pub err:  io::Error,
 }
 
+impl Error {
+    /// The code which identifies the cause of this error.
+    pub fn code(&self) -> ErrorCode { ErrorCode::of(&self.err) }
+}
+
 /// Installer status
 #[derive(Copy, Clone, Debug)]
 pub struct Status {
@@ -91,12 +115,27 @@ pub struct Status {
     pub percent: i32,
 }
 
+/// Describes what an install would do, as generated by `Installer::dry_run`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct InstallReport {
+    /// Either `bios` or `efi`.
+    pub bootloader: String,
+    /// The canonicalized path of the image which would be extracted.
+    pub squashfs:   PathBuf,
+    /// The UUID of the old root partition, if home will be retained.
+    pub old_root:   Option<String>,
+    /// The ordered disk operations which would be performed.
+    pub disks:      DisksReport,
+}
+
 /// An installer object
 pub struct Installer {
     error_cb:         Option<Box<dyn FnMut(&Error)>>,
     status_cb:        Option<Box<dyn FnMut(&Status)>>,
     timezone_cb:      Option<Box<dyn FnMut() -> Region>>,
     user_creation_cb: Option<Box<dyn FnMut() -> UserAccountCreate>>,
+    hooks:            Hooks,
+    cancellation:     Cancellation,
 }
 
 impl Default for Installer {
@@ -112,6 +151,8 @@ impl Default for Installer {
             status_cb:        None,
             timezone_cb:      None,
             user_creation_cb: None,
+            hooks:            Hooks::default(),
+            cancellation:     Cancellation::new(),
         }
     }
 }
@@ -138,7 +179,33 @@ impl Installer {
     /// will be applied when configuring the new installation.
     ///
     /// If `config.old_root` is set, then home at that location will be retained.
-    pub fn install(&mut self, mut disks: Disks, config: &Config) -> io::Result<()> {
+    ///
+    /// Each completed step is recorded in a journal at `JOURNAL_PATH`, which is removed once
+    /// the install succeeds. Should a later step fail, the install may be resumed from the
+    /// journal with `Installer::resume`.
+    ///
+    /// Before the disks are partitioned, their partition tables are backed up to
+    /// `TABLES_PATH`, so that they may be restored with `Disks::restore_tables` should the
+    /// install fail. The backups are removed once the install succeeds.
+    ///
+    /// The install may be cancelled from another thread with the token of `cancellation`,
+    /// which kills the command that is running, and stops the extraction of the image. The
+    /// target is then unmounted, and its logical volumes and encrypted partitions are closed.
+    pub fn install(&mut self, disks: Disks, config: &Config) -> io::Result<()> {
+        let cancellation = self.cancellation.clone();
+        let result = executor::with_cancellation(cancellation.clone(), || {
+            self.run_install(disks, config, &cancellation)
+        });
+
+        rollback(&cancellation, result)
+    }
+
+    fn run_install(
+        &mut self,
+        mut disks: Disks,
+        config: &Config,
+        cancellation: &Cancellation,
+    ) -> io::Result<()> {
         let mut recovery_conf = if Path::new("/cdrom/recovery.conf").exists() {
             Some(RecoveryEnv::new()?)
         } else {
@@ -146,12 +213,17 @@ impl Installer {
         };
 
         disks.remove_untouched_disks();
+        InstallJournal::remove(JOURNAL_PATH)
+            .with_context(|err| format!("removing previous journal: {}", err))?;
+
+        let image = Path::new(&config.squashfs);
+        steps::validate_image(image, config.image_format, &disks, config.old_root.as_deref())?;
+
         let steps = &mut InstallerState::new(self);
+        steps.journal.old_root = config.old_root.clone();
 
         Self::backup(disks, config, steps, |mut disks, config, steps| {
-            if !hostname::is_valid(&config.hostname) {
-                return Err(io::Error::new(io::ErrorKind::InvalidInput, "hostname is not valid"));
-            }
+            validate_hostname(&config.hostname)?;
 
             let bootloader = Bootloader::detect();
             disks
@@ -159,11 +231,23 @@ impl Installer {
                 .with_context(|err| format!("partition validation: {}", err))?;
 
             let (squashfs, remove_pkgs) = steps.apply(Step::Init, "initializing", |steps| {
-                Installer::initialize(&mut disks, config, percent!(steps))
+                let (squashfs, remove_pkgs) =
+                    Installer::initialize(&mut disks, config, percent!(steps))?;
+                steps.journal.squashfs = squashfs.clone();
+                steps.journal.remove_pkgs = remove_pkgs.clone();
+                Ok((squashfs, remove_pkgs))
             })?;
 
+            if config.flags & VERIFY_IMAGE != 0 || config.squashfs_sha256.is_some() {
+                steps.apply(Step::Verify, "verifying image", |steps| {
+                    Installer::verify(&squashfs, config, cancellation, percent!(steps))
+                })?;
+            }
+
             steps.apply(Step::Partition, "partitioning", |steps| {
-                Installer::partition(&mut disks, percent!(steps))
+                Installer::partition(&mut disks, config, percent!(steps))?;
+                steps.journal.disks = Some(disks.clone());
+                Ok(())
             })?;
 
             // Mount the temporary directory, and all of our mount targets.
@@ -172,61 +256,250 @@ impl Installer {
             let mount_dir = TempDir::new(Self::CHROOT_ROOT)
                 .with_context(|err| format!("chroot root temp mount: {}", err))?;
 
-            info!("mounting all targets to the temporary chroot");
-
-            let mut mounts = disks
-                .mount_all_targets(mount_dir.path())
-                .with_context(|err| format!("mounting all targets: {}", err))?;
+            // Raw ext images are copied onto the root partition before it is mounted. The targets
+            // are mounted within the step, so that its after hooks run against the new install
+            // rather than the host, as they do for every other format.
+            let block_copied = steps::is_block_copied(&squashfs, config.image_format)?;
+            let mounts = if block_copied && !PARTITIONING_TEST.load(Ordering::SeqCst) {
+                steps.apply(Step::Extract, "copying image", |steps| {
+                    steps::copy_raw_image(&squashfs, &disks, cancellation, percent!(steps))?;
+                    Installer::mount_targets(&disks, mount_dir.path(), steps)
+                })?
+            } else {
+                Installer::mount_targets(&disks, mount_dir.path(), steps)?
+            };
 
             if PARTITIONING_TEST.load(Ordering::SeqCst) {
                 info!("PARTITION_TEST enabled: exiting before unsquashing");
                 return Ok(());
             }
 
-            let iso_os_release = steps.apply(Step::Extract, "extracting", |steps| {
-                Installer::extract(squashfs.as_path(), mount_dir.path(), percent!(steps))
+            let iso_os_release = if block_copied {
+                Installer::os_release(mount_dir.path())?
+            } else {
+                steps.apply(Step::Extract, "extracting", |steps| {
+                    let mount_dir = mount_dir.path();
+                    Installer::extract(&squashfs, config, mount_dir, cancellation, percent!(steps))
+                })?
+            };
+
+            Self::finish(
+                steps,
+                recovery_conf.as_mut(),
+                &disks,
+                mount_dir,
+                mounts,
+                bootloader,
+                config,
+                &iso_os_release,
+                &remove_pkgs,
+            )
+        })?;
+
+        let _ = deactivate_logical_devices();
+
+        if let Some(conf) = recovery_conf.as_mut() {
+            conf.remove("MODE");
+            conf.write()?;
+        }
+
+        steps::remove_table_backups()
+            .with_context(|err| format!("removing partition table backups: {}", err))?;
+
+        InstallJournal::remove(JOURNAL_PATH)
+            .with_context(|err| format!("removing journal: {}", err))
+    }
+
+    /// Resumes an install which failed after its image was extracted, by running only the
+    /// configure and bootloader steps that have yet to complete.
+    ///
+    /// The disks configuration and the extracted image are read from the journal that
+    /// `install` wrote, so the disks are not partitioned again, and the image is not
+    /// extracted again. The target must still be intact: every device that it is mounted
+    /// from must exist, so encrypted volumes must remain unlocked. Installs which retained
+    /// home cannot be resumed, as the backup of the old install is not journaled.
+    ///
+    /// Like `install`, the resumed install may be cancelled with the token of `cancellation`.
+    pub fn resume(&mut self, config: &Config) -> io::Result<()> {
+        let cancellation = self.cancellation.clone();
+        let result =
+            executor::with_cancellation(cancellation.clone(), || self.run_resume(config));
+
+        rollback(&cancellation, result)
+    }
+
+    fn run_resume(&mut self, config: &Config) -> io::Result<()> {
+        let journal = InstallJournal::load(JOURNAL_PATH).map_err(|why| {
+            io::Error::from(InstallError::new(ErrorCode::ResumeUnavailable, why.to_string()))
+        })?;
+
+        if !journal.is_complete(Step::Extract) {
+            return Err(InstallError::new(
+                ErrorCode::ResumeUnavailable,
+                "install cannot be resumed before its image has been extracted",
+            )
+            .into());
+        }
+
+        if journal.old_root.is_some() || config.old_root.is_some() {
+            return Err(InstallError::new(
+                ErrorCode::ResumeUnavailable,
+                "install which retains home cannot be resumed",
+            )
+            .into());
+        }
+
+        validate_hostname(&config.hostname)?;
+
+        let mut disks = journal.disks.clone().ok_or_else(|| {
+            InstallError::new(ErrorCode::ResumeUnavailable, "journal does not contain the disks")
+        })?;
+        disks.reload_identifiers();
+
+        let missing = disks
+            .get_partitions()
+            .find(|part| part.target.is_some() && !part.get_device_path().exists());
+
+        if let Some(part) = missing {
+            return Err(InstallError::new(
+                ErrorCode::ResumeUnavailable,
+                format!("target is no longer intact: {:?} does not exist", part.get_device_path()),
+            )
+            .into());
+        }
+
+        let mut recovery_conf = if Path::new("/cdrom/recovery.conf").exists() {
+            Some(RecoveryEnv::new()?)
+        } else {
+            None
+        };
+
+        let remove_pkgs = journal.remove_pkgs.clone();
+        let steps = &mut InstallerState::new(self);
+        steps.journal = journal;
+
+        info!("resuming install from {}", JOURNAL_PATH);
+
+        let mount_dir = TempDir::new(Self::CHROOT_ROOT)
+            .with_context(|err| format!("chroot root temp mount: {}", err))?;
+
+        let mounts = Installer::mount_targets(&disks, mount_dir.path(), steps)?;
+
+        let iso_os_release = OsRelease::new_from(&mount_dir.path().join("etc/os-release"))
+            .with_context(|why| {
+                format!("target is no longer intact: failed to parse /etc/os-release: {}", why)
             })?;
 
+        Self::finish(
+            steps,
+            recovery_conf.as_mut(),
+            &disks,
+            mount_dir,
+            mounts,
+            Bootloader::detect(),
+            config,
+            &iso_os_release,
+            &remove_pkgs,
+        )?;
+
+        let _ = deactivate_logical_devices();
+
+        if let Some(conf) = recovery_conf.as_mut() {
+            conf.remove("MODE");
+            conf.write()?;
+        }
+
+        steps::remove_table_backups()
+            .with_context(|err| format!("removing partition table backups: {}", err))?;
+
+        InstallJournal::remove(JOURNAL_PATH)
+            .with_context(|err| format!("removing journal: {}", err))
+    }
+
+    /// Validates the configuration and reports every disk operation and mount that `install`
+    /// would perform, without modifying any block devices.
+    pub fn dry_run(&self, disks: &Disks, config: &Config) -> io::Result<InstallReport> {
+        validate_hostname(&config.hostname)?;
+
+        let bootloader = Bootloader::detect();
+        disks
+            .verify_partitions(bootloader)
+            .with_context(|err| format!("partition validation: {}", err))?;
+
+        disks.verify_keyfile_paths().with_context(|err| format!("keyfile validation: {}", err))?;
+
+        let squashfs = Path::new(&config.squashfs)
+            .canonicalize()
+            .with_context(|err| format!("config.squashfs: {}", err))?;
+
+        steps::validate_image(&squashfs, config.image_format, disks, config.old_root.as_deref())?;
+
+        let report = disks.dry_run().with_context(|err| format!("disk dry run: {}", err))?;
+
+        Ok(InstallReport {
+            bootloader: match bootloader {
+                Bootloader::Bios => "bios".into(),
+                Bootloader::Efi => "efi".into(),
+            },
+            squashfs,
+            old_root: config.old_root.clone(),
+            disks: report,
+        })
+    }
+
+    /// Configures the extracted install and its boot loader, and then unmounts it.
+    ///
+    /// Steps which the journal records as completed are skipped.
+    #[allow(clippy::too_many_arguments)]
+    fn finish(
+        steps: &mut InstallerState,
+        mut recovery_conf: Option<&mut RecoveryEnv>,
+        disks: &Disks,
+        mount_dir: TempDir,
+        mut mounts: Mounts,
+        bootloader: Bootloader,
+        config: &Config,
+        iso_os_release: &OsRelease,
+        remove_pkgs: &[String],
+    ) -> io::Result<()> {
+        if !steps.journal.is_complete(Step::Configure) {
             let timezone = steps.installer.timezone_cb.as_mut().map(|func| func());
             let user = steps.installer.user_creation_cb.as_mut().map(|func| func());
 
             steps.apply(Step::Configure, "configuring chroot", |steps| {
+                disks
+                    .create_swap_file(mount_dir.path())
+                    .with_context(|err| format!("creating swap file: {}", err))?;
+
                 Installer::configure(
-                    recovery_conf.as_mut(),
-                    &disks,
+                    recovery_conf.as_deref_mut(),
+                    disks,
                     mount_dir.path(),
-                    &config,
-                    &iso_os_release,
+                    config,
+                    iso_os_release,
                     timezone.as_ref(),
                     user.as_ref(),
-                    &remove_pkgs,
+                    remove_pkgs,
                     percent!(steps),
                 )
             })?;
+        }
 
+        if !steps.journal.is_complete(Step::Bootloader) {
             steps.apply(Step::Bootloader, "configuring bootloader", |steps| {
                 Installer::bootloader(
-                    &disks,
+                    disks,
                     mount_dir.path(),
                     bootloader,
-                    &config,
-                    &iso_os_release,
+                    config,
+                    iso_os_release,
                     percent!(steps),
                 )
             })?;
-
-            mounts.unmount(false).with_context(|err| format!("chroot unmount: {}", err))?;
-            mount_dir.close().with_context(|err| format!("closing mount directory: {}", err))
-        })?;
-
-        let _ = deactivate_logical_devices();
-
-        if let Some(conf) = recovery_conf.as_mut() {
-            conf.remove("MODE");
-            conf.write()?;
         }
 
-        Ok(())
+        mounts.unmount(false).with_context(|err| format!("chroot unmount: {}", err))?;
+        mount_dir.close().with_context(|err| format!("closing mount directory: {}", err))
     }
 
     /// Create a backup of key data on the system, execute the given functi on, and then restore
@@ -252,9 +525,20 @@ impl Installer {
                 .get_partition_with_target(Path::new("/"))
                 .ok_or(ReinstallError::NoRootPartition)?;
 
-            let (home, home_is_root) = disks
-                .get_partition_with_target(Path::new("/home"))
-                .map_or((old_root, true), |p| (p, false));
+            let subvolume_of = |partition: &PartitionInfo, target: &str| {
+                partition.get_subvolume(Path::new(target)).map(|subvolume| subvolume.name.clone())
+            };
+
+            // Home may be a separate partition, a subvolume of the old root, or a directory
+            // within the old root.
+            let (home, home_subvolume, home_is_root) =
+                match disks.get_partition_with_target(Path::new("/home")) {
+                    Some(home) => (home, subvolume_of(home, "/home"), false),
+                    None => match subvolume_of(old_root, "/home") {
+                        Some(subvolume) => (old_root, Some(subvolume), false),
+                        None => (old_root, subvolume_of(old_root, "/"), true),
+                    },
+                };
 
             if home.will_format() {
                 return Err(ReinstallError::ReformattingHome.into());
@@ -263,26 +547,37 @@ impl Installer {
             let home_path = home.get_device_path();
             let root_path = new_root.get_device_path().to_path_buf();
             let root_fs = new_root.filesystem.ok_or_else(|| ReinstallError::NoFilesystem)?;
+            let root_subvolume = subvolume_of(new_root, "/");
             let old_root_path = old_root.get_device_path();
             let old_root_fs = old_root.filesystem.ok_or_else(|| ReinstallError::NoFilesystem)?;
+            let old_root_subvolume = subvolume_of(old_root, "/");
             let home_fs = home.filesystem.ok_or_else(|| ReinstallError::NoFilesystem)?;
 
-            account_files = AccountFiles::new(old_root_path, old_root_fs)?;
+            account_files =
+                AccountFiles::new(old_root_path, old_root_fs, old_root_subvolume.as_deref())?;
 
             let backup = steps.apply(Step::Backup, "backing up", |steps| {
                 let mut callback = percent!(steps);
 
-                let backup = Backup::new(home_path, home_fs, home_is_root, &account_files)?;
+                let backup = Backup::new(
+                    home_path,
+                    home_fs,
+                    home_subvolume.as_deref(),
+                    home_is_root,
+                    &account_files,
+                )?;
                 callback(25);
 
                 validate_backup_conditions(&disks, &config.squashfs)?;
                 callback(50);
 
+                let subvolume = old_root_subvolume.as_deref();
                 if config.flags & KEEP_OLD_ROOT != 0 {
-                    move_root(old_root_path, old_root_fs)?;
-                    old_backup = Some((old_root_path.to_path_buf(), old_root_fs));
+                    move_root(old_root_path, old_root_fs, subvolume)?;
+                    let path = old_root_path.to_path_buf();
+                    old_backup = Some((path, old_root_fs, old_root_subvolume.clone()));
                 } else {
-                    remove_root(old_root_path, old_root_fs)?;
+                    remove_root(old_root_path, old_root_fs, subvolume)?;
                 }
 
                 callback(100);
@@ -290,7 +585,7 @@ impl Installer {
                 Ok(backup)
             })?;
 
-            Some((backup, root_path, root_fs))
+            Some((backup, root_path, root_fs, root_subvolume))
         } else {
             None
         };
@@ -299,19 +594,23 @@ impl Installer {
         if let Err(why) = func(disks, config, steps) {
             error!("errored while installing system: {}", why);
 
-            if let Some((path, fs)) = old_backup {
-                recover_root(&path, fs)?;
+            if let Some((path, fs, subvolume)) = old_backup {
+                // The old install is recovered even if the install was cancelled.
+                executor::with_cancellation(Cancellation::new(), || {
+                    recover_root(&path, fs, subvolume.as_deref())
+                })?;
             }
 
             return Err(why);
         }
 
         // Then restore the backup, if it exists.
-        if let Some((backup, root_path, root_fs)) = backup {
+        if let Some((backup, root_path, root_fs, root_subvolume)) = backup {
             info!("applying backup");
-            backup.restore(&root_path, root_fs)?;
+            let subvolume = root_subvolume.as_deref();
+            backup.restore(&root_path, root_fs, subvolume)?;
 
-            if let Err(why) = delete_old_install(&root_path, root_fs) {
+            if let Err(why) = delete_old_install(&root_path, root_fs, subvolume) {
                 warn!("failed to delete old install: {}", why);
             }
         }
@@ -387,6 +686,50 @@ impl Installer {
         self.user_creation_cb = Some(Box::new(callback));
     }
 
+    /// The token which cancels the install of this installer, which may be cancelled from any
+    /// thread. Once cancelled, the installer may not install again.
+    ///
+    /// ```ignore,rust
+    /// use distinst::Installer;
+    /// let installer = Installer::new();
+    /// let cancellation = installer.cancellation();
+    /// std::thread::spawn(move || cancellation.cancel());
+    /// ```
+    pub fn cancellation(&self) -> Cancellation { self.cancellation.clone() }
+
+    /// Runs an executable before or after a step. Hooks which run after the extract and
+    /// configure steps are copied into the target, and run within a chroot of it.
+    ///
+    /// The step and time are given to the hook as `DISTINST_STEP` and `DISTINST_HOOK`, and the
+    /// path of the target, once it has been mounted, as `DISTINST_TARGET`. A hook which exits
+    /// unsuccessfully fails its step.
+    ///
+    /// ```ignore,rust
+    /// use distinst::{HookTime, Installer, Step};
+    /// let mut installer = Installer::new();
+    /// installer.add_hook_command(Step::Configure, HookTime::After, "/usr/lib/site/hook.sh");
+    /// ```
+    pub fn add_hook_command<P: Into<PathBuf>>(&mut self, step: Step, time: HookTime, command: P) {
+        self.hooks.add_command(step, time, command.into());
+    }
+
+    /// Runs a closure before or after a step. An error returned by the closure fails its step.
+    ///
+    /// ```ignore,rust
+    /// use distinst::{HookTime, Installer, Step};
+    /// let mut installer = Installer::new();
+    /// installer.add_hook(Step::Extract, HookTime::After, |context| {
+    ///     println!("extracted to {:?}", context.target);
+    ///     Ok(())
+    /// });
+    /// ```
+    pub fn add_hook<F>(&mut self, step: Step, time: HookTime, hook: F)
+    where
+        F: FnMut(&HookContext) -> io::Result<()> + 'static,
+    {
+        self.hooks.add_closure(step, time, Box::new(hook));
+    }
+
     fn initialize<F: FnMut(i32)>(
         disks: &mut Disks,
         config: &Config,
@@ -395,30 +738,63 @@ impl Installer {
         steps::initialize(disks, config, callback)
     }
 
+    /// Verifies the image before the disks are modified.
+    fn verify<F: FnMut(i32)>(
+        squashfs: &Path,
+        config: &Config,
+        cancellation: &Cancellation,
+        callback: F,
+    ) -> io::Result<()> {
+        steps::verify(squashfs, config.squashfs_sha256.as_deref(), cancellation, callback)
+    }
+
     /// Apply all partitioning and formatting changes to the disks
     /// configuration specified.
-    fn partition<F: FnMut(i32)>(disks: &mut Disks, callback: F) -> io::Result<()> {
-        steps::partition(disks, callback)
+    fn partition<F: FnMut(i32)>(disks: &mut Disks, config: &Config, callback: F) -> io::Result<()> {
+        steps::partition(disks, config.move_journal.as_deref(), callback)
+    }
+
+    /// Mounts all of the targets to the temporary chroot, which hooks are then given as the
+    /// target of the install.
+    fn mount_targets(
+        disks: &Disks,
+        mount_dir: &Path,
+        steps: &mut InstallerState,
+    ) -> io::Result<Mounts> {
+        info!("mounting all targets to the temporary chroot");
+
+        let mounts = disks
+            .mount_all_targets(mount_dir)
+            .with_context(|err| format!("mounting all targets: {}", err))?;
+        steps.target = Some(mount_dir.to_path_buf());
+        Ok(mounts)
+    }
+
+    /// Extracts the image into the new install, and then gets the os-release data.
+    fn extract<F: FnMut(i32)>(
+        squashfs: &Path,
+        config: &Config,
+        mount_dir: &Path,
+        cancellation: &Cancellation,
+        callback: F,
+    ) -> io::Result<OsRelease> {
+        info!("Extracting {}", squashfs.display());
+        steps::extract_image(squashfs, config.image_format, mount_dir, cancellation, callback)?;
+        Self::os_release(mount_dir)
     }
 
-    /// Extracts the squashfs image into the new install, and then gets the os-release data.
+    /// Gets the os-release data of the installed image.
     ///
     /// We get the os-release data here because the host that is installing the image may differ
     /// from the image that is being installed, and thus may be a completely different distro.
-    fn extract<P: AsRef<Path>, F: FnMut(i32)>(
-        squashfs: P,
-        mount_dir: P,
-        callback: F,
-    ) -> io::Result<OsRelease> {
-        info!("Extracting {}", squashfs.as_ref().display());
-        let mount_dir = mount_dir.as_ref();
-        squashfs::extract(squashfs, mount_dir, callback)?;
+    fn os_release(mount_dir: &Path) -> io::Result<OsRelease> {
         OsRelease::new_from(&mount_dir.join("etc/os-release")).with_context(|why| {
             format!("failed to parse /etc/os-release from extracted image: {}", why)
         })
     }
 
     /// Configures the new install after it has been extracted.
+    #[allow(clippy::too_many_arguments)]
     fn configure<P: AsRef<Path>, S: AsRef<str>, F: FnMut(i32)>(
         recovery_conf: Option<&mut RecoveryEnv>,
         disks: &Disks,
@@ -456,8 +832,30 @@ impl Installer {
     }
 }
 
-impl From<ReinstallError> for io::Error {
-    fn from(why: ReinstallError) -> io::Error {
-        io::Error::new(io::ErrorKind::Other, format!("{}", why))
+/// Closes the logical volumes and encrypted partitions that a cancelled install opened. The
+/// target has already been unmounted, as its mounts were dropped when the install returned.
+///
+/// Installs which failed for any other reason are left as they are, so that they may be
+/// resumed.
+fn rollback(cancellation: &Cancellation, result: io::Result<()>) -> io::Result<()> {
+    if result.is_err() && cancellation.is_cancelled() {
+        info!("install was cancelled: closing logical volumes and encrypted partitions");
+        if let Err(why) = deactivate_logical_devices() {
+            error!("failed to deactivate logical devices: {}", why);
+        }
     }
+
+    result
+}
+
+fn validate_hostname(hostname: &str) -> io::Result<()> {
+    if hostname::is_valid(hostname) {
+        Ok(())
+    } else {
+        Err(InstallError::new(ErrorCode::InvalidHostname, "hostname is not valid").into())
+    }
+}
+
+impl From<ReinstallError> for io::Error {
+    fn from(why: ReinstallError) -> io::Error { InstallError::from(why).into() }
 }
@@ -1,16 +1,28 @@
-use super::{Error, Installer, Status, Step};
-use libc;
-use std::{io, sync::atomic::Ordering};
-use crate::KILL_SWITCH;
+use super::{
+    hooks::HookTime,
+    journal::{InstallJournal, JOURNAL_PATH},
+    Error, Installer, Status, Step,
+};
+use crate::errors::{ErrorCode, InstallError};
+use std::{io, path::PathBuf};
 
 pub struct InstallerState<'a> {
     pub installer: &'a mut Installer,
     pub status:    Status,
+    /// Persisted after each step completes, so that a failed install may be resumed.
+    pub journal:   InstallJournal,
+    /// The directory that the target is mounted at, once it has been mounted.
+    pub target:    Option<PathBuf>,
 }
 
 impl<'a> InstallerState<'a> {
     pub fn new(installer: &'a mut Installer) -> Self {
-        Self { installer, status: Status { step: Step::Init, percent: 0 } }
+        Self {
+            installer,
+            status:  Status { step: Step::Init, percent: 0 },
+            journal: InstallJournal::default(),
+            target:  None,
+        }
     }
 
     pub fn apply<T, F>(&mut self, step: Step, msg: &str, mut action: F) -> io::Result<T>
@@ -21,8 +33,8 @@ impl<'a> InstallerState<'a> {
             libc::sync();
         }
 
-        if KILL_SWITCH.load(Ordering::SeqCst) {
-            return Err(io::Error::new(io::ErrorKind::Interrupted, "process killed"));
+        if self.installer.cancellation.is_cancelled() {
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "install was cancelled"));
         }
 
         self.status.step = step;
@@ -31,10 +43,23 @@ impl<'a> InstallerState<'a> {
         self.emit_status(status);
 
         info!("starting {} step", msg);
-        match action(self) {
-            Ok(value) => Ok(value),
+        let result = self
+            .run_hooks(step, HookTime::Before)
+            .and_then(|_| action(self))
+            .and_then(|value| self.run_hooks(step, HookTime::After).map(|_| value));
+
+        match result {
+            Ok(value) => {
+                self.journal.complete(step);
+                if let Err(why) = self.journal.write(JOURNAL_PATH) {
+                    warn!("{}", why);
+                }
+
+                Ok(value)
+            }
             Err(err) => {
                 error!("{} error: {}", msg, err);
+                let err = with_step_code(err, step);
                 let error = Error { step: self.status.step, err };
                 self.emit_error(&error);
                 Err(error.err)
@@ -42,7 +67,23 @@ impl<'a> InstallerState<'a> {
         }
     }
 
+    fn run_hooks(&mut self, step: Step, time: HookTime) -> io::Result<()> {
+        self.installer.hooks.run(step, time, self.target.as_deref())
+    }
+
     pub fn emit_status(&mut self, status: Status) { self.installer.emit_status(status); }
 
     pub fn emit_error(&mut self, error: &Error) { self.installer.emit_error(&error); }
 }
+
+/// Assigns the code of the given step to an error whose cause is not otherwise known.
+///
+/// OS errors are returned as they are, so that their errno remains available to the FFI.
+fn with_step_code(err: io::Error, step: Step) -> io::Error {
+    if err.raw_os_error().is_some() || ErrorCode::of(&err) != ErrorCode::Unknown {
+        return err;
+    }
+
+    let kind = err.kind();
+    InstallError::new(ErrorCode::for_step(step), err.to_string()).into_io_error(kind)
+}
@@ -1,18 +1,17 @@
 use crate::chroot::Chroot;
 use crate::disks::{Bootloader, Disks};
 use crate::errors::IoContext;
-use libc;
 use os_release::OsRelease;
 use std::{
     ffi::{OsStr, OsString},
-    fs, io,
+    fs, io, iter,
     os::unix::ffi::{OsStrExt, OsStringExt},
     path::{Path, PathBuf},
 };
 use crate::Config;
 use crate::MODIFY_BOOT_ORDER;
 
-use super::mount_efivars;
+use super::{mount_efivars, ESP_SYNC_HOOK};
 
 pub fn bootloader<F: FnMut(i32)>(
     disks: &Disks,
@@ -128,28 +127,46 @@ pub fn bootloader<F: FnMut(i32)>(
                         chroot.command("update-initramfs", &["-c", "-k", "all"]).run()?;
                     }
 
+                    // The mirrors receive a copy of the boot loader that was just installed.
+                    let mirrors = disks.get_esp_mirrors();
+                    if !mirrors.is_empty() {
+                        info!("copying /boot/efi to {} mirrors", mirrors.len());
+                        let args: &[&str] = &[];
+                        chroot.command(Path::new("/").join(ESP_SYNC_HOOK), args).run()?;
+                    }
+
                     if config.flags & MODIFY_BOOT_ORDER != 0 {
-                        let efi_part_num = efi_part_num.to_string();
                         let loader = if &name == "Pop!_OS" {
                             "\\EFI\\systemd\\systemd-bootx64.efi".into()
                         } else {
                             format!("\\EFI\\{}\\shimx64.efi", name)
                         };
 
-                        let args: &[&OsStr] = &[
-                            "--create".as_ref(),
-                            "--disk".as_ref(),
-                            bootloader_dev.as_ref(),
-                            "--part".as_ref(),
-                            efi_part_num.as_ref(),
-                            "--write-signature".as_ref(),
-                            "--label".as_ref(),
-                            iso_os_release.pretty_name.as_ref(),
-                            "--loader".as_ref(),
-                            loader.as_ref(),
-                        ][..];
-
-                        chroot.command("efibootmgr", args).run()?;
+                        // Each entry is placed first in the boot order, so the mirrors are
+                        // registered before the EFI partition that they mirror.
+                        let entries = mirrors
+                            .iter()
+                            .rev()
+                            .map(|&(dev, part)| (dev, part.number))
+                            .chain(iter::once((bootloader_dev, efi_part_num)));
+
+                        for (dev, part_num) in entries {
+                            let part_num = part_num.to_string();
+                            let args: &[&OsStr] = &[
+                                "--create".as_ref(),
+                                "--disk".as_ref(),
+                                dev.as_ref(),
+                                "--part".as_ref(),
+                                part_num.as_ref(),
+                                "--write-signature".as_ref(),
+                                "--label".as_ref(),
+                                iso_os_release.pretty_name.as_ref(),
+                                "--loader".as_ref(),
+                                loader.as_ref(),
+                            ][..];
+
+                            chroot.command("efibootmgr", args).run()?;
+                        }
                     }
                 }
             }
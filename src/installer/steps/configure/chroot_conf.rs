@@ -1,13 +1,15 @@
-use crate::chroot::{Chroot, Command};
+use crate::chroot::{Chroot, Command, Redirect};
+use crate::disks::LvmEncryption;
+use crate::installer::steps::ESP_SYNC_HOOK;
 use crate::errors::IoContext;
 use crate::misc;
 use partition_identity::PartitionID;
 use proc_mounts::MountList;
 use std::{
-    fs,
+    fs::{self, Permissions},
     io::{self, Write},
-    path::Path,
-    process::Stdio,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
 };
 use sys_mount::*;
 use crate::timezones::Region;
@@ -48,7 +50,7 @@ impl<'a> ChrootConfigurator<'a> {
             },
         );
 
-        command.stdout(Stdio::null());
+        command.stdout(Redirect::Null);
         command.run()
     }
 
@@ -125,7 +127,7 @@ impl<'a> ChrootConfigurator<'a> {
             let args: &[&str] = &["list"];
             let output = self.chroot.command("ubuntu-drivers", args).run_with_stdout()?;
 
-            for result in output.lines().map(|line| line.split(",").nth(0)) {
+            for result in output.lines().map(|line| line.split(',').next()) {
                 match result {
                     Some(package) => packages.push(package),
                     None => continue,
@@ -143,7 +145,7 @@ impl<'a> ChrootConfigurator<'a> {
                 },
             );
 
-            command.stdout(Stdio::null());
+            command.stdout(Redirect::Null);
             command.run()
         } else {
             Ok(())
@@ -225,6 +227,76 @@ impl<'a> ChrootConfigurator<'a> {
         }
     }
 
+    /// Enrolls the additional unlock methods of the LUKS volume on `device`, which is unlocked
+    /// with its password, or otherwise its keyfile.
+    pub fn enroll_luks(&self, device: &Path, encryption: &LvmEncryption) -> io::Result<()> {
+        let password = encryption.password.as_ref().filter(|password| !password.is_empty());
+        let keyfile = encryption.keydata.as_ref().and_then(|&(_, ref paths)| paths.as_ref());
+
+        let unlock_key_file = match (password, keyfile) {
+            (Some(_), _) => PathBuf::from("/dev/stdin"),
+            (None, Some(&(_, ref target))) => target.join(&encryption.physical_volume),
+            // Passwords are not retained by the journal of a resumed install.
+            (None, None) => {
+                warn!("no key is available to enroll unlock methods on {}", device.display());
+                return Ok(());
+            }
+        };
+
+        for enrollment in &encryption.enrollments {
+            info!("enrolling {} on {}", enrollment.kind(), device.display());
+            let args = enrollment.cryptenroll_args(device, &unlock_key_file);
+            let command = self.chroot.command("systemd-cryptenroll", &args);
+            let mut command = match password {
+                Some(password) => command.stdin_input(password),
+                None => command,
+            };
+
+            command.run().with_context(|why| {
+                format!("failed to enroll {} on {}: {}", enrollment.kind(), device.display(), why)
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Configures the initramfs to include what is required to unlock enrolled LUKS volumes.
+    pub fn enrollment_initramfs(&self) -> io::Result<()> {
+        info!("configuring initramfs for enrolled LUKS volumes");
+        let hooks = self.chroot.path.join("etc/initramfs-tools/hooks");
+        fs::create_dir_all(&hooks)?;
+        let hook = hooks.join("distinst-cryptenroll");
+        fs::write(&hook, &include_bytes!("../../../scripts/cryptenroll-hook.sh")[..])
+            .and_then(|_| fs::set_permissions(&hook, Permissions::from_mode(0o755)))
+            .with_context(|err| format!("failed to write {:?}: {}", hook, err))?;
+
+        // Installs which use dracut instead include its own modules.
+        let dracut = self.chroot.path.join("etc/dracut.conf.d");
+        fs::create_dir_all(&dracut)?;
+        let conf = dracut.join("90-distinst-cryptenroll.conf");
+        fs::write(&conf, "add_dracutmodules+=\" tpm2-tss fido2 \"\n")
+            .with_context(|err| format!("failed to write {:?}: {}", conf, err))
+    }
+
+    /// Installs the hook which keeps the mirrors of the EFI partition in sync with `/boot/efi`
+    /// when kernels and initramfs images are installed, updated, or removed.
+    pub fn esp_sync_hook(&self, hook: &str) -> io::Result<()> {
+        info!("installing the EFI partition sync hook");
+        let name = Path::new(ESP_SYNC_HOOK).file_name().expect("hook has a file name");
+        let postinst = self.chroot.path.join(ESP_SYNC_HOOK);
+        let postrm = self.chroot.path.join("etc/kernel/postrm.d").join(name);
+        let initramfs = self.chroot.path.join("etc/initramfs/post-update.d").join(name);
+
+        for path in &[postinst, postrm, initramfs] {
+            fs::create_dir_all(path.parent().expect("hook has a parent directory"))?;
+            fs::write(path, hook)
+                .and_then(|_| fs::set_permissions(path, Permissions::from_mode(0o755)))
+                .with_context(|err| format!("failed to write {:?}: {}", path, err))?;
+        }
+
+        Ok(())
+    }
+
     /// Use locale-gen and update-locale to set the locale of the machine.
     pub fn generate_locale(&self, locale: &str) -> io::Result<()> {
         info!("generating locales via `locale-gen` and `update-locale`");
@@ -6,14 +6,13 @@ use crate::installer::{conf::RecoveryEnv, steps::normalize_os_release_name};
 use crate::chroot::Chroot;
 use crate::distribution;
 use crate::errors::*;
+use crate::executor::Context;
 use crate::external::remount_rw;
 use crate::hardware_support;
 use crate::installer::traits::InstallerDiskOps;
-use libc;
 use crate::misc;
 use os_release::OsRelease;
 use partition_identity::PartitionID;
-use rayon;
 use std::{
     fs::{self, Permissions},
     io::{self, Write},
@@ -64,6 +63,7 @@ macro_rules! map_errors {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn configure<D: InstallerDiskOps, P: AsRef<Path>, S: AsRef<str>, F: FnMut(i32)>(
     recovery_conf: Option<&mut RecoveryEnv>,
     disks: &D,
@@ -120,6 +120,7 @@ pub fn configure<D: InstallerDiskOps, P: AsRef<Path>, S: AsRef<str>, F: FnMut(i3
         let mut b: io::Result<()> = Ok(());
         let mut c: io::Result<()> = Ok(());
         let mut configure_graphics = Ok(false);
+        let context = Context::current();
 
         rayon::scope(|s| {
             s.spawn(|_| b = lvm_autodetection());
@@ -129,7 +130,9 @@ pub fn configure<D: InstallerDiskOps, P: AsRef<Path>, S: AsRef<str>, F: FnMut(i3
                     hardware_support::append_packages(install_pkgs, &iso_os_release);
                 }
 
-                configure_graphics = hardware_support::switchable_graphics::configure_graphics(&mount_dir);
+                configure_graphics = context.enter(|| {
+                    hardware_support::switchable_graphics::configure_graphics(&mount_dir)
+                });
             });
         });
 
@@ -142,6 +145,17 @@ pub fn configure<D: InstallerDiskOps, P: AsRef<Path>, S: AsRef<str>, F: FnMut(i3
         configure_graphics?
     };
 
+    // The arrays must be defined before the initramfs is updated, so that they are assembled
+    // at boot.
+    if let Some(mdadm_conf) = disks
+        .generate_mdadm_conf()
+        .with_context(|why| format!("failed to generate mdadm.conf: {}", why))?
+    {
+        info!("writing /etc/mdadm/mdadm.conf");
+        fs::create_dir_all(mount_dir.join("etc/mdadm"))?;
+        file_create!(&mount_dir.join("etc/mdadm/mdadm.conf"), [mdadm_conf.as_bytes()]);
+    }
+
     {
         info!("chrooting into target on {}", mount_dir.display());
 
@@ -187,11 +201,15 @@ pub fn configure<D: InstallerDiskOps, P: AsRef<Path>, S: AsRef<str>, F: FnMut(i3
 
         callback(30);
 
+        let context = Context::current();
         let (retain, lang_output) = rayon::join(
             // Get packages required by this disk configuration.
             || distribution::debian::get_required_packages(disks, iso_os_release),
             // Attempt to run the check-language-support external command.
-            || distribution::debian::check_language_support(&config.lang, &chroot),
+            || {
+                let lang = &config.lang;
+                context.enter(|| distribution::debian::check_language_support(lang, &chroot))
+            },
         );
 
         let lang_output = lang_output?;
@@ -317,6 +335,24 @@ pub fn configure<D: InstallerDiskOps, P: AsRef<Path>, S: AsRef<str>, F: FnMut(i3
             .with_context(|why| format!("error setting keyboard layout: {}", why))?;
         callback(85);
 
+        let enrollments = disks.get_enrollments();
+        if !enrollments.is_empty() {
+            chroot
+                .enrollment_initramfs()
+                .with_context(|why| format!("error configuring initramfs: {}", why))?;
+
+            for (device, encryption) in &enrollments {
+                chroot.enroll_luks(device, encryption)?;
+            }
+        }
+
+        if let Some(hook) = disks
+            .generate_esp_sync_hook()
+            .with_context(|why| format!("failed to generate the EFI sync hook: {}", why))?
+        {
+            chroot.esp_sync_hook(&hook)?;
+        }
+
         chroot
             .update_initramfs()
             .with_context(|why| format!("error updating initramfs: {}", why))?;
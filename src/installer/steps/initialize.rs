@@ -1,6 +1,7 @@
 use crate::disks::*;
+use crate::errors::{ErrorCode, InstallError};
+use crate::executor::Context;
 use crate::misc;
-use rayon;
 use std::{
     io::{self, BufRead},
     path::{Path, PathBuf},
@@ -21,12 +22,13 @@ pub fn initialize<F: FnMut(i32)>(
                 Ok(squashfs)
             } else {
                 error!("config.squashfs: supplied file does not exist");
-                Err(io::Error::new(io::ErrorKind::NotFound, "invalid squashfs path"))
+                let why = InstallError::new(ErrorCode::ImageNotFound, "invalid squashfs path");
+                Err(io::Error::from(why))
             }
         }
         Err(err) => {
             error!("config.squashfs: {}", err);
-            Err(err)
+            Err(io::Error::from(InstallError::new(ErrorCode::ImageNotFound, err.to_string())))
         }
     };
 
@@ -67,10 +69,11 @@ pub fn initialize<F: FnMut(i32)>(
     let mut res_c = Ok(());
     let mut res_d = Ok(PathBuf::new());
 
+    let context = Context::current();
     rayon::scope(|s| {
         s.spawn(|_| {
             // Deactivate any open logical volumes & close any encrypted partitions.
-            if let Err(why) = disks.deactivate_device_maps() {
+            if let Err(why) = context.enter(|| disks.deactivate_device_maps()) {
                 error!("device map deactivation error: {}", why);
                 res_a = Err(io::Error::new(
                     io::ErrorKind::Other,
@@ -80,7 +83,7 @@ pub fn initialize<F: FnMut(i32)>(
             }
 
             // Unmount any mounted devices.
-            if let Err(why) = disks.unmount_devices() {
+            if let Err(why) = context.enter(|| disks.unmount_devices()) {
                 error!("device unmount error: {}", why);
                 res_a = Err(io::Error::new(
                     io::ErrorKind::Other,
@@ -106,7 +109,7 @@ pub fn initialize<F: FnMut(i32)>(
     disks
         .physical
         .iter_mut()
-        .zip(unmount.into_iter())
+        .zip(unmount)
         .filter(|&(_, unmount)| unmount)
         .map(|(disk, _)| {
             if let Err(why) = disk.unmount_all_partitions_with_target() {
@@ -2,21 +2,31 @@ mod bootloader;
 mod configure;
 mod initialize;
 mod partition;
+mod source;
+mod verify;
 
-pub use self::{bootloader::*, configure::*, initialize::*, partition::*};
+pub use self::{
+    bootloader::*, configure::*, initialize::*, partition::*, source::*, verify::*,
+};
 
 use std::{
     borrow::Cow,
     fs, io,
     path::{Path, PathBuf},
+    str::FromStr,
     sync::atomic::Ordering,
 };
 
 use sys_mount::*;
 use crate::NO_EFI_VARIABLES;
 
+/// The kernel hook within the target which copies `/boot/efi` to its mirrors. It is also
+/// installed as a hook of the initramfs, and of the removal of kernels.
+pub(crate) const ESP_SYNC_HOOK: &str = "etc/kernel/postinst.d/zz-distinst-esp-sync";
+
 /// Installation step
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Step {
     Backup,
     Init,
@@ -24,6 +34,46 @@ pub enum Step {
     Extract,
     Configure,
     Bootloader,
+    Verify,
+}
+
+impl Step {
+    /// The name of the step, as it is given on the command line and to hooks.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Step::Backup => "backup",
+            Step::Init => "init",
+            Step::Partition => "partition",
+            Step::Extract => "extract",
+            Step::Configure => "configure",
+            Step::Bootloader => "bootloader",
+            Step::Verify => "verify",
+        }
+    }
+}
+
+impl FromStr for Step {
+    type Err = io::Error;
+
+    fn from_str(input: &str) -> io::Result<Self> {
+        let step = match input {
+            "backup" => Step::Backup,
+            "init" => Step::Init,
+            "partition" => Step::Partition,
+            "extract" => Step::Extract,
+            "configure" => Step::Configure,
+            "bootloader" => Step::Bootloader,
+            "verify" => Step::Verify,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{:?} is not an install step", input),
+                ))
+            }
+        };
+
+        Ok(step)
+    }
 }
 
 fn mount_cdrom(mount_dir: &Path) -> io::Result<Option<(UnmountDrop<Mount>, PathBuf)>> {
@@ -1,40 +1,82 @@
-use crate::disks::{operations::FormatPartitions, Disks};
+use crate::disks::{operations::FormatPartitions, Disk, Disks};
 use crate::errors::IoContext;
+use crate::executor::{check_cancelled, Context};
 use crate::external::{blockdev, pvs, vgactivate, vgdeactivate};
 use itertools::Itertools;
 use rayon::{self, prelude::*};
-use std::{collections::BTreeMap, io, path::PathBuf, thread::sleep, time::Duration};
-
-pub fn partition<F: FnMut(i32)>(disks: &mut Disks, mut callback: F) -> io::Result<()> {
-    let (pvs_result, commit_result): (
-        io::Result<BTreeMap<PathBuf, Option<String>>>,
-        io::Result<()>,
-    ) = rayon::join(
-        || {
-            // This collection of physical volumes and their optional volume groups
-            // will be used to obtain a list of volume groups associated with our
-            // modified partitions.
-            pvs().with_context(|why| format!("failed to get PVS map: {}", why))
-        },
-        || {
-            // Perform layout changes serially, due to libparted thread safety issues,
-            // and collect a list of partitions to format which can be done in parallel.
-            // Once partitions have been formatted in parallel, reload the disk configuration.
-            let mut partitions_to_format = FormatPartitions(Vec::new());
-            for disk in disks.get_physical_devices_mut() {
-                info!("{}: Committing changes to disk", disk.path().display());
-                if let Some(partitions) =
-                    disk.commit().with_context(|why| format!("disk commit error: {}", why))?
-                {
-                    partitions_to_format.0.extend_from_slice(&partitions.0);
-                }
-            }
+use std::{
+    collections::BTreeMap,
+    fs, io, mem, panic,
+    path::{Path, PathBuf},
+    sync::{mpsc, Mutex},
+    thread::{self, sleep},
+    time::Duration,
+};
+
+/// The location that the partition tables of the disks are backed up to before they are
+/// partitioned, which `Disks::restore_tables` restores them from.
+pub const TABLES_PATH: &str = "/tmp/distinst-tables.json";
+
+/// Partition moves are journaled at `move_journal`, if it is set, so that they may be resumed.
+/// The progress of partition moves is reported within the first quarter of the step.
+pub fn partition<F: FnMut(i32)>(
+    disks: &mut Disks,
+    move_journal: Option<&Path>,
+    mut callback: F,
+) -> io::Result<()> {
+    disks
+        .backup_tables(TABLES_PATH)
+        .with_context(|why| format!("failed to back up partition tables: {}", why))?;
 
-            partitions_to_format.format()?;
+    // Commands which run on the threads of the pool are executed within this context.
+    let context = Context::current();
 
-            disks.physical.iter_mut().map(|disk| disk.reload().map_err(io::Error::from)).collect()
-        },
-    );
+    // The disks are committed on a thread of their own, so that the progress of partition
+    // moves may be reported from this thread.
+    let (progress_tx, progress_rx) = mpsc::channel();
+    let mut physical = mem::take(&mut disks.physical);
+    let committer = {
+        let (context, journal) = (context.clone(), move_journal.map(Path::to_path_buf));
+        thread::spawn(move || {
+            let progress_tx = Mutex::new(progress_tx);
+            let move_progress = |copied: u64, total: u64| {
+                let percent = (copied * 25 / total.max(1)) as i32;
+                let _ = progress_tx.lock().expect("progress sender poisoned").send(percent);
+            };
+
+            let (pvs_result, commit_result): (
+                io::Result<BTreeMap<PathBuf, Option<String>>>,
+                io::Result<()>,
+            ) = rayon::join(
+                || {
+                    // This collection of physical volumes and their optional volume groups
+                    // will be used to obtain a list of volume groups associated with our
+                    // modified partitions.
+                    context.enter(pvs).with_context(|why| format!("failed to get PVS map: {}", why))
+                },
+                || {
+                    context.enter(|| {
+                        commit_physical_devices(&mut physical, journal.as_deref(), &move_progress)
+                    })
+                },
+            );
+
+            (physical, pvs_result, commit_result)
+        })
+    };
+
+    // Progress is received until the committing thread drops its sender.
+    let mut last = None;
+    for percent in progress_rx {
+        if last != Some(percent) {
+            last = Some(percent);
+            callback(percent);
+        }
+    }
+
+    let (physical, pvs_result, commit_result) =
+        committer.join().unwrap_or_else(|why| panic::resume_unwind(why));
+    disks.physical = physical;
 
     let pvs = commit_result.and(pvs_result)?;
 
@@ -62,7 +104,7 @@ pub fn partition<F: FnMut(i32)>(disks: &mut Disks, mut callback: F) -> io::Resul
     // This is to ensure that everything's been written and the OS is ready to
     // proceed.
     disks.physical.par_iter().for_each(|disk| {
-        let _ = blockdev(&disk.path(), &["--flushbufs", "--rereadpt"]);
+        let _ = context.enter(|| blockdev(&disk.path(), &["--flushbufs", "--rereadpt"]));
     });
 
     // Give a bit of time to ensure that logical volumes can be re-activated.
@@ -73,9 +115,59 @@ pub fn partition<F: FnMut(i32)>(disks: &mut Disks, mut callback: F) -> io::Resul
     vgs.iter().map(|vg| vgactivate(vg)).collect::<io::Result<()>>()?;
 
     let res = disks
-        .commit_logical_partitions()
-        .with_context(|why| format!("failed to commit logical partitions: {}", why));
+        .commit_raid_devices()
+        .with_context(|why| format!("failed to commit RAID arrays: {}", why))
+        .and_then(|_| {
+            disks
+                .commit_logical_partitions()
+                .with_context(|why| format!("failed to commit logical partitions: {}", why))
+        })
+        .and_then(|_| {
+            disks
+                .create_subvolumes()
+                .with_context(|why| format!("failed to create subvolumes: {}", why))
+        });
 
     callback(100);
     res
 }
+
+/// Removes the partition table backups, once they are no longer needed.
+pub fn remove_table_backups() -> io::Result<()> {
+    match fs::remove_file(TABLES_PATH) {
+        Err(ref why) if why.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// Performs layout changes on each disk in parallel, and collects a list of partitions to
+/// format, which can also be done in parallel. Changes which require libparted are serialized,
+/// due to its thread safety issues. Once partitions have been formatted, the disk configuration
+/// is reloaded.
+fn commit_physical_devices(
+    physical: &mut [Disk],
+    move_journal: Option<&Path>,
+    move_progress: &(dyn Fn(u64, u64) + Sync),
+) -> io::Result<()> {
+    let context = Context::current();
+    let committed = physical
+        .par_iter_mut()
+        .map(|disk| {
+            context.enter(|| {
+                check_cancelled()?;
+                info!("{}: Committing changes to disk", disk.path().display());
+                disk.commit_with_journal(move_journal, move_progress)
+                    .with_context(|why| format!("disk commit error: {}", why))
+            })
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let mut partitions_to_format = FormatPartitions(Vec::new());
+    for partitions in committed.into_iter().flatten() {
+        partitions_to_format.0.extend_from_slice(&partitions.0);
+    }
+
+    partitions_to_format.format()?;
+
+    physical.iter_mut().map(|disk| disk.reload().map_err(io::Error::from)).collect()
+}
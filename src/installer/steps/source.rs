@@ -0,0 +1,182 @@
+use crate::disks::Disks;
+use crate::errors::{ErrorCode, InstallError};
+use crate::executor::{self, Cancellation};
+use crate::external;
+use crate::squashfs::{
+    self,
+    raw::{self, RawFilesystem},
+    Format,
+};
+use disk_types::{BlockDeviceExt, FileSystem};
+use partition_identity::PartitionID;
+use std::{io, path::Path, str::FromStr};
+use tempdir::TempDir;
+
+/// The kind of image that `Config::squashfs` refers to, which selects how it is installed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    /// A squashfs image, tar archive, directory, or OCI layout, detected from its contents.
+    Auto,
+    /// A squashfs image, which is extracted.
+    Squashfs,
+    /// A tar archive, which may be compressed with gzip, xz, or zstd, and is extracted.
+    Tar,
+    /// A directory tree, which is copied.
+    Directory,
+    /// A directory in the OCI image layout, whose layers are unpacked in order.
+    Oci,
+    /// A raw file system image. Ext2/3/4 images are copied onto the root partition and then
+    /// grown to fill it. EROFS images are read-only, so their contents are copied instead.
+    Raw,
+}
+
+impl Default for ImageFormat {
+    fn default() -> Self { ImageFormat::Auto }
+}
+
+impl FromStr for ImageFormat {
+    type Err = io::Error;
+
+    fn from_str(input: &str) -> io::Result<Self> {
+        let format = match input {
+            "auto" => ImageFormat::Auto,
+            "squashfs" => ImageFormat::Squashfs,
+            "tar" => ImageFormat::Tar,
+            "directory" => ImageFormat::Directory,
+            "oci" => ImageFormat::Oci,
+            "raw" => ImageFormat::Raw,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{:?} is not an image format", input),
+                ))
+            }
+        };
+
+        Ok(format)
+    }
+}
+
+fn invalid_config(why: String) -> io::Error {
+    InstallError::new(ErrorCode::InvalidConfig, why).into()
+}
+
+/// Whether the image is copied onto the root partition before it is mounted, rather than
+/// extracted into the mounted targets.
+pub fn is_block_copied(image: &Path, format: ImageFormat) -> io::Result<bool> {
+    Ok(format == ImageFormat::Raw && raw::detect(image)? == RawFilesystem::Ext)
+}
+
+/// Checks that the image is of the selected format before any disk is modified.
+pub fn validate_image(
+    image: &Path,
+    format: ImageFormat,
+    disks: &Disks,
+    old_root: Option<&str>,
+) -> io::Result<()> {
+    if !image.exists() {
+        let why = format!("{:?} does not exist", image);
+        return Err(InstallError::new(ErrorCode::ImageNotFound, why).into());
+    }
+
+    let is_dir = image.is_dir();
+    let expects_dir = match format {
+        ImageFormat::Auto => return Ok(()),
+        ImageFormat::Directory | ImageFormat::Oci => true,
+        ImageFormat::Squashfs | ImageFormat::Tar | ImageFormat::Raw => false,
+    };
+
+    if is_dir != expects_dir {
+        let kind = if expects_dir { "a directory" } else { "a file" };
+        return Err(invalid_config(format!("{:?} image {:?} is not {}", format, image, kind)));
+    }
+
+    if format == ImageFormat::Oci && !image.join("oci-layout").exists() {
+        return Err(invalid_config(format!("{:?} is not an OCI image layout", image)));
+    }
+
+    if format == ImageFormat::Raw {
+        if old_root.is_some() {
+            let why = "raw images cannot be installed while retaining home";
+            return Err(invalid_config(why.into()));
+        }
+
+        let filesystem =
+            raw::detect(image).map_err(|why| invalid_config(format!("raw image: {}", why)))?;
+
+        if filesystem == RawFilesystem::Ext {
+            let root = disks.get_partition_with_target(Path::new("/"));
+            match root.and_then(|root| root.filesystem) {
+                Some(FileSystem::Ext2) | Some(FileSystem::Ext3) | Some(FileSystem::Ext4) => (),
+                _ => {
+                    return Err(invalid_config(
+                        "raw ext images require an ext2, ext3, or ext4 root partition".into(),
+                    ))
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Copies a raw ext2/3/4 image onto the root partition, and then grows it to fill the
+/// partition. The UUID that the partition was formatted with is restored, so that installs
+/// from the same image do not share a UUID.
+pub fn copy_raw_image<F: FnMut(i32)>(
+    image: &Path,
+    disks: &Disks,
+    cancellation: &Cancellation,
+    callback: F,
+) -> io::Result<()> {
+    let root = disks
+        .get_partition_with_target(Path::new("/"))
+        .ok_or_else(|| invalid_config("raw images require a root partition".into()))?;
+
+    let device = root.get_device_path();
+    let uuid = PartitionID::get_uuid(device).map(|id| id.id);
+
+    info!("copying raw image {:?} to {:?}", image, device);
+    raw::copy(image, device, cancellation.flag(), callback)?;
+
+    external::fsck(device, Some(("e2fsck", "-fy")))?;
+    external::resize2fs(device)?;
+
+    if let Some(uuid) = uuid {
+        external::set_ext_uuid(device, &uuid)?;
+    }
+
+    Ok(())
+}
+
+/// Extracts or copies the image into the mounted targets at `mount_dir`.
+pub fn extract_image<F: FnMut(i32)>(
+    image: &Path,
+    format: ImageFormat,
+    mount_dir: &Path,
+    cancellation: &Cancellation,
+    callback: F,
+) -> io::Result<()> {
+    let cancelled = cancellation.flag();
+    let format = match format {
+        ImageFormat::Auto => return squashfs::extract(image, mount_dir, cancelled, callback),
+        ImageFormat::Squashfs => Format::Squashfs,
+        ImageFormat::Tar => Format::Tar,
+        ImageFormat::Directory => Format::Directory,
+        ImageFormat::Oci => Format::Oci,
+        ImageFormat::Raw => {
+            // The contents of an EROFS image are copied from a read-only mount of it.
+            let tempdir = TempDir::new("distinst-image")?;
+            external::mount_image_ro(image, tempdir.path(), "erofs")?;
+            let source = tempdir.path();
+            let result =
+                squashfs::extract_as(Format::Directory, source, mount_dir, cancelled, callback);
+            // The image is unmounted even if the extraction was cancelled.
+            executor::with_cancellation(Cancellation::new(), || external::umount(tempdir.path()))?;
+            return result;
+        }
+    };
+
+    squashfs::extract_as(format, image, mount_dir, cancelled, callback)
+}
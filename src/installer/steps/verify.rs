@@ -0,0 +1,80 @@
+use crate::{
+    errors::{ErrorCode, InstallError},
+    executor::Cancellation,
+    squashfs::verify::{self, Digest},
+};
+use std::{fs, io, path::Path};
+
+/// The root of the install media, which `md5sum.txt` describes the files of.
+const CDROM: &str = "/cdrom";
+
+/// Verifies the image before any disk is modified, so that a corrupted image does not destroy
+/// the data on the disks before its extraction fails.
+///
+/// The superblock of a squashfs image is checked, and then the image is checked against the
+/// given SHA-256 digest, or against its entry in the `md5sum.txt` of the install media. As
+/// verification was requested, an image which has no digest to be checked against is an error.
+pub fn verify<F: FnMut(i32)>(
+    squashfs: &Path,
+    sha256: Option<&str>,
+    cancellation: &Cancellation,
+    callback: F,
+) -> io::Result<()> {
+    let corrupt = |why: io::Error| -> io::Error {
+        InstallError::new(ErrorCode::ImageCorrupt, format!("{:?}: {}", squashfs, why)).into()
+    };
+
+    if squashfs.is_dir() {
+        let why = format!("{:?} is a directory, which has no digest", squashfs);
+        return Err(InstallError::new(ErrorCode::InvalidConfig, why).into());
+    }
+
+    if squashfs.extension().map_or(false, |ext| ext == "squashfs") {
+        let superblock = verify::check_superblock(squashfs).map_err(corrupt)?;
+        info!("squashfs superblock is valid: {} bytes used", superblock.bytes_used);
+    }
+
+    let (digest, expected) = match sha256 {
+        Some(sha256) => (Digest::Sha256, sha256.to_owned()),
+        None => match md5sum_from_cdrom(squashfs)? {
+            Some(md5) => (Digest::Md5, md5),
+            None => {
+                let why = format!(
+                    "{:?} is not listed in {}/md5sum.txt, and no SHA-256 digest was given",
+                    squashfs, CDROM
+                );
+                return Err(InstallError::new(ErrorCode::InvalidConfig, why).into());
+            }
+        },
+    };
+
+    if !digest.is_valid(&expected) {
+        let why = format!("{:?} is not a valid digest", expected);
+        return Err(InstallError::new(ErrorCode::InvalidConfig, why).into());
+    }
+
+    info!("verifying the checksum of {:?}", squashfs);
+    verify::verify(squashfs, digest, &expected, cancellation.flag(), callback).map_err(|why| {
+        if why.kind() == io::ErrorKind::InvalidData {
+            corrupt(why)
+        } else {
+            why
+        }
+    })
+}
+
+/// The MD5 digest of the image, if it is on the install media and listed in its `md5sum.txt`.
+fn md5sum_from_cdrom(squashfs: &Path) -> io::Result<Option<String>> {
+    let relative = match squashfs.strip_prefix(CDROM) {
+        Ok(relative) => relative,
+        Err(_) => return Ok(None),
+    };
+
+    let md5sums = match fs::read_to_string(Path::new(CDROM).join("md5sum.txt")) {
+        Ok(md5sums) => md5sums,
+        Err(ref why) if why.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(why) => return Err(why),
+    };
+
+    Ok(verify::md5sum_entry(&md5sums, relative))
+}
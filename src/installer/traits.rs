@@ -1,9 +1,9 @@
 use self::FileSystem::*;
 use super::bitflags::FileSystemSupport;
 use disk_types::{BlockDeviceExt, FileSystem, PartitionExt};
-use crate::disks::{Disks};
+use crate::disks::{DiskExt, Disks, LvmEncryption};
 use crate::errors::IntoIoResult;
-use crate::external::generate_unique_id;
+use crate::external::{generate_unique_id, mdadm_detail_scan};
 use fstab_generate::BlockInfo;
 use crate::misc::hasher;
 use partition_identity::PartitionID;
@@ -11,6 +11,7 @@ use std::{
     borrow::Cow,
     ffi::{OsStr, OsString},
     io,
+    path::PathBuf,
 };
 
 pub trait InstallerDiskOps: Sync {
@@ -22,12 +23,22 @@ pub trait InstallerDiskOps: Sync {
 
     /// Reports file systems that need to be supported in the install.
     fn get_support_flags(&self) -> FileSystemSupport;
+
+    /// LUKS volumes which have unlock methods to enroll, alongside the paths of their devices.
+    fn get_enrollments(&self) -> Vec<(PathBuf, LvmEncryption)>;
+
+    /// Generates the mdadm.conf file in memory, if the install has RAID arrays to assemble.
+    fn generate_mdadm_conf(&self) -> io::Result<Option<String>>;
+
+    /// Generates the hook which keeps the mirrors of the EFI partition in sync with
+    /// `/boot/efi`, if the install has any.
+    fn generate_esp_sync_hook(&self) -> io::Result<Option<String>>;
 }
 
 impl InstallerDiskOps for Disks {
     /// Generates the crypttab and fstab files in memory.
     fn generate_fstabs(&self) -> (OsString, OsString) {
-        let &Disks { ref logical, ref physical, .. } = self;
+        let &Disks { ref logical, ref physical, ref raid, .. } = self;
 
         info!("generating /etc/crypttab & /etc/fstab in memory");
         let mut crypttab = OsString::with_capacity(1024);
@@ -42,6 +53,9 @@ impl InstallerDiskOps for Disks {
                     .chain(x.partitions.iter())
                     .map(|p| (true, &None, p))
             })
+            .chain(raid.iter().flat_map(|x| {
+                x.get_file_system().into_iter().chain(x.get_partitions()).map(|p| (true, &None, p))
+            }))
             .chain(logical.iter().flat_map(|x| {
                 let luks_parent = &x.luks_parent;
                 let is_unencrypted: bool = x.encryption.is_none();
@@ -61,8 +75,9 @@ impl InstallerDiskOps for Disks {
                     match (enc.password.is_some(), enc.keydata.as_ref()) {
                         (true, None) => Cow::Borrowed(OsStr::new("none")),
                         (false, None) => Cow::Borrowed(OsStr::new("/dev/urandom")),
-                        (true, Some(_key)) => unimplemented!(),
-                        (false, Some(&(_, ref key))) => {
+                        // The keyfile unlocks the volume at boot, and the password remains
+                        // in its own keyslot as a fallback.
+                        (_, Some(&(_, ref key))) => {
                             let path = key
                                 .clone()
                                 .expect("should have been populated")
@@ -90,7 +105,12 @@ impl InstallerDiskOps for Disks {
                                             crypttab.push(&uuid.id);
                                             crypttab.push(" ");
                                             crypttab.push(&password);
-                                            crypttab.push(" luks\n");
+                                            crypttab.push(" luks");
+                                            for enrollment in &enc.enrollments {
+                                                crypttab.push(",");
+                                                crypttab.push(enrollment.crypttab_option());
+                                            }
+                                            crypttab.push("\n");
                                         }
                                     }
                                     None => warn!(
@@ -103,7 +123,7 @@ impl InstallerDiskOps for Disks {
                         }
                     }
                 }
-                if let Some(blockinfo) = partition.get_block_info() {
+                for blockinfo in partition.get_block_infos() {
                     blockinfo.write_entry(&mut fstab);
                 }
             } else if partition.is_swap() {
@@ -136,11 +156,18 @@ impl InstallerDiskOps for Disks {
                     fstab.push(partition.get_device_path());
                     fstab.push("  none  swap  defaults  0  0\n");
                 }
-            } else if let Some(blockinfo) = partition.get_block_info() {
-                blockinfo.write_entry(&mut fstab);
+            } else {
+                for blockinfo in partition.get_block_infos() {
+                    blockinfo.write_entry(&mut fstab);
+                }
             }
         }
 
+        if let Some(ref swap) = self.swap_file {
+            fstab.push(&swap.path);
+            fstab.push("  none  swap  defaults  0  0\n");
+        }
+
         info!("generated the following crypttab data:\n{}", crypttab.to_string_lossy(),);
 
         info!("generated the following fstab data:\n{}", fstab.to_string_lossy());
@@ -152,7 +179,7 @@ impl InstallerDiskOps for Disks {
 
     fn get_block_info_of(&self, path: &str) -> io::Result<BlockInfo> {
         self.get_partitions()
-            .filter_map(|part| part.get_block_info())
+            .flat_map(|part| part.get_block_infos())
             .find(|entry| entry.mount() == path)
             .into_io_result(|| "root partition not found")
     }
@@ -174,6 +201,78 @@ impl InstallerDiskOps for Disks {
             };
         }
 
+        if !self.raid.is_empty() {
+            flags |= FileSystemSupport::RAID;
+        }
+
         flags
     }
+
+    fn get_enrollments(&self) -> Vec<(PathBuf, LvmEncryption)> {
+        let physical = self.physical.iter().flat_map(|disk| {
+            disk.file_system.iter().chain(disk.partitions.iter()).map(|p| (None, p))
+        });
+
+        let logical = self.logical.iter().flat_map(|device| {
+            let parent = device.luks_parent.as_ref();
+            device.file_system.iter().chain(device.partitions.iter()).map(move |p| (parent, p))
+        });
+
+        let raid = self.raid.iter().flat_map(|array| {
+            array.get_file_system().into_iter().chain(array.get_partitions()).map(|p| (None, p))
+        });
+
+        physical
+            .chain(raid)
+            .chain(logical)
+            .filter_map(|(parent, partition)| match partition.volume_group {
+                Some((_, Some(ref enc))) if !enc.enrollments.is_empty() => {
+                    let device = parent.cloned();
+                    let device = device.unwrap_or_else(|| partition.get_device_path().into());
+                    Some((device, enc.clone()))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn generate_mdadm_conf(&self) -> io::Result<Option<String>> {
+        if self.raid.is_empty() {
+            return Ok(None);
+        }
+
+        info!("generating /etc/mdadm/mdadm.conf in memory");
+        let arrays = mdadm_detail_scan()?;
+        Ok(Some(
+            [
+                "# mdadm.conf\n#\n",
+                "# Please refer to mdadm.conf(5) for information about this file.\n\n",
+                "HOMEHOST <system>\nMAILADDR root\n\n",
+                "# definitions of existing MD arrays\n",
+                &arrays,
+            ]
+            .concat(),
+        ))
+    }
+
+    fn generate_esp_sync_hook(&self) -> io::Result<Option<String>> {
+        let mirrors = self.get_esp_mirrors();
+        if mirrors.is_empty() {
+            return Ok(None);
+        }
+
+        info!("generating the EFI partition sync hook in memory");
+        let mut ids = Vec::with_capacity(mirrors.len());
+        for (_, mirror) in mirrors {
+            let path = mirror.get_device_path();
+            let id = PartitionID::get_partuuid(path).into_io_result(|| {
+                format!("mirrored EFI partition {} does not have a PartUUID", path.display())
+            })?;
+
+            ids.push(["PARTUUID=", &id.id].concat());
+        }
+
+        let hook = include_str!("../scripts/esp-sync-hook.sh");
+        Ok(Some(hook.replace("@MIRRORS@", &ids.join(" "))))
+    }
 }
@@ -8,6 +8,7 @@ pub extern crate disk_types;
 pub extern crate distinst_bootloader as bootloader;
 pub extern crate distinst_chroot as chroot;
 pub extern crate distinst_disks as disks;
+pub extern crate distinst_executor as executor;
 pub extern crate distinst_external_commands as external;
 pub extern crate distinst_hardware_support as hardware_support;
 pub extern crate distinst_locale_support as locale;
@@ -25,7 +26,6 @@ extern crate apt_cli_wrappers;
 extern crate bitflags;
 #[macro_use]
 extern crate cascade;
-extern crate err_derive;
 #[macro_use]
 extern crate derive_more;
 extern crate dirs;
@@ -46,12 +46,19 @@ extern crate libparted;
 extern crate log;
 extern crate logind_dbus;
 extern crate rayon;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
 extern crate systemd_boot_conf;
 extern crate tempdir;
+extern crate toml;
 
 pub use crate::bootloader::*;
 pub use disk_types::*;
 pub use crate::disks::*;
+pub use crate::errors::{ErrorCode, InstallError};
+pub use crate::executor::Cancellation;
 pub use crate::misc::device_layout_hash;
 pub use crate::upgrade::*;
 
@@ -63,6 +70,7 @@ mod logging;
 mod upgrade;
 
 pub mod auto;
+pub mod plan;
 pub(crate) mod errors;
 
 /// Useful DBus interfaces for installers to implement.
@@ -84,12 +92,9 @@ use systemd_boot_conf::SystemdBootConf;
 
 pub use self::{installer::*, logging::log};
 
-/// When set to true, this will stop the installation process.
-pub static KILL_SWITCH: AtomicBool = AtomicBool::new(false);
-
 pub use crate::bootloader::FORCE_BOOTLOADER;
 
-/// Exits before the unsquashfs step
+/// Exits before the extract step
 pub static PARTITIONING_TEST: AtomicBool = AtomicBool::new(false);
 
 /// Even if the system is EFI, the efivars directory will not be mounted in the chroot.
@@ -114,7 +119,7 @@ pub fn device_map_exists(name: &str) -> bool {
 ///
 /// - The value in `/cdrom/casper/filesystem.size`
 /// - The size of a default boot / esp partition
-/// - The size of a default swap partition
+/// - The size of a default swap partition, or swap file, which share the same size
 /// - The size of a default recovery partition.
 ///
 /// The input parameter will undergo a max comparison to the estimated minimum requirement.
@@ -1,5 +1,3 @@
-use dirs;
-use fern;
 use log::{Level, LevelFilter};
 use std::io;
 
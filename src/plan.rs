@@ -0,0 +1,335 @@
+//! Declarative install plans, which describe the disk layout and installer configuration of an
+//! install in a single TOML or JSON file.
+
+use crate::{
+    disks::{Disks, DisksPlan},
+    Config, ImageFormat, INSTALL_HARDWARE_SUPPORT, KEEP_OLD_ROOT, MODIFY_BOOT_ORDER,
+    RUN_UBUNTU_DRIVERS, VERIFY_IMAGE,
+};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Describes everything that is required to perform an install.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct InstallPlan {
+    pub config: ConfigPlan,
+    #[serde(default)]
+    pub disks:  DisksPlan,
+}
+
+/// The declarative equivalent of the installer's `Config`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigPlan {
+    pub hostname:                 String,
+    #[serde(default = "default_keyboard_layout")]
+    pub keyboard_layout:          String,
+    #[serde(default)]
+    pub keyboard_model:           Option<String>,
+    #[serde(default)]
+    pub keyboard_variant:         Option<String>,
+    #[serde(default)]
+    pub old_root:                 Option<String>,
+    #[serde(default = "default_lang")]
+    pub lang:                     String,
+    pub remove:                   String,
+    pub squashfs:                 String,
+    /// The format of the image, which is detected from its contents by default.
+    #[serde(default)]
+    pub image_format:             ImageFormat,
+    #[serde(default)]
+    pub modify_boot_order:        bool,
+    #[serde(default)]
+    pub install_hardware_support: bool,
+    #[serde(default)]
+    pub keep_old_root:            bool,
+    #[serde(default)]
+    pub run_ubuntu_drivers:       bool,
+    /// Verifies the squashfs against `/cdrom/md5sum.txt` before the disks are modified.
+    #[serde(default)]
+    pub verify_image:             bool,
+    /// Verifies the squashfs against this SHA-256 digest before the disks are modified.
+    #[serde(default)]
+    pub squashfs_sha256:          Option<String>,
+    /// Journals partition moves at this path, so that an interrupted move may be resumed.
+    #[serde(default)]
+    pub move_journal:             Option<PathBuf>,
+}
+
+fn default_keyboard_layout() -> String { "us".into() }
+
+fn default_lang() -> String { "en_US.UTF-8".into() }
+
+impl ConfigPlan {
+    /// The install flags that this plan enables.
+    pub fn flags(&self) -> u8 {
+        let mut flags = 0;
+
+        if self.modify_boot_order {
+            flags |= MODIFY_BOOT_ORDER;
+        }
+
+        if self.install_hardware_support {
+            flags |= INSTALL_HARDWARE_SUPPORT;
+        }
+
+        if self.keep_old_root {
+            flags |= KEEP_OLD_ROOT;
+        }
+
+        if self.run_ubuntu_drivers {
+            flags |= RUN_UBUNTU_DRIVERS;
+        }
+
+        if self.verify_image {
+            flags |= VERIFY_IMAGE;
+        }
+
+        flags
+    }
+}
+
+impl From<ConfigPlan> for Config {
+    fn from(plan: ConfigPlan) -> Config {
+        Config {
+            flags:            plan.flags(),
+            hostname:         plan.hostname,
+            keyboard_layout:  plan.keyboard_layout,
+            keyboard_model:   plan.keyboard_model,
+            keyboard_variant: plan.keyboard_variant,
+            old_root:         plan.old_root,
+            lang:             plan.lang,
+            remove:           plan.remove,
+            squashfs:         plan.squashfs,
+            squashfs_sha256:  plan.squashfs_sha256,
+            image_format:     plan.image_format,
+            move_journal:     plan.move_journal,
+        }
+    }
+}
+
+impl InstallPlan {
+    /// Reads a plan from a file, which is parsed as JSON if it has a `.json` extension, and TOML
+    /// otherwise.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path).map_err(|why| {
+            io::Error::new(why.kind(), format!("unable to read plan at {:?}: {}", path, why))
+        })?;
+
+        if path.extension().map_or(false, |ext| ext == "json") {
+            Self::from_json(&contents)
+        } else {
+            Self::from_toml(&contents)
+        }
+    }
+
+    /// Parses a plan from a JSON string.
+    pub fn from_json(contents: &str) -> io::Result<Self> {
+        serde_json::from_str(contents).map_err(|why| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("invalid JSON plan: {}", why))
+        })
+    }
+
+    /// Parses a plan from a TOML string.
+    pub fn from_toml(contents: &str) -> io::Result<Self> {
+        toml::from_str(contents).map_err(|why| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("invalid TOML plan: {}", why))
+        })
+    }
+
+    /// Probes and configures the disks described by the plan, and returns them alongside the
+    /// installer configuration.
+    pub fn apply(self) -> io::Result<(Disks, Config)> {
+        let mut disks = Disks::default();
+        disks.apply_plan(&self.disks)?;
+        Ok((disks, self.config.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disks::{
+        EncryptionPlan, Enrollment, LuksOptions, LvmPlan, PartitionKindPlan, Pbkdf, Subvolume,
+        SwapFile, SwapFilePlan, TablePlan,
+    };
+    use std::path::PathBuf;
+
+    const PLAN: &str = r#"
+[config]
+hostname = "pop-os"
+remove = "/cdrom/casper/filesystem.manifest-remove"
+squashfs = "/cdrom/casper/filesystem.squashfs"
+modify_boot_order = true
+
+[[disks.disks]]
+path = "/dev/sda"
+table = "gpt"
+
+[[disks.disks.partitions]]
+start = "start"
+end = "512M"
+filesystem = "fat32"
+mount = "/boot/efi"
+flags = ["esp"]
+
+[[disks.disks.partitions]]
+start = "512M"
+end = "end"
+lvm = { volume_group = "data", encryption = { physical_volume = "cryptdata", password = "pass" } }
+
+[[disks.volume_groups]]
+name = "data"
+
+[[disks.volume_groups.volumes]]
+name = "root"
+size = "100%"
+filesystem = "ext4"
+mount = "/"
+"#;
+
+    #[test]
+    fn toml_plan() {
+        let plan = InstallPlan::from_toml(PLAN).unwrap();
+
+        assert_eq!(plan.config.lang, "en_US.UTF-8");
+        assert_eq!(plan.config.keyboard_layout, "us");
+        assert_eq!(plan.config.flags(), MODIFY_BOOT_ORDER);
+
+        let disk = &plan.disks.disks[0];
+        assert_eq!(disk.path, PathBuf::from("/dev/sda"));
+        assert_eq!(disk.table, Some(TablePlan::Gpt));
+        assert_eq!(disk.partitions.len(), 2);
+        assert_eq!(disk.partitions[0].kind, PartitionKindPlan::Primary);
+        assert_eq!(disk.partitions[0].flags, vec!["esp".to_owned()]);
+        assert_eq!(
+            disk.partitions[1].lvm,
+            Some(LvmPlan {
+                volume_group: "data".into(),
+                encryption:   Some(EncryptionPlan {
+                    physical_volume: "cryptdata".into(),
+                    password:        Some("pass".into()),
+                    keyfile:         None,
+                    luks:            LuksOptions::default(),
+                    enroll:          Vec::new(),
+                }),
+            })
+        );
+
+        let group = &plan.disks.volume_groups[0];
+        assert_eq!(group.volumes[0].mount, Some(PathBuf::from("/")));
+    }
+
+    #[test]
+    fn subvolume_plan() {
+        let plan = InstallPlan::from_toml(
+            r#"
+[config]
+hostname = "pop-os"
+remove = "/cdrom/casper/filesystem.manifest-remove"
+squashfs = "/cdrom/casper/filesystem.squashfs"
+
+[[disks.disks]]
+path = "/dev/sda"
+
+[[disks.disks.partitions]]
+start = "start"
+end = "end"
+filesystem = "btrfs"
+mount = "/"
+subvolumes = [
+    { name = "@", target = "/" },
+    { name = "@home", target = "/home" },
+    { name = "@snapshots" },
+]
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            plan.disks.disks[0].partitions[0].subvolumes,
+            vec![
+                Subvolume::new("@", PathBuf::from("/")),
+                Subvolume::new("@home", PathBuf::from("/home")),
+                Subvolume::new("@snapshots", None::<PathBuf>),
+            ]
+        );
+    }
+
+    #[test]
+    fn swap_file_plan() {
+        let plan = format!("{}\n[disks.swap_file]\nsize = \"4096MiB\"\n", PLAN);
+        let plan = InstallPlan::from_toml(&plan).unwrap();
+        let swap = plan.disks.swap_file.as_ref().unwrap().to_swap_file().unwrap();
+
+        assert_eq!(swap.path, PathBuf::from(SwapFile::PATH));
+        assert_eq!(swap.sectors, 4096 * 2048);
+
+        let invalid = SwapFilePlan { size: "50%".into(), path: None };
+        assert!(invalid.to_swap_file().is_err());
+    }
+
+    #[test]
+    fn verify_image_plan() {
+        let sha256 = "a".repeat(64);
+        let config = format!("verify_image = true\nsquashfs_sha256 = {:?}\n", sha256);
+        let plan = PLAN.replacen("[[disks.disks]]", &[&config, "[[disks.disks]]"].concat(), 1);
+        let plan = InstallPlan::from_toml(&plan).unwrap();
+        assert_eq!(plan.config.flags(), MODIFY_BOOT_ORDER | VERIFY_IMAGE);
+
+        let config = Config::from(plan.config);
+        assert_eq!(config.squashfs_sha256, Some(sha256));
+    }
+
+    #[test]
+    fn image_format_plan() {
+        let plan = InstallPlan::from_toml(PLAN).unwrap();
+        assert_eq!(plan.config.image_format, ImageFormat::Auto);
+
+        let config = "image_format = \"oci\"\n";
+        let plan = PLAN.replacen("[[disks.disks]]", &[config, "[[disks.disks]]"].concat(), 1);
+        let plan = InstallPlan::from_toml(&plan).unwrap();
+        assert_eq!(Config::from(plan.config).image_format, ImageFormat::Oci);
+
+        assert_eq!("raw".parse::<ImageFormat>().unwrap(), ImageFormat::Raw);
+        assert!("ext4".parse::<ImageFormat>().is_err());
+    }
+
+    #[test]
+    fn luks_plan() {
+        let luks = r#"password = "pass", luks = { pbkdf = "argon2id", pbkdf_memory = 65536 }"#;
+        let plan = InstallPlan::from_toml(&PLAN.replace(r#"password = "pass""#, luks)).unwrap();
+        let encryption = plan.disks.disks[0].partitions[1].lvm.as_ref().unwrap();
+        let luks = &encryption.encryption.as_ref().unwrap().luks;
+
+        assert_eq!(luks.key_size, 512);
+        assert_eq!(luks.pbkdf, Some(Pbkdf::Argon2id));
+        assert_eq!(luks.pbkdf_memory, Some(65536));
+
+        let enroll = r#"password = "pass", enroll = [{ kind = "tpm2" }, { kind = "fido2" }]"#;
+        let plan = InstallPlan::from_toml(&PLAN.replace(r#"password = "pass""#, enroll)).unwrap();
+        let encryption = plan.disks.disks[0].partitions[1].lvm.as_ref().unwrap();
+        let enroll = &encryption.encryption.as_ref().unwrap().enroll;
+        assert_eq!(enroll, &[Enrollment::tpm2(), Enrollment::fido2()]);
+
+        let unknown = r#"password = "pass", luks = { pbkdf_mem = 65536 }"#;
+        assert!(InstallPlan::from_toml(&PLAN.replace(r#"password = "pass""#, unknown)).is_err());
+    }
+
+    #[test]
+    fn json_plan_matches_toml() {
+        let plan = InstallPlan::from_toml(PLAN).unwrap();
+        let json = serde_json::to_string(&plan).unwrap();
+        assert_eq!(InstallPlan::from_json(&json).unwrap(), plan);
+    }
+
+    #[test]
+    fn unknown_fields_rejected() {
+        let plan = PLAN.replace("modify_boot_order", "modify_bot_order");
+        assert!(InstallPlan::from_toml(&plan).is_err());
+    }
+}
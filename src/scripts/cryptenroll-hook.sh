@@ -0,0 +1,26 @@
+#!/bin/sh
+#
+# Copies the cryptsetup token plugins of systemd into the initramfs, alongside the TPM2 and
+# FIDO2 libraries that they load, so that LUKS volumes which were enrolled with
+# systemd-cryptenroll may be unlocked at boot.
+
+PREREQ="cryptroot"
+prereqs()
+{
+   echo "$PREREQ"
+}
+case $1 in
+prereqs)
+   prereqs
+   exit 0
+   ;;
+esac
+. /usr/share/initramfs-tools/hook-functions
+
+for library in /usr/lib/*/cryptsetup/libcryptsetup-token-systemd-*.so \
+   /usr/lib/*/libtss2-*.so.? /usr/lib/*/libfido2.so.?
+do
+   [ -e "$library" ] && copy_exec "$library"
+done
+
+exit 0
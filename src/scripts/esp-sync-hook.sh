@@ -0,0 +1,31 @@
+#!/bin/sh
+#
+# Copies the contents of /boot/efi to each mirror of the EFI partition, so that the system
+# remains bootable from any of its disks. Mirrors which cannot be found, such as those on a
+# failed disk, are skipped.
+
+set -e
+
+MIRRORS="@MIRRORS@"
+
+[ -d /boot/efi/EFI ] || exit 0
+
+ESP="$(findmnt -n -o SOURCE /boot/efi || true)"
+MIRROR_DIR="$(mktemp -d)"
+trap 'umount "$MIRROR_DIR" 2>/dev/null; rmdir "$MIRROR_DIR"' EXIT
+
+for mirror in $MIRRORS; do
+    if ! device="$(findfs "$mirror" 2>/dev/null)"; then
+        echo "esp-sync: $mirror was not found, and will not be updated" >&2
+        continue
+    fi
+
+    [ "$device" = "$ESP" ] && continue
+
+    mount -t vfat "$device" "$MIRROR_DIR"
+    find "$MIRROR_DIR" -mindepth 1 -delete
+    cp -r /boot/efi/. "$MIRROR_DIR"/
+    umount "$MIRROR_DIR"
+done
+
+exit 0
@@ -1,36 +1,35 @@
 use apt_cli_wrappers::AptUpgradeEvent;
 use crate::auto::{InstallOption, InstallOptionError, RecoveryOption};
-use crate::chroot::SystemdNspawn;
-use err_derive::Error;
+use crate::chroot::{Redirect, SystemdNspawn};
 use crate::disks::Disks;
 use crate::errors::IoContext;
 use crate::external::remount_rw;
 use crate::installer::{steps::mount_efivars, RecoveryEnv};
-use std::{io, path::Path, process::Stdio};
+use std::{io, path::Path};
 use systemd_boot_conf::SystemdBootConf;
 use tempdir::TempDir;
 
-#[derive(Debug, Error)]
+#[derive(Debug, Fail)]
 pub enum UpgradeError {
-    #[error(display = "attempted to recover from errors, but failed: {}", _0)]
+    #[fail(display = "attempted to recover from errors, but failed: {}", _0)]
     AttemptFailed(io::Error),
-    #[error(display = "failed to mount file systems to chroot: {}", _0)]
+    #[fail(display = "failed to mount file systems to chroot: {}", _0)]
     ChrootMount(io::Error),
-    #[error(display = "failed to create temporary chroot mount directory: {}", _0)]
+    #[fail(display = "failed to create temporary chroot mount directory: {}", _0)]
     ChrootTempCreate(io::Error),
-    #[error(display = "failed to configure disk(s): {}", _0)]
+    #[fail(display = "failed to configure disk(s): {}", _0)]
     Configure(InstallOptionError),
-    #[error(display = "failed to mount efivars directory: _0")]
+    #[fail(display = "failed to mount efivars directory: {}", _0)]
     EfiVars(io::Error),
-    #[error(display = "failed to mount $CHROOT/etc to /etc: {}", _0)]
+    #[fail(display = "failed to mount $CHROOT/etc to /etc: {}", _0)]
     EtcMount(io::Error),
-    #[error(display = "failed to find the Pop_OS-current entry in systemd-boot's efi loaders")]
+    #[fail(display = "failed to find the Pop_OS-current entry in systemd-boot's efi loaders")]
     MissingCurrentEntry,
-    #[error(display = "attempted an upgrade, but the upgrade mode was not set")]
+    #[fail(display = "attempted an upgrade, but the upgrade mode was not set")]
     ModeNotSet,
-    #[error(display = "systemd-boot loader conf error: {}", _0)]
+    #[fail(display = "systemd-boot loader conf error: {}", _0)]
     SystemdBootConf(systemd_boot_conf::Error),
-    #[error(display = "failed to remove upgrade flag from recovery.conf: {}", _0)]
+    #[fail(display = "failed to remove upgrade flag from recovery.conf: {}", _0)]
     UpgradeFlag(io::Error),
 }
 
@@ -135,8 +134,8 @@ fn systemd_boot_entry_restore<P: AsRef<Path>>(base: P) -> Result<(), UpgradeErro
 fn apt_upgrade<F: Fn(UpgradeEvent)>(chroot: &mut SystemdNspawn, callback: &F) -> io::Result<()> {
     chroot
         .command("apt-get", &["-y", "--allow-downgrades", "--show-progress", "full-upgrade"])
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
+        .stdout(Redirect::Piped)
+        .stderr(Redirect::Piped)
         .run_with_callbacks(
             |info| {
                 info!("apt-info: '{}'", info);
@@ -176,8 +175,8 @@ fn dpkg_configure_all<F: Fn(UpgradeEvent)>(
 ) -> io::Result<()> {
     chroot
         .command("dpkg", &["--configure", "-a"])
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
+        .stdout(Redirect::Piped)
+        .stderr(Redirect::Piped)
         .run_with_callbacks(
             |info| {
                 info!("dpkg-info: '{}'", info);
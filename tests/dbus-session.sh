@@ -0,0 +1,33 @@
+#!/bin/sh
+# Serves the installer on a private session bus, and probes the disks and install options
+# through it. Must be run as root, so that the disks may be probed.
+
+DAEMON="target/debug/distinst-daemon"
+DEST="com.system76.Distinst"
+OBJECT="/com/system76/Distinst"
+
+if ! test -e "${DAEMON}"; then
+    cargo build --manifest-path daemon/Cargo.toml
+fi
+
+if test "${1}" != "--in-session"; then
+    exec dbus-run-session -- "${0}" --in-session
+fi
+
+set -e -x
+
+"${DAEMON}" --session &
+DAEMON_PID=$!
+trap 'kill ${DAEMON_PID}' EXIT
+
+gdbus wait --session --timeout 10 "${DEST}"
+
+gdbus call --session --dest "${DEST}" --object-path "${OBJECT}" \
+    --method "${DEST}.ProbeDisks" | grep -q '"physical"'
+
+gdbus call --session --dest "${DEST}" --object-path "${OBJECT}" \
+    --method "${DEST}.InstallOptions" 0 0 | grep -q '"kind"'
+
+# Installing without a configuration is refused, rather than starting an install.
+! gdbus call --session --dest "${DEST}" --object-path "${OBJECT}" \
+    --method "${DEST}.Install" '{}'
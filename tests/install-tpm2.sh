@@ -0,0 +1,61 @@
+#!/bin/sh
+#
+# Installs to an encrypted disk which is enrolled with a software TPM, which requires swtpm and
+# the tpm_vtpm_proxy kernel module.
+FS="/cdrom/casper/filesystem.squashfs"
+REMOVE="/cdrom/casper/filesystem.manifest-remove"
+
+if ! test -e "target/debug/distinst"; then
+    cargo build --manifest-path cli/Cargo.toml
+fi
+
+if ! test "${1}"; then
+    echo "must provide a block device as an argument"
+    exit 1
+fi
+
+if ! test -b "${1}"; then
+    echo "provided argument is not a block device"
+    exit 1
+fi
+
+for file in "$FS" "$REMOVE"; do
+    if ! test -e "${file}"; then
+        echo "failed to find ${file}"
+        exit 1
+    fi
+done
+
+if ! command -v swtpm > /dev/null; then
+    echo "swtpm must be installed"
+    exit 1
+fi
+
+set -e -x
+
+STATE="$(mktemp -d)"
+sudo modprobe tpm_vtpm_proxy
+sudo swtpm chardev --vtpm-proxy --tpm2 --tpmstate "dir=${STATE}" --daemon \
+    --pid "file=${STATE}/swtpm.pid" > "${STATE}/swtpm.log"
+trap 'sudo kill "$(cat "${STATE}/swtpm.pid")"; sudo rm -rf "${STATE}"' EXIT
+
+# swtpm reports the device that it created, such as `/dev/tpm1`.
+TPM="$(sed -n 's/^New TPM device: \(\/dev\/tpm[0-9]*\).*/\1/p' "${STATE}/swtpm.log")"
+
+echo "system76" | sudo target/debug/distinst \
+    -s "${FS}" \
+    -r "${REMOVE}" \
+    -h "pop-testing" \
+    -k "us" \
+    -l "en_US.UTF-8" \
+    -b "$1" \
+    -t "$1:gpt" \
+    -n "$1:primary:start:512M:fat32:mount=/boot/efi:flags=esp" \
+    -n "$1:primary:512M:end:enc=cryptdata,data,pass=password,tpm2=${TPM},tpm2-pcrs=7" \
+    --logical "data:root:-4096M:ext4:mount=/" \
+    --logical "data:swap:4096M:swap" \
+    --username "oem" \
+    --realname "System76 OEM Account" \
+    --tz "America/Denver"
+
+sudo cryptsetup luksDump "$1"2 | grep -q systemd-tpm2
@@ -0,0 +1,75 @@
+#!/bin/sh
+FS="tests/filesystem.squashfs"
+REMOVE="tests/filesystem.manifest-remove"
+PLAN="tests/partition-plan.toml"
+RUNS=3
+
+if ! test -e "target/debug/distinst"; then
+    cargo build --manifest-path cli/Cargo.toml
+fi
+
+if ! test "${1}"; then
+    echo "must provide a block device as an argument"
+    exit 1
+fi
+
+if ! test -b "${1}"; then
+    echo "'${1}' is not a block device"
+    exit 1
+fi
+
+for file in "$FS" "$REMOVE"; do
+    if ! test -e "${file}"; then
+        echo "failed to find ${file}"
+        exit 1
+    fi
+done
+
+set -e -x
+
+cat > "${PLAN}" <<PLAN
+[config]
+hostname = "pop-testing"
+keyboard_layout = "us"
+lang = "en_US.UTF-8"
+remove = "${REMOVE}"
+squashfs = "${FS}"
+
+[[disks.disks]]
+path = "${1}"
+table = "gpt"
+
+[[disks.disks.partitions]]
+start = "start"
+end = "512M"
+filesystem = "fat32"
+mount = "/boot/efi"
+flags = ["esp"]
+
+[[disks.disks.partitions]]
+start = "512M"
+end = "end"
+lvm = { volume_group = "data", encryption = { physical_volume = "cryptdata", password = "password" } }
+
+[[disks.volume_groups]]
+name = "data"
+
+[[disks.volume_groups.volumes]]
+name = "root"
+size = "-4096M"
+filesystem = "ext4"
+mount = "/"
+
+[[disks.volume_groups.volumes]]
+name = "swap"
+size = "4096M"
+filesystem = "swap"
+PLAN
+
+echo 'Running LVM on LUKS plan test'
+index=0; while test ${index} -ne ${RUNS}; do
+    sudo env RUST_BACKTRACE=1 target/debug/distinst --test --plan "${PLAN}"
+    index=$((index + 1))
+done
+
+rm "${PLAN}"
@@ -0,0 +1,97 @@
+#!/bin/sh
+FS="tests/filesystem.squashfs"
+REMOVE="tests/filesystem.manifest-remove"
+PLAN="tests/partition-raid.toml"
+RUNS=3
+
+if ! test -e "target/debug/distinst"; then
+    cargo build --manifest-path cli/Cargo.toml
+fi
+
+if ! test "${1}" || ! test "${2}"; then
+    echo "must provide two block devices as arguments"
+    exit 1
+fi
+
+for device in "${1}" "${2}"; do
+    if ! test -b "${device}"; then
+        echo "'${device}' is not a block device"
+        exit 1
+    fi
+done
+
+for file in "$FS" "$REMOVE"; do
+    if ! test -e "${file}"; then
+        echo "failed to find ${file}"
+        exit 1
+    fi
+done
+
+set -e -x
+
+cat > "${PLAN}" <<PLAN
+[config]
+hostname = "pop-testing"
+keyboard_layout = "us"
+lang = "en_US.UTF-8"
+remove = "${REMOVE}"
+squashfs = "${FS}"
+
+[[disks.disks]]
+path = "${1}"
+table = "gpt"
+
+[[disks.disks.partitions]]
+start = "start"
+end = "512M"
+filesystem = "fat32"
+mount = "/boot/efi"
+flags = ["esp"]
+
+[[disks.disks.partitions]]
+start = "512M"
+end = "end"
+raid = "root"
+
+[[disks.disks]]
+path = "${2}"
+table = "gpt"
+
+[[disks.disks.partitions]]
+start = "start"
+end = "512M"
+filesystem = "fat32"
+esp_mirror = true
+
+[[disks.disks.partitions]]
+start = "512M"
+end = "end"
+raid = "root"
+
+[[disks.raid]]
+name = "root"
+level = "raid1"
+lvm = { volume_group = "data", encryption = { physical_volume = "cryptdata", password = "password" } }
+
+[[disks.volume_groups]]
+name = "data"
+
+[[disks.volume_groups.volumes]]
+name = "root"
+size = "-4096M"
+filesystem = "ext4"
+mount = "/"
+
+[[disks.volume_groups.volumes]]
+name = "swap"
+size = "4096M"
+filesystem = "swap"
+PLAN
+
+echo 'Running LVM on LUKS on RAID1 with a mirrored ESP plan test'
+index=0; while test ${index} -ne ${RUNS}; do
+    sudo env RUST_BACKTRACE=1 target/debug/distinst --test --plan "${PLAN}"
+    index=$((index + 1))
+done
+
+rm "${PLAN}"